@@ -21,6 +21,7 @@ use monero_serai::wallet::{
 
 use core::ops::Deref;
 use paymo::core::utils::{generate_user_key_pair, generate_user_tag};
+use paymo::core::JointWallet;
 use rand_core::OsRng;
 use sha3::{Digest, Keccak256};
 use zeroize::Zeroizing;
@@ -43,17 +44,22 @@ async fn main() {
     let (bob_view_secret, bob_view_public) = generate_user_key_pair();
     let (bob_spend_secret, bob_spend_public) = generate_user_key_pair();
 
-    let joint_view_private = alice_view_secret + bob_view_secret;
-    let joint_spend_private = alice_spend_secret + bob_spend_secret;
+    let joint_wallet = JointWallet::new(
+        &alice_spend_public,
+        &bob_spend_public,
+        &alice_view_secret,
+        &bob_view_secret,
+        Network::Mainnet,
+    );
 
+    let joint_spend_private = alice_spend_secret + bob_spend_secret;
     let joint_view_public = alice_view_public + bob_view_public;
-    let joint_spend_public = alice_spend_public + bob_spend_public;
 
     // TODO generate tag when spending from shared address
 
     println!(
         "Joint view private is: {}",
-        hex::encode(joint_view_private.as_bytes())
+        hex::encode(joint_wallet.view_key().as_bytes())
     );
     println!(
         "Joint view public is: {}",
@@ -61,13 +67,12 @@ async fn main() {
     );
     println!(
         "Joint spend public is: {}",
-        hex::encode(joint_spend_public.compress().as_bytes())
+        hex::encode(joint_wallet.spend_key().compress().as_bytes())
     );
 
     println!();
 
-    let joint_view_pair = ViewPair::new(joint_spend_public, Zeroizing::new(joint_view_private));
-    let joint_address = joint_view_pair.address(Network::Mainnet, AddressSpec::Standard);
+    let joint_address = joint_wallet.address();
 
     println!("Joint address is: {}", joint_address.to_string());
 
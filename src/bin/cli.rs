@@ -19,6 +19,15 @@ fn main() -> paymo::Result<()> {
 
     let opts = opts.unwrap();
 
+    if let Some(command) = &opts.command {
+        if let Err(err) = client::send_command(&opts.shared.data_dir, command) {
+            error!("{err}");
+            return Err(err);
+        }
+
+        return Ok(());
+    }
+
     let conf = config::Config::from_path(&opts.config_file);
     if let Err(err) = conf {
         error!("{err}");
@@ -5,6 +5,7 @@ use crate::msgs;
 
 pub const CLIENT_PUB_SOCKET: &str = "ipc://{data_dir}/pub-client.ipc";
 pub const CLIENT_SUB_SOCKET: &str = "ipc://{data_dir}/sub-client.ipc";
+pub const CLIENT_CMD_SOCKET: &str = "ipc://{data_dir}/cmd-client.ipc";
 
 pub fn connect_to_client_sockets(
     data_dir: PathBuf,
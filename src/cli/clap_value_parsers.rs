@@ -1,25 +1,44 @@
 use super::Error;
+use crate::core::calibration::{self, TimeSpec};
+use crate::core::utils::decode_scalar;
+use crate::walletd::ViewKey;
 use monero_serai::wallet::address;
+use zeroize::Zeroizing;
 
-pub fn parse_address_network(s: &str) -> Result<address::MoneroAddress, String> {
-    let addr = address::MoneroAddress::from_str_raw(s).map_err(|e| e.to_string())?;
+/// Minimum number of squarings of the puzzles, however the time is given.
+pub const MIN_TIME: u64 = 100;
 
-    // regtest and mainnet have the same magic bytes
-    if addr.meta.network != address::Network::Mainnet {
-        return Err(Error::UnsupportedAddress.to_string());
-    }
+// its network is checked against the one of the config, see `Client::add_conf`
+pub fn parse_address(s: &str) -> Result<address::MoneroAddress, String> {
+    address::MoneroAddress::from_str_raw(s).map_err(|e| e.to_string())
+}
 
-    Ok(addr)
+pub fn parse_view_key(s: &str) -> Result<ViewKey, String> {
+    let bytes = hex::decode(s).map_err(|_| Error::InvalidViewKey.to_string())?;
+    let bytes = Zeroizing::new(bytes);
+    let key = decode_scalar(&bytes).ok_or_else(|| Error::InvalidViewKey.to_string())?;
+
+    Ok(ViewKey::new(key))
 }
 
-pub fn parse_t_duration(s: &str) -> Result<u64, String> {
-    let min_hardness = 100;
+pub fn parse_time(s: &str) -> Result<TimeSpec, String> {
+    let time: TimeSpec = s.parse().map_err(|e: calibration::Error| e.to_string())?;
+
+    if let TimeSpec::Squarings(squarings) = time {
+        if squarings < MIN_TIME {
+            return Err(Error::InvalidTime(squarings).to_string());
+        }
+    }
+
+    Ok(time)
+}
 
-    let hardness = s.parse().map_err(|e| Into::<Error>::into(e).to_string())?;
+pub fn parse_time_margin(s: &str) -> Result<f64, String> {
+    let margin: f64 = s.parse().map_err(|e| Into::<Error>::into(e).to_string())?;
 
-    if hardness < min_hardness {
-        return Err(Error::InvalidTime(hardness).to_string());
+    if !margin.is_finite() || margin < 1.0 {
+        return Err(Error::InvalidTimeMargin(margin).to_string());
     }
 
-    Ok(hardness)
+    Ok(margin)
 }
@@ -1,20 +1,39 @@
 use colored::Colorize;
-use log::debug;
+use log::{debug, warn};
 use prost::Message;
-use std::path::PathBuf;
+use std::env;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::str::FromStr;
 use std::thread;
 use std::time::Duration;
 
-use curve25519_dalek::edwards::CompressedEdwardsY;
-use monero_serai::wallet::address;
+use curve25519_dalek::{
+    constants::ED25519_BASEPOINT_TABLE, edwards::EdwardsPoint, scalar::Scalar, traits::IsIdentity,
+};
+use monero_serai::{ringct::hash_to_point, rpc::Rpc, wallet::address};
+use rand_core::OsRng;
+use zeroize::{Zeroize, Zeroizing};
 
-use super::opts::Opts;
+use super::clap_value_parsers::MIN_TIME;
+use super::opts::{ChannelCommand, Command, Opts};
 use crate::config::Config;
-use crate::core::utils::{generate_user_key_pair, generate_user_tag, hash};
-use crate::core::{self, Role};
-use crate::msgs::{self, peerd_msg};
+use crate::core::backup;
+use crate::core::calibration::{format_duration, Calibration};
+use crate::core::channel::{ChannelState, Closing, OwnKeys, Params, Phase};
+use crate::core::checkpoint::{CancelToken, CheckpointedSolver, Progress};
+use crate::core::db::{ChannelDb, ChannelStore};
+use crate::core::keystore::{self, Keystore};
+use crate::core::proofs::{DleqProof, SchnorrProof};
+use crate::core::sealed;
+use crate::core::seed::MasterSeed;
+use crate::core::timelock::{Backend, TimeLockPuzzle};
+use crate::core::utils::{decode_point, decode_scalar, hash};
+use crate::core::{self, classgroup, lhtlp, vtdlog, Role};
+use crate::msgs::{self, client_cmd, joint_spend_msg, pay_msg, peerd_msg, vtdlog_msg};
+use crate::peerd::transport::NodeKey;
+use crate::walletd;
+use crate::watcherd::{self, KeyImages};
 
 pub struct Client {
     role: core::Role,
@@ -23,9 +42,35 @@ pub struct Client {
 
     pub_socket: Option<zmq::Socket>,
     sub_socket: Option<zmq::Socket>,
+    cmd_socket: Option<zmq::Socket>,
+    vtdlog_socket: Option<zmq::Socket>,
 
     channel: core::Channel,
 
+    calibration: Option<Calibration>,
+
+    vtdlog: Option<Box<dyn vtdlog::Exchange>>,
+    bob_vtdlog: Option<msgs::VtdlogCommit>,
+    refund_solver: Option<thread::JoinHandle<()>>,
+
+    joint_spend: Option<walletd::JointSpend>,
+    unsigned_spend: Option<walletd::UnsignedSpend>,
+
+    payer: Option<walletd::Payer>,
+    payee: Option<walletd::Payee>,
+    // hash and height of the published refund, until it has the confirmations of the channel
+    refund_tx: Option<([u8; 32], usize)>,
+    spend_builder: Option<Box<dyn walletd::SpendBuilder>>,
+    chain: Option<Box<dyn watcherd::Chain>>,
+    // of our address; Alice may run without it, but then cannot close the channel with Bob
+    view_key: Option<walletd::ViewKey>,
+
+    store: Option<ChannelStore>,
+    keystore: Option<Keystore>,
+    // of the other party, which the channel is stored with: Alice's is in her URL, and Bob's
+    // is the one of his handshake
+    their_node_key: Option<[u8; 32]>,
+
     walled_process: Option<process::Child>,
     watcherd_process: Option<process::Child>,
     peerd_process: Option<process::Child>,
@@ -41,22 +86,44 @@ pub struct Client {
 
 impl Client {
     pub fn from_opts(opts: Opts) -> Self {
-        let peerd_url = if opts.role == Role::Bob {
+        let peerd_url = if opts.role == Some(Role::Bob) {
             opts.bob_opts.clone().unwrap().connect
         } else {
             None
         };
 
         Self {
-            role: opts.role.clone(),
+            role: opts.role.clone().unwrap(),
 
             zmq_context: zmq::Context::new(),
 
             pub_socket: None,
             sub_socket: None,
+            cmd_socket: None,
+            vtdlog_socket: None,
 
             channel: core::Channel::from_opts(&opts),
 
+            calibration: opts.calibration,
+
+            vtdlog: None,
+            bob_vtdlog: None,
+            refund_solver: None,
+
+            joint_spend: None,
+            unsigned_spend: None,
+
+            payer: None,
+            payee: None,
+            refund_tx: None,
+            spend_builder: None,
+            chain: None,
+            view_key: opts.view_key.clone(),
+
+            store: None,
+            keystore: None,
+            their_node_key: None,
+
             walled_process: None,
             watcherd_process: None,
             peerd_process: None,
@@ -80,14 +147,34 @@ impl Client {
 
             Some(url)
         } else {
-            self.peerd_url.take()
+            // Bob only talks to the node whose key is in Alice's URL
+            match self.peerd_url.take() {
+                Some(url) if url.node_key.is_none() => {
+                    return Err(crate::peerd::Error::MissingNodeKey(url.to_string()).into())
+                }
+                url => url,
+            }
         };
 
+        if self.role == Role::Bob {
+            self.their_node_key = peerd_url.as_ref().and_then(|url| url.node_key);
+        }
+
+        // the joint address is on the network of our own address
+        let network = address::Network::from(conf.network);
+        if self.channel.address(&self.role)?.meta.network != network {
+            return Err(Error::OtherNetwork(network).into());
+        }
+
         self.peerd_url = peerd_url;
         self.monerod_rpc_url = Some(conf.monero.daemon.parse()?);
         self.monerod_zmq_url = Some(conf.monero.daemon_zmq.parse()?);
         self.monero_wallet_url = Some(conf.monero.wallet_rpc.parse()?);
 
+        let builder = walletd::DaemonSpendBuilder::new(self.rpc()?)?;
+        self.set_spend_builder(Box::new(builder));
+        self.chain = Some(Box::new(watcherd::DaemonChain::new(self.rpc()?)?));
+
         Ok(self)
     }
 
@@ -116,22 +203,48 @@ impl Client {
         sub_socket.set_subscribe(msgs::Process::Walletd.as_str_name().as_bytes())?;
         sub_socket.set_subscribe(msgs::Process::Watcherd.as_str_name().as_bytes())?;
 
+        let cmd_addr = str::replace(
+            crate::bus::CLIENT_CMD_SOCKET,
+            "{data_dir}",
+            self.data_dir.to_str().unwrap(),
+        );
+        debug!("Client cmd socket: {}", cmd_addr);
+
+        let cmd_socket = self.zmq_context.socket(zmq::REP)?;
+        cmd_socket.bind(&cmd_addr)?;
+
+        let vtdlog_socket = self.zmq_context.socket(zmq::PAIR)?;
+        vtdlog_socket.bind(VTDLOG_SOLVER_SOCKET)?;
+
         self.pub_socket = Some(pub_socket);
         self.sub_socket = Some(sub_socket);
+        self.cmd_socket = Some(cmd_socket);
+        self.vtdlog_socket = Some(vtdlog_socket);
 
         Ok(())
     }
 
-    fn spawn_peerd(&self) -> crate::Result<process::Child> {
+    fn spawn_peerd(&self, resume: bool) -> crate::Result<process::Child> {
         let mut args = vec![("-d", self.data_dir.to_str().unwrap())];
-        let peerd_url = self.peerd_url.as_ref().unwrap().to_string();
+        let peerd_url = self.peerd_url.as_ref().unwrap();
+        let (bind, connect) = (peerd_url.endpoint(), peerd_url.to_string());
+        let their_node_key = self.their_node_key.map(hex::encode);
 
         if self.role == Role::Alice {
-            args.push(("--bind", &peerd_url));
+            args.push(("--bind", &bind));
+
+            // only Bob's node resumes the channel
+            if let Some(bob_key) = &their_node_key {
+                args.push(("--bob-key", bob_key));
+            }
         } else {
-            args.push(("--connect", &peerd_url));
+            args.push(("--connect", &connect));
         };
 
+        if resume {
+            args.push(("--resume", "true"));
+        }
+
         core::spawn_process(core::PaymoProcess::Peerd, args)
     }
 
@@ -139,7 +252,16 @@ impl Client {
     pub fn run(mut self) -> crate::Result<()> {
         self.bind_client_sockets()?;
 
-        self.peerd_process = Some(self.spawn_peerd()?);
+        self.keystore = Some(unlock_keystore(&self.data_dir)?);
+
+        // created here rather than by peerd, so that Alice's URL has its public key
+        let node_key = NodeKey::load_or_create(&self.data_dir)?;
+        if self.role == Role::Alice {
+            self.peerd_url.as_mut().unwrap().node_key = Some(node_key.public_key());
+        }
+
+        let resumed = self.resume()?;
+        self.peerd_process = Some(self.spawn_peerd(resumed)?);
 
         // temporary solution to garantee sockets are connected
         thread::sleep(Duration::from_millis(200));
@@ -157,357 +279,2464 @@ impl Client {
         Ok(())
     }
 
-    fn recv(&mut self) -> crate::Result<()> {
-        loop {
-            let sub_socket = self.sub_socket.as_ref().unwrap();
+    // resumes the channel of the data directory that is set up and not closed yet, if any,
+    // instead of opening a new one; it must have been opened with the same role and address
+    fn resume(&mut self) -> crate::Result<bool> {
+        let stores = ChannelDb::new(&self.data_dir).load_all()?;
+        let store = match stores.into_iter().find(ChannelStore::is_resumable) {
+            Some(store) => store,
+            None => return Ok(false),
+        };
 
-            let process_key = sub_socket.recv_string(0)?;
-            let process_key = msgs::Process::from_str_name(&process_key.unwrap());
-            let process_key = process_key.unwrap();
+        let channel = store.channel(self.keystore.as_ref().unwrap())?;
+        let address = self.channel.address(&self.role)?.to_string();
+        if channel.role() != &self.role || channel.address(&self.role)?.to_string() != address {
+            return Err(Error::OtherChannel.into());
+        }
 
-            let data = sub_socket.recv_bytes(0)?;
+        // with the node it was opened with
+        let their_node_key = store.their_node_key().ok_or(Error::NoNodeKey)?;
+        match self.role {
+            Role::Alice => self.their_node_key = Some(their_node_key),
+            Role::Bob if self.their_node_key != Some(their_node_key) => {
+                return Err(Error::OtherNode.into())
+            }
+            Role::Bob => (),
+        }
 
-            match process_key {
-                msgs::Process::Peerd => self.recv_from_peerd(data)?,
-                msgs::Process::Walletd => todo!(),
-                msgs::Process::Watcherd => todo!(),
-                msgs::Process::TypeUnspecified => break,
+        let state = walletd::Payments::new(store.dir()).state()?;
+        if state != store.payment() {
+            warn!(
+                "The latest payment of the channel is state {}, but the channel database has \
+                 state {}",
+                state.number,
+                store.payment().number
+            );
+        }
+
+        let bob_vtdlog = store.bob_vtdlog();
+
+        self.channel = channel;
+        self.store = Some(store);
+
+        println!(
+            "{} {} channel, state {}, {} to Bob",
+            "CHANNEL RESUMED:".green(),
+            self.channel.phase().name(),
+            state.number,
+            monero::Amount::from_pico(state.bob_amount)
+                .to_string()
+                .green()
+        );
+
+        // the refund solver starts again from its checkpoints
+        if let (Role::Alice, Some((commit, opening))) = (&self.role, bob_vtdlog) {
+            self.solve_refund(commit, opening)?;
+        }
+
+        Ok(true)
+    }
+
+    // saves the channel once its keys are exchanged, so that it is resumed after a restart
+    fn save_channel(&mut self) -> crate::Result<()> {
+        match &mut self.store {
+            Some(store) => store.save(&self.channel)?,
+            None => {
+                let their_node_key = self.their_node_key.ok_or(Error::NoNodeKey)?;
+                let store = ChannelDb::new(&self.data_dir).create(&self.channel, their_node_key)?;
+                self.store = Some(store);
             }
         }
 
         Ok(())
     }
 
-    fn recv_from_peerd(&mut self, data: Vec<u8>) -> crate::Result<()> {
-        use peerd_msg::PeerdMsgType::*;
+    // the latest payment, in the directory of the channel
+    fn payments(&self) -> crate::Result<walletd::Payments> {
+        let store = self.store.as_ref().ok_or(Error::ChannelNotOpen)?;
 
-        let msg = msgs::PeerdMsg::decode(data.as_slice())?;
-        debug!("Received message from peerd: {msg:?}");
+        Ok(walletd::Payments::new(store.dir()))
+    }
 
-        match msg.msg_type() {
-            ReqChannelInfo => {
-                debug!("Received ReqChannelInfo");
+    fn save_payment(&mut self, state: &ChannelState) -> crate::Result<()> {
+        let store = self.store.as_mut().ok_or(Error::ChannelNotOpen)?;
+        store.save_payment(state)?;
+
+        Ok(())
+    }
 
-                let channel_amount = self.channel.channel_amount.as_ref();
-                let channel_amount = channel_amount.unwrap().as_pico();
+    /// Solves the VTD-Log of the other party, resuming from the checkpoints in the data
+    /// directory. A malformed commitment aborts the protocol, and the puzzles that do not
+    /// hide their share are printed as evidence.
+    pub fn solve_vtdlog<P: TimeLockPuzzle>(
+        &self,
+        vtdlog: &mut vtdlog::Vtdlog<P>,
+    ) -> crate::Result<Scalar> {
+        solve_vtdlog(&self.data_dir, vtdlog)
+    }
 
-                let time = self.channel.time.unwrap();
+    /// Bob's transaction spending the joint output, signed with Alice once peerd starts the
+    /// joint spend.
+    pub fn prepare_joint_spend(&mut self, spend: walletd::UnsignedSpend) {
+        self.unsigned_spend = Some(spend);
+    }
 
-                let confirmations = self.channel.confirmations.as_ref();
-                let confirmations = *confirmations.unwrap();
+    /// Builds the spends of the joint output: the ones Alice pre-signs for every payment, and
+    /// the one Bob proposes to close the channel.
+    pub fn set_spend_builder(&mut self, builder: Box<dyn walletd::SpendBuilder>) {
+        self.spend_builder = Some(builder);
+    }
 
-                let channel_info = msgs::ChannelInfo {
-                    channel_amount,
-                    time,
-                    confirmations,
-                };
+    fn rpc(&self) -> crate::Result<Rpc> {
+        let url = self.monerod_rpc_url.as_ref().unwrap().to_string();
+        let rpc = Rpc::new(url).map_err(|e| core::Error::from(core::wallet::Error::Rpc(e)))?;
 
-                let msg = peerd_msg::Data::ChannelInfo(channel_info);
+        Ok(rpc)
+    }
 
-                self.send_to_peerd(ResChannelInfo, Some(msg))?;
-            }
+    fn chain(&self) -> &dyn watcherd::Chain {
+        self.chain.as_deref().unwrap()
+    }
 
-            SendChannelInfo => {
-                debug!("Received SendChannelInfo");
+    // the joint output, found on chain with the joint wallet once the channel is set up
+    fn fund_channel(&mut self) -> crate::Result<()> {
+        let keys = self.channel.keys()?;
+        let channel_amount = keys.params.channel_amount.as_pico();
 
-                let channel_info = msg.data.unwrap();
-                let channel_info = if let peerd_msg::Data::ChannelInfo(channel_info) = channel_info
-                {
-                    channel_info
-                } else {
-                    unreachable!()
-                };
+        let rpc = self.rpc()?;
 
-                let channel_amount = monero::Amount::from_pico(channel_info.channel_amount);
+        let runtime = tokio::runtime::Runtime::new()?;
+        let output = runtime
+            .block_on(
+                keys.joint_wallet
+                    .find_output(&rpc, keys.from_height, channel_amount),
+            )
+            .map_err(core::Error::from)?;
+        let (output, height) = output.ok_or(Error::ChannelNotFunded)?;
 
-                self.channel.channel_amount = Some(channel_amount);
-                self.channel.time = Some(channel_info.time);
-                self.channel.confirmations = Some(channel_info.confirmations);
+        self.channel.fund(output, height)?;
+        self.save_channel()?;
+        println!("{} block {height}", "CHANNEL FUNDED:".green());
 
-                debug!("{:#?}", self.channel);
-            }
+        Ok(())
+    }
 
-            AliceReqAddress => {
-                let address = self.channel.alice_address.as_ref().unwrap().to_string();
-                let data = peerd_msg::Data::Address(address);
-                self.send_to_peerd(peerd_msg::PeerdMsgType::ResAddress, Some(data))?;
-            }
+    // the channel is open once it is funded, and the joint output has the confirmations of the
+    // channel; it is only looked for the first time it is needed
+    fn open_channel(&mut self) -> crate::Result<()> {
+        // the refund spends the joint output, even before it is confirmed
+        if self.refund_tx.is_some() {
+            return Err(Error::ChannelClosed.into());
+        }
 
-            BobReqAddress => {
-                let address = self.channel.bob_address.as_ref().unwrap().to_string();
-                let data = peerd_msg::Data::Address(address);
-                self.send_to_peerd(peerd_msg::PeerdMsgType::ResAddress, Some(data))?;
+        let funded = match self.channel.phase() {
+            Phase::Open(_) => return Ok(()),
+            Phase::KeysExchanged(keys) if keys.is_set_up() => false,
+            Phase::Funded(_) => true,
+            Phase::Closing(..) | Phase::Closed(..) | Phase::Refunded(_) => {
+                return Err(Error::ChannelClosed.into())
             }
+            _ => return Err(Error::ChannelNotOpen.into()),
+        };
 
-            AliceUpdateBobAddress => {
-                debug!("Received AliceUpdateBobAddress");
+        if !funded {
+            self.fund_channel()?;
+        }
 
-                let bob_address = if let peerd_msg::Data::Address(bob_address) = msg.data.unwrap() {
-                    bob_address
-                } else {
-                    unreachable!()
-                };
-                self.channel.bob_address =
-                    Some(address::MoneroAddress::from_str_raw(&bob_address)?);
+        let height = self.chain().height()?;
 
-                debug!("{:#?}", self.channel);
-            }
+        self.channel.open(height)?;
+        self.save_channel()?;
+        println!("{}", "CHANNEL OPEN".green());
 
-            BobUpdateAliceAddress => {
-                debug!("Received BobUpdateAliceAddress");
+        Ok(())
+    }
 
-                let alice_address =
-                    if let peerd_msg::Data::Address(alice_address) = msg.data.unwrap() {
-                        alice_address
-                    } else {
-                        unreachable!()
-                    };
-                self.channel.alice_address =
-                    Some(address::MoneroAddress::from_str_raw(&alice_address)?);
+    // Alice's side of the payments, from the latest one in the data directory
+    fn payer(&mut self) -> crate::Result<&mut walletd::Payer> {
+        if self.payer.is_none() {
+            self.open_channel()?;
+            let state = self.payments()?.state()?;
 
-                debug!("{:#?}", self.channel);
-            }
+            self.payer = Some(walletd::Payer::new(self.channel.funding()?, state));
+        }
 
-            AliceCreateSecret => {
-                let (alice_secret, alice_public_key) = generate_user_key_pair();
-                let alice_hash = hash(alice_public_key.compress().as_bytes());
+        Ok(self.payer.as_mut().unwrap())
+    }
 
-                self.channel.alice_secret = Some(alice_secret);
-                self.channel.alice_public_key = Some(alice_public_key);
-                self.channel.alice_hash = Some(alice_hash.to_vec());
+    // Bob's side of the payments, from the latest one in the data directory
+    fn payee(&mut self) -> crate::Result<&mut walletd::Payee> {
+        if self.payee.is_none() {
+            self.open_channel()?;
+            let state = self.payments()?.state()?;
 
-                debug!("Alice's hash is {}", hex::encode(alice_hash));
-            }
+            let view_key = self.view_key.clone().ok_or(Error::NoViewKey)?;
 
-            BobCreateSecret => {
-                let (bob_secret, bob_public_key) = generate_user_key_pair();
+            self.payee = Some(walletd::Payee::new(
+                self.channel.funding()?,
+                state,
+                view_key,
+            ));
+        }
 
-                self.channel.bob_secret = Some(bob_secret);
-                self.channel.bob_public_key = Some(bob_public_key);
-            }
+        Ok(self.payee.as_mut().unwrap())
+    }
 
-            AliceReqHash => {
-                let data =
-                    peerd_msg::Data::Hash(self.channel.alice_hash.as_ref().unwrap().to_vec());
-                self.send_to_peerd(peerd_msg::PeerdMsgType::AliceResHash, Some(data))?;
-            }
+    // the address of the other party must be on the network of ours
+    fn check_network(&self, address: &address::MoneroAddress) -> crate::Result<()> {
+        let network = self.channel.address(&self.role)?.meta.network;
+        if address.meta.network != network {
+            return Err(Error::OtherNetwork(network).into());
+        }
 
-            BobUpdateAliceHash => {
-                let alice_hash = if let peerd_msg::Data::Hash(alice_hash) = msg.data.unwrap() {
-                    alice_hash
-                } else {
-                    unreachable!()
-                };
+        Ok(())
+    }
 
-                self.channel.alice_hash = Some(alice_hash);
-            }
+    // requests paying Bob `amount` more; the payment goes on through peerd
+    fn pay(&mut self, amount: u64) -> crate::Result<String> {
+        if self.role != Role::Alice {
+            return Err(Error::OnlyAlicePays.into());
+        }
 
-            AliceReqPubkey => {
-                let data = peerd_msg::Data::Pubkey(
-                    self.channel
-                        .alice_public_key
-                        .as_ref()
-                        .unwrap()
-                        .compress()
-                        .to_bytes()
-                        .to_vec(),
-                );
-                self.send_to_peerd(peerd_msg::PeerdMsgType::AliceResPubkey, Some(data))?;
-            }
+        // the channel is only open once Alice can be refunded
+        self.open_channel()?;
 
-            BobReqPubkey => {
-                let data = peerd_msg::Data::Pubkey(
-                    self.channel
-                        .bob_public_key
-                        .as_ref()
-                        .unwrap()
-                        .compress()
-                        .to_bytes()
-                        .to_vec(),
-                );
-                self.send_to_peerd(peerd_msg::PeerdMsgType::BobResPubkey, Some(data))?;
-            }
+        if self.closed_by_bob()? {
+            return Err(Error::ChannelClosed.into());
+        }
 
-            AliceUpdateBobKey => {
-                let bob_pubkey = if let peerd_msg::Data::Pubkey(bob_pubkey) = msg.data.unwrap() {
-                    bob_pubkey
-                } else {
-                    unreachable!()
-                };
-                let bob_pubkey = CompressedEdwardsY::from_slice(&bob_pubkey)
-                    .decompress()
-                    .unwrap();
+        if self.spend_builder.is_none() {
+            return Err(Error::NoSpendBuilder.into());
+        }
 
-                self.channel.bob_public_key = Some(bob_pubkey);
+        let (state, request) = self.payer()?.request(amount)?;
 
-                let joint_pubkey = self.channel.alice_public_key.as_ref().unwrap()
-                    + self.channel.bob_public_key.as_ref().unwrap();
-                self.channel.joint_public_key = Some(joint_pubkey);
-                println!(
-                    "{} {}",
-                    "JOINT PUBKEY:".green(),
-                    hex::encode(joint_pubkey.compress().to_bytes()).green()
-                );
+        let data = peerd_msg::Data::Pay(request);
+        self.send_to_peerd(peerd_msg::PeerdMsgType::SendPay, Some(data))?;
 
-                let alice_tag =
-                    generate_user_tag(&joint_pubkey, self.channel.alice_secret.as_ref().unwrap());
-                self.channel.alice_tag = Some(alice_tag);
-            }
+        Ok(format!(
+            "Requested state {}: {} to Bob",
+            state.number,
+            monero::Amount::from_pico(state.bob_amount)
+        ))
+    }
 
-            BobUpdateAliceKey => {
-                let alice_pubkey = if let peerd_msg::Data::Pubkey(alice_pubkey) = msg.data.unwrap()
-                {
-                    alice_pubkey
-                } else {
-                    unreachable!()
-                };
-                let alice_pubkey = CompressedEdwardsY::from_slice(&alice_pubkey)
-                    .decompress()
-                    .unwrap();
+    // starts closing the channel with the latest state Bob acknowledged; Bob leads the joint
+    // spend that pays it, or closes the channel alone
+    fn close(&mut self) -> crate::Result<String> {
+        if self.role == Role::Bob {
+            return self.close_alone();
+        }
 
-                let expected_hash = self.channel.alice_hash.as_ref().unwrap();
-                let computed_hash = hash(alice_pubkey.compress().as_bytes());
+        self.open_channel()?;
 
-                println!("Expected hash: {}", hex::encode(expected_hash));
-                println!("Computed hash: {}", hex::encode(computed_hash));
+        if self.closed_by_bob()? {
+            return Err(Error::ChannelClosed.into());
+        }
 
-                assert_eq!(expected_hash, &computed_hash);
+        // the outputs of Bob's closing transaction are checked with it
+        if self.view_key.is_none() {
+            return Err(Error::NoViewKey.into());
+        }
 
-                self.channel.alice_public_key = Some(alice_pubkey);
+        let payer = self.payer()?;
+        if payer.pending().is_some() {
+            return Err(walletd::Error::PaymentPending.into());
+        }
+        let state = payer.state();
 
-                let joint_pubkey = self.channel.alice_public_key.as_ref().unwrap()
-                    + self.channel.bob_public_key.as_ref().unwrap();
-                self.channel.joint_public_key = Some(joint_pubkey);
-                println!(
-                    "{} {}",
-                    "JOINT PUBKEY:".green(),
-                    hex::encode(joint_pubkey.compress().to_bytes()).green()
-                );
+        self.start_closing(state)?;
 
-                let bob_tag =
-                    generate_user_tag(&joint_pubkey, self.channel.bob_secret.as_ref().unwrap());
-                self.channel.bob_tag = Some(bob_tag);
-            }
+        let data = peerd_msg::Data::Close((&state).into());
+        self.send_to_peerd(peerd_msg::PeerdMsgType::SendClose, Some(data))?;
 
-            AliceReqTag => {
-                let data = peerd_msg::Data::Tag(
-                    self.channel
-                        .alice_tag
-                        .as_ref()
-                        .unwrap()
-                        .compress()
-                        .as_bytes()
-                        .to_vec(),
-                );
-                self.send_to_peerd(peerd_msg::PeerdMsgType::AliceResTag, Some(data))?;
-            }
+        let channel_amount = self.channel.params()?.channel_amount.as_pico();
+        Ok(format!(
+            "Closing with state {}: {} to Bob, {} to Alice before the fee",
+            state.number,
+            monero::Amount::from_pico(state.bob_amount),
+            monero::Amount::from_pico(state.alice_amount(channel_amount))
+        ))
+    }
 
-            BobReqTag => {
-                let data = peerd_msg::Data::Tag(
-                    self.channel
-                        .bob_tag
-                        .as_ref()
-                        .unwrap()
-                        .compress()
-                        .as_bytes()
-                        .to_vec(),
-                );
-                self.send_to_peerd(peerd_msg::PeerdMsgType::BobResTag, Some(data))?;
-            }
+    // Bob accepts closing the channel if Alice closes it with the latest state he acknowledged,
+    // and starts the joint spend paying it
+    fn recv_close(&mut self, state: ChannelState) -> crate::Result<msgs::JointSpendMsg> {
+        if self.role != Role::Bob {
+            return Err(walletd::Error::UnexpectedMessage.into());
+        }
 
-            AliceUpdateBobTag => {
-                let bob_tag = if let peerd_msg::Data::Tag(bob_tag) = msg.data.unwrap() {
-                    bob_tag
-                } else {
-                    unreachable!()
-                };
-                let bob_tag = CompressedEdwardsY::from_slice(&bob_tag)
-                    .decompress()
-                    .unwrap();
+        if state != self.payee()?.state() {
+            return Err(walletd::Error::UnexpectedState.into());
+        }
 
-                self.channel.bob_tag = Some(bob_tag);
+        let funding = self.channel.funding()?;
+        let builder = self.spend_builder.as_mut().ok_or(Error::NoSpendBuilder)?;
+        let spend = builder.build(
+            funding,
+            &funding.keys.bob_address,
+            state.bob_amount,
+            &funding.keys.alice_address,
+        )?;
 
-                let joint_tag = self.channel.alice_tag.as_ref().unwrap()
-                    + self.channel.bob_tag.as_ref().unwrap();
+        self.start_closing(state)?;
+        self.prepare_joint_spend(spend);
 
-                println!(
-                    "{} {}",
-                    "JOINT TAG:".green(),
-                    hex::encode(joint_tag.compress().to_bytes()).green()
-                );
-                self.channel.joint_tag = Some(joint_tag);
-            }
+        let joint_spend = walletd::JointSpend::new(&mut OsRng, self.channel.funding()?);
+        let init = joint_spend.init();
+        self.joint_spend = Some(joint_spend);
 
-            BobUpdateAliceTag => {
-                let alice_tag = if let peerd_msg::Data::Tag(alice_tag) = msg.data.unwrap() {
-                    alice_tag
-                } else {
-                    unreachable!()
-                };
-                let alice_tag = CompressedEdwardsY::from_slice(&alice_tag)
-                    .decompress()
-                    .unwrap();
+        Ok(init)
+    }
 
-                self.channel.alice_tag = Some(alice_tag);
+    // Bob completes the latest payment with his share of the key and publishes it, without
+    // Alice; he must do it before the time of the channel is up, or she may be refunded first
+    fn close_alone(&mut self) -> crate::Result<String> {
+        self.open_channel()?;
 
-                let joint_tag = self.channel.alice_tag.as_ref().unwrap()
-                    + self.channel.bob_tag.as_ref().unwrap();
+        let payment = self.payments()?.load()?;
+        let payment = payment.ok_or(Error::NoPayment)?;
 
-                println!(
-                    "{} {}",
-                    "JOINT TAG:".green(),
-                    hex::encode(joint_tag.compress().to_bytes()).green()
-                );
-                self.channel.joint_tag = Some(joint_tag);
-            }
+        let payee = self.payee()?;
+        let state = payee.state();
+        let tx = payee.complete(&payment)?;
 
-            ResChannelInfo => unreachable!(),
-            ResAddress => unreachable!(),
-            AliceResHash => unreachable!(),
-            AliceResPubkey => unreachable!(),
-            BobResPubkey => unreachable!(),
-            AliceResTag => unreachable!(),
-            BobResTag => unreachable!(),
-            Unspecified => unreachable!(),
+        // Alice may have been refunded, and any other spend of the joint output is a double spend
+        let image = walletd::payment_image(&payment)?;
+        if !self.chain().spent(&[image])?.is_empty() {
+            return Err(Error::ChannelOutputSpent.into());
         }
 
-        Ok(())
+        let from_height = self.chain().height()?;
+        self.chain().publish(&tx)?;
+
+        let hash = tx.hash();
+        self.channel.start_closing(Closing {
+            state,
+            from_height,
+            tx: Some(tx),
+        })?;
+        self.save_channel()?;
+
+        println!(
+            "{} {}",
+            "CLOSING TRANSACTION PUBLISHED:".green(),
+            hex::encode(hash).green()
+        );
+
+        Ok(format!(
+            "Closing alone with state {}: {} to Bob",
+            state.number,
+            monero::Amount::from_pico(state.bob_amount)
+        ))
     }
 
-    fn send_to_peerd(
-        &self,
-        msg_type: peerd_msg::PeerdMsgType,
-        data: Option<peerd_msg::Data>,
-    ) -> crate::Result<()> {
-        let process_key = msgs::Process::Peerd.as_str_name();
-        let msg = msgs::PeerdMsg {
-            msg_type: msg_type as i32,
-            data,
+    // Alice finds out whether Bob closed the channel alone: the joint output is then spent, with
+    // the key image of the payments
+    fn closed_by_bob(&mut self) -> crate::Result<bool> {
+        if !matches!(self.channel.phase(), Phase::Open(_)) {
+            return Ok(false);
+        }
+
+        let payments = self.payments()?;
+        let payment = match payments.load()? {
+            Some(payment) => payment,
+            // Bob has nothing to close the channel with before the first payment
+            None => return Ok(false),
         };
 
-        let pub_socket = self.pub_socket.as_ref().unwrap();
+        let image = walletd::payment_image(&payment)?;
+        if self.chain().spent(&[image])?.is_empty() {
+            return Ok(false);
+        }
 
-        pub_socket.send(process_key, zmq::SNDMORE)?;
-        pub_socket.send(msg.encode_to_vec(), 0)?;
+        let state = payments.state()?;
+        self.channel.close(state)?;
+        self.save_channel()?;
+
+        let channel_amount = self.channel.params()?.channel_amount.as_pico();
+        print_balance("CHANNEL CLOSED BY BOB:", &state, channel_amount);
+
+        Ok(true)
+    }
+
+    // the closing transaction cannot be in a block below the current height
+    fn start_closing(&mut self, state: ChannelState) -> crate::Result<()> {
+        let from_height = self.chain().height()?;
+
+        self.channel.start_closing(Closing {
+            state,
+            from_height,
+            tx: None,
+        })?;
 
         Ok(())
     }
-}
 
-// this killing process is bad; se should gracefully shutdown the child processes
-impl Drop for Client {
-    fn drop(&mut self) {
-        if self.peerd_process.is_some() {
-            self.peerd_process.take().unwrap().kill().unwrap();
-        }
-        if self.walled_process.is_some() {
-            self.walled_process.take().unwrap().kill().unwrap();
+    // publishes the signed closing transaction; the channel is closed once it has the
+    // confirmations of the channel
+    fn finish_close(&mut self) -> crate::Result<()> {
+        let tx = self.channel.closing()?.tx.as_ref().unwrap();
+
+        // both parties publish it, so the other one may have been first
+        if let Err(err) = self.chain().publish(tx) {
+            warn!("Could not publish the closing transaction: {err}");
         }
-        if self.watcherd_process.is_some() {
-            self.watcherd_process.take().unwrap().kill().unwrap();
+
+        println!(
+            "{} {}",
+            "CLOSING TRANSACTION PUBLISHED:".green(),
+            hex::encode(tx.hash()).green()
+        );
+
+        Ok(())
+    }
+
+    // whether a transaction of ours spending the joint output waits for its confirmations:
+    // the closing transaction, also after a restart, or the refund
+    fn awaits_confirmations(&self) -> bool {
+        matches!(self.channel.closing(), Ok(Closing { tx: Some(_), .. }))
+            || self.refund_tx.is_some()
+    }
+
+    // closes or refunds the channel once our transaction has the confirmations of the channel;
+    // called from the event loop, which handles commands and messages in the meantime
+    fn check_confirmations(&mut self) -> crate::Result<()> {
+        if let Ok(Closing {
+            state,
+            from_height,
+            tx: Some(tx),
+        }) = self.channel.closing()
+        {
+            let state = *state;
+            if !self.confirmed(&tx.hash(), *from_height)? {
+                return Ok(());
+            }
+
+            self.channel.close(state)?;
+            self.save_channel()?;
+
+            let channel_amount = self.channel.params()?.channel_amount.as_pico();
+            print_balance("CHANNEL CLOSED:", &state, channel_amount);
+        } else if let Some((hash, from_height)) = self.refund_tx {
+            if !self.confirmed(&hash, from_height)? {
+                return Ok(());
+            }
+
+            self.channel.refund()?;
+            self.save_channel()?;
+            self.refund_tx = None;
+
+            println!(
+                "{} {} back to Alice, before the fee",
+                "CHANNEL REFUNDED:".green(),
+                self.channel.params()?.channel_amount.to_string().green()
+            );
         }
+
+        Ok(())
     }
-}
 
-#[derive(Debug, thiserror::Error)]
-pub enum Error {}
+    // whether the transaction `hash`, which cannot be in a block below `from_height`, has the
+    // confirmations of the channel
+    fn confirmed(&self, hash: &[u8; 32], from_height: usize) -> crate::Result<bool> {
+        let confirmations = self.channel.params()?.confirmations as usize;
+
+        let confirmed = self.chain().confirmations(hash, from_height)?;
+        debug!("Confirmations of {}: {confirmed:?}", hex::encode(hash));
+
+        Ok(matches!(confirmed, Some(confirmed) if confirmed >= confirmations))
+    }
+
+    // next message of the VTD-Log opening, after `msg` from the other party; Alice starts
+    // solving Bob's VTD-Log once she verified it
+    fn recv_vtdlog(&mut self, msg: msgs::VtdlogMsg) -> crate::Result<msgs::VtdlogMsg> {
+        use vtdlog_msg::Data;
+
+        if let (Role::Alice, None, Some(Data::Commit(commit))) =
+            (&self.role, &self.vtdlog, &msg.data)
+        {
+            let keys = self.channel.keys()?;
+
+            // it must hide the secret of Bob's public key
+            if decode_point(&commit.h) != Some(keys.bob_public_key) {
+                println!("{} {}", "PROTOCOL ABORTED:".red(), Error::InvalidVtdlog);
+                return Err(Error::InvalidVtdlog.into());
+            }
+
+            self.vtdlog = Some(vtdlog::solver(&keys.params.vtdlog_params, keys.params.time));
+            self.bob_vtdlog = Some(commit.clone());
+        }
+
+        let exchange = self.vtdlog.as_mut();
+        let exchange = exchange.ok_or(core::Error::from(vtdlog::Error::UnexpectedMessage))?;
+
+        let data = msg.data.clone();
+        let reply = match exchange.recv_msg(msg) {
+            Ok(reply) => reply,
+            Err(err) => {
+                println!("{} {err}", "PROTOCOL ABORTED:".red());
+                return Err(core::Error::from(err).into());
+            }
+        };
+
+        if let Some(reply) = reply {
+            // Bob is done once he opens his VTD-Log
+            if let Some(Data::Opening(_)) = &reply.data {
+                self.channel.set_vtdlog_opened()?;
+                self.save_channel()?;
+            }
+
+            return Ok(reply);
+        }
+
+        // only the solver is done, once the opening is verified
+        let opening = if let Some(Data::Opening(opening)) = data {
+            opening
+        } else {
+            unreachable!()
+        };
+
+        self.start_refund_solver(opening)?;
+
+        Ok(msgs::VtdlogMsg { data: None })
+    }
+
+    fn start_refund_solver(&mut self, opening: msgs::VtdlogProof) -> crate::Result<()> {
+        self.channel.set_vtdlog_opened()?;
+        self.save_channel()?;
+
+        // kept to solve it again after a restart
+        let commit = self.bob_vtdlog.take().unwrap();
+        let store = self.store.as_mut().ok_or(Error::ChannelNotOpen)?;
+        store.save_bob_vtdlog(commit.clone(), opening.clone())?;
+
+        self.solve_refund(commit, opening)
+    }
+
+    fn solve_refund(
+        &mut self,
+        commit: msgs::VtdlogCommit,
+        opening: msgs::VtdlogProof,
+    ) -> crate::Result<()> {
+        let socket = self.zmq_context.socket(zmq::PAIR)?;
+        socket.connect(VTDLOG_SOLVER_SOCKET)?;
+
+        let params = self.channel.params()?;
+        let backend = params.vtdlog_params.timelock.backend;
+        let time = params.time;
+
+        self.refund_solver = Some(spawn_refund_solver(
+            self.data_dir.clone(),
+            backend,
+            commit,
+            opening,
+            socket,
+        ));
+
+        println!(
+            "{} Alice is refunded once it is solved, unless Bob closed the channel before",
+            "SOLVING BOB'S VTD-LOG:".green()
+        );
+        self.print_solve_time(time);
+
+        Ok(())
+    }
+
+    // Bob's secret, from the refund solver, once the time of the channel is up
+    fn recv_refund(&mut self) -> crate::Result<()> {
+        let data = Zeroizing::new(self.vtdlog_socket.as_ref().unwrap().recv_bytes(0)?);
+        let bob_public_key = self.channel.keys()?.bob_public_key;
+
+        match decode_scalar(&data).map(Zeroizing::new) {
+            Some(bob_secret) if &*bob_secret * &ED25519_BASEPOINT_TABLE == bob_public_key => {
+                println!("{}", "BOB'S VTD-LOG SOLVED".green());
+                self.refund(bob_secret)
+            }
+            _ => {
+                println!("{} {}", "NO REFUND:".red(), Error::VtdlogUnsolved);
+                Ok(())
+            }
+        }
+    }
+
+    // Alice spends the joint output alone, back to her address, with Bob's secret; unless the
+    // channel is already closed
+    fn refund(&mut self, bob_secret: Zeroizing<Scalar>) -> crate::Result<()> {
+        let funded = match self.channel.phase() {
+            Phase::Funded(_) | Phase::Open(_) => true,
+            Phase::KeysExchanged(_) => false,
+            _ => return Ok(()),
+        };
+
+        // the joint output may not have been needed yet
+        if !funded {
+            if let Err(err) = self.fund_channel() {
+                println!("{} {err}", "NO REFUND:".yellow());
+                return Ok(());
+            }
+        }
+
+        let funding = self.channel.funding()?;
+        let output = funding.output.clone();
+        let alice_secret = funding.keys.own.secret.clone();
+        let alice_address = funding.keys.alice_address;
+
+        let key = Zeroizing::new(*alice_secret + *bob_secret + output.key_offset);
+        let image = *key * hash_to_point(output.key);
+        self.update_output_image(image)?;
+
+        if !self.chain().spent(&[image])?.is_empty() {
+            println!(
+                "{} the channel output is already spent",
+                "NO REFUND:".yellow()
+            );
+            return Ok(());
+        }
+
+        let from_height = self.chain().height()?;
+
+        let funding = self.channel.funding()?;
+        let builder = self.spend_builder.as_mut().ok_or(Error::NoSpendBuilder)?;
+        let spend = builder.build(funding, &alice_address, 0, &alice_address)?;
+
+        let tx = walletd::sign_refund(&mut OsRng, spend, &output, &alice_secret, &bob_secret)?;
+        let hash = tx.hash();
+
+        self.chain().publish(&tx)?;
+
+        println!(
+            "{} {}",
+            "REFUND TRANSACTION PUBLISHED:".green(),
+            hex::encode(hash).green()
+        );
+
+        self.refund_tx = Some((hash, from_height));
+
+        Ok(())
+    }
+
+    // next message of the payment, after `msg` from the other party; empty once it is over
+    fn recv_pay(&mut self, msg: msgs::PayMsg) -> crate::Result<msgs::PayMsg> {
+        use pay_msg::Data::*;
+
+        let data = msg.data.ok_or(walletd::Error::InvalidMessage)?;
+
+        // Alice requests and pre-signs, Bob accepts and acknowledges
+        let sender = match data {
+            Request(_) | Payment(_) => Role::Alice,
+            Image(_) | Ack(_) => Role::Bob,
+        };
+        if sender == self.role {
+            return Err(walletd::Error::UnexpectedMessage.into());
+        }
+
+        let reply = match data {
+            Request(request) => self.payee()?.receive_request(&mut OsRng, request)?,
+
+            Image(image) => {
+                let state = self
+                    .payer()?
+                    .pending()
+                    .ok_or(walletd::Error::UnexpectedMessage)?;
+
+                let funding = self.channel.funding()?;
+                let builder = self.spend_builder.as_mut().ok_or(Error::NoSpendBuilder)?;
+                let spend = builder.build(
+                    funding,
+                    &funding.keys.bob_address,
+                    state.bob_amount,
+                    &funding.keys.alice_address,
+                )?;
+
+                let reply = self.payer()?.pre_sign(&mut OsRng, image, spend)?;
+                if let Some(Payment(payment)) = &reply.data {
+                    self.update_output_image(walletd::payment_image(payment)?)?;
+                }
+
+                reply
+            }
+
+            Payment(payment) => {
+                let (state, ack) = self.payee()?.receive_payment(&payment)?;
+
+                self.payments()?.save(&payment)?;
+                self.save_payment(&state)?;
+                print_payment("PAYMENT RECEIVED:", &state);
+
+                self.update_output_image(walletd::payment_image(&payment)?)?;
+
+                ack
+            }
+
+            Ack(ack) => {
+                let payment = self.payer()?.receive_ack(ack)?;
+                self.payments()?.save(&payment)?;
+
+                let state = self.payer()?.state();
+                self.save_payment(&state)?;
+                print_payment("PAYMENT ACKNOWLEDGED:", &state);
+
+                msgs::PayMsg { data: None }
+            }
+        };
+
+        Ok(reply)
+    }
+
+    // next message of the joint spend, after `msg` from the other party
+    fn recv_joint_spend(&mut self, msg: msgs::JointSpendMsg) -> crate::Result<msgs::JointSpendMsg> {
+        use joint_spend_msg::Data::*;
+
+        if self.joint_spend.is_none() {
+            let funding = self.channel.funding()?;
+            self.joint_spend = Some(walletd::JointSpend::new(&mut OsRng, funding));
+        }
+
+        let joint_spend = self.joint_spend.as_mut().unwrap();
+
+        let reply = match msg.data.ok_or(walletd::Error::InvalidMessage)? {
+            Init(init) => {
+                let image = joint_spend.receive_init(init)?;
+                println!(
+                    "{} {}",
+                    "JOINT KEY IMAGE:".green(),
+                    hex::encode(image.compress().to_bytes()).green()
+                );
+
+                match self.role {
+                    Role::Alice => joint_spend.init(),
+                    Role::Bob => {
+                        let spend = self.unsigned_spend.take();
+                        let spend = spend.ok_or(walletd::Error::UnexpectedMessage)?;
+                        joint_spend.propose(&mut OsRng, spend)?
+                    }
+                }
+            }
+
+            Proposal(proposal) => {
+                let state = self.channel.closing()?.state;
+                let view_key = self.view_key.as_ref().ok_or(Error::NoViewKey)?;
+                joint_spend.sign(proposal, &state, view_key)?
+            }
+
+            Partial(partial) => {
+                let (tx, signed) = joint_spend.complete(partial)?;
+                self.joint_spend = None;
+
+                if let Ok(closing) = self.channel.closing_mut() {
+                    closing.tx = Some(tx.clone());
+                    self.save_channel()?;
+                }
+
+                println!(
+                    "{} {}",
+                    "JOINT SPEND SIGNED:".green(),
+                    hex::encode(tx.hash()).green()
+                );
+
+                signed
+            }
+
+            Signed(signed) => {
+                let tx = joint_spend.receive_signed(signed)?;
+                self.joint_spend = None;
+
+                if let Ok(closing) = self.channel.closing_mut() {
+                    closing.tx = Some(tx.clone());
+                    self.save_channel()?;
+                }
+
+                println!(
+                    "{} {}",
+                    "JOINT SPEND SIGNED:".green(),
+                    hex::encode(tx.hash()).green()
+                );
+
+                msgs::JointSpendMsg { data: None }
+            }
+        };
+
+        Ok(reply)
+    }
+
+    // our tag, with a proof that it uses the secret of our public key
+    fn tag_msg(&self) -> crate::Result<msgs::Tag> {
+        let keys = self.channel.keys()?;
+        let h = hash_to_point(keys.joint_public_key);
+        let proof = DleqProof::prove(&mut OsRng, &keys.own.secret, &h);
+
+        Ok(msgs::Tag {
+            tag: keys.tag.compress().to_bytes().to_vec(),
+            proof: Some((&proof).into()),
+        })
+    }
+
+    // the tag of the other party, if it is proven to use the secret of their public key;
+    // otherwise, the channel is rejected
+    fn verify_tag(&self, tag: msgs::Tag) -> crate::Result<EdwardsPoint> {
+        let keys = self.channel.keys()?;
+        let their_public_key = match self.role {
+            Role::Alice => keys.bob_public_key,
+            Role::Bob => keys.alice_public_key,
+        };
+
+        let point = decode_point(&tag.tag);
+        let proof = tag.proof.map(DleqProof::try_from);
+
+        match (point, proof) {
+            (Some(point), Some(Ok(proof)))
+                if proof.verify(
+                    &hash_to_point(keys.joint_public_key),
+                    &their_public_key,
+                    &point,
+                ) =>
+            {
+                Ok(point)
+            }
+            _ => {
+                println!("{} {}", "PROTOCOL ABORTED:".red(), Error::InvalidTag);
+                Err(Error::InvalidTag.into())
+            }
+        }
+    }
+
+    // the channel moves on with the public key of the other party, if it is the one they
+    // committed to
+    fn exchange_keys(&mut self, pubkey: msgs::Pubkey) -> crate::Result<()> {
+        let their_role = match self.role {
+            Role::Alice => Role::Bob,
+            Role::Bob => Role::Alice,
+        };
+
+        let their_hash = self.channel.negotiation()?.their_hash()?;
+        let (public_key, view_secret) = verify_pubkey(pubkey, their_hash, &their_role)?;
+
+        let height = self.chain().height()?;
+
+        let keys = self
+            .channel
+            .exchange_keys(public_key, view_secret, height)?;
+
+        println!(
+            "{} {}",
+            "JOINT PUBKEY:".green(),
+            hex::encode(keys.joint_public_key.compress().to_bytes()).green()
+        );
+        println!(
+            "{} {}",
+            "JOINT ADDRESS:".green(),
+            keys.joint_wallet.address().to_string().green()
+        );
+
+        self.save_channel()?;
+
+        Ok(())
+    }
+
+    fn update_joint_tag(&mut self, their_tag: EdwardsPoint) -> crate::Result<()> {
+        let joint_tag = self.channel.set_joint_tag(their_tag)?;
+
+        println!(
+            "{} {}",
+            "JOINT TAG:".green(),
+            hex::encode(joint_tag.compress().to_bytes()).green()
+        );
+
+        self.save_channel()?;
+
+        Ok(())
+    }
+
+    // the key image of the joint output, which watcherd watches, once it is known
+    fn update_output_image(&mut self, image: EdwardsPoint) -> crate::Result<()> {
+        if !self.channel.set_output_image(image)? {
+            return Ok(());
+        }
+
+        println!(
+            "{} {}",
+            "OUTPUT KEY IMAGE:".green(),
+            hex::encode(image.compress().to_bytes()).green()
+        );
+
+        KeyImages::new(&self.data_dir).add(&image)?;
+        self.save_channel()?;
+
+        Ok(())
+    }
+
+    fn print_solve_time(&self, time: u64) {
+        let solve_time = self.calibration.as_ref().unwrap().solve_time(time);
+
+        println!(
+            "{} about {} on this machine ({time} squarings)",
+            "PUZZLES TAKE:".green(),
+            format_duration(solve_time).bold(),
+        );
+    }
+
+    fn recv(&mut self) -> crate::Result<()> {
+        loop {
+            // the chain is polled in between, until our transaction is confirmed
+            let timeout = if self.awaits_confirmations() {
+                match self.check_confirmations() {
+                    Err(err) if err.is_fatal() => return Err(err),
+                    Err(err) => {
+                        warn!("Could not check the confirmations of our transaction: {err}")
+                    }
+                    Ok(()) => (),
+                }
+
+                CONFIRMATIONS_POLL.as_millis() as i64
+            } else {
+                -1
+            };
+
+            let (from_processes, from_cmd, from_solver) = {
+                let mut items = [
+                    self.sub_socket.as_ref().unwrap().as_poll_item(zmq::POLLIN),
+                    self.cmd_socket.as_ref().unwrap().as_poll_item(zmq::POLLIN),
+                    self.vtdlog_socket
+                        .as_ref()
+                        .unwrap()
+                        .as_poll_item(zmq::POLLIN),
+                ];
+                zmq::poll(&mut items, timeout)?;
+
+                (
+                    items[0].is_readable(),
+                    items[1].is_readable(),
+                    items[2].is_readable(),
+                )
+            };
+
+            if from_cmd {
+                self.recv_cmd()?;
+            }
+
+            if from_solver {
+                self.recv_refund()?;
+            }
+
+            if !from_processes {
+                continue;
+            }
+
+            let sub_socket = self.sub_socket.as_ref().unwrap();
+
+            let process_key = sub_socket.recv_string(0)?;
+            let process_key = msgs::Process::from_str_name(&process_key.unwrap());
+            let process_key = process_key.unwrap();
+
+            let data = sub_socket.recv_bytes(0)?;
+
+            match process_key {
+                msgs::Process::Peerd => self.handle_from_peerd(data)?,
+                msgs::Process::Walletd => todo!(),
+                msgs::Process::Watcherd => todo!(),
+                msgs::Process::TypeUnspecified => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    // commands of other invocations of paymo-cli; a failed command does not stop the client
+    fn recv_cmd(&mut self) -> crate::Result<()> {
+        let data = self.cmd_socket.as_ref().unwrap().recv_bytes(0)?;
+
+        let res = match msgs::ClientCmd::decode(data.as_slice()) {
+            Ok(msgs::ClientCmd {
+                cmd: Some(client_cmd::Cmd::Pay(amount)),
+            }) => self.pay(amount),
+            Ok(msgs::ClientCmd {
+                cmd: Some(client_cmd::Cmd::Close(_)),
+            }) => self.close(),
+            Ok(_) => Err(Error::InvalidCommand.into()),
+            Err(err) => Err(err.into()),
+        };
+
+        let res = match res {
+            Ok(info) => msgs::ClientCmdRes {
+                error: String::new(),
+                info,
+            },
+            Err(err) => msgs::ClientCmdRes {
+                error: err.to_string(),
+                info: String::new(),
+            },
+        };
+
+        self.cmd_socket
+            .as_ref()
+            .unwrap()
+            .send(res.encode_to_vec(), 0)?;
+
+        Ok(())
+    }
+
+    // a message that does not fit the channel, e.g. a duplicated one, is dropped as a failed
+    // command is
+    fn handle_from_peerd(&mut self, data: Vec<u8>) -> crate::Result<()> {
+        match self.recv_from_peerd(data) {
+            Err(err) if err.is_fatal() => Err(err),
+            Err(err) => {
+                warn!("Dropped a message from peerd: {err}");
+                Ok(())
+            }
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn recv_from_peerd(&mut self, data: Vec<u8>) -> crate::Result<()> {
+        use peerd_msg::PeerdMsgType::*;
+
+        let msg = msgs::PeerdMsg::decode(data.as_slice())?;
+        debug!("Received message from peerd: {msg:?}");
+
+        let msg_type = msg.msg_type();
+
+        match msg_type {
+            ReqChannelInfo => {
+                debug!("Received ReqChannelInfo");
+
+                let bob_key = match &msg.data {
+                    Some(peerd_msg::Data::NodeKey(key)) => key.as_slice().try_into().ok(),
+                    _ => None,
+                };
+                self.their_node_key = Some(bob_key.ok_or(Error::InvalidPeerdMsg(msg_type))?);
+
+                let params = self.channel.negotiation()?.params()?;
+
+                let channel_info = msgs::ChannelInfo {
+                    channel_amount: params.channel_amount.as_pico(),
+                    time: params.time,
+                    confirmations: params.confirmations,
+                    vtdlog_params: Some((&params.vtdlog_params).into()),
+                };
+
+                self.print_solve_time(channel_info.time);
+
+                let msg = peerd_msg::Data::ChannelInfo(channel_info);
+
+                self.send_to_peerd(ResChannelInfo, Some(msg))?;
+            }
+
+            SendChannelInfo => {
+                debug!("Received SendChannelInfo");
+
+                let channel_info =
+                    if let Some(peerd_msg::Data::ChannelInfo(channel_info)) = msg.data {
+                        channel_info
+                    } else {
+                        return Err(Error::InvalidPeerdMsg(msg_type).into());
+                    };
+
+                // Alice may propose parameters below the minimums we accept
+                let vtdlog_params = channel_info
+                    .vtdlog_params
+                    .ok_or(vtdlog::Error::InvalidParams)
+                    .and_then(vtdlog::VtdlogParams::try_from)
+                    .map_err(core::Error::from)?;
+
+                let params = Params {
+                    channel_amount: monero::Amount::from_pico(channel_info.channel_amount),
+                    time: channel_info.time,
+                    confirmations: channel_info.confirmations,
+                    vtdlog_params,
+                };
+
+                let calibration = Calibration::measure(&params.vtdlog_params.timelock);
+                self.channel.negotiation_mut()?.set_params(params)?;
+
+                self.calibration = Some(calibration);
+                self.print_solve_time(channel_info.time);
+
+                debug!("{:#?}", self.channel);
+            }
+
+            AliceReqAddress => {
+                let address = self.channel.negotiation()?.address(&Role::Alice)?;
+                let data = peerd_msg::Data::Address(address.to_string());
+                self.send_to_peerd(peerd_msg::PeerdMsgType::ResAddress, Some(data))?;
+            }
+
+            BobReqAddress => {
+                let address = self.channel.negotiation()?.address(&Role::Bob)?;
+                let data = peerd_msg::Data::Address(address.to_string());
+                self.send_to_peerd(peerd_msg::PeerdMsgType::ResAddress, Some(data))?;
+            }
+
+            AliceUpdateBobAddress => {
+                debug!("Received AliceUpdateBobAddress");
+
+                let bob_address = if let Some(peerd_msg::Data::Address(bob_address)) = msg.data {
+                    bob_address
+                } else {
+                    return Err(Error::InvalidPeerdMsg(msg_type).into());
+                };
+                let bob_address = address::MoneroAddress::from_str_raw(&bob_address)?;
+                self.check_network(&bob_address)?;
+                self.channel
+                    .negotiation_mut()?
+                    .set_address(&Role::Bob, bob_address)?;
+
+                debug!("{:#?}", self.channel);
+            }
+
+            BobUpdateAliceAddress => {
+                debug!("Received BobUpdateAliceAddress");
+
+                let alice_address = if let Some(peerd_msg::Data::Address(alice_address)) = msg.data
+                {
+                    alice_address
+                } else {
+                    return Err(Error::InvalidPeerdMsg(msg_type).into());
+                };
+                let alice_address = address::MoneroAddress::from_str_raw(&alice_address)?;
+                self.check_network(&alice_address)?;
+                self.channel
+                    .negotiation_mut()?
+                    .set_address(&Role::Alice, alice_address)?;
+
+                debug!("{:#?}", self.channel);
+            }
+
+            AliceCreateSecret | BobCreateSecret => {
+                // the index is kept before anything depends on the keys
+                let keys = self.keystore.as_mut().unwrap().new_channel_keys()?;
+                debug!("Our keys have index {}", keys.index);
+                debug!("Our hash is {}", hex::encode(keys.hash()));
+
+                self.channel.negotiation_mut()?.set_keys(keys)?;
+            }
+
+            AliceReqHash => {
+                let hash = self.channel.negotiation()?.keys()?.hash();
+                let data = peerd_msg::Data::Hash(hash.to_vec());
+                self.send_to_peerd(peerd_msg::PeerdMsgType::AliceResHash, Some(data))?;
+            }
+
+            BobReqHash => {
+                let hash = self.channel.negotiation()?.keys()?.hash();
+                let data = peerd_msg::Data::Hash(hash.to_vec());
+                self.send_to_peerd(peerd_msg::PeerdMsgType::BobResHash, Some(data))?;
+            }
+
+            BobUpdateAliceHash | AliceUpdateBobHash => {
+                let their_hash = if let Some(peerd_msg::Data::Hash(their_hash)) = msg.data {
+                    their_hash
+                } else {
+                    return Err(Error::InvalidPeerdMsg(msg_type).into());
+                };
+
+                self.channel.negotiation_mut()?.set_their_hash(their_hash)?;
+            }
+
+            AliceReqPubkey => {
+                let keys = self.channel.own_keys()?;
+                let data = peerd_msg::Data::Pubkey(pubkey_msg(keys, &Role::Alice));
+                self.send_to_peerd(peerd_msg::PeerdMsgType::AliceResPubkey, Some(data))?;
+            }
+
+            BobReqPubkey => {
+                let keys = self.channel.own_keys()?;
+                let data = peerd_msg::Data::Pubkey(pubkey_msg(keys, &Role::Bob));
+                self.send_to_peerd(peerd_msg::PeerdMsgType::BobResPubkey, Some(data))?;
+            }
+
+            AliceUpdateBobKey | BobUpdateAliceKey => {
+                let their_pubkey = if let Some(peerd_msg::Data::Pubkey(their_pubkey)) = msg.data {
+                    their_pubkey
+                } else {
+                    return Err(Error::InvalidPeerdMsg(msg_type).into());
+                };
+
+                self.exchange_keys(their_pubkey)?;
+            }
+
+            AliceReqTag => {
+                let data = peerd_msg::Data::Tag(self.tag_msg()?);
+                self.send_to_peerd(peerd_msg::PeerdMsgType::AliceResTag, Some(data))?;
+            }
+
+            BobReqTag => {
+                let data = peerd_msg::Data::Tag(self.tag_msg()?);
+                self.send_to_peerd(peerd_msg::PeerdMsgType::BobResTag, Some(data))?;
+            }
+
+            AliceUpdateBobTag | BobUpdateAliceTag => {
+                let their_tag = if let Some(peerd_msg::Data::Tag(their_tag)) = msg.data {
+                    their_tag
+                } else {
+                    return Err(Error::InvalidPeerdMsg(msg_type).into());
+                };
+                let their_tag = self.verify_tag(their_tag)?;
+
+                self.update_joint_tag(their_tag)?;
+            }
+
+            StartJointSpend => {
+                let joint_spend = walletd::JointSpend::new(&mut OsRng, self.channel.funding()?);
+
+                let data = peerd_msg::Data::JointSpend(joint_spend.init());
+                self.joint_spend = Some(joint_spend);
+
+                self.send_to_peerd(SendJointSpend, Some(data))?;
+            }
+
+            RecvJointSpend => {
+                let joint_spend = if let Some(peerd_msg::Data::JointSpend(msg)) = msg.data {
+                    msg
+                } else {
+                    return Err(Error::InvalidPeerdMsg(msg_type).into());
+                };
+
+                let reply = self.recv_joint_spend(joint_spend)?;
+                let data = peerd_msg::Data::JointSpend(reply);
+                self.send_to_peerd(SendJointSpend, Some(data))?;
+
+                // the closing transaction is signed
+                if matches!(self.channel.closing(), Ok(Closing { tx: Some(_), .. })) {
+                    self.finish_close()?;
+                }
+            }
+
+            BobReqVtdlog => {
+                let keys = self.channel.keys()?;
+                let params = &keys.params;
+
+                // Bob opens a single VTD-Log, with fresh shares and puzzles, so that nothing
+                // else says anything about the shares Alice does not open
+                if self.vtdlog.is_some() || keys.vtdlog_opened {
+                    return Err(core::Error::from(vtdlog::Error::UnexpectedMessage).into());
+                }
+
+                let mut committer = vtdlog::committer(
+                    &mut OsRng,
+                    &params.vtdlog_params,
+                    params.time,
+                    *keys.own.secret,
+                );
+                let commit = committer.commit_msg().map_err(core::Error::from)?;
+                self.vtdlog = Some(committer);
+
+                self.send_to_peerd(SendVtdlog, Some(peerd_msg::Data::Vtdlog(commit)))?;
+            }
+
+            RecvVtdlog => {
+                let vtdlog = if let Some(peerd_msg::Data::Vtdlog(msg)) = msg.data {
+                    msg
+                } else {
+                    return Err(Error::InvalidPeerdMsg(msg_type).into());
+                };
+
+                let reply = self.recv_vtdlog(vtdlog)?;
+                self.send_to_peerd(SendVtdlog, Some(peerd_msg::Data::Vtdlog(reply)))?;
+            }
+
+            RecvClose => {
+                let state = if let Some(peerd_msg::Data::Close(state)) = msg.data {
+                    state
+                } else {
+                    return Err(Error::InvalidPeerdMsg(msg_type).into());
+                };
+
+                let init = self.recv_close(state.into())?;
+                let data = peerd_msg::Data::JointSpend(init);
+                self.send_to_peerd(SendJointSpend, Some(data))?;
+            }
+
+            RecvPay => {
+                let pay = if let Some(peerd_msg::Data::Pay(msg)) = msg.data {
+                    msg
+                } else {
+                    return Err(Error::InvalidPeerdMsg(msg_type).into());
+                };
+
+                let reply = self.recv_pay(pay)?;
+                let data = peerd_msg::Data::Pay(reply);
+                self.send_to_peerd(SendPay, Some(data))?;
+            }
+
+            PeerReset => {
+                if let Some(state) = self.payer.as_mut().and_then(walletd::Payer::abort) {
+                    println!(
+                        "{} state {}, which Bob did not acknowledge before restarting",
+                        "PAYMENT ABORTED:".yellow(),
+                        state.number
+                    );
+                }
+            }
+
+            // only the client sends these
+            ResChannelInfo | ResAddress | AliceResHash | AliceResPubkey | BobResPubkey
+            | AliceResTag | BobResTag | BobResHash | SendJointSpend | SendPay | SendClose
+            | SendVtdlog | Unspecified => {
+                return Err(Error::InvalidPeerdMsg(msg_type).into());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn send_to_peerd(
+        &self,
+        msg_type: peerd_msg::PeerdMsgType,
+        data: Option<peerd_msg::Data>,
+    ) -> crate::Result<()> {
+        let process_key = msgs::Process::Peerd.as_str_name();
+        let msg = msgs::PeerdMsg {
+            msg_type: msg_type as i32,
+            data,
+        };
+
+        let pub_socket = self.pub_socket.as_ref().unwrap();
+
+        pub_socket.send(process_key, zmq::SNDMORE)?;
+        pub_socket.send(msg.encode_to_vec(), 0)?;
+
+        Ok(())
+    }
+}
+
+/// Sends `command` to the client running in `data_dir`, and prints what it answers; channel
+/// commands run here instead, without the client.
+pub fn send_command(data_dir: &Path, command: &Command) -> crate::Result<()> {
+    let cmd = match command {
+        Command::Pay { amount } => client_cmd::Cmd::Pay(amount.as_pico()),
+        Command::Close => client_cmd::Cmd::Close(true),
+        Command::Channel(ChannelCommand::Export { file, channel }) => {
+            return export_channel(data_dir, channel.as_deref(), file);
+        }
+        Command::Channel(ChannelCommand::Import { file }) => {
+            return import_channel(data_dir, file);
+        }
+    };
+    let cmd = msgs::ClientCmd { cmd: Some(cmd) };
+
+    let addr = str::replace(
+        crate::bus::CLIENT_CMD_SOCKET,
+        "{data_dir}",
+        data_dir.to_str().unwrap(),
+    );
+
+    let context = zmq::Context::new();
+    let socket = context.socket(zmq::REQ)?;
+    socket.set_rcvtimeo(CMD_TIMEOUT_MS)?;
+    socket.set_linger(0)?;
+    socket.connect(&addr)?;
+
+    socket.send(cmd.encode_to_vec(), 0)?;
+
+    let data = match socket.recv_bytes(0) {
+        Ok(data) => data,
+        Err(zmq::Error::EAGAIN) => return Err(Error::ClientNotRunning(data_dir.into()).into()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let res = msgs::ClientCmdRes::decode(data.as_slice())?;
+    if !res.error.is_empty() {
+        return Err(Error::CommandFailed(res.error).into());
+    }
+
+    println!("{}", res.info.green());
+
+    Ok(())
+}
+
+// the channel `id` of the data directory, or the one the client resumes, with our keys of
+// it, its latest payment and the checkpoints of the refund solver
+fn export_channel(data_dir: &Path, id: Option<&str>, file: &Path) -> crate::Result<()> {
+    let stores = ChannelDb::new(data_dir).load_all()?;
+    let store = match id {
+        Some(id) => stores.into_iter().find(|store| store.id() == id),
+        None => stores.into_iter().find(ChannelStore::is_resumable),
+    }
+    .ok_or(Error::ChannelNotFound)?;
+
+    let keystore = unlock_keystore(data_dir)?;
+    let channel = store.channel(&keystore)?;
+    let own = &channel.keys()?.own;
+
+    // a crash may have come between saving the payment and its state
+    let payment = walletd::Payments::new(store.dir())
+        .load()?
+        .filter(|payment| payment.state == store.stored().payment);
+
+    // Alice's checkpoints are the ones of Bob's VTD-Log, which she only solves for the
+    // channel she resumes
+    let checkpoints = match store.bob_vtdlog() {
+        Some(_) => CheckpointedSolver::new(data_dir, CancelToken::new()).checkpoints()?,
+        None => vec![],
+    };
+
+    let passphrase = backup_passphrase(true)?;
+
+    let mut backup = msgs::ChannelBackup {
+        channel: Some(store.stored().clone()),
+        secret: own.secret.to_bytes().to_vec(),
+        view_secret: own.view_secret.to_bytes().to_vec(),
+        payment,
+        checkpoints: checkpoints
+            .into_iter()
+            .map(|(digest, checkpoint)| msgs::SolverCheckpoint {
+                digest: digest.to_vec(),
+                checkpoint,
+            })
+            .collect(),
+    };
+
+    let res = backup::write(file, &backup, &passphrase);
+    backup.secret.zeroize();
+    backup.view_secret.zeroize();
+    res?;
+
+    println!(
+        "{} {} channel {} to {}",
+        "CHANNEL EXPORTED:".green(),
+        channel.phase().name(),
+        store.id(),
+        file.display()
+    );
+
+    Ok(())
+}
+
+// the channel of a backup, checked against the config and network of the data directory, so
+// that the client resumes it the next time it runs
+fn import_channel(data_dir: &Path, file: &Path) -> crate::Result<()> {
+    let config = Config::from_path(&data_dir.join("paymo.toml"))?;
+
+    let passphrase = backup_passphrase(false)?;
+    let backup = backup::read(file, &passphrase)?;
+
+    import_backup(data_dir, &config, backup)
+}
+
+// the backup is checked before anything is written to the data directory
+fn import_backup(
+    data_dir: &Path,
+    config: &Config,
+    mut backup: msgs::ChannelBackup,
+) -> crate::Result<()> {
+    let stored = backup
+        .channel
+        .take()
+        .ok_or(Error::InvalidBackup("channel"))?;
+    let keys = backup_keys(&backup, stored.key_index);
+    backup.secret.zeroize();
+    backup.view_secret.zeroize();
+
+    let keys = keys
+        .filter(|keys| decode_point(&stored.public_key) == Some(keys.public_key))
+        .ok_or(Error::InvalidBackup("keys"))?;
+
+    // the network of the config, which the addresses given on the command line are on
+    let network = address::Network::from(config.network);
+    for address in [&stored.alice_address, &stored.bob_address] {
+        let address = address::MoneroAddress::from_str_raw(address)
+            .map_err(|e| Error::InvalidBackupAddress(e.to_string()))?;
+        if address.meta.network != network {
+            return Err(Error::InvalidBackup("network").into());
+        }
+    }
+
+    // parameters a channel of this client could not have been opened with
+    let valid_params = matches!(
+        &stored.info,
+        Some(info) if info.channel_amount > 0 && info.confirmations > 0 && info.time >= MIN_TIME
+    );
+    if !valid_params {
+        return Err(Error::InvalidBackup("parameters").into());
+    }
+
+    // the signed payment is the latest one of the channel
+    if let Some(payment) = &backup.payment {
+        if payment.state != stored.payment {
+            return Err(Error::InvalidBackup("payment").into());
+        }
+    }
+
+    let checkpoints = backup
+        .checkpoints
+        .iter()
+        .map(|checkpoint| {
+            <[u8; 32]>::try_from(checkpoint.digest.as_slice())
+                .map(|digest| (digest, &checkpoint.checkpoint))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| Error::InvalidBackup("checkpoints"))?;
+
+    let mut keystore = unlock_keystore(data_dir)?;
+    keystore.import_keys(&keys)?;
+
+    let store = ChannelDb::new(data_dir).import(stored, &keystore)?;
+
+    if let Some(payment) = &backup.payment {
+        walletd::Payments::new(store.dir()).import(payment)?;
+    }
+
+    let solver = CheckpointedSolver::new(data_dir, CancelToken::new());
+    for (digest, checkpoint) in checkpoints {
+        solver.import(&digest, checkpoint)?;
+    }
+
+    println!(
+        "{} {} channel {}, resumed the next time the client runs",
+        "CHANNEL IMPORTED:".green(),
+        store.channel(&keystore)?.phase().name(),
+        store.id()
+    );
+
+    Ok(())
+}
+
+fn backup_keys(backup: &msgs::ChannelBackup, index: u32) -> Option<OwnKeys> {
+    let secret = Zeroizing::new(decode_scalar(&backup.secret)?);
+    let view_secret = Zeroizing::new(decode_scalar(&backup.view_secret)?);
+
+    Some(OwnKeys {
+        index,
+        public_key: &*secret * &ED25519_BASEPOINT_TABLE,
+        secret,
+        view_secret,
+    })
+}
+
+// see `Client::solve_vtdlog`
+fn solve_vtdlog<P: TimeLockPuzzle>(
+    data_dir: &Path,
+    vtdlog: &mut vtdlog::Vtdlog<P>,
+) -> crate::Result<Scalar> {
+    let solver = CheckpointedSolver::new(data_dir, CancelToken::new());
+    let on_progress = |progress: Progress| debug!("Solved {progress:?}");
+
+    let err = match vtdlog.solve_checkpointed(&solver, on_progress) {
+        Ok(x) => return Ok(x),
+        Err(err) => err,
+    };
+
+    match &err {
+        vtdlog::Error::NotEnoughShares { blame, .. } => {
+            println!("{} {err}", "PROTOCOL ABORTED:".red());
+
+            for blame in blame {
+                let share = blame.share.as_ref().map(hex::encode);
+                println!(
+                    "puzzle {} ({}) hides {} instead of the discrete logarithm of H_i = {}",
+                    blame.index,
+                    hex::encode(blame.puzzle),
+                    share.as_deref().unwrap_or("nothing"),
+                    hex::encode(blame.H_i.compress().as_bytes()),
+                );
+            }
+        }
+        vtdlog::Error::InvalidReconstruction => {
+            println!("{} {err}", "PROTOCOL ABORTED:".red());
+        }
+        _ => return Err(core::Error::from(err).into()),
+    }
+
+    Err(Error::VtdlogAborted(err).into())
+}
+
+// solves Bob's VTD-Log in the background, from his verified commitment, and sends his secret
+// to `socket` once the time of the channel is up; an empty message if it cannot be solved
+fn spawn_refund_solver(
+    data_dir: PathBuf,
+    backend: Backend,
+    commit: msgs::VtdlogCommit,
+    opening: msgs::VtdlogProof,
+    socket: zmq::Socket,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        // the puzzles are decoded again in this thread, since they cannot be sent to it
+        let x = match backend {
+            Backend::Lhtlp => solve_vtdlog_msgs::<lhtlp::Puzzle>(&data_dir, commit, opening),
+            Backend::ClassGroup => {
+                solve_vtdlog_msgs::<classgroup::Puzzle>(&data_dir, commit, opening)
+            }
+        };
+
+        let data = match x {
+            Ok(x) => x.to_bytes().to_vec(),
+            Err(err) => {
+                warn!("Could not solve the VTD-Log of Bob: {err}");
+                vec![]
+            }
+        };
+
+        if let Err(err) = socket.send(data, 0) {
+            warn!("Could not send the solution of the VTD-Log of Bob: {err}");
+        }
+    })
+}
+
+fn solve_vtdlog_msgs<P: TimeLockPuzzle>(
+    data_dir: &Path,
+    commit: msgs::VtdlogCommit,
+    opening: msgs::VtdlogProof,
+) -> crate::Result<Scalar> {
+    let commit = vtdlog::Commit::<P>::try_from(commit).map_err(core::Error::from)?;
+    let opening = vtdlog::Proof::<P>::try_from(opening).map_err(core::Error::from)?;
+
+    solve_vtdlog(
+        data_dir,
+        &mut vtdlog::Vtdlog::from_verified(commit, opening),
+    )
+}
+
+// how long a command waits for the answer of the running client
+const CMD_TIMEOUT_MS: i32 = 30_000;
+
+// where the refund solver sends Bob's secret
+const VTDLOG_SOLVER_SOCKET: &str = "inproc://vtdlog-solver";
+
+// how often the confirmations of the closing transaction or of the refund are checked
+const CONFIRMATIONS_POLL: Duration = Duration::from_secs(20);
+
+// the passphrase of the keystore, instead of asking for it
+const PASSPHRASE_ENV: &str = "PAYMO_PASSPHRASE";
+
+// the mnemonic of the seed to restore, instead of asking for it
+const MNEMONIC_ENV: &str = "PAYMO_MNEMONIC";
+
+// the passphrase of channel backups, instead of asking for it
+const BACKUP_PASSPHRASE_ENV: &str = "PAYMO_BACKUP_PASSPHRASE";
+
+const UNLOCK_ATTEMPTS: usize = 3;
+
+// the keystore is created, with a new passphrase, the first time a client runs in the data
+// directory
+fn unlock_keystore(data_dir: &Path) -> crate::Result<Keystore> {
+    let from_env = env::var(PASSPHRASE_ENV).ok().map(Zeroizing::new);
+
+    if !Keystore::exists(data_dir) {
+        let passphrase = match from_env {
+            Some(passphrase) => passphrase,
+            None => {
+                let passphrase = prompt_passphrase("New keystore passphrase: ")?;
+                if passphrase != prompt_passphrase("Repeat the passphrase: ")? {
+                    return Err(Error::PassphraseMismatch.into());
+                }
+
+                passphrase
+            }
+        };
+
+        let seed = restore_seed()?;
+
+        // channels already stored with keys of this seed keep their indices
+        let next_index = ChannelDb::new(data_dir)
+            .load_all()?
+            .iter()
+            .map(|store| store.key_index() + 1)
+            .max()
+            .unwrap_or(0);
+
+        let keystore = Keystore::create(data_dir, &passphrase, seed, next_index)?;
+        println!("{}", "KEYSTORE CREATED".green());
+
+        return Ok(keystore);
+    }
+
+    if let Some(passphrase) = from_env {
+        return Ok(Keystore::unlock(data_dir, &passphrase)?);
+    }
+
+    let mut attempts = 1;
+    loop {
+        let passphrase = prompt_passphrase("Keystore passphrase: ")?;
+
+        match Keystore::unlock(data_dir, &passphrase) {
+            Err(keystore::Error::Sealed(sealed::Error::WrongPassphrase))
+                if attempts < UNLOCK_ATTEMPTS =>
+            {
+                println!("{}", "Wrong passphrase, try again".red());
+                attempts += 1;
+            }
+            result => return Ok(result?),
+        }
+    }
+}
+
+// the seed of an earlier keystore, from its mnemonic, or a new one whose mnemonic is shown
+// once
+fn restore_seed() -> crate::Result<MasterSeed> {
+    let mnemonic = match env::var(MNEMONIC_ENV).ok().map(Zeroizing::new) {
+        Some(mnemonic) => mnemonic,
+        None => prompt_passphrase("Mnemonic of the seed to restore, or empty for a new one: ")?,
+    };
+
+    if !mnemonic.trim().is_empty() {
+        let seed = MasterSeed::from_mnemonic(&mnemonic)?;
+        println!("{}", "SEED RESTORED".green());
+
+        return Ok(seed);
+    }
+
+    let (seed, mnemonic) = MasterSeed::generate();
+    println!(
+        "{} it restores the keys of every channel\n{}",
+        "WRITE DOWN THIS SEED:".green(),
+        mnemonic.as_str()
+    );
+
+    Ok(seed)
+}
+
+// a new backup asks for its passphrase twice
+fn backup_passphrase(new: bool) -> crate::Result<Zeroizing<String>> {
+    if let Ok(passphrase) = env::var(BACKUP_PASSPHRASE_ENV) {
+        return Ok(Zeroizing::new(passphrase));
+    }
+
+    let passphrase = prompt_passphrase("Backup passphrase: ")?;
+    if new && passphrase != prompt_passphrase("Repeat the passphrase: ")? {
+        return Err(Error::PassphraseMismatch.into());
+    }
+
+    Ok(passphrase)
+}
+
+fn prompt_passphrase(prompt: &str) -> crate::Result<Zeroizing<String>> {
+    Ok(Zeroizing::new(rpassword::prompt_password(prompt)?))
+}
+
+fn print_balance(title: &str, state: &ChannelState, channel_amount: u64) {
+    println!(
+        "{} state {}, {} to Bob, {} to Alice before the fee",
+        title.green(),
+        state.number,
+        monero::Amount::from_pico(state.bob_amount)
+            .to_string()
+            .green(),
+        monero::Amount::from_pico(state.alice_amount(channel_amount))
+            .to_string()
+            .green()
+    );
+}
+
+fn print_payment(title: &str, state: &ChannelState) {
+    println!(
+        "{} state {}, {} to Bob",
+        title.green(),
+        state.number,
+        monero::Amount::from_pico(state.bob_amount)
+            .to_string()
+            .green()
+    );
+}
+
+// proofs of knowledge are bound to the role of whoever proves, so that the other party
+// cannot send back our own public key as theirs
+fn pubkey_context(role: &Role) -> &'static [u8] {
+    match role {
+        Role::Alice => b"PayMo_pubkey_Alice",
+        Role::Bob => b"PayMo_pubkey_Bob",
+    }
+}
+
+// `public_key`, with a proof of knowledge of `secret`, and our share of the joint view key
+fn pubkey_msg(keys: &OwnKeys, role: &Role) -> msgs::Pubkey {
+    let proof = SchnorrProof::prove(&mut OsRng, &keys.secret, pubkey_context(role));
+
+    msgs::Pubkey {
+        key: keys.public_key.compress().to_bytes().to_vec(),
+        proof: Some((&proof).into()),
+        view_secret: keys.view_secret.to_bytes().to_vec(),
+    }
+}
+
+// the public key of the other party, with the given `role`, if it is the one they committed to
+// with `expected_hash`, and they know its secret; otherwise, the channel is rejected. Also
+// returns their share of the joint view key
+fn verify_pubkey(
+    pubkey: msgs::Pubkey,
+    expected_hash: &[u8],
+    role: &Role,
+) -> crate::Result<(EdwardsPoint, Scalar)> {
+    let key = decode_point(&pubkey.key).filter(|key| !key.is_identity());
+    let proof = pubkey.proof.map(SchnorrProof::try_from);
+    let view_secret = decode_scalar(&pubkey.view_secret);
+
+    println!("Expected hash: {}", hex::encode(expected_hash));
+    println!("Computed hash: {}", hex::encode(hash(&pubkey.key)));
+
+    match (key, proof, view_secret) {
+        (Some(key), Some(Ok(proof)), Some(view_secret))
+            if hash(&pubkey.key) == expected_hash && proof.verify(&key, pubkey_context(role)) =>
+        {
+            Ok((key, view_secret))
+        }
+        _ => {
+            println!("{} {}", "PROTOCOL ABORTED:".red(), Error::InvalidPubkey);
+            Err(Error::InvalidPubkey.into())
+        }
+    }
+}
+
+// this killing process is bad; se should gracefully shutdown the child processes
+impl Drop for Client {
+    fn drop(&mut self) {
+        if self.peerd_process.is_some() {
+            self.peerd_process.take().unwrap().kill().unwrap();
+        }
+        if self.walled_process.is_some() {
+            self.walled_process.take().unwrap().kill().unwrap();
+        }
+        if self.watcherd_process.is_some() {
+            self.watcherd_process.take().unwrap().kill().unwrap();
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Protocol aborted, the VTD-Log of the other party cannot be solved: {0}")]
+    VtdlogAborted(vtdlog::Error),
+
+    #[error(
+        "Channel rejected, the tag of the other party does not use the secret of their public key"
+    )]
+    InvalidTag,
+
+    #[error(
+        "Channel rejected, the public key of the other party is not the one they committed to, or \
+         they do not know its secret"
+    )]
+    InvalidPubkey,
+
+    #[error(
+        "Channel rejected, the VTD-Log of the other party does not hide the secret of their public \
+         key"
+    )]
+    InvalidVtdlog,
+
+    #[error("The VTD-Log of the other party could not be solved")]
+    VtdlogUnsolved,
+
+    #[error("Only Alice pays in a channel")]
+    OnlyAlicePays,
+
+    #[error("The channel is closed")]
+    ChannelClosed,
+
+    #[error("No payment received yet, so there is nothing to close the channel with")]
+    NoPayment,
+
+    #[error("The channel output is already spent")]
+    ChannelOutputSpent,
+
+    #[error("The channel is not open yet")]
+    ChannelNotOpen,
+
+    #[error("The channel is not funded yet: no output of the channel amount at the joint address")]
+    ChannelNotFunded,
+
+    #[error("No transaction builder for the spends of the joint output")]
+    NoSpendBuilder,
+
+    #[error("Unknown command")]
+    InvalidCommand,
+
+    #[error("Unexpected or malformed message from peerd: {0:?}")]
+    InvalidPeerdMsg(peerd_msg::PeerdMsgType),
+
+    #[error("No channel client is running in {0}")]
+    ClientNotRunning(PathBuf),
+
+    #[error("Command failed: {0}")]
+    CommandFailed(String),
+
+    #[error("The channel in the data directory was opened with another role or address")]
+    OtherChannel,
+
+    #[error("The channel in the data directory was opened with another node than Alice's URL")]
+    OtherNode,
+
+    #[error("No node key of the other party to store or resume the channel with")]
+    NoNodeKey,
+
+    #[error(
+        "No --view-key: Bob needs it for the payments, and Alice to close the channel with him"
+    )]
+    NoViewKey,
+
+    #[error("The address is not on the {0:?} network of the channel")]
+    OtherNetwork(address::Network),
+
+    #[error("No such channel in the data directory")]
+    ChannelNotFound,
+
+    #[error("Invalid channel backup: bad {0}")]
+    InvalidBackup(&'static str),
+
+    #[error("Invalid channel backup: {0}")]
+    InvalidBackupAddress(String),
+
+    #[error("The passphrases do not match")]
+    PassphraseMismatch,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::fs;
+    use std::rc::Rc;
+
+    use curve25519_dalek::traits::Identity;
+    use monero_serai::{
+        random_scalar,
+        ringct::{bulletproofs::Bulletproofs, RctBase, RctPrunable, RctSignatures},
+        transaction::{Input, Output, Timelock, Transaction, TransactionPrefix},
+        wallet::address::{AddressSpec, MoneroAddress, Network},
+        wallet::ViewPair,
+        Commitment,
+    };
+
+    use crate::config;
+    use crate::core::channel::{Funding, Keys};
+    use crate::core::jspend::JointOutput;
+    use crate::core::utils::temp_dir;
+    use crate::core::vtdlog::VtdlogParams;
+
+    const CHANNEL_AMOUNT: u64 = 1_000_000_000_000;
+    const RING_LEN: usize = 16;
+
+    // the chain the clients see: a published transaction spends its key image at once, and
+    // is confirmed right away, unless the chain is `unconfirmed`
+    #[derive(Default)]
+    struct MockChain {
+        spent: Vec<EdwardsPoint>,
+        published: Vec<Transaction>,
+        unconfirmed: bool,
+    }
+
+    // kept by the test while the client owns it
+    #[derive(Clone, Default)]
+    struct Chain(Rc<RefCell<MockChain>>);
+
+    impl Chain {
+        fn published(&self) -> usize {
+            self.0.borrow().published.len()
+        }
+    }
+
+    impl watcherd::Chain for Chain {
+        fn height(&self) -> Result<usize, watcherd::Error> {
+            Ok(100)
+        }
+
+        fn spent(&self, images: &[EdwardsPoint]) -> Result<Vec<EdwardsPoint>, watcherd::Error> {
+            let chain = self.0.borrow();
+
+            Ok(images
+                .iter()
+                .filter(|image| chain.spent.contains(image))
+                .copied()
+                .collect())
+        }
+
+        fn confirmations(
+            &self,
+            hash: &[u8; 32],
+            _from_height: usize,
+        ) -> Result<Option<usize>, watcherd::Error> {
+            let chain = self.0.borrow();
+
+            let confirmations = if chain.unconfirmed { 0 } else { 1 };
+
+            Ok(chain
+                .published
+                .iter()
+                .any(|tx| &tx.hash() == hash)
+                .then_some(confirmations))
+        }
+
+        fn publish(&self, tx: &Transaction) -> Result<(), watcherd::Error> {
+            let mut chain = self.0.borrow_mut();
+
+            for input in &tx.prefix.inputs {
+                if let Input::ToKey { key_image, .. } = input {
+                    chain.spent.push(*key_image);
+                }
+            }
+            chain.published.push(tx.clone());
+
+            Ok(())
+        }
+    }
+
+    // builds the spends of the joint output without a daemon, with `fee`
+    struct MockBuilder {
+        fee: u64,
+    }
+
+    impl walletd::SpendBuilder for MockBuilder {
+        fn build(
+            &mut self,
+            funding: &Funding,
+            _to: &MoneroAddress,
+            _amount: u64,
+            _change: &MoneroAddress,
+        ) -> Result<walletd::UnsignedSpend, walletd::Error> {
+            Ok(unsigned_spend(funding, self.fee))
+        }
+    }
+
+    // a spend of the joint output with random decoys, and a single output of what is left
+    // after the fee
+    fn unsigned_spend(funding: &Funding, fee: u64) -> walletd::UnsignedSpend {
+        let output = &funding.output;
+        let random_point = || &random_scalar(&mut OsRng) * &ED25519_BASEPOINT_TABLE;
+
+        let mut ring: Vec<[EdwardsPoint; 2]> = (0..RING_LEN)
+            .map(|_| [random_point(), random_point()])
+            .collect();
+        ring[RING_LEN / 2] = [output.key, output.commitment.calculate()];
+
+        let out = Commitment::new(random_scalar(&mut OsRng), output.commitment.amount - fee);
+
+        let tx = Transaction {
+            prefix: TransactionPrefix {
+                version: 2,
+                timelock: Timelock::None,
+                inputs: vec![Input::ToKey {
+                    amount: 0,
+                    key_offsets: vec![1; RING_LEN],
+                    key_image: EdwardsPoint::identity(),
+                }],
+                outputs: vec![Output {
+                    amount: 0,
+                    key: random_point().compress(),
+                    view_tag: Some(0),
+                }],
+                extra: vec![],
+            },
+            signatures: vec![],
+            rct_signatures: RctSignatures {
+                base: RctBase {
+                    fee,
+                    ecdh_info: vec![[0; 8]],
+                    commitments: vec![out.calculate()],
+                },
+                prunable: RctPrunable::Clsag {
+                    bulletproofs: vec![
+                        Bulletproofs::prove(&mut OsRng, &[out.clone()], false).unwrap()
+                    ],
+                    clsags: vec![],
+                    pseudo_outs: vec![Commitment::new(out.mask, out.amount).calculate()],
+                },
+            },
+        };
+
+        walletd::UnsignedSpend {
+            tx,
+            ring,
+            z: output.commitment.mask - out.mask,
+        }
+    }
+
+    fn view_key() -> (MoneroAddress, walletd::ViewKey) {
+        let spend = &random_scalar(&mut OsRng) * &ED25519_BASEPOINT_TABLE;
+        let view = Zeroizing::new(random_scalar(&mut OsRng));
+
+        let address =
+            ViewPair::new(spend, view.clone()).address(Network::Mainnet, AddressSpec::Standard);
+
+        (address, walletd::ViewKey::new(*view))
+    }
+
+    // Alice's and Bob's clients of an open channel, on the same chain
+    fn open_clients(chain: &Chain, dir: &Path) -> (Client, Client) {
+        let params = Params {
+            channel_amount: monero::Amount::from_pico(CHANNEL_AMOUNT),
+            time: 100,
+            confirmations: 1,
+            vtdlog_params: VtdlogParams::MIN,
+        };
+        let alice = MasterSeed::generate().0.channel_keys(0);
+        let bob = MasterSeed::generate().0.channel_keys(0);
+        let (alice_address, alice_view) = view_key();
+        let (bob_address, bob_view) = view_key();
+
+        let keys = |role: Role| {
+            let (own, theirs) = match role {
+                Role::Alice => (&alice, &bob),
+                Role::Bob => (&bob, &alice),
+            };
+
+            let mut keys = Keys::new(
+                role,
+                params.clone(),
+                alice_address,
+                bob_address,
+                own.clone(),
+                theirs.public_key,
+                *theirs.view_secret,
+            );
+            keys.vtdlog_opened = true;
+
+            keys
+        };
+
+        let joint_tag = keys(Role::Alice).tag + keys(Role::Bob).tag;
+        let key_offset = random_scalar(&mut OsRng);
+        let output = JointOutput {
+            key: &key_offset * &ED25519_BASEPOINT_TABLE + keys(Role::Alice).joint_public_key,
+            key_offset,
+            commitment: Commitment::new(random_scalar(&mut OsRng), CHANNEL_AMOUNT),
+        };
+
+        let client = |role: Role, view_key, data_dir: PathBuf| {
+            fs::create_dir_all(&data_dir).unwrap();
+
+            let mut keys = keys(role.clone());
+            keys.joint_tag = Some(joint_tag);
+            let funding = Funding {
+                keys,
+                joint_tag,
+                output: output.clone(),
+                height: 10,
+                image: None,
+            };
+
+            let mut client = Client {
+                role: role.clone(),
+                zmq_context: zmq::Context::new(),
+                pub_socket: None,
+                sub_socket: None,
+                cmd_socket: None,
+                vtdlog_socket: None,
+                channel: core::Channel::from_phase(role, Phase::Open(funding)),
+                calibration: None,
+                vtdlog: None,
+                bob_vtdlog: None,
+                refund_solver: None,
+                joint_spend: None,
+                unsigned_spend: None,
+                payer: None,
+                payee: None,
+                refund_tx: None,
+                spend_builder: Some(Box::new(MockBuilder { fee: 0 })),
+                chain: Some(Box::new(chain.clone())),
+                view_key: Some(view_key),
+                store: None,
+                keystore: None,
+                their_node_key: Some([9; 32]),
+                walled_process: None,
+                watcherd_process: None,
+                peerd_process: None,
+                data_dir,
+                peerd_url: None,
+                monerod_rpc_url: None,
+                monerod_zmq_url: None,
+                monero_wallet_url: None,
+            };
+            client.save_channel().unwrap();
+
+            client
+        };
+
+        (
+            client(Role::Alice, alice_view, dir.join("alice")),
+            client(Role::Bob, bob_view, dir.join("bob")),
+        )
+    }
+
+    // Alice pays Bob `amount`, and both of them keep the payment, as after its
+    // acknowledgement
+    fn pay(alice: &mut Client, bob: &mut Client, amount: u64) -> ChannelState {
+        let (state, request) = alice.payer().unwrap().request(amount).unwrap();
+        let request = match request.data {
+            Some(pay_msg::Data::Request(request)) => request,
+            data => panic!("unexpected {data:?}"),
+        };
+
+        let image = bob.payee().unwrap().receive_request(&mut OsRng, request);
+        let image = match image.unwrap().data {
+            Some(pay_msg::Data::Image(image)) => image,
+            data => panic!("unexpected {data:?}"),
+        };
+
+        let spend = unsigned_spend(alice.channel.funding().unwrap(), 0);
+        let payment = alice.payer().unwrap().pre_sign(&mut OsRng, image, spend);
+        let payment = match payment.unwrap().data {
+            Some(pay_msg::Data::Payment(payment)) => payment,
+            data => panic!("unexpected {data:?}"),
+        };
+
+        for client in [alice, bob] {
+            client.payments().unwrap().save(&payment).unwrap();
+            client.payer = None;
+            client.payee = None;
+        }
+
+        state
+    }
+
+    fn bob_secret(bob: &Client) -> Zeroizing<Scalar> {
+        bob.channel.funding().unwrap().keys.own.secret.clone()
+    }
+
+    #[test]
+    fn bob_closes_alone_once() {
+        let chain = Chain::default();
+        let dir = temp_dir();
+        let (mut alice, mut bob) = open_clients(&chain, &dir);
+
+        // nothing to close the channel with before the first payment
+        assert!(matches!(
+            bob.close_alone(),
+            Err(crate::Error::Client(Error::NoPayment))
+        ));
+
+        let state = pay(&mut alice, &mut bob, CHANNEL_AMOUNT / 4);
+        bob.close_alone().unwrap();
+        assert_eq!(chain.published(), 1);
+
+        match bob.channel.phase() {
+            Phase::Closing(_, closing) => {
+                assert_eq!(closing.state, state);
+                assert_eq!(
+                    closing.tx.as_ref().map(Transaction::hash),
+                    Some(chain.0.borrow().published[0].hash())
+                );
+            }
+            phase => panic!("unexpected {}", phase.name()),
+        }
+
+        assert!(matches!(
+            bob.close_alone(),
+            Err(crate::Error::Client(Error::ChannelClosed))
+        ));
+        assert_eq!(chain.published(), 1);
+    }
+
+    #[test]
+    fn cooperative_close_pays_the_acknowledged_state() {
+        let chain = Chain::default();
+        let dir = temp_dir();
+        let (mut alice, mut bob) = open_clients(&chain, &dir);
+
+        let state = pay(&mut alice, &mut bob, CHANNEL_AMOUNT / 4);
+
+        // Bob only closes with the latest state he acknowledged
+        let other = ChannelState {
+            number: state.number + 1,
+            bob_amount: state.bob_amount,
+        };
+        assert!(matches!(
+            bob.recv_close(other),
+            Err(crate::Error::Walletd(walletd::Error::UnexpectedState))
+        ));
+
+        alice.start_closing(state).unwrap();
+        let init = bob.recv_close(state).unwrap();
+        assert!(matches!(bob.channel.closing(), Ok(closing) if closing.state == state));
+
+        let init = alice.recv_joint_spend(init).unwrap();
+        let proposal = bob.recv_joint_spend(init).unwrap();
+
+        // the spend of the mocked builder does not pay Alice her balance
+        assert!(matches!(
+            alice.recv_joint_spend(proposal),
+            Err(crate::Error::Walletd(walletd::Error::UnexpectedOutputs))
+        ));
+        assert_eq!(chain.published(), 0);
+
+        // the channel is already closing
+        assert!(bob.recv_close(state).is_err());
+    }
+
+    #[test]
+    fn alice_refuses_a_close_whose_fee_takes_her_balance() {
+        let chain = Chain::default();
+        let dir = temp_dir();
+        let (mut alice, mut bob) = open_clients(&chain, &dir);
+
+        let state = pay(&mut alice, &mut bob, CHANNEL_AMOUNT / 4);
+        bob.spend_builder = Some(Box::new(MockBuilder {
+            fee: state.alice_amount(CHANNEL_AMOUNT),
+        }));
+
+        alice.start_closing(state).unwrap();
+        let init = bob.recv_close(state).unwrap();
+        let init = alice.recv_joint_spend(init).unwrap();
+        let proposal = bob.recv_joint_spend(init).unwrap();
+
+        assert!(matches!(
+            alice.recv_joint_spend(proposal),
+            Err(crate::Error::Walletd(walletd::Error::FeeTooHigh(_)))
+        ));
+        assert_eq!(chain.published(), 0);
+    }
+
+    #[test]
+    fn duplicated_messages_are_dropped() {
+        let chain = Chain::default();
+        let dir = temp_dir();
+        let (mut alice, _bob) = open_clients(&chain, &dir);
+
+        // Bob's hash once more, after the channel was opened
+        let msg = msgs::PeerdMsg {
+            msg_type: peerd_msg::PeerdMsgType::AliceUpdateBobHash as i32,
+            data: Some(peerd_msg::Data::Hash(vec![1; 32])),
+        };
+        assert!(alice.recv_from_peerd(msg.encode_to_vec()).is_err());
+
+        alice.handle_from_peerd(msg.encode_to_vec()).unwrap();
+        assert!(matches!(alice.channel.phase(), Phase::Open(_)));
+    }
+
+    #[test]
+    fn payments_in_progress_are_aborted_when_bob_restarts() {
+        let chain = Chain::default();
+        let dir = temp_dir();
+        let (mut alice, _bob) = open_clients(&chain, &dir);
+
+        let (state, _) = alice.payer().unwrap().request(CHANNEL_AMOUNT / 4).unwrap();
+        assert!(matches!(
+            alice.close(),
+            Err(crate::Error::Walletd(walletd::Error::PaymentPending))
+        ));
+
+        let reset = msgs::PeerdMsg {
+            msg_type: peerd_msg::PeerdMsgType::PeerReset as i32,
+            data: None,
+        };
+        alice.handle_from_peerd(reset.encode_to_vec()).unwrap();
+        assert_eq!(alice.payer().unwrap().pending(), None);
+
+        // the same state can be requested again
+        let (next, _) = alice.payer().unwrap().request(CHANNEL_AMOUNT / 4).unwrap();
+        assert_eq!(next, state);
+    }
+
+    #[test]
+    fn alice_is_not_refunded_once_bob_closed_alone() {
+        let chain = Chain::default();
+        let dir = temp_dir();
+        let (mut alice, mut bob) = open_clients(&chain, &dir);
+
+        let state = pay(&mut alice, &mut bob, CHANNEL_AMOUNT / 4);
+        assert!(!alice.closed_by_bob().unwrap());
+
+        bob.close_alone().unwrap();
+
+        assert!(alice.closed_by_bob().unwrap());
+        assert!(matches!(alice.channel.phase(), Phase::Closed(_, closed) if *closed == state));
+        assert!(matches!(
+            alice.pay(1),
+            Err(crate::Error::Client(Error::ChannelClosed))
+        ));
+
+        alice.refund(bob_secret(&bob)).unwrap();
+        assert_eq!(chain.published(), 1);
+        assert!(matches!(alice.channel.phase(), Phase::Closed(..)));
+    }
+
+    #[test]
+    fn commands_are_handled_while_the_refund_is_unconfirmed() {
+        let chain = Chain::default();
+        chain.0.borrow_mut().unconfirmed = true;
+        let dir = temp_dir();
+        let (mut alice, mut bob) = open_clients(&chain, &dir);
+
+        pay(&mut alice, &mut bob, CHANNEL_AMOUNT / 4);
+
+        // the refund is published without waiting for its confirmations
+        alice.refund(bob_secret(&bob)).unwrap();
+        alice.check_confirmations().unwrap();
+        assert_eq!(chain.published(), 1);
+        assert!(alice.awaits_confirmations());
+        assert!(matches!(alice.channel.phase(), Phase::Open(_)));
+
+        // a command of another invocation is answered meanwhile
+        let cmd_socket = alice.zmq_context.socket(zmq::REP).unwrap();
+        cmd_socket.bind("inproc://cmd").unwrap();
+        alice.cmd_socket = Some(cmd_socket);
+
+        let cmd = alice.zmq_context.socket(zmq::REQ).unwrap();
+        cmd.connect("inproc://cmd").unwrap();
+        let request = msgs::ClientCmd {
+            cmd: Some(client_cmd::Cmd::Pay(1)),
+        };
+        cmd.send(request.encode_to_vec(), 0).unwrap();
+
+        alice.recv_cmd().unwrap();
+        let res = msgs::ClientCmdRes::decode(cmd.recv_bytes(0).unwrap().as_slice()).unwrap();
+        assert!(res.error.contains(&Error::ChannelClosed.to_string()));
+
+        chain.0.borrow_mut().unconfirmed = false;
+        alice.check_confirmations().unwrap();
+        assert!(matches!(alice.channel.phase(), Phase::Refunded(_)));
+        assert!(!alice.awaits_confirmations());
+    }
+
+    #[test]
+    fn backups_of_another_network_are_rejected() {
+        let dir = temp_dir();
+        let keys = MasterSeed::generate().0.channel_keys(0);
+        let (alice_address, _) = view_key();
+        let (bob_address, _) = view_key();
+
+        let backup = msgs::ChannelBackup {
+            channel: Some(msgs::StoredChannel {
+                alice_address: alice_address.to_string(),
+                bob_address: bob_address.to_string(),
+                public_key: keys.public_key.compress().to_bytes().to_vec(),
+                key_index: keys.index,
+                ..Default::default()
+            }),
+            secret: keys.secret.to_bytes().to_vec(),
+            view_secret: keys.view_secret.to_bytes().to_vec(),
+            ..Default::default()
+        };
+        let config = Config {
+            bind_port: 9000,
+            bind_ip: "0.0.0.0".into(),
+            network: config::Network::Testnet,
+            monero: config::MoneroConfig {
+                daemon: "http://localhost:18081".into(),
+                daemon_zmq: "tcp://0.0.0.0:18082".into(),
+                wallet_rpc: "http://localhost:18083".into(),
+            },
+        };
+
+        assert!(matches!(
+            import_backup(&dir, &config, backup),
+            Err(crate::Error::Client(Error::InvalidBackup("network")))
+        ));
+        assert!(fs::read_dir(&*dir).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn bob_cannot_close_alone_once_alice_is_refunded() {
+        let chain = Chain::default();
+        let dir = temp_dir();
+        let (mut alice, mut bob) = open_clients(&chain, &dir);
+
+        pay(&mut alice, &mut bob, CHANNEL_AMOUNT / 4);
+
+        alice.refund(bob_secret(&bob)).unwrap();
+        alice.check_confirmations().unwrap();
+        assert_eq!(chain.published(), 1);
+        assert!(matches!(alice.channel.phase(), Phase::Refunded(_)));
+        assert!(!alice.closed_by_bob().unwrap());
+
+        assert!(matches!(
+            bob.close_alone(),
+            Err(crate::Error::Client(Error::ChannelOutputSpent))
+        ));
+        assert!(matches!(bob.channel.phase(), Phase::Open(_)));
+
+        // a second refund does nothing
+        alice.refund(bob_secret(&bob)).unwrap();
+        assert_eq!(chain.published(), 1);
+    }
+}
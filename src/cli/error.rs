@@ -7,15 +7,23 @@ pub enum Error {
     #[error("File does not exist: {0}")]
     FileNotFound(PathBuf),
 
-    #[error("Unsupported address: only regtest addresses are supported at the moment")]
-    UnsupportedAddress,
+    #[error("Invalid view key: must be the private view key of --address, in hex")]
+    InvalidViewKey,
 
     #[error(transparent)]
-    ParseInt(#[from] std::num::ParseIntError),
+    ParseFloat(#[from] std::num::ParseFloatError),
 
-    #[error("Invalid --time: must be greater than 100, but {0:?} was provided")]
+    #[error("Invalid --time: must amount to at least 100 squarings, but {0:?} was provided")]
     InvalidTime(u64),
 
+    #[error(
+        "Invalid --time-margin: must be a finite number of at least 1, but {0:?} was provided"
+    )]
+    InvalidTimeMargin(f64),
+
+    #[error("Invalid VTD-Log parameters: {0}")]
+    InvalidVtdlogParams(crate::core::vtdlog::Error),
+
     #[error(transparent)]
     Cmd(#[from] CmdError),
 }
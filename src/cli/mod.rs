@@ -5,4 +5,4 @@ pub mod error;
 mod opts;
 
 pub use error::Error;
-pub use opts::Opts;
+pub use opts::{ChannelCommand, Command, Opts};
@@ -1,4 +1,4 @@
-use clap::{CommandFactory, Parser};
+use clap::{CommandFactory, Parser, Subcommand};
 use clap_complete::{generate, Shell};
 use log::{debug, info};
 use monero_serai::wallet::address;
@@ -7,21 +7,42 @@ use std::path;
 
 use crate::peerd;
 
-use super::clap_value_parsers::{parse_address_network, parse_t_duration};
+use super::clap_value_parsers::{
+    parse_address, parse_time, parse_time_margin, parse_view_key, MIN_TIME,
+};
 use super::error::{CmdError, Error};
+use crate::core::calibration::{self, Calibration, TimeSpec};
+use crate::core::timelock::{Backend, TimeLockParams};
+use crate::core::vtdlog::VtdlogParams;
 use crate::core::Role;
+use crate::walletd::ViewKey;
 
 #[derive(Parser, Debug)]
 #[command(name="paymo-cli", bin_name="paymo-cli", author, version, about, long_about = None)]
+#[command(subcommand_negates_reqs = true)]
 pub struct Opts {
     #[clap(flatten)]
     pub shared: crate::opts::SharedOpts,
 
-    #[arg(long, value_enum)]
-    pub role: Role,
-
-    #[arg(short, long, value_name = "XMR ADDRESS", value_parser = parse_address_network)]
-    pub address: address::MoneroAddress,
+    /// Required to open a channel; commands act on the client already running in the data
+    /// directory.
+    #[arg(long, value_enum, required = true)]
+    pub role: Option<Role>,
+
+    #[arg(
+        short,
+        long,
+        value_name = "XMR ADDRESS",
+        value_parser = parse_address,
+        required = true
+    )]
+    pub address: Option<address::MoneroAddress>,
+
+    /// Private view key of --address, in hex, with which the transactions of the channel are
+    /// checked to pay it what they should before they are signed or accepted; required for
+    /// Bob, and for Alice to close the channel together with him.
+    #[arg(long, value_name = "HEX", value_parser = parse_view_key)]
+    pub view_key: Option<ViewKey>,
 
     #[clap(flatten)]
     pub alice_opts: Option<AliceOpts>,
@@ -32,8 +53,50 @@ pub struct Opts {
     #[clap(skip)]
     pub config_file: path::PathBuf,
 
+    /// Speed of this machine at solving the channel puzzles; only measured by Alice, since
+    /// Bob only learns the puzzle parameters from her.
+    #[clap(skip)]
+    pub calibration: Option<Calibration>,
+
     #[clap(long, value_name = "SHELL", value_enum)]
     pub generate_completion: Option<Shell>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Pays Bob through the open channel; only Alice pays.
+    Pay {
+        /// Amount to pay, e.g. "0.1 xmr".
+        amount: monero::Amount,
+    },
+
+    /// Closes the channel, paying Bob what Alice paid him so far: Alice closes it together with
+    /// Bob, and Bob alone, with the latest payment.
+    Close,
+
+    /// Moves a channel to another data directory, through an encrypted backup file; runs
+    /// without the client, which must not be running in the data directory.
+    #[command(subcommand)]
+    Channel(ChannelCommand),
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ChannelCommand {
+    /// Writes a channel, with our keys of it, to a new backup file.
+    Export {
+        file: path::PathBuf,
+
+        /// Channel to export, as named in the `channels` folder of the data directory; the one
+        /// the client resumes, by default.
+        #[arg(long, value_name = "ID")]
+        channel: Option<String>,
+    },
+
+    /// Adds the channel of a backup file to the data directory, for the client to resume it.
+    Import { file: path::PathBuf },
 }
 
 impl Opts {
@@ -43,7 +106,13 @@ impl Opts {
 
         opts.shared.expand_data_dir()?;
 
+        // the running client already has everything else
+        if opts.command.is_some() {
+            return Ok(opts);
+        }
+
         opts.validate_role_opts()?;
+        opts.calibrate_time()?;
 
         opts.generate_shell_completion();
         opts.populate_config_file()?;
@@ -54,7 +123,13 @@ impl Opts {
     }
 
     fn validate_role_opts(&self) -> crate::Result<()> {
-        match self.role {
+        if let Some(view_key) = &self.view_key {
+            if !view_key.is_of(self.address.as_ref().unwrap()) {
+                return Err(Error::InvalidViewKey.into());
+            }
+        }
+
+        match self.role.as_ref().unwrap() {
             Role::Alice => self.validate_alice_opts()?,
             Role::Bob => self.validate_bob_opts()?,
         }
@@ -94,6 +169,11 @@ impl Opts {
             return Err(err.into());
         }
 
+        if let Err(e) = alice_opts.vtdlog_params().validate() {
+            let err = Error::InvalidVtdlogParams(e);
+            return Err(err.into());
+        }
+
         Ok(())
     }
 
@@ -112,6 +192,34 @@ impl Opts {
             return Err(err.into());
         }
 
+        // the payments are only acknowledged once they pay his address
+        if self.view_key.is_none() {
+            let err: Error =
+                CmdError::MissingArguments(vec!["view_key".to_string()], "Bob".to_string()).into();
+
+            return Err(err.into());
+        }
+
+        Ok(())
+    }
+
+    // the puzzles depend on the machine, so --time is converted to squarings on Alice's side
+    fn calibrate_time(&mut self) -> crate::Result<()> {
+        let alice_opts = match &self.alice_opts {
+            Some(alice_opts) => alice_opts,
+            None => return Ok(()),
+        };
+
+        info!("Measuring how fast this machine solves time-lock puzzles");
+        let calibration = Calibration::measure(&alice_opts.vtdlog_params().timelock);
+
+        let squarings = alice_opts.squarings(&calibration).unwrap();
+        if squarings < MIN_TIME {
+            return Err(Error::InvalidTime(squarings).into());
+        }
+
+        self.calibration = Some(calibration);
+
         Ok(())
     }
 
@@ -145,12 +253,67 @@ pub struct AliceOpts {
     #[clap(long)]
     pub channel_amount: Option<monero::Amount>,
 
-    /// Time is the number of squarings that should be performed; must be greater than 100 for now.
-    #[clap(short, long, value_parser = parse_t_duration)]
-    pub time: Option<u64>,
+    /// How long the time-lock puzzles take to solve: a duration such as 6h, a number of blocks
+    /// such as 720blocks, or the number of squarings itself; must amount to at least 100
+    /// squarings.
+    #[clap(short, long, value_parser = parse_time)]
+    pub time: Option<TimeSpec>,
+
+    /// Safety factor on --time: durations and block counts are converted to squarings at the
+    /// speed of this machine, then multiplied by it, so that the puzzles still take at least
+    /// --time to solve on a machine that many times faster. Defaults to 2.
+    #[clap(long, value_parser = parse_time_margin)]
+    pub time_margin: Option<f64>,
 
     #[clap(long)]
     pub confirmations: Option<u32>,
+
+    /// Number of shares of the VTD-Log; defaults to 40.
+    #[clap(long)]
+    pub vtdlog_shares: Option<usize>,
+
+    /// Number of shares needed to recover the VTD-Log secret; defaults to 21.
+    #[clap(long)]
+    pub vtdlog_threshold: Option<usize>,
+
+    /// Time-lock puzzles used by the VTD-Log; defaults to lhtlp.
+    #[clap(long, value_enum)]
+    pub timelock_backend: Option<Backend>,
+
+    /// Bit length of the time-lock puzzles modulus (lhtlp) or discriminant (class group);
+    /// defaults to 2048 and 1827, respectively.
+    #[clap(long)]
+    pub timelock_security_param: Option<u64>,
+}
+
+impl AliceOpts {
+    /// Number of squarings of the puzzles, from --time and --time-margin.
+    pub fn squarings(&self, calibration: &Calibration) -> Option<u64> {
+        let margin = self
+            .time_margin
+            .unwrap_or(calibration::DEFAULT_SAFETY_MARGIN);
+
+        Some(calibration.squarings(self.time.as_ref()?, margin))
+    }
+
+    /// VTD-Log parameters proposed to Bob, with the defaults for the ones not provided.
+    pub fn vtdlog_params(&self) -> VtdlogParams {
+        let default = VtdlogParams::default();
+
+        let timelock =
+            TimeLockParams::with_backend(self.timelock_backend.unwrap_or(default.timelock.backend));
+
+        VtdlogParams {
+            n: self.vtdlog_shares.unwrap_or(default.n),
+            threshold: self.vtdlog_threshold.unwrap_or(default.threshold),
+            timelock: TimeLockParams {
+                security_param: self
+                    .timelock_security_param
+                    .unwrap_or(timelock.security_param),
+                ..timelock
+            },
+        }
+    }
 }
 
 #[derive(Parser, Debug, Clone)]
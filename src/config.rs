@@ -1,5 +1,6 @@
 use config::File;
 use log::info;
+use monero_serai::wallet::address;
 use serde::Deserialize;
 use std::path::Path;
 
@@ -10,10 +11,32 @@ pub struct MoneroConfig {
     pub wallet_rpc: String,
 }
 
+/// Monero network of the addresses; regtest uses the addresses of mainnet.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    #[default]
+    Mainnet,
+    Testnet,
+    Stagenet,
+}
+
+impl From<Network> for address::Network {
+    fn from(network: Network) -> Self {
+        match network {
+            Network::Mainnet => address::Network::Mainnet,
+            Network::Testnet => address::Network::Testnet,
+            Network::Stagenet => address::Network::Stagenet,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub bind_port: u16,
     pub bind_ip: String,
+    #[serde(default)]
+    pub network: Network,
     pub monero: MoneroConfig,
 }
 
@@ -0,0 +1,474 @@
+#![allow(non_snake_case)]
+use curve25519_dalek::{
+    constants::ED25519_BASEPOINT_TABLE, edwards::EdwardsPoint, scalar::Scalar, traits::IsIdentity,
+};
+use monero_serai::{
+    hash_to_scalar, random_scalar,
+    ringct::{clsag::Clsag, hash_to_point},
+};
+use rand_core::{CryptoRng, RngCore};
+use zeroize::Zeroizing;
+
+use super::utils::{decode_point, decode_scalar};
+use crate::msgs;
+
+// domain separators of the CLSAG hashes, padded to 32 bytes
+const AGG_0: &[u8] = b"CLSAG_agg_0";
+const AGG_1: &[u8] = b"CLSAG_agg_1";
+const ROUND: &[u8] = b"CLSAG_round";
+
+/// Everything a CLSAG signs over, plus the index of the real input in the ring, which both
+/// parties of a channel know, since they own the input together.
+#[derive(Clone, Debug)]
+pub struct ClsagContext {
+    /// (P_i, C_i) of every member of the ring.
+    pub ring: Vec<[EdwardsPoint; 2]>,
+    pub index: usize,
+    /// Key image of the real input, I = x * H_p(P).
+    pub image: EdwardsPoint,
+    pub pseudo_out: EdwardsPoint,
+    pub msg: [u8; 32],
+}
+
+/// Statement of the witness y: Y = y * G and Y_H = y * H_p(P), where P is the key of the
+/// real input. Whoever pre-signs knows x - y, and the signature is only valid once adapted
+/// with y. The two points must be proven to have the same discrete logarithm separately, e.g.
+/// with a [`DleqProof`](super::proofs::DleqProof) for H_p(P).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Statement {
+    pub Y: EdwardsPoint,
+    pub Y_H: EdwardsPoint,
+}
+
+impl Statement {
+    pub fn new(y: &Scalar, P: &EdwardsPoint) -> Self {
+        Self {
+            Y: y * &ED25519_BASEPOINT_TABLE,
+            Y_H: y * hash_to_point(*P),
+        }
+    }
+}
+
+/// CLSAG that only verifies once adapted with the witness of its statement; same fields as
+/// [`Clsag`], where `D` is also divided by 8 and `c1` is the challenge of the first member.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PreSignature {
+    pub D: EdwardsPoint,
+    pub s: Vec<Scalar>,
+    pub c1: Scalar,
+}
+
+// aggregation coefficients and the beginning of every round hash, which only differ in the
+// L and R of the previous member
+pub(super) struct Transcript {
+    pub(super) mu_P: Scalar,
+    pub(super) mu_C: Scalar,
+    round: Vec<u8>,
+}
+
+impl Transcript {
+    // `D` is the one included in the signature, i.e. divided by 8
+    pub(super) fn new(ctx: &ClsagContext, D: &EdwardsPoint) -> Self {
+        let mut keys = vec![];
+        for member in &ctx.ring {
+            keys.extend(member[0].compress().to_bytes());
+        }
+        for member in &ctx.ring {
+            keys.extend(member[1].compress().to_bytes());
+        }
+
+        let aggregation = |domain: &[u8]| {
+            let mut to_hash = padded(domain);
+            to_hash.extend(&keys);
+            to_hash.extend(ctx.image.compress().to_bytes());
+            to_hash.extend(D.compress().to_bytes());
+            to_hash.extend(ctx.pseudo_out.compress().to_bytes());
+
+            hash_to_scalar(&to_hash)
+        };
+
+        let mut round = padded(ROUND);
+        round.extend(&keys);
+        round.extend(ctx.pseudo_out.compress().to_bytes());
+        round.extend(ctx.msg);
+
+        Self {
+            mu_P: aggregation(AGG_0),
+            mu_C: aggregation(AGG_1),
+            round,
+        }
+    }
+
+    fn challenge(&self, L: &EdwardsPoint, R: &EdwardsPoint) -> Scalar {
+        let mut to_hash = self.round.clone();
+        to_hash.extend(L.compress().to_bytes());
+        to_hash.extend(R.compress().to_bytes());
+
+        hash_to_scalar(&to_hash)
+    }
+
+    // challenge of the next member, given the challenge `c` and response `s` of member `i`;
+    // with a statement, the real member is checked against x - y instead of x
+    fn next(
+        &self,
+        ctx: &ClsagContext,
+        i: usize,
+        D: &EdwardsPoint,
+        s: &Scalar,
+        c: &Scalar,
+        statement: Option<&Statement>,
+    ) -> Scalar {
+        let [P, C] = ctx.ring[i];
+
+        let (key, image) = match statement {
+            Some(statement) if i == ctx.index => (P - statement.Y, ctx.image - statement.Y_H),
+            _ => (P, ctx.image),
+        };
+
+        let c_P = self.mu_P * c;
+        let c_C = self.mu_C * c;
+
+        let L = s * &ED25519_BASEPOINT_TABLE + c_P * key + c_C * (C - ctx.pseudo_out);
+        let R = s * hash_to_point(P) + c_P * image + c_C * D;
+
+        self.challenge(&L, &R)
+    }
+
+    // goes around the ring from the member right after the real one, whose challenge is the
+    // one of the nonces (L, R), back to the real one; returns c1 and the challenge of the
+    // real member
+    pub(super) fn around_ring(
+        &self,
+        ctx: &ClsagContext,
+        D: &EdwardsPoint,
+        s: &[Scalar],
+        L: &EdwardsPoint,
+        R: &EdwardsPoint,
+    ) -> (Scalar, Scalar) {
+        let n = ctx.ring.len();
+        let l = ctx.index;
+
+        let mut c = self.challenge(L, R);
+        let mut c1 = None;
+
+        for i in (l + 1..l + n).map(|i| i % n) {
+            if i == 0 {
+                c1 = Some(c);
+            }
+
+            c = self.next(ctx, i, D, &s[i], &c, None);
+        }
+
+        (c1.unwrap_or(c), c)
+    }
+}
+
+fn padded(domain: &[u8]) -> Vec<u8> {
+    let mut bytes = domain.to_vec();
+    bytes.resize(32, 0);
+    bytes
+}
+
+pub(super) fn inv_eight() -> Scalar {
+    Scalar::from(8u8).invert()
+}
+
+// challenges c_0, ..., c_n of the members of the ring, starting from c_0 = c1; the ring
+// closes if c_n = c_0. Also returns the aggregation coefficient mu_P
+fn challenges(
+    ctx: &ClsagContext,
+    pre: &PreSignature,
+    statement: Option<&Statement>,
+) -> Option<(Vec<Scalar>, Scalar)> {
+    let n = ctx.ring.len();
+
+    if n == 0 || pre.s.len() != n || ctx.index >= n || ctx.image.is_identity() {
+        return None;
+    }
+
+    let D = pre.D.mul_by_cofactor();
+    if D.is_identity() {
+        return None;
+    }
+
+    let transcript = Transcript::new(ctx, &(D * inv_eight()));
+
+    let mut c = vec![pre.c1];
+    for i in 0..n {
+        c.push(transcript.next(ctx, i, &D, &pre.s[i], &c[i], statement));
+    }
+
+    Some((c, transcript.mu_P))
+}
+
+/// Pre-signs `ctx` with `key` = x - y, where x is the key of the real input, and `z` the
+/// difference between the mask of its commitment and the one of the pseudo output.
+pub fn pre_sign<R: RngCore + CryptoRng>(
+    rng: &mut R,
+    ctx: &ClsagContext,
+    key: &Scalar,
+    z: &Scalar,
+) -> PreSignature {
+    let n = ctx.ring.len();
+    let l = ctx.index;
+
+    let H = hash_to_point(ctx.ring[l][0]);
+    let D = z * H;
+
+    let transcript = Transcript::new(ctx, &(D * inv_eight()));
+
+    let mut s: Vec<Scalar> = (0..n).map(|_| random_scalar(rng)).collect();
+    let alpha = Zeroizing::new(random_scalar(rng));
+
+    let L = &*alpha * &ED25519_BASEPOINT_TABLE;
+    let (c1, c) = transcript.around_ring(ctx, &D, &s, &L, &(*alpha * H));
+
+    s[l] = *alpha - c * (transcript.mu_P * key + transcript.mu_C * z);
+
+    PreSignature {
+        D: D * inv_eight(),
+        s,
+        c1,
+    }
+}
+
+/// Checks that `pre` becomes a valid signature once adapted with the witness of `statement`.
+pub fn pre_verify(ctx: &ClsagContext, pre: &PreSignature, statement: &Statement) -> bool {
+    match challenges(ctx, pre, Some(statement)) {
+        Some((c, _)) => c[ctx.ring.len()] == c[0],
+        None => false,
+    }
+}
+
+/// Completes `pre` with the witness `y`, into a CLSAG that verifies for `ctx`.
+pub fn adapt(ctx: &ClsagContext, pre: &PreSignature, y: &Scalar) -> Result<Clsag, Error> {
+    let (c, mu_P) = challenges(ctx, pre, None).ok_or(Error::InvalidPreSignature)?;
+
+    let mut s = pre.s.clone();
+    s[ctx.index] -= mu_P * c[ctx.index] * y;
+
+    Ok(Clsag {
+        D: pre.D,
+        s,
+        c1: pre.c1,
+    })
+}
+
+/// Recovers the witness of `statement` from a pre-signature and its adapted signature.
+pub fn extract(
+    ctx: &ClsagContext,
+    pre: &PreSignature,
+    signature: &Clsag,
+    statement: &Statement,
+) -> Result<Scalar, Error> {
+    let (c, mu_P) = challenges(ctx, pre, None).ok_or(Error::InvalidPreSignature)?;
+
+    let l = ctx.index;
+
+    // only the response of the real member is different
+    let same_members = (0..ctx.ring.len())
+        .filter(|i| *i != l)
+        .all(|i| pre.s.get(i) == signature.s.get(i));
+
+    if signature.D != pre.D || signature.c1 != pre.c1 || !same_members {
+        return Err(Error::UnrelatedSignature);
+    }
+
+    let s_l = signature.s.get(l).ok_or(Error::UnrelatedSignature)?;
+    let y = (pre.s[l] - s_l) * (mu_P * c[l]).invert();
+
+    if &y * &ED25519_BASEPOINT_TABLE != statement.Y {
+        return Err(Error::UnrelatedSignature);
+    }
+
+    Ok(y)
+}
+
+impl From<&PreSignature> for msgs::PreSignature {
+    fn from(pre: &PreSignature) -> Self {
+        msgs::PreSignature {
+            d: pre.D.compress().to_bytes().to_vec(),
+            s: pre.s.iter().map(|s| s.to_bytes().to_vec()).collect(),
+            c1: pre.c1.to_bytes().to_vec(),
+        }
+    }
+}
+
+impl TryFrom<msgs::PreSignature> for PreSignature {
+    type Error = Error;
+
+    fn try_from(pre: msgs::PreSignature) -> Result<Self, Self::Error> {
+        let s = pre
+            .s
+            .iter()
+            .map(|s| decode_scalar(s))
+            .collect::<Option<Vec<_>>>();
+
+        Ok(PreSignature {
+            D: decode_point(&pre.d).ok_or(Error::InvalidPreSignature)?,
+            s: s.ok_or(Error::InvalidPreSignature)?,
+            c1: decode_scalar(&pre.c1).ok_or(Error::InvalidPreSignature)?,
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid CLSAG pre-signature")]
+    InvalidPreSignature,
+
+    #[error("The CLSAG signature was not adapted from the pre-signature")]
+    UnrelatedSignature,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use monero_serai::Commitment;
+    use rand_core::OsRng;
+
+    const RING_LEN: usize = 11;
+
+    struct Fixture {
+        ctx: ClsagContext,
+        // x - y
+        key: Scalar,
+        y: Scalar,
+        z: Scalar,
+        statement: Statement,
+    }
+
+    fn fixture(index: usize) -> Fixture {
+        let x = random_scalar(&mut OsRng);
+        let y = random_scalar(&mut OsRng);
+
+        let amount = 1_000_000;
+        let mask = random_scalar(&mut OsRng);
+        let pseudo_mask = random_scalar(&mut OsRng);
+
+        let mut ring: Vec<[EdwardsPoint; 2]> = (0..RING_LEN)
+            .map(|_| {
+                [
+                    &random_scalar(&mut OsRng) * &ED25519_BASEPOINT_TABLE,
+                    &random_scalar(&mut OsRng) * &ED25519_BASEPOINT_TABLE,
+                ]
+            })
+            .collect();
+
+        let P = &x * &ED25519_BASEPOINT_TABLE;
+        ring[index] = [P, Commitment::new(mask, amount).calculate()];
+
+        let ctx = ClsagContext {
+            ring,
+            index,
+            image: x * hash_to_point(P),
+            pseudo_out: Commitment::new(pseudo_mask, amount).calculate(),
+            msg: random_scalar(&mut OsRng).to_bytes(),
+        };
+
+        Fixture {
+            ctx,
+            key: x - y,
+            y,
+            z: mask - pseudo_mask,
+            statement: Statement::new(&y, &P),
+        }
+    }
+
+    fn verify(ctx: &ClsagContext, signature: &Clsag) -> bool {
+        signature
+            .verify(&ctx.ring, &ctx.image, &ctx.pseudo_out, &ctx.msg)
+            .is_ok()
+    }
+
+    #[test]
+    fn pre_sign_adapt_and_extract() {
+        for index in [0, 5, RING_LEN - 1] {
+            let f = fixture(index);
+
+            let pre = pre_sign(&mut OsRng, &f.ctx, &f.key, &f.z);
+            assert!(pre_verify(&f.ctx, &pre, &f.statement));
+
+            // not a valid signature on its own
+            let unadapted = Clsag {
+                D: pre.D,
+                s: pre.s.clone(),
+                c1: pre.c1,
+            };
+            assert!(!verify(&f.ctx, &unadapted));
+
+            let signature = adapt(&f.ctx, &pre, &f.y).unwrap();
+            assert!(verify(&f.ctx, &signature));
+
+            assert_eq!(
+                extract(&f.ctx, &pre, &signature, &f.statement).unwrap(),
+                f.y
+            );
+        }
+    }
+
+    #[test]
+    fn pre_verify_rejects_other_statements() {
+        let f = fixture(3);
+        let pre = pre_sign(&mut OsRng, &f.ctx, &f.key, &f.z);
+
+        let other = Statement::new(&random_scalar(&mut OsRng), &f.ctx.ring[3][0]);
+        assert!(!pre_verify(&f.ctx, &pre, &other));
+
+        // Y_H not consistent with Y
+        let inconsistent = Statement {
+            Y_H: f.statement.Y_H + ED25519_BASEPOINT_TABLE.basepoint(),
+            ..f.statement
+        };
+        assert!(!pre_verify(&f.ctx, &pre, &inconsistent));
+
+        let mut tampered = pre.clone();
+        tampered.s[0] += Scalar::one();
+        assert!(!pre_verify(&f.ctx, &tampered, &f.statement));
+
+        let mut other_msg = f.ctx.clone();
+        other_msg.msg[0] ^= 1;
+        assert!(!pre_verify(&other_msg, &pre, &f.statement));
+    }
+
+    #[test]
+    fn extract_rejects_unrelated_signatures() {
+        let f = fixture(7);
+        let pre = pre_sign(&mut OsRng, &f.ctx, &f.key, &f.z);
+
+        // a signature adapted from another pre-signature of the same input
+        let other = pre_sign(&mut OsRng, &f.ctx, &f.key, &f.z);
+        let signature = adapt(&f.ctx, &other, &f.y).unwrap();
+        assert!(verify(&f.ctx, &signature));
+
+        assert!(matches!(
+            extract(&f.ctx, &pre, &signature, &f.statement),
+            Err(Error::UnrelatedSignature)
+        ));
+
+        // adapted with the wrong witness
+        let signature = adapt(&f.ctx, &pre, &random_scalar(&mut OsRng)).unwrap();
+        assert!(matches!(
+            extract(&f.ctx, &pre, &signature, &f.statement),
+            Err(Error::UnrelatedSignature)
+        ));
+    }
+
+    #[test]
+    fn pre_signature_msgs_roundtrip() {
+        let f = fixture(1);
+        let pre = pre_sign(&mut OsRng, &f.ctx, &f.key, &f.z);
+
+        let msg = msgs::PreSignature::from(&pre);
+        let decoded = PreSignature::try_from(msg.clone()).unwrap();
+        assert_eq!(decoded, pre);
+        assert!(pre_verify(&f.ctx, &decoded, &f.statement));
+
+        let mut invalid = msg;
+        invalid.s[4] = vec![0xff; 32];
+        assert!(matches!(
+            PreSignature::try_from(invalid),
+            Err(Error::InvalidPreSignature)
+        ));
+    }
+}
@@ -0,0 +1,133 @@
+use std::path::{Path, PathBuf};
+
+use prost::Message;
+use zeroize::Zeroizing;
+
+use super::sealed::{self, Format, KdfCost, SealingKey, DEFAULT_KDF_COST};
+use crate::msgs;
+
+pub const VERSION: u32 = 1;
+
+// so that a keystore is never read as a backup
+const FORMAT: Format = Format {
+    domain: b"PayMo channel backup",
+    version: VERSION,
+};
+
+/// Writes `backup` to a new file at `path`, encrypted under a key derived from `passphrase`.
+pub fn write(path: &Path, backup: &msgs::ChannelBackup, passphrase: &str) -> Result<(), Error> {
+    write_with_cost(path, backup, passphrase, DEFAULT_KDF_COST)
+}
+
+pub fn write_with_cost(
+    path: &Path,
+    backup: &msgs::ChannelBackup,
+    passphrase: &str,
+    cost: KdfCost,
+) -> Result<(), Error> {
+    if path.exists() {
+        return Err(Error::AlreadyExists(path.into()));
+    }
+
+    let plaintext = Zeroizing::new(backup.encode_to_vec());
+    SealingKey::new(passphrase, cost)?.seal(FORMAT, path, &plaintext)?;
+
+    Ok(())
+}
+
+/// Decrypts the backup at `path`; as with the keystore, a wrong passphrase and a modified
+/// backup fail the same way. The caller zeroizes the secrets of the backup once it is done
+/// with them.
+pub fn read(path: &Path, passphrase: &str) -> Result<msgs::ChannelBackup, Error> {
+    let (plaintext, _) = sealed::open(FORMAT, path, passphrase)?;
+
+    msgs::ChannelBackup::decode(plaintext.as_slice()).map_err(|_| Error::Corrupted(path.into()))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Sealed(#[from] sealed::Error),
+
+    #[error("A file already exists at {0}")]
+    AlreadyExists(PathBuf),
+
+    #[error("Corrupted backup: {0}")]
+    Corrupted(PathBuf),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::core::utils::temp_dir;
+
+    // cheap enough for tests
+    const TEST_COST: KdfCost = KdfCost {
+        m_cost: 64,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    fn backup() -> msgs::ChannelBackup {
+        msgs::ChannelBackup {
+            channel: Some(msgs::StoredChannel {
+                alice_address: "alice".into(),
+                ..Default::default()
+            }),
+            secret: vec![7; 32],
+            checkpoints: vec![msgs::SolverCheckpoint {
+                digest: vec![1; 32],
+                checkpoint: vec![2; 48],
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn backups_are_read() {
+        let dir = temp_dir();
+        let path = dir.join("channel.backup");
+        write_with_cost(&path, &backup(), "passphrase", TEST_COST).unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        assert!(!bytes.windows(32).any(|window| window == [7; 32]));
+
+        assert_eq!(read(&path, "passphrase").unwrap(), backup());
+        assert!(matches!(
+            read(&path, "passphrasf"),
+            Err(Error::Sealed(sealed::Error::WrongPassphrase))
+        ));
+
+        // a backup is never overwritten
+        assert!(matches!(
+            write_with_cost(&path, &backup(), "passphrase", TEST_COST),
+            Err(Error::AlreadyExists(_))
+        ));
+    }
+
+    #[test]
+    fn modified_backups_are_rejected() {
+        let dir = temp_dir();
+        let path = dir.join("channel.backup");
+        write_with_cost(&path, &backup(), "passphrase", TEST_COST).unwrap();
+        let file = msgs::SealedFile::decode(fs::read(&path).unwrap().as_slice()).unwrap();
+
+        let mut flipped = file.clone();
+        flipped.ciphertext[0] ^= 1;
+        fs::write(&path, flipped.encode_to_vec()).unwrap();
+        assert!(matches!(
+            read(&path, "passphrase"),
+            Err(Error::Sealed(sealed::Error::WrongPassphrase))
+        ));
+
+        let mut newer = file;
+        newer.version = VERSION + 1;
+        fs::write(&path, newer.encode_to_vec()).unwrap();
+        assert!(matches!(
+            read(&path, "passphrase"),
+            Err(Error::Sealed(sealed::Error::UnsupportedVersion(_)))
+        ));
+    }
+}
@@ -0,0 +1,250 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use rand_core::OsRng;
+
+use super::classgroup;
+use super::lhtlp;
+use super::timelock::{Backend, TimeLockParams, TimeLockPuzzle};
+
+/// Target time between two Monero blocks.
+pub const BLOCK_TIME: Duration = Duration::from_secs(120);
+
+/// How many times faster than the machine that measured them the puzzles may be solved,
+/// by default.
+pub const DEFAULT_SAFETY_MARGIN: f64 = 2.0;
+
+// how long the squarings are benchmarked for
+const BENCHMARK_TIME: Duration = Duration::from_millis(500);
+const BENCHMARK_STEPS: u64 = 1 << 8;
+
+/// Time parameter of the puzzles: either the number of squarings itself, or how long the
+/// puzzles should take to solve, as a duration (`90s`, `30m`, `6h`, `2d`) or as a number of
+/// blocks (`720blocks`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeSpec {
+    Squarings(u64),
+    Duration(Duration),
+    Blocks(u64),
+}
+
+impl TimeSpec {
+    /// None for a number of squarings, which depends on the machine.
+    pub fn duration(&self) -> Option<Duration> {
+        match *self {
+            TimeSpec::Squarings(_) => None,
+            TimeSpec::Duration(duration) => Some(duration),
+            TimeSpec::Blocks(blocks) => Some(blocks_duration(blocks)),
+        }
+    }
+}
+
+fn blocks_duration(blocks: u64) -> Duration {
+    Duration::from_secs(BLOCK_TIME.as_secs().saturating_mul(blocks))
+}
+
+impl FromStr for TimeSpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidTimeSpec(s.to_string());
+
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (amount, unit) = s.split_at(split);
+        let amount: u64 = amount.parse().map_err(|_| invalid())?;
+
+        let seconds = match unit {
+            "" => return Ok(TimeSpec::Squarings(amount)),
+            "blocks" => return Ok(TimeSpec::Blocks(amount)),
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+
+        let seconds = amount.checked_mul(seconds).ok_or_else(invalid)?;
+
+        Ok(TimeSpec::Duration(Duration::from_secs(seconds)))
+    }
+}
+
+impl fmt::Display for TimeSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeSpec::Squarings(squarings) => write!(f, "{squarings} squarings"),
+            TimeSpec::Duration(duration) => write!(f, "{}", format_duration(*duration)),
+            TimeSpec::Blocks(blocks) => write!(f, "{blocks} blocks"),
+        }
+    }
+}
+
+/// How fast this machine performs the sequential squarings of a time-lock puzzle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Calibration {
+    pub squarings_per_second: f64,
+}
+
+impl Calibration {
+    /// Benchmarks the squarings of a puzzle set up as the ones of a VTD-Log with the given
+    /// parameters, i.e. with the same modulus or discriminant size; takes about a second,
+    /// most of it generating the puzzle.
+    pub fn measure(params: &TimeLockParams) -> Self {
+        match params.backend {
+            Backend::Lhtlp => Self::measure_with::<lhtlp::Puzzle>(params),
+            Backend::ClassGroup => Self::measure_with::<classgroup::Puzzle>(params),
+        }
+    }
+
+    fn measure_with<P: TimeLockPuzzle>(params: &TimeLockParams) -> Self {
+        let (puzzle, _) = P::generate(&mut OsRng, params, 1, &[&[1]]).remove(0);
+
+        let mut state = puzzle.squarings_start();
+        let mut steps = 0;
+
+        let start = Instant::now();
+        while start.elapsed() < BENCHMARK_TIME {
+            state = puzzle
+                .square(&state, BENCHMARK_STEPS)
+                .expect("squarings of a generated puzzle");
+            steps += BENCHMARK_STEPS;
+        }
+
+        Self {
+            squarings_per_second: steps as f64 / start.elapsed().as_secs_f64(),
+        }
+    }
+
+    /// Number of squarings for `time`. Durations are multiplied by `margin`, so that a solver
+    /// `margin` times faster than this machine still needs the whole duration; solving the
+    /// puzzles here then takes `margin` times longer.
+    pub fn squarings(&self, time: &TimeSpec, margin: f64) -> u64 {
+        let duration = match *time {
+            TimeSpec::Squarings(squarings) => return squarings,
+            TimeSpec::Duration(duration) => duration,
+            TimeSpec::Blocks(blocks) => blocks_duration(blocks),
+        };
+
+        (duration.as_secs_f64() * margin * self.squarings_per_second).ceil() as u64
+    }
+
+    /// Expected time to solve a puzzle with `squarings` squarings on this machine.
+    pub fn solve_time(&self, squarings: u64) -> Duration {
+        Duration::from_secs_f64(squarings as f64 / self.squarings_per_second)
+    }
+}
+
+/// Duration rounded to its two largest units, e.g. `6h 12m`.
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+
+    let units = [
+        (seconds / (24 * 60 * 60), "d"),
+        (seconds / (60 * 60) % 24, "h"),
+        (seconds / 60 % 60, "m"),
+        (seconds % 60, "s"),
+    ];
+
+    let parts: Vec<String> = units
+        .iter()
+        .skip_while(|(amount, _)| *amount == 0)
+        .take(2)
+        .filter(|(amount, _)| *amount != 0)
+        .map(|(amount, unit)| format!("{amount}{unit}"))
+        .collect();
+
+    if parts.is_empty() {
+        return "0s".to_string();
+    }
+
+    parts.join(" ")
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid time {0:?}: expected squarings, a duration such as 6h, or blocks such as 720blocks")]
+    InvalidTimeSpec(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_time_specs() {
+        let parse = |s: &str| s.parse::<TimeSpec>().ok();
+
+        assert_eq!(parse("1000"), Some(TimeSpec::Squarings(1000)));
+        assert_eq!(parse("720blocks"), Some(TimeSpec::Blocks(720)));
+        assert_eq!(
+            parse("90s"),
+            Some(TimeSpec::Duration(Duration::from_secs(90)))
+        );
+        assert_eq!(
+            parse("30m"),
+            Some(TimeSpec::Duration(Duration::from_secs(1800)))
+        );
+        assert_eq!(
+            parse("6h"),
+            Some(TimeSpec::Duration(Duration::from_secs(21600)))
+        );
+        assert_eq!(
+            parse("2d"),
+            Some(TimeSpec::Duration(Duration::from_secs(172800)))
+        );
+
+        for s in [
+            "",
+            "h",
+            "6x",
+            "-6h",
+            "6 h",
+            "6hours",
+            "1.5h",
+            "99999999999999999d",
+        ] {
+            assert_eq!(parse(s), None, "{s}");
+        }
+    }
+
+    #[test]
+    fn convert_time_specs() {
+        let calibration = Calibration {
+            squarings_per_second: 1000.0,
+        };
+
+        assert_eq!(calibration.squarings(&TimeSpec::Squarings(1234), 2.0), 1234);
+        assert_eq!(
+            calibration.squarings(&"1h".parse().unwrap(), 1.0),
+            3_600_000
+        );
+        assert_eq!(
+            calibration.squarings(&"1h".parse().unwrap(), 2.5),
+            9_000_000
+        );
+        assert_eq!(
+            calibration.squarings(&"30blocks".parse().unwrap(), 1.0),
+            3_600_000
+        );
+
+        assert_eq!(calibration.solve_time(3_600_000), Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn format_durations() {
+        let format = |seconds| format_duration(Duration::from_secs(seconds));
+
+        assert_eq!(format(0), "0s");
+        assert_eq!(format(59), "59s");
+        assert_eq!(format(3600), "1h");
+        assert_eq!(format(6 * 3600 + 12 * 60 + 5), "6h 12m");
+        assert_eq!(format(2 * 86400 + 30), "2d");
+    }
+
+    #[test]
+    fn measure_squarings() {
+        let calibration = Calibration::measure(&TimeLockParams::MIN);
+        assert!(calibration.squarings_per_second > 0.0);
+    }
+}
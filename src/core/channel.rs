@@ -1,6 +1,774 @@
 // TODO section 6.3, figure 7
 
-// TODO OpChannel
-// TODO Pay
-// TODO ClChannel
-// TODO all protocol
+use std::mem;
+
+use curve25519_dalek::{
+    constants::ED25519_BASEPOINT_TABLE, edwards::EdwardsPoint, scalar::Scalar, traits::IsIdentity,
+};
+use monero_serai::{transaction::Transaction, wallet::address::MoneroAddress};
+use zeroize::Zeroizing;
+
+use super::jspend::JointOutput;
+use super::utils::{generate_user_tag, hash};
+use super::vtdlog::VtdlogParams;
+use super::{JointWallet, Role};
+use crate::msgs;
+
+/// State of an open channel: how much of the channel amount Alice paid Bob so far, and how
+/// many payments that took. Every payment replaces the previous state, and the channel closes
+/// with the last one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChannelState {
+    pub number: u64,
+    /// Cumulative amount paid to Bob, in piconeros.
+    pub bob_amount: u64,
+}
+
+impl ChannelState {
+    /// State after Alice pays Bob `amount` more, out of `channel_amount`.
+    pub fn pay(&self, amount: u64, channel_amount: u64) -> Result<Self, Error> {
+        if amount == 0 {
+            return Err(Error::InvalidAmount);
+        }
+
+        let bob_amount = self
+            .bob_amount
+            .checked_add(amount)
+            .filter(|bob_amount| *bob_amount <= channel_amount)
+            .ok_or(Error::InsufficientBalance(channel_amount - self.bob_amount))?;
+
+        Ok(Self {
+            number: self.number + 1,
+            bob_amount,
+        })
+    }
+
+    /// Checks that `next` is the state right after this one, and that it pays Bob more.
+    pub fn check_next(&self, next: &Self, channel_amount: u64) -> Result<(), Error> {
+        if next.number != self.number + 1 {
+            return Err(Error::UnexpectedNumber(self.number + 1, next.number));
+        }
+
+        if next.bob_amount <= self.bob_amount || next.bob_amount > channel_amount {
+            return Err(Error::InvalidAmount);
+        }
+
+        Ok(())
+    }
+
+    /// What is left to Alice, before the fee of the closing transaction.
+    pub fn alice_amount(&self, channel_amount: u64) -> u64 {
+        channel_amount - self.bob_amount
+    }
+}
+
+impl From<&ChannelState> for msgs::ChannelState {
+    fn from(state: &ChannelState) -> Self {
+        msgs::ChannelState {
+            number: state.number,
+            bob_amount: state.bob_amount,
+        }
+    }
+}
+
+impl From<msgs::ChannelState> for ChannelState {
+    fn from(state: msgs::ChannelState) -> Self {
+        ChannelState {
+            number: state.number,
+            bob_amount: state.bob_amount,
+        }
+    }
+}
+
+/// Parameters of a channel, which Alice proposes and Bob accepts.
+#[derive(Clone, Debug)]
+pub struct Params {
+    pub channel_amount: monero::Amount,
+    /// Squarings of the time-lock puzzles.
+    pub time: u64,
+    pub confirmations: u32,
+    pub vtdlog_params: VtdlogParams,
+}
+
+/// Our key pair in the channel, and our share of the joint view key; they are derived from
+/// the seed of the keystore with `index`, see [`super::seed::MasterSeed::channel_keys`].
+#[derive(Clone, Debug)]
+pub struct OwnKeys {
+    pub index: u32,
+    pub secret: Zeroizing<Scalar>,
+    pub public_key: EdwardsPoint,
+    pub view_secret: Zeroizing<Scalar>,
+}
+
+impl OwnKeys {
+    /// Hash of the public key, which commits to it before the public keys are exchanged.
+    pub fn hash(&self) -> [u8; 32] {
+        hash(self.public_key.compress().as_bytes())
+    }
+}
+
+/// What the parties agree on before exchanging their public keys; each of them is only
+/// received once.
+#[derive(Debug, Default)]
+pub struct Negotiation {
+    params: Option<Params>,
+    alice_address: Option<MoneroAddress>,
+    bob_address: Option<MoneroAddress>,
+    keys: Option<OwnKeys>,
+    their_hash: Option<Vec<u8>>,
+}
+
+impl Negotiation {
+    pub fn params(&self) -> Result<&Params, Error> {
+        get(&self.params, "the channel parameters")
+    }
+
+    pub fn set_params(&mut self, params: Params) -> Result<(), Error> {
+        set(&mut self.params, params, "the channel parameters")
+    }
+
+    pub fn address(&self, role: &Role) -> Result<&MoneroAddress, Error> {
+        match role {
+            Role::Alice => get(&self.alice_address, "Alice's address"),
+            Role::Bob => get(&self.bob_address, "Bob's address"),
+        }
+    }
+
+    pub fn set_address(&mut self, role: &Role, address: MoneroAddress) -> Result<(), Error> {
+        match role {
+            Role::Alice => set(&mut self.alice_address, address, "Alice's address"),
+            Role::Bob => set(&mut self.bob_address, address, "Bob's address"),
+        }
+    }
+
+    pub fn keys(&self) -> Result<&OwnKeys, Error> {
+        get(&self.keys, "our keys")
+    }
+
+    pub fn set_keys(&mut self, keys: OwnKeys) -> Result<(), Error> {
+        set(&mut self.keys, keys, "our keys")
+    }
+
+    /// Hash the other party committed to their public key with.
+    pub fn their_hash(&self) -> Result<&[u8], Error> {
+        get(&self.their_hash, "the hash of the other party").map(Vec::as_slice)
+    }
+
+    pub fn set_their_hash(&mut self, hash: Vec<u8>) -> Result<(), Error> {
+        set(&mut self.their_hash, hash, "the hash of the other party")
+    }
+}
+
+fn get<'a, T>(value: &'a Option<T>, name: &'static str) -> Result<&'a T, Error> {
+    value.as_ref().ok_or(Error::Missing(name))
+}
+
+fn set<T>(value: &mut Option<T>, new: T, name: &'static str) -> Result<(), Error> {
+    if value.is_some() {
+        return Err(Error::Duplicate(name));
+    }
+
+    *value = Some(new);
+
+    Ok(())
+}
+
+/// The channel once both public keys are verified, so that the joint address is known; the
+/// tags and Bob's VTD-Log are exchanged next.
+#[derive(Debug)]
+pub struct Keys {
+    pub role: Role,
+    pub params: Params,
+
+    pub alice_address: MoneroAddress,
+    pub bob_address: MoneroAddress,
+
+    pub own: OwnKeys,
+    pub alice_public_key: EdwardsPoint,
+    pub bob_public_key: EdwardsPoint,
+    pub joint_public_key: EdwardsPoint,
+    pub joint_wallet: JointWallet,
+
+    /// Our tag, the key image of the joint public key with our secret.
+    pub tag: EdwardsPoint,
+    /// Once the tag of the other party is proven. It is not the key image of the joint output,
+    /// whose key also has the key offset of the output, only known once it is found.
+    pub joint_tag: Option<EdwardsPoint>,
+    /// Once Bob's VTD-Log is opened to Alice, so that she can be refunded.
+    pub vtdlog_opened: bool,
+    /// Height of the chain when the keys were exchanged: the joint output pays an address
+    /// derived from them, so it cannot be in a block below it.
+    pub from_height: usize,
+}
+
+impl Keys {
+    /// The joint address is on the network of our own address; the tags are not exchanged yet.
+    pub fn new(
+        role: Role,
+        params: Params,
+        alice_address: MoneroAddress,
+        bob_address: MoneroAddress,
+        own: OwnKeys,
+        their_public_key: EdwardsPoint,
+        their_view_secret: Scalar,
+    ) -> Self {
+        let (alice_public_key, bob_public_key, alice_view, bob_view, network) = match role {
+            Role::Alice => (
+                own.public_key,
+                their_public_key,
+                *own.view_secret,
+                their_view_secret,
+                alice_address.meta.network,
+            ),
+            Role::Bob => (
+                their_public_key,
+                own.public_key,
+                their_view_secret,
+                *own.view_secret,
+                bob_address.meta.network,
+            ),
+        };
+
+        let joint_public_key = alice_public_key + bob_public_key;
+        let joint_wallet = JointWallet::new(
+            &alice_public_key,
+            &bob_public_key,
+            &alice_view,
+            &bob_view,
+            network,
+        );
+
+        Self {
+            role,
+            params,
+            alice_address,
+            bob_address,
+            tag: generate_user_tag(&joint_public_key, &own.secret),
+            own,
+            alice_public_key,
+            bob_public_key,
+            joint_public_key,
+            joint_wallet,
+            joint_tag: None,
+            vtdlog_opened: false,
+            from_height: 0,
+        }
+    }
+
+    /// Public key and share of the joint view key of the other party.
+    pub fn theirs(&self) -> (EdwardsPoint, Scalar) {
+        let view = self.joint_wallet.view_key() - *self.own.view_secret;
+
+        match self.role {
+            Role::Alice => (self.bob_public_key, view),
+            Role::Bob => (self.alice_public_key, view),
+        }
+    }
+
+    /// Whether the channel can be funded: Alice can then be refunded if Bob disappears.
+    pub fn is_set_up(&self) -> bool {
+        self.joint_tag.is_some() && self.vtdlog_opened
+    }
+}
+
+/// The channel once its joint output is on chain.
+#[derive(Debug)]
+pub struct Funding {
+    pub keys: Keys,
+    pub joint_tag: EdwardsPoint,
+    pub output: JointOutput,
+    /// Height of the block with the joint output.
+    pub height: usize,
+    /// Key image of the joint output, once the shares of both parties are known: from the
+    /// first payment, or Alice's refund.
+    pub image: Option<EdwardsPoint>,
+}
+
+/// The channel being closed, with the state it pays and, once signed, its closing transaction.
+#[derive(Debug)]
+pub struct Closing {
+    pub state: ChannelState,
+    /// The closing transaction cannot be in a block below it.
+    pub from_height: usize,
+    pub tx: Option<Transaction>,
+}
+
+/// Phase of a channel. It only moves forward, and every message of the protocol is only
+/// expected in some of them, so that out of order or duplicated messages are rejected.
+// a client has a single channel, so its phases are not boxed
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum Phase {
+    Negotiating(Negotiation),
+    KeysExchanged(Keys),
+    Funded(Funding),
+    /// Once the joint output has the confirmations of the channel.
+    Open(Funding),
+    Closing(Funding, Closing),
+    Closed(Funding, ChannelState),
+    Refunded(Funding),
+}
+
+impl Phase {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::Negotiating(_) => "negotiating",
+            Phase::KeysExchanged(_) => "keys exchanged",
+            Phase::Funded(_) => "funded",
+            Phase::Open(_) => "open",
+            Phase::Closing(..) => "closing",
+            Phase::Closed(..) => "closed",
+            Phase::Refunded(_) => "refunded",
+        }
+    }
+}
+
+impl Default for Phase {
+    fn default() -> Self {
+        Phase::Negotiating(Negotiation::default())
+    }
+}
+
+/// A channel, as one of its parties sees it.
+#[derive(Debug)]
+pub struct Channel {
+    role: Role,
+    phase: Phase,
+}
+
+impl Channel {
+    pub fn new(role: Role, negotiation: Negotiation) -> Self {
+        Self {
+            role,
+            phase: Phase::Negotiating(negotiation),
+        }
+    }
+
+    /// A channel restored in `phase`, e.g. from the channel database.
+    pub fn from_phase(role: Role, phase: Phase) -> Self {
+        Self { role, phase }
+    }
+
+    pub fn role(&self) -> &Role {
+        &self.role
+    }
+
+    pub fn phase(&self) -> &Phase {
+        &self.phase
+    }
+
+    /// Once it is closed or refunded.
+    pub fn is_closed(&self) -> bool {
+        matches!(self.phase, Phase::Closed(..) | Phase::Refunded(_))
+    }
+
+    pub fn negotiation(&self) -> Result<&Negotiation, Error> {
+        match &self.phase {
+            Phase::Negotiating(negotiation) => Ok(negotiation),
+            phase => Err(Error::UnexpectedPhase(phase.name())),
+        }
+    }
+
+    pub fn negotiation_mut(&mut self) -> Result<&mut Negotiation, Error> {
+        match &mut self.phase {
+            Phase::Negotiating(negotiation) => Ok(negotiation),
+            phase => Err(Error::UnexpectedPhase(phase.name())),
+        }
+    }
+
+    pub fn keys(&self) -> Result<&Keys, Error> {
+        match &self.phase {
+            Phase::Negotiating(_) => Err(Error::UnexpectedPhase(self.phase.name())),
+            Phase::KeysExchanged(keys) => Ok(keys),
+            _ => Ok(&self.funding()?.keys),
+        }
+    }
+
+    pub fn funding(&self) -> Result<&Funding, Error> {
+        match &self.phase {
+            Phase::Funded(funding)
+            | Phase::Open(funding)
+            | Phase::Closing(funding, _)
+            | Phase::Closed(funding, _)
+            | Phase::Refunded(funding) => Ok(funding),
+            phase => Err(Error::UnexpectedPhase(phase.name())),
+        }
+    }
+
+    pub fn closing(&self) -> Result<&Closing, Error> {
+        match &self.phase {
+            Phase::Closing(_, closing) => Ok(closing),
+            phase => Err(Error::UnexpectedPhase(phase.name())),
+        }
+    }
+
+    pub fn closing_mut(&mut self) -> Result<&mut Closing, Error> {
+        match &mut self.phase {
+            Phase::Closing(_, closing) => Ok(closing),
+            phase => Err(Error::UnexpectedPhase(phase.name())),
+        }
+    }
+
+    pub fn params(&self) -> Result<&Params, Error> {
+        match &self.phase {
+            Phase::Negotiating(negotiation) => negotiation.params(),
+            _ => Ok(&self.keys()?.params),
+        }
+    }
+
+    pub fn address(&self, role: &Role) -> Result<&MoneroAddress, Error> {
+        let keys = match &self.phase {
+            Phase::Negotiating(negotiation) => return negotiation.address(role),
+            _ => self.keys()?,
+        };
+
+        Ok(match role {
+            Role::Alice => &keys.alice_address,
+            Role::Bob => &keys.bob_address,
+        })
+    }
+
+    pub fn own_keys(&self) -> Result<&OwnKeys, Error> {
+        match &self.phase {
+            Phase::Negotiating(negotiation) => negotiation.keys(),
+            _ => Ok(&self.keys()?.own),
+        }
+    }
+
+    /// Moves to [`Phase::KeysExchanged`] with the verified public key and view secret of the
+    /// other party, at `height`, the current height.
+    pub fn exchange_keys(
+        &mut self,
+        their_public_key: EdwardsPoint,
+        their_view_secret: Scalar,
+        height: usize,
+    ) -> Result<&Keys, Error> {
+        let negotiation = self.negotiation()?;
+
+        let mut keys = Keys::new(
+            self.role.clone(),
+            negotiation.params()?.clone(),
+            *negotiation.address(&Role::Alice)?,
+            *negotiation.address(&Role::Bob)?,
+            negotiation.keys()?.clone(),
+            their_public_key,
+            their_view_secret,
+        );
+        keys.from_height = height;
+        self.phase = Phase::KeysExchanged(keys);
+
+        self.keys()
+    }
+
+    /// Sets the joint tag, the key image of the joint public key, from the tag of the other
+    /// party, once it is proven. Also checks that our own tag and the joint public key are the
+    /// ones of our secret, so that the joint tag is consistent with the joint public key.
+    pub fn set_joint_tag(&mut self, their_tag: EdwardsPoint) -> Result<EdwardsPoint, Error> {
+        let keys = self.keys_exchanged()?;
+        if keys.joint_tag.is_some() {
+            return Err(Error::Duplicate("the tag of the other party"));
+        }
+
+        let joint_public_key = keys.joint_public_key;
+        let joint_tag = keys.tag + their_tag;
+
+        if joint_public_key != keys.alice_public_key + keys.bob_public_key
+            || keys.own.public_key != &*keys.own.secret * &ED25519_BASEPOINT_TABLE
+            || keys.tag != generate_user_tag(&joint_public_key, &keys.own.secret)
+            || joint_tag.is_identity()
+        {
+            return Err(Error::InconsistentJointTag);
+        }
+
+        keys.joint_tag = Some(joint_tag);
+
+        Ok(joint_tag)
+    }
+
+    /// Once Bob opened his VTD-Log to Alice.
+    pub fn set_vtdlog_opened(&mut self) -> Result<(), Error> {
+        let keys = self.keys_exchanged()?;
+        if keys.joint_tag.is_none() {
+            return Err(Error::Missing("the joint tag"));
+        }
+        if keys.vtdlog_opened {
+            return Err(Error::Duplicate("Bob's VTD-Log"));
+        }
+
+        keys.vtdlog_opened = true;
+
+        Ok(())
+    }
+
+    /// Moves to [`Phase::Funded`] once the joint output is found on chain, in the block at
+    /// `height`; only once the channel is set up.
+    pub fn fund(&mut self, output: JointOutput, height: usize) -> Result<(), Error> {
+        match mem::take(&mut self.phase) {
+            Phase::KeysExchanged(keys) if keys.is_set_up() => {
+                self.phase = Phase::Funded(Funding {
+                    joint_tag: keys.joint_tag.unwrap(),
+                    keys,
+                    output,
+                    height,
+                    image: None,
+                });
+
+                Ok(())
+            }
+            Phase::KeysExchanged(keys) => {
+                self.phase = Phase::KeysExchanged(keys);
+                Err(Error::NotSetUp)
+            }
+            phase => Err(self.unexpected(phase)),
+        }
+    }
+
+    /// Moves to [`Phase::Open`] once the joint output has the confirmations of the channel at
+    /// `height`, the current height.
+    pub fn open(&mut self, height: usize) -> Result<(), Error> {
+        let funding = match &self.phase {
+            Phase::Funded(funding) => funding,
+            phase => return Err(Error::UnexpectedPhase(phase.name())),
+        };
+
+        let confirmations = height.saturating_sub(funding.height);
+        let expected = funding.keys.params.confirmations;
+        if confirmations < expected as usize {
+            return Err(Error::Unconfirmed(confirmations, expected));
+        }
+
+        match mem::take(&mut self.phase) {
+            Phase::Funded(funding) => self.phase = Phase::Open(funding),
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    /// Moves to [`Phase::Closing`]; only an open channel is closed.
+    pub fn start_closing(&mut self, closing: Closing) -> Result<(), Error> {
+        match mem::take(&mut self.phase) {
+            Phase::Open(funding) => {
+                self.phase = Phase::Closing(funding, closing);
+                Ok(())
+            }
+            phase => Err(self.unexpected(phase)),
+        }
+    }
+
+    /// Moves to [`Phase::Closed`], once the transaction paying `state` has its confirmations.
+    /// An open channel is also closed when Bob closes it alone.
+    pub fn close(&mut self, state: ChannelState) -> Result<(), Error> {
+        match mem::take(&mut self.phase) {
+            Phase::Open(funding) => {
+                self.phase = Phase::Closed(funding, state);
+                Ok(())
+            }
+            Phase::Closing(funding, closing) if closing.state == state => {
+                self.phase = Phase::Closed(funding, state);
+                Ok(())
+            }
+            phase => Err(self.unexpected(phase)),
+        }
+    }
+
+    /// Sets the key image of the joint output; returns whether it was not known yet. Every
+    /// spend of the joint output has the same one, so a different one is rejected.
+    pub fn set_output_image(&mut self, image: EdwardsPoint) -> Result<bool, Error> {
+        let funding = match &mut self.phase {
+            Phase::Funded(funding) | Phase::Open(funding) | Phase::Closing(funding, _) => funding,
+            phase => return Err(Error::UnexpectedPhase(phase.name())),
+        };
+
+        match funding.image {
+            Some(known) if known == image => Ok(false),
+            Some(_) => Err(Error::InconsistentOutputImage),
+            None => {
+                funding.image = Some(image);
+                Ok(true)
+            }
+        }
+    }
+
+    /// Moves to [`Phase::Refunded`], once Alice spent the joint output alone.
+    pub fn refund(&mut self) -> Result<(), Error> {
+        match mem::take(&mut self.phase) {
+            Phase::Funded(funding) | Phase::Open(funding) => {
+                self.phase = Phase::Refunded(funding);
+                Ok(())
+            }
+            phase => Err(self.unexpected(phase)),
+        }
+    }
+
+    fn keys_exchanged(&mut self) -> Result<&mut Keys, Error> {
+        match &mut self.phase {
+            Phase::KeysExchanged(keys) => Ok(keys),
+            phase => Err(Error::UnexpectedPhase(phase.name())),
+        }
+    }
+
+    // puts back `phase`, which does not allow the transition
+    fn unexpected(&mut self, phase: Phase) -> Error {
+        let err = Error::UnexpectedPhase(phase.name());
+        self.phase = phase;
+
+        err
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Payments must pay Bob more than the previous state, and at most the channel amount")]
+    InvalidAmount,
+
+    #[error("Insufficient balance: at most {0} piconeros can still be paid")]
+    InsufficientBalance(u64),
+
+    #[error("Unexpected state number: expected {0}, got {1}")]
+    UnexpectedNumber(u64, u64),
+
+    #[error("Unexpected message in the {0} phase of the channel")]
+    UnexpectedPhase(&'static str),
+
+    #[error("Duplicated message: {0} is already known")]
+    Duplicate(&'static str),
+
+    #[error("Unexpected message: {0} is not known yet")]
+    Missing(&'static str),
+
+    #[error("The joint tag is not consistent with the joint public key")]
+    InconsistentJointTag,
+
+    #[error("The key image is not the one of the joint output")]
+    InconsistentOutputImage,
+
+    #[error("The channel is not set up yet: the tags and Bob's VTD-Log are not exchanged")]
+    NotSetUp,
+
+    #[error("The joint output has {0} of the {1} confirmations of the channel")]
+    Unconfirmed(usize, u32),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::seed::MasterSeed;
+
+    const CHANNEL_AMOUNT: u64 = 1_000_000;
+
+    #[test]
+    fn payments_update_the_state() {
+        let state = ChannelState::default();
+
+        let next = state.pay(400_000, CHANNEL_AMOUNT).unwrap();
+        assert_eq!(
+            next,
+            ChannelState {
+                number: 1,
+                bob_amount: 400_000
+            }
+        );
+        assert!(state.check_next(&next, CHANNEL_AMOUNT).is_ok());
+        assert_eq!(next.alice_amount(CHANNEL_AMOUNT), 600_000);
+
+        let last = next.pay(600_000, CHANNEL_AMOUNT).unwrap();
+        assert_eq!(last.alice_amount(CHANNEL_AMOUNT), 0);
+
+        assert!(matches!(
+            last.pay(1, CHANNEL_AMOUNT),
+            Err(Error::InsufficientBalance(0))
+        ));
+        assert!(matches!(
+            next.pay(u64::MAX, CHANNEL_AMOUNT),
+            Err(Error::InsufficientBalance(600_000))
+        ));
+        assert!(matches!(
+            next.pay(0, CHANNEL_AMOUNT),
+            Err(Error::InvalidAmount)
+        ));
+    }
+
+    #[test]
+    fn invalid_next_states_are_rejected() {
+        let state = ChannelState {
+            number: 3,
+            bob_amount: 500_000,
+        };
+
+        // replayed, skipped and decreasing states
+        for (number, bob_amount) in [(3, 600_000), (5, 600_000), (4, 500_000), (4, 100_000)] {
+            let next = ChannelState { number, bob_amount };
+            assert!(state.check_next(&next, CHANNEL_AMOUNT).is_err());
+        }
+
+        let next = ChannelState {
+            number: 4,
+            bob_amount: CHANNEL_AMOUNT + 1,
+        };
+        assert!(matches!(
+            state.check_next(&next, CHANNEL_AMOUNT),
+            Err(Error::InvalidAmount)
+        ));
+    }
+
+    #[test]
+    fn negotiation_values_are_received_once() {
+        let mut negotiation = Negotiation::default();
+
+        assert!(matches!(negotiation.their_hash(), Err(Error::Missing(_))));
+        assert!(matches!(negotiation.keys(), Err(Error::Missing(_))));
+
+        negotiation.set_their_hash(vec![1; 32]).unwrap();
+        assert!(matches!(
+            negotiation.set_their_hash(vec![2; 32]),
+            Err(Error::Duplicate(_))
+        ));
+        assert_eq!(negotiation.their_hash().unwrap(), &[1; 32]);
+
+        let (seed, _) = MasterSeed::generate();
+        let keys = seed.channel_keys(0);
+        let hash = keys.hash();
+        negotiation.set_keys(keys).unwrap();
+        assert!(matches!(
+            negotiation.set_keys(seed.channel_keys(1)),
+            Err(Error::Duplicate(_))
+        ));
+        assert_eq!(negotiation.keys().unwrap().hash(), hash);
+    }
+
+    #[test]
+    fn out_of_order_transitions_are_rejected() {
+        let mut channel = Channel::new(Role::Bob, Negotiation::default());
+
+        assert!(matches!(channel.keys(), Err(Error::UnexpectedPhase(_))));
+        assert!(matches!(channel.funding(), Err(Error::UnexpectedPhase(_))));
+        assert!(matches!(
+            channel.set_vtdlog_opened(),
+            Err(Error::UnexpectedPhase(_))
+        ));
+        assert!(matches!(channel.open(100), Err(Error::UnexpectedPhase(_))));
+        assert!(matches!(
+            channel.close(ChannelState::default()),
+            Err(Error::UnexpectedPhase(_))
+        ));
+        assert!(matches!(channel.refund(), Err(Error::UnexpectedPhase(_))));
+
+        // the rejected transitions keep what was negotiated so far
+        channel
+            .negotiation_mut()
+            .unwrap()
+            .set_their_hash(vec![1; 32])
+            .unwrap();
+        assert!(matches!(
+            channel.start_closing(Closing {
+                state: ChannelState::default(),
+                from_height: 0,
+                tx: None,
+            }),
+            Err(Error::UnexpectedPhase("negotiating"))
+        ));
+        assert!(channel.negotiation().unwrap().their_hash().is_ok());
+        assert!(!channel.is_closed());
+    }
+}
@@ -0,0 +1,335 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use log::{debug, warn};
+use sha3::{Digest, Keccak256};
+
+use super::timelock::{self, TimeLockPuzzle};
+use super::utils::atomic_write;
+
+/// How many squarings are done between two checkpoints, by default.
+pub const DEFAULT_INTERVAL: u64 = 1 << 20;
+
+// squarings done between two checks of the cancellation flag and two progress reports;
+// checkpoints are only written at the end of a chunk
+const CHUNK_STEPS: u64 = 1 << 12;
+
+const CHECKSUM_BYTES: usize = 32;
+
+/// Digest of a puzzle, and the contents of its checkpoint file.
+pub type Checkpoint = ([u8; 32], Vec<u8>);
+
+/// How far the solver is into a puzzle, in squarings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    pub done: u64,
+    pub total: u64,
+}
+
+/// Cooperative cancellation of a solve; can be cloned and cancelled from another thread.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Solves time-lock puzzles, saving the state of the squarings in the data directory every
+/// `interval` squarings, so that a solve interrupted by a crash or a cancellation resumes
+/// from its last checkpoint instead of from scratch.
+///
+/// A checkpoint is kept after the puzzle is solved, so that solving it again is immediate;
+/// call [`CheckpointedSolver::remove`] once the secret is no longer needed.
+pub struct CheckpointedSolver {
+    dir: PathBuf,
+    interval: u64,
+    cancel: CancelToken,
+}
+
+impl CheckpointedSolver {
+    /// Checkpoints are kept in `data_dir/checkpoints`, one file per puzzle.
+    pub fn new(data_dir: &Path, cancel: CancelToken) -> Self {
+        Self {
+            dir: data_dir.join("checkpoints"),
+            interval: DEFAULT_INTERVAL,
+            cancel,
+        }
+    }
+
+    pub fn with_interval(mut self, interval: u64) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Same as [`TimeLockPuzzle::solve`], resuming from the checkpoint of `puzzle`, if any;
+    /// `on_progress` is called after every few thousand squarings. Returns
+    /// [`Error::Cancelled`], after saving a checkpoint, if the solve was cancelled.
+    pub fn solve<P: TimeLockPuzzle>(
+        &self,
+        puzzle: &P,
+        mut on_progress: impl FnMut(Progress),
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let path = self.path(puzzle);
+        let total = puzzle.time();
+
+        let (mut done, mut state) = match self.load(puzzle, &path)? {
+            Some(checkpoint) => {
+                debug!("Resuming puzzle from squaring {}/{total}", checkpoint.0);
+                checkpoint
+            }
+            None => (0, puzzle.squarings_start()),
+        };
+        let mut saved = done;
+
+        while done < total {
+            if self.cancel.is_cancelled() {
+                if done > saved {
+                    self.save(&path, done, &state)?;
+                }
+
+                return Err(Error::Cancelled);
+            }
+
+            let steps = CHUNK_STEPS.min(total - done);
+            state = puzzle.square(&state, steps)?;
+            done += steps;
+
+            if done - saved >= self.interval || done == total {
+                self.save(&path, done, &state)?;
+                saved = done;
+            }
+
+            on_progress(Progress { done, total });
+        }
+
+        Ok(puzzle.finish(&state)?)
+    }
+
+    /// Removes the checkpoint of the puzzle with the given [`TimeLockPuzzle::digest`], if any.
+    pub fn remove(&self, digest: &[u8; 32]) -> Result<(), Error> {
+        match fs::remove_file(self.dir.join(hex::encode(digest))) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Every checkpoint, with the digest of its puzzle, to take them to another data directory.
+    pub fn checkpoints(&self) -> Result<Vec<Checkpoint>, Error> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut checkpoints = vec![];
+        for entry in entries {
+            let path = entry?.path();
+
+            // temporary files are named `<digest>.tmp`
+            let digest = path
+                .file_name()
+                .and_then(|name| hex::decode(name.to_string_lossy().as_ref()).ok())
+                .and_then(|digest| <[u8; 32]>::try_from(digest).ok());
+
+            if let Some(digest) = digest {
+                checkpoints.push((digest, fs::read(&path)?));
+            }
+        }
+
+        Ok(checkpoints)
+    }
+
+    /// Adds a checkpoint from [`CheckpointedSolver::checkpoints`]; like any other, it is
+    /// discarded when its puzzle is solved if it is corrupted.
+    pub fn import(&self, digest: &[u8; 32], checkpoint: &[u8]) -> Result<(), Error> {
+        self.write(&self.dir.join(hex::encode(digest)), checkpoint)
+    }
+
+    fn path<P: TimeLockPuzzle>(&self, puzzle: &P) -> PathBuf {
+        self.dir.join(hex::encode(puzzle.digest()))
+    }
+
+    // checksum || done (8 bytes, big-endian) || state; a checkpoint that is corrupted or
+    // ahead of the puzzle is discarded, and the puzzle is solved from scratch
+    fn load<P: TimeLockPuzzle>(
+        &self,
+        puzzle: &P,
+        path: &Path,
+    ) -> Result<Option<(u64, Vec<u8>)>, Error> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        if bytes.len() < CHECKSUM_BYTES + 8 {
+            warn!("Discarding truncated checkpoint {}", path.display());
+            return Ok(None);
+        }
+
+        let (checksum, contents) = bytes.split_at(CHECKSUM_BYTES);
+        if Keccak256::digest(contents)[..] != *checksum {
+            warn!("Discarding corrupted checkpoint {}", path.display());
+            return Ok(None);
+        }
+
+        let (done, state) = contents.split_at(8);
+        let done = u64::from_be_bytes(done.try_into().unwrap());
+
+        if done > puzzle.time() {
+            warn!("Discarding checkpoint {} past the puzzle", path.display());
+            return Ok(None);
+        }
+
+        Ok(Some((done, state.to_vec())))
+    }
+
+    fn save(&self, path: &Path, done: u64, state: &[u8]) -> Result<(), Error> {
+        let mut contents = done.to_be_bytes().to_vec();
+        contents.extend(state);
+
+        let mut checkpoint = Keccak256::digest(&contents).to_vec();
+        checkpoint.extend(contents);
+
+        self.write(path, &checkpoint)
+    }
+
+    // replaces the old checkpoint at once, so that a crash while saving never leaves a
+    // partial checkpoint behind
+    fn write(&self, path: &Path, checkpoint: &[u8]) -> Result<(), Error> {
+        fs::create_dir_all(&self.dir)?;
+        atomic_write(path, checkpoint)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Could not access the checkpoint: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Solve was cancelled")]
+    Cancelled,
+
+    #[error(transparent)]
+    TimeLock(#[from] timelock::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::classgroup;
+    use crate::core::lhtlp::{self, LhtlpParams};
+    use crate::core::utils::temp_dir;
+
+    const TIME: u64 = 3 * CHUNK_STEPS + 100;
+
+    // cancels the solve after the first chunk, then resumes it
+    fn cancel_and_resume<P: TimeLockPuzzle>(puzzle: &mut P, secret: &[u8]) {
+        let data_dir = temp_dir();
+        let cancel = CancelToken::new();
+        let solver = CheckpointedSolver::new(&data_dir, cancel.clone()).with_interval(1 << 30);
+
+        let result = solver.solve(&*puzzle, |_| cancel.cancel());
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert!(solver.path(&*puzzle).exists());
+
+        let solver = CheckpointedSolver::new(&data_dir, CancelToken::new());
+        let mut reports = vec![];
+        let solution = solver.solve(&*puzzle, |progress| reports.push(progress));
+
+        assert_eq!(solution.unwrap().unwrap(), secret);
+        assert_eq!(puzzle.solve().unwrap(), secret);
+        assert_eq!(reports[0].done, 2 * CHUNK_STEPS);
+        assert_eq!(reports.last().unwrap().done, TIME);
+
+        // solved again from the final checkpoint
+        reports.clear();
+        let solution = solver.solve(&*puzzle, |progress| reports.push(progress));
+        assert_eq!(solution.unwrap().unwrap(), secret);
+        assert!(reports.is_empty());
+
+        solver.remove(&puzzle.digest()).unwrap();
+        assert!(!solver.path(&*puzzle).exists());
+    }
+
+    #[test]
+    fn lhtlp_cancel_and_resume() {
+        let secret = b"paymo!";
+        let mut puzzle = lhtlp::Puzzle::single(LhtlpParams::MIN, TIME, secret);
+
+        cancel_and_resume(&mut puzzle, secret);
+    }
+
+    #[test]
+    fn class_group_cancel_and_resume() {
+        let secret = b"paymo!";
+        let mut puzzle = classgroup::Puzzle::single(classgroup::MIN_SECURITY_PARAM, TIME, secret);
+
+        cancel_and_resume(&mut puzzle, secret);
+    }
+
+    #[test]
+    fn checkpoints_are_imported() {
+        let secret = b"paymo!";
+        let puzzle = lhtlp::Puzzle::single(LhtlpParams::MIN, TIME, secret);
+
+        let from = temp_dir();
+        let cancel = CancelToken::new();
+        let solver = CheckpointedSolver::new(&from, cancel.clone()).with_interval(1 << 30);
+        assert!(solver.checkpoints().unwrap().is_empty());
+
+        let result = solver.solve(&puzzle, |_| cancel.cancel());
+        assert!(matches!(result, Err(Error::Cancelled)));
+
+        let checkpoints = solver.checkpoints().unwrap();
+        assert_eq!(checkpoints.len(), 1);
+        assert_eq!(checkpoints[0].0, puzzle.digest());
+
+        let to = temp_dir();
+        let solver = CheckpointedSolver::new(&to, CancelToken::new());
+        for (digest, checkpoint) in &checkpoints {
+            solver.import(digest, checkpoint).unwrap();
+        }
+
+        let mut reports = vec![];
+        let solution = solver.solve(&puzzle, |progress| reports.push(progress));
+        assert_eq!(solution.unwrap().unwrap(), secret);
+        assert_eq!(reports[0].done, 2 * CHUNK_STEPS);
+    }
+
+    #[test]
+    fn corrupted_checkpoints_are_discarded() {
+        let secret = b"paymo!";
+        let puzzle = lhtlp::Puzzle::single(LhtlpParams::MIN, TIME, secret);
+
+        let data_dir = temp_dir();
+        let solver = CheckpointedSolver::new(&data_dir, CancelToken::new());
+        let path = solver.path(&puzzle);
+
+        fs::create_dir_all(&solver.dir).unwrap();
+        for contents in [&b"paymo"[..], &[0; CHECKSUM_BYTES + 8]] {
+            fs::write(&path, contents).unwrap();
+
+            let mut reports = vec![];
+            let solution = solver.solve(&puzzle, |progress| reports.push(progress));
+
+            assert_eq!(solution.unwrap().unwrap(), secret);
+            assert_eq!(reports[0].done, CHUNK_STEPS);
+        }
+    }
+}
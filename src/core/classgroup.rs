@@ -0,0 +1,937 @@
+#![allow(non_snake_case)]
+// Linearly homomorphic time-lock puzzles in the class group of an imaginary quadratic order,
+// following the CL framework (Castagnos-Laguillaumie, CT-RSA 2015) as used for time-lock
+// puzzles by Thyagarajan et al. (CCS 2021).
+//
+// the discriminant is Delta_q = -q^3 * p, where q is the order of the ed25519 group and p is a
+// random prime; the class group has a subgroup F = <f> of order q where discrete logarithms
+// are easy, and its order is unknown to everyone, so nobody can shortcut the squarings of
+// the setup, which then comes with a Wesolowski proof that h = g^(2^T) and needs no trust.
+//
+//     setup:  g = random q-th power, h = g^(2^T), pi = g^floor(2^T / l)
+//     check:  pi^l * g^(2^T mod l) = h, where l is a prime derived from T, p, g and h
+//     puzzle: u = g^r, v = h^r * f^s
+//     solve:  f^s = v / u^(2^T), then s is read from the reduced form of f^s
+//
+// the message space is Z_q, so secrets must be lower than q.
+
+use curve25519_dalek::constants::BASEPOINT_ORDER;
+use rand_core::{CryptoRng, OsRng, RngCore};
+use sha3::{Digest, Keccak256};
+
+use super::mpz::Mpz;
+use crate::msgs;
+
+// smallest discriminant of the maximal order accepted; fast to work with, but only meant for tests
+pub const MIN_SECURITY_PARAM: u64 = 512;
+
+// discriminant used by default, for real channels (128 bits of security)
+pub const DEFAULT_SECURITY_PARAM: u64 = 1827;
+
+// decoded discriminants bigger than this are rejected, so a peer cannot make us work with huge numbers
+pub const MAX_DISCRIMINANT_BITS: u64 = 4096;
+
+// extra bits of the puzzle randomness, so that g^r is statistically close to uniform
+const RANDOMNESS_EXTRA_BITS: u64 = 128;
+
+const MILLER_RABIN_ROUNDS: i32 = 32;
+
+// bit length of the Wesolowski challenge l, 2 * 128 bits of security
+const PROOF_CHALLENGE_BITS: usize = 256;
+
+// bits of the quotient floor(2^T / l) handled at once when computing the proof
+const PROOF_WINDOW_BITS: u64 = 8;
+
+const SMALL_PRIMES: [i64; 24] = [
+    3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+];
+
+/// Reduced binary quadratic form (a, b, c), with b^2 - 4ac equal to the discriminant.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Form {
+    a: Mpz,
+    b: Mpz,
+    c: Mpz,
+}
+
+/// Public parameters: T, the prime p fixing the discriminant, and g, h = g^(2^T), along
+/// with a proof of the latter.
+#[derive(Clone)]
+pub struct Params {
+    T: u64,
+    p: Mpz,
+    g: Form,
+    h: Form,
+    proof: Form,
+
+    // derived from p
+    q: Mpz,
+    disc: Mpz,
+}
+
+pub struct Puzzle {
+    pp: Params,
+    u: Form,
+    v: Form,
+}
+
+/// Randomness used when generating a puzzle; revealing it together with the secret
+/// allows anyone to check which secret the puzzle hides, without solving it.
+#[derive(Clone)]
+pub struct Opening {
+    pub r: Vec<u8>,
+}
+
+// order of the ed25519 group, which is also the message space of the puzzles
+fn q() -> Mpz {
+    let mut bytes = BASEPOINT_ORDER.to_bytes();
+    bytes.reverse();
+
+    Mpz::from_bytes(&bytes)
+}
+
+fn random_below_bits<R: RngCore + CryptoRng>(rng: &mut R, bits: u64) -> Mpz {
+    let mut bytes = vec![0u8; ((bits + 7) / 8) as usize];
+    rng.fill_bytes(&mut bytes);
+
+    let excess = bytes.len() as u64 * 8 - bits;
+    if let Some(first) = bytes.first_mut() {
+        *first &= 0xff >> excess;
+    }
+
+    Mpz::from_bytes(&bytes)
+}
+
+// Legendre symbol (a / p), for an odd prime p
+fn legendre(a: &Mpz, p: &Mpz) -> i32 {
+    a.modulo(p).jacobi(p)
+}
+
+impl Form {
+    fn new(a: Mpz, b: Mpz, disc: &Mpz) -> Option<Self> {
+        let four_a = a.mul_i64(4);
+        let numerator = b.mul(&b).sub(disc);
+
+        if a.sign().is_le() || !four_a.divides(&numerator) {
+            return None;
+        }
+
+        Some(Self {
+            c: numerator.div_exact(&four_a),
+            a,
+            b,
+        })
+    }
+
+    fn identity(disc: &Mpz) -> Self {
+        Self::new(Mpz::from_i64(1), Mpz::from_i64(1), disc).unwrap()
+    }
+
+    fn is_reduced(&self) -> bool {
+        self.a.neg() < self.b
+            && self.b <= self.a
+            && self.a <= self.c
+            && !(self.a == self.c && self.b.is_negative())
+    }
+
+    // -a < b <= a
+    fn normalize(&mut self) {
+        if self.a.neg() < self.b && self.b <= self.a {
+            return;
+        }
+
+        let two_a = self.a.mul_i64(2);
+        let r = self.a.sub(&self.b).div_floor(&two_a);
+
+        // c = a r^2 + b r + c, b = b + 2 a r
+        self.c = self.a.mul(&r).add(&self.b).mul(&r).add(&self.c);
+        self.b = self.b.add(&two_a.mul(&r));
+    }
+
+    fn reduce(mut self) -> Self {
+        self.normalize();
+
+        while self.a > self.c {
+            std::mem::swap(&mut self.a, &mut self.c);
+            self.b = self.b.neg();
+
+            self.normalize();
+        }
+
+        if self.a == self.c && self.b.is_negative() {
+            self.b = self.b.neg();
+        }
+
+        self
+    }
+
+    // composition of positive definite forms of the same discriminant, see Cohen,
+    // "A Course in Computational Algebraic Number Theory", algorithm 5.4.7
+    fn compose(&self, other: &Form, disc: &Mpz) -> Form {
+        let (f1, f2) = if self.a > other.a {
+            (other, self)
+        } else {
+            (self, other)
+        };
+
+        let s = f1.b.add(&f2.b).div_exact(&Mpz::from_i64(2));
+        let n = f2.b.sub(&s);
+
+        let (y1, d) = if f1.a.divides(&f2.a) {
+            (Mpz::new(), f1.a.clone())
+        } else {
+            let (d, u, _) = f2.a.gcdext(&f1.a);
+            (u, d)
+        };
+
+        let (x2, y2, d1) = if d.divides(&s) {
+            (Mpz::new(), Mpz::from_i64(-1), d)
+        } else {
+            let (d1, x2, y2) = s.gcdext(&d);
+            (x2, y2.neg(), d1)
+        };
+
+        let v1 = f1.a.div_exact(&d1);
+        let v2 = f2.a.div_exact(&d1);
+
+        let r = y1.mul(&y2).mul(&n).sub(&x2.mul(&f2.c)).modulo(&v1);
+
+        let b3 = f2.b.add(&v2.mul(&r).mul_i64(2));
+        let a3 = v1.mul(&v2);
+
+        Form::new(a3, b3, disc)
+            .expect("composition of forms of the same discriminant")
+            .reduce()
+    }
+
+    fn mul(&self, other: &Form, disc: &Mpz) -> Form {
+        self.compose(other, disc)
+    }
+
+    fn square(&self, disc: &Mpz) -> Form {
+        self.compose(self, disc)
+    }
+
+    fn inverse(&self) -> Form {
+        Form {
+            a: self.a.clone(),
+            b: self.b.neg(),
+            c: self.c.clone(),
+        }
+        .reduce()
+    }
+
+    fn pow(&self, exponent: &Mpz, disc: &Mpz) -> Form {
+        let mut result = Form::identity(disc);
+
+        for i in (0..exponent.bits()).rev() {
+            result = result.square(disc);
+
+            if exponent.bit(i) {
+                result = result.mul(self, disc);
+            }
+        }
+
+        result
+    }
+
+    // a and b as canonical big-endian integers, b preceded by its sign
+    fn to_bytes(&self) -> Vec<u8> {
+        let (a, b) = (self.a.to_bytes(), self.b.to_bytes());
+
+        let mut bytes = vec![];
+        bytes.extend((a.len() as u32).to_be_bytes());
+        bytes.extend(a);
+        bytes.push(self.b.is_negative() as u8);
+        bytes.extend((b.len() as u32).to_be_bytes());
+        bytes.extend(b);
+
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8], disc: &Mpz) -> Result<Self, Error> {
+        let (a, bytes) = split_integer(bytes)?;
+        let (sign, bytes) = bytes.split_first().ok_or(Error::InvalidEncoding)?;
+        let (b, bytes) = split_integer(bytes)?;
+
+        if !bytes.is_empty() || *sign > 1 {
+            return Err(Error::InvalidEncoding);
+        }
+
+        let form = msgs::ClassGroupForm {
+            a: a.to_vec(),
+            b: b.to_vec(),
+            b_negative: *sign == 1,
+        };
+
+        Form::from_msg(&form, disc)
+    }
+
+    fn from_msg(form: &msgs::ClassGroupForm, disc: &Mpz) -> Result<Self, Error> {
+        check_canonical(&form.a, false)?;
+        check_canonical(&form.b, true)?;
+
+        if form.b.is_empty() && form.b_negative {
+            return Err(Error::InvalidEncoding);
+        }
+
+        let a = Mpz::from_bytes(&form.a);
+        let b = Mpz::from_bytes(&form.b);
+        let b = if form.b_negative { b.neg() } else { b };
+
+        let form = Form::new(a, b, disc).ok_or(Error::InvalidForm)?;
+
+        if !form.is_reduced() {
+            return Err(Error::InvalidForm);
+        }
+
+        Ok(form)
+    }
+}
+
+impl From<&Form> for msgs::ClassGroupForm {
+    fn from(form: &Form) -> Self {
+        msgs::ClassGroupForm {
+            a: form.a.to_bytes(),
+            b: form.b.to_bytes(),
+            b_negative: form.b.is_negative(),
+        }
+    }
+}
+
+// integer preceded by its length, as in `Form::to_bytes`, and what follows it
+fn split_integer(bytes: &[u8]) -> Result<(&[u8], &[u8]), Error> {
+    if bytes.len() < 4 {
+        return Err(Error::InvalidEncoding);
+    }
+
+    let (len, bytes) = bytes.split_at(4);
+    let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+
+    if bytes.len() < len {
+        return Err(Error::InvalidEncoding);
+    }
+
+    Ok(bytes.split_at(len))
+}
+
+// canonical integers are big-endian and have no leading zeros; zero is the empty string
+fn check_canonical(bytes: &[u8], allow_zero: bool) -> Result<(), Error> {
+    match bytes.first() {
+        None if allow_zero => Ok(()),
+        Some(b) if *b != 0 => Ok(()),
+        _ => Err(Error::InvalidEncoding),
+    }
+}
+
+impl Params {
+    /// Generates p and g, and computes h = g^(2^T) and its proof; this takes about twice as
+    /// long as solving a puzzle, but the parameters can be reused.
+    pub fn setup(security_param: u64, time: u64) -> Self {
+        let q = q();
+
+        // p > 4q, so that the powers of f are reduced forms
+        let p_bits = (security_param.saturating_sub(q.bits()) + 1).max(q.bits() + 3);
+
+        let p = loop {
+            let mut p = random_below_bits(&mut OsRng, p_bits);
+            p.set_bit(p_bits - 1);
+            p.set_bit(0);
+            p.set_bit(1);
+
+            // q = 1 mod 4 and p = 3 mod 4, so that -q*p = 1 mod 4; (q / p) = -1
+            if legendre(&q, &p) == -1 && p.is_probable_prime(MILLER_RABIN_ROUNDS) {
+                break p;
+            }
+        };
+
+        let disc = discriminant(&q, &p);
+        let g = generator(&q, &p, &disc);
+
+        let mut h = g.clone();
+        for _ in 0..time {
+            h = h.square(&disc);
+        }
+
+        let l = proof_challenge(time, &p, &g, &h);
+        let proof = prove_squarings(&g, time, &l, &disc);
+
+        Self {
+            T: time,
+            p,
+            g,
+            h,
+            proof,
+            q,
+            disc,
+        }
+    }
+
+    // pi^l * g^(2^T mod l) = h; l is only known once h is, so pi cannot be made up
+    fn verify_proof(&self) -> bool {
+        let l = proof_challenge(self.T, &self.p, &self.g, &self.h);
+        let r = Mpz::from_i64(2).powm(&Mpz::from_bytes(&self.T.to_be_bytes()), &l);
+
+        let h = self
+            .proof
+            .pow(&l, &self.disc)
+            .mul(&self.g.pow(&r, &self.disc), &self.disc);
+
+        h == self.h
+    }
+
+    /// f^m, for m in [0, q); its reduced form is (q^2, L(m) q, .), where L(m) is the odd
+    /// integer in [-q, q] congruent to 1/m mod q
+    fn f_pow(&self, m: &Mpz) -> Form {
+        if m.is_zero() {
+            return Form::identity(&self.disc);
+        }
+
+        let L = m.invert(&self.q).expect("q is prime");
+        let L = if L.bit(0) { L } else { L.sub(&self.q) };
+
+        Form::new(self.q.mul(&self.q), L.mul(&self.q), &self.disc)
+            .unwrap()
+            .reduce()
+    }
+
+    // inverse of `f_pow`, or None if `form` is not in F
+    fn f_log(&self, form: &Form) -> Option<Mpz> {
+        if *form == Form::identity(&self.disc) {
+            return Some(Mpz::new());
+        }
+
+        if form.a != self.q.mul(&self.q) || !self.q.divides(&form.b) {
+            return None;
+        }
+
+        let L = form.b.div_exact(&self.q).modulo(&self.q);
+
+        L.invert(&self.q)
+    }
+
+    fn randomness_bits(&self) -> u64 {
+        self.security_bits() / 2 + RANDOMNESS_EXTRA_BITS
+    }
+
+    fn gen_with_randomness(&self, secret: &Mpz, r: &Mpz) -> (Form, Form) {
+        let u = self.g.pow(r, &self.disc);
+        let v = self
+            .h
+            .pow(r, &self.disc)
+            .mul(&self.f_pow(secret), &self.disc);
+
+        (u, v)
+    }
+
+    /// Bit length of the discriminant of the maximal order, -q * p.
+    pub fn security_bits(&self) -> u64 {
+        self.q.mul(&self.p).bits()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let p = self.p.to_bytes();
+
+        let mut bytes = vec![];
+        bytes.extend(self.T.to_be_bytes());
+        bytes.extend((p.len() as u32).to_be_bytes());
+        bytes.extend(p);
+        bytes.extend(self.g.to_bytes());
+        bytes.extend(self.h.to_bytes());
+
+        bytes
+    }
+}
+
+// prime l of PROOF_CHALLENGE_BITS bits, from the hash of the parameters to prove
+fn proof_challenge(time: u64, p: &Mpz, g: &Form, h: &Form) -> Mpz {
+    let transcript = Keccak256::new()
+        .chain_update(b"PayMo class group setup proof")
+        .chain_update(time.to_be_bytes())
+        .chain_update(p.to_bytes())
+        .chain_update(g.to_bytes())
+        .chain_update(h.to_bytes());
+
+    (0u64..)
+        .map(|counter| {
+            let mut l = Mpz::from_bytes(
+                &transcript
+                    .clone()
+                    .chain_update(counter.to_be_bytes())
+                    .finalize(),
+            );
+            l.set_bit(PROOF_CHALLENGE_BITS as u64 - 1);
+            l.set_bit(0);
+
+            l
+        })
+        .find(|l| l.is_probable_prime(MILLER_RABIN_ROUNDS))
+        .unwrap()
+}
+
+// g^floor(2^T / l), with the quotient computed by long division, PROOF_WINDOW_BITS bits at
+// a time; about as many squarings as computing h, see Wesolowski, "Efficient verifiable
+// delay functions", section 4.1
+fn prove_squarings(g: &Form, time: u64, l: &Mpz, disc: &Mpz) -> Form {
+    let mut table = vec![Form::identity(disc)];
+    for i in 1..1 << PROOF_WINDOW_BITS {
+        table.push(table[i - 1].mul(g, disc));
+    }
+
+    let mut proof = Form::identity(disc);
+    let mut remainder = Mpz::from_i64(1);
+    let mut left = time;
+
+    while left > 0 {
+        let bits = left.min(PROOF_WINDOW_BITS);
+        left -= bits;
+
+        // remainder < l, so the next digit of the quotient fits in `bits` bits
+        let shifted = remainder.mul_i64(1 << bits);
+        let digit = shifted.div_floor(l);
+        remainder = shifted.modulo(l);
+
+        for _ in 0..bits {
+            proof = proof.square(disc);
+        }
+
+        let digit = digit.to_bytes().first().copied().unwrap_or(0);
+        proof = proof.mul(&table[digit as usize], disc);
+    }
+
+    proof
+}
+
+// Delta_q = -q^3 * p
+fn discriminant(q: &Mpz, p: &Mpz) -> Mpz {
+    q.mul(q).mul(q).mul(p).neg()
+}
+
+// g = lift(r^2)^q, where r is an ideal above a small split prime; see CL15, section 4.1
+fn generator(q: &Mpz, p: &Mpz, disc: &Mpz) -> Form {
+    let disc_K = q.mul(p).neg();
+
+    for prime in SMALL_PRIMES {
+        let prime = Mpz::from_i64(prime);
+
+        if legendre(&disc_K, &prime) != 1 {
+            continue;
+        }
+
+        // b odd with b^2 = disc_K mod 4 * prime
+        let residue = disc_K.modulo(&prime);
+        let b = (0..)
+            .map(Mpz::from_i64)
+            .find(|b| b.mul(b).modulo(&prime) == residue)
+            .unwrap();
+        let b = if b.bit(0) { b } else { prime.sub(&b) };
+
+        let r = Form::new(prime, b, &disc_K).unwrap().reduce();
+        let r_squared = r.square(&disc_K);
+
+        // the lift is only well defined for forms with a prime to q
+        if q.divides(&r_squared.a) {
+            continue;
+        }
+
+        let lift = Form::new(r_squared.a.clone(), r_squared.b.mul(q), disc)
+            .unwrap()
+            .reduce();
+
+        return lift.pow(q, disc);
+    }
+
+    unreachable!("there is always a small split prime")
+}
+
+impl Puzzle {
+    pub fn single(security_param: u64, time: u64, secret: &[u8]) -> Self {
+        Self::single_with_opening(&mut OsRng, security_param, time, secret).0
+    }
+
+    /// Same as [`Puzzle::single`], but also returns the randomness used, so that the
+    /// creator can later prove which secret the puzzle hides; the randomness is drawn from
+    /// `rng`, the public parameters are not.
+    pub fn single_with_opening<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        security_param: u64,
+        time: u64,
+        secret: &[u8],
+    ) -> (Self, Opening) {
+        let params = Params::setup(security_param, time);
+
+        Self::with_params(rng, &params, secret)
+    }
+
+    /// New puzzle with already generated public parameters; `secret` is a big-endian
+    /// integer, and must be lower than q.
+    pub fn with_params<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        params: &Params,
+        secret: &[u8],
+    ) -> (Self, Opening) {
+        let secret = Mpz::from_bytes(secret);
+        assert!(secret < params.q);
+
+        let r = random_below_bits(rng, params.randomness_bits());
+        let (u, v) = params.gen_with_randomness(&secret, &r);
+
+        let puzzle = Self {
+            pp: params.clone(),
+            u,
+            v,
+        };
+
+        (puzzle, Opening { r: r.to_bytes() })
+    }
+
+    /// Homomorphically adds puzzles that share the same public parameters; the result
+    /// hides the sum of their secrets, mod q.
+    pub fn eval(puzzles: &[&Puzzle]) -> Self {
+        assert!(!puzzles.is_empty());
+        assert!(puzzles.iter().all(|puzzle| puzzle.same_params(puzzles[0])));
+
+        let pp = puzzles[0].pp.clone();
+        let disc = &pp.disc;
+
+        let (u, v) = puzzles.iter().fold(
+            (Form::identity(disc), Form::identity(disc)),
+            |(u, v), puzzle| (u.mul(&puzzle.u, disc), v.mul(&puzzle.v, disc)),
+        );
+
+        Self { pp, u, v }
+    }
+
+    /// Whether both puzzles were generated with the same public parameters.
+    pub fn same_params(&self, other: &Puzzle) -> bool {
+        self.pp.to_bytes() == other.pp.to_bytes()
+    }
+
+    pub fn params(&self) -> &Params {
+        &self.pp
+    }
+
+    /// Performs the T sequential squarings; returns the secret as a big-endian integer
+    /// without leading zeros, or None if the puzzle was not properly generated.
+    pub fn solve(&self) -> Option<Vec<u8>> {
+        let w = self.squarings(self.u.clone(), self.pp.T);
+
+        self.open(&w)
+    }
+
+    /// Number of sequential squarings T needed to solve this puzzle.
+    pub fn time(&self) -> u64 {
+        self.pp.T
+    }
+
+    /// Starting point of the squarings done by [`Puzzle::square`], u.
+    pub fn squarings_start(&self) -> Vec<u8> {
+        self.u.to_bytes()
+    }
+
+    /// Performs `steps` more sequential squarings of the form `w`; after T of them, starting
+    /// from [`Puzzle::squarings_start`], [`Puzzle::finish`] recovers the secret.
+    pub fn square(&self, w: &[u8], steps: u64) -> Result<Vec<u8>, Error> {
+        let w = Form::from_bytes(w, &self.pp.disc)?;
+
+        Ok(self.squarings(w, steps).to_bytes())
+    }
+
+    /// Same as [`Puzzle::solve`], given w = u^(2^T).
+    pub fn finish(&self, w: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let w = Form::from_bytes(w, &self.pp.disc)?;
+
+        Ok(self.open(&w))
+    }
+
+    fn squarings(&self, mut w: Form, steps: u64) -> Form {
+        for _ in 0..steps {
+            w = w.square(&self.pp.disc);
+        }
+
+        w
+    }
+
+    // f^s = v / w
+    fn open(&self, w: &Form) -> Option<Vec<u8>> {
+        let f_s = self.v.mul(&w.inverse(), &self.pp.disc);
+
+        Some(self.pp.f_log(&f_s)?.to_bytes())
+    }
+
+    /// Checks that this puzzle hides `secret`, by regenerating it with the revealed randomness.
+    pub fn verify_opening(&self, secret: &[u8], opening: &Opening) -> bool {
+        let secret = Mpz::from_bytes(secret);
+        let r = Mpz::from_bytes(&opening.r);
+
+        if secret >= self.pp.q || r.bits() > self.pp.randomness_bits() {
+            return false;
+        }
+
+        let (u, v) = self.pp.gen_with_randomness(&secret, &r);
+
+        u == self.u && v == self.v
+    }
+
+    /// Hash of the public parameters and of the puzzle itself.
+    pub fn digest(&self) -> [u8; 32] {
+        Keccak256::new()
+            .chain_update(b"PayMo class group puzzle")
+            .chain_update(self.pp.to_bytes())
+            .chain_update(self.u.to_bytes())
+            .chain_update(self.v.to_bytes())
+            .finalize()
+            .into()
+    }
+}
+
+impl From<&Puzzle> for msgs::ClassGroupPuzzle {
+    fn from(puzzle: &Puzzle) -> Self {
+        let pp = &puzzle.pp;
+
+        msgs::ClassGroupPuzzle {
+            params: Some(msgs::ClassGroupParams {
+                t: pp.T,
+                p: pp.p.to_bytes(),
+                g: Some((&pp.g).into()),
+                h: Some((&pp.h).into()),
+                proof: Some((&pp.proof).into()),
+            }),
+            u: Some((&puzzle.u).into()),
+            v: Some((&puzzle.v).into()),
+        }
+    }
+}
+
+impl TryFrom<msgs::ClassGroupPuzzle> for Puzzle {
+    type Error = Error;
+
+    fn try_from(puzzle: msgs::ClassGroupPuzzle) -> Result<Self, Self::Error> {
+        let params = puzzle.params.ok_or(Error::InvalidParams)?;
+        check_canonical(&params.p, false)?;
+
+        let q = q();
+        let p = Mpz::from_bytes(&params.p);
+
+        // p must be a prime bigger than 4q, with p = 3 mod 4 and (q / p) = -1
+        let valid_p = q.mul(&p).bits() <= MAX_DISCRIMINANT_BITS
+            && p > q.mul_i64(4)
+            && p.bit(0)
+            && p.bit(1)
+            && legendre(&q, &p) == -1
+            && p.is_probable_prime(MILLER_RABIN_ROUNDS);
+
+        if !valid_p {
+            return Err(Error::InvalidParams);
+        }
+
+        let disc = discriminant(&q, &p);
+
+        let form = |form: Option<msgs::ClassGroupForm>| {
+            Form::from_msg(&form.ok_or(Error::InvalidEncoding)?, &disc)
+        };
+
+        let pp = Params {
+            T: params.t,
+            p,
+            g: form(params.g)?,
+            h: form(params.h)?,
+            proof: form(params.proof)?,
+            q,
+            disc: disc.clone(),
+        };
+
+        if !pp.verify_proof() {
+            return Err(Error::InvalidParams);
+        }
+
+        Ok(Self {
+            u: form(puzzle.u)?,
+            v: form(puzzle.v)?,
+            pp,
+        })
+    }
+}
+
+impl Opening {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.r.clone()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        check_canonical(bytes, false)?;
+
+        Ok(Self { r: bytes.to_vec() })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid encoding of a class group puzzle")]
+    InvalidEncoding,
+
+    #[error("Invalid class group puzzle public parameters")]
+    InvalidParams,
+
+    #[error("Invalid binary quadratic form")]
+    InvalidForm,
+
+    #[error("Invalid class group discriminant size: {0} bits")]
+    InvalidSecurityParam(u64),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> Params {
+        Params::setup(MIN_SECURITY_PARAM, 10)
+    }
+
+    #[test]
+    fn group_laws() {
+        let pp = params();
+        let disc = &pp.disc;
+
+        let identity = Form::identity(disc);
+        assert!(pp.g.is_reduced());
+        assert_eq!(pp.g.mul(&identity, disc), pp.g);
+        assert_eq!(pp.g.mul(&pp.g.inverse(), disc), identity);
+
+        let x = pp.g.pow(&Mpz::from_i64(12345), disc);
+        let y = pp.h.pow(&Mpz::from_i64(6789), disc);
+        assert_eq!(x.mul(&y, disc), y.mul(&x, disc));
+        assert_eq!(
+            x.mul(&y, disc).mul(&pp.g, disc),
+            x.mul(&y.mul(&pp.g, disc), disc)
+        );
+
+        let mut h = pp.g.clone();
+        for _ in 0..10 {
+            h = h.square(disc);
+        }
+        assert_eq!(h, pp.h);
+        assert_eq!(pp.g.pow(&Mpz::from_i64(1024), disc), pp.h);
+    }
+
+    #[test]
+    fn setup_proof() {
+        for time in [0, 1, 7, 8, 9, 1000] {
+            let pp = Params::setup(MIN_SECURITY_PARAM, time);
+            assert!(pp.verify_proof());
+
+            let l = proof_challenge(time, &pp.p, &pp.g, &pp.h);
+            let mut two_t = Mpz::new();
+            two_t.set_bit(time);
+            assert_eq!(pp.proof, pp.g.pow(&two_t.div_floor(&l), &pp.disc));
+
+            let longer = Params {
+                T: time + 1,
+                ..pp.clone()
+            };
+            assert!(!longer.verify_proof());
+        }
+    }
+
+    #[test]
+    fn discrete_logarithms_in_f() {
+        let pp = params();
+
+        let f = pp.f_pow(&Mpz::from_i64(1));
+        assert_eq!(f.pow(&pp.q, &pp.disc), Form::identity(&pp.disc));
+
+        for m in [0, 1, 2, 3, 1000] {
+            let m = Mpz::from_i64(m);
+            assert_eq!(pp.f_log(&f.pow(&m, &pp.disc)), Some(m));
+        }
+
+        let m = random_below_bits(&mut OsRng, 250);
+        assert_eq!(pp.f_pow(&m), f.pow(&m, &pp.disc));
+        assert_eq!(pp.f_log(&pp.f_pow(&m)), Some(m));
+
+        assert_eq!(pp.f_log(&pp.g), None);
+    }
+
+    #[test]
+    fn generate_and_solve_puzzle() {
+        let secret = b"paymo!";
+        let (puzzle, opening) =
+            Puzzle::single_with_opening(&mut OsRng, MIN_SECURITY_PARAM, 1000, secret);
+
+        assert!(puzzle.verify_opening(secret, &opening));
+        assert!(!puzzle.verify_opening(b"paymo?", &opening));
+
+        assert_eq!(puzzle.solve().unwrap(), secret);
+    }
+
+    #[test]
+    fn add_puzzles() {
+        let pp = Params::setup(MIN_SECURITY_PARAM, 1000);
+
+        let (a, _) = Puzzle::with_params(&mut OsRng, &pp, &[0x01; 31]);
+        let (b, _) = Puzzle::with_params(&mut OsRng, &pp, &[0x02; 31]);
+
+        assert_eq!(Puzzle::eval(&[&a, &b]).solve().unwrap(), [0x03; 31]);
+    }
+
+    #[test]
+    fn solve_in_steps() {
+        let secret = b"paymo!";
+        let (puzzle, _) = Puzzle::single_with_opening(&mut OsRng, MIN_SECURITY_PARAM, 1000, secret);
+
+        let mut w = puzzle.squarings_start();
+        for steps in [0, 1, 499, 500] {
+            w = puzzle.square(&w, steps).unwrap();
+        }
+
+        assert_eq!(puzzle.finish(&w).unwrap().unwrap(), secret);
+        assert_eq!(Form::from_bytes(&w, &puzzle.pp.disc).unwrap().to_bytes(), w);
+
+        assert!(puzzle.square(&w[1..], 1).is_err());
+        assert!(puzzle.finish(&[w.as_slice(), &[0]].concat()).is_err());
+    }
+
+    #[test]
+    fn puzzle_msg_roundtrip() {
+        let (puzzle, _) =
+            Puzzle::single_with_opening(&mut OsRng, MIN_SECURITY_PARAM, 100, b"paymo!");
+
+        let msg = msgs::ClassGroupPuzzle::from(&puzzle);
+        let decoded = Puzzle::try_from(msg).unwrap();
+
+        assert_eq!(puzzle.digest(), decoded.digest());
+        assert_eq!(decoded.solve().unwrap(), b"paymo!");
+
+        // h = g^(2^T) must be proven
+        let mut msg = msgs::ClassGroupPuzzle::from(&puzzle);
+        let params = msg.params.as_mut().unwrap();
+        params.t += 1;
+        assert!(Puzzle::try_from(msg).is_err());
+
+        let mut msg = msgs::ClassGroupPuzzle::from(&puzzle);
+        let params = msg.params.as_mut().unwrap();
+        params.proof = params.g.clone();
+        assert!(Puzzle::try_from(msg).is_err());
+
+        // p must be a prime
+        let mut msg = msgs::ClassGroupPuzzle::from(&puzzle);
+        let p = &mut msg.params.as_mut().unwrap().p;
+        *p = Mpz::from_bytes(p).add(&Mpz::from_i64(4)).to_bytes();
+        assert!(Puzzle::try_from(msg).is_err());
+
+        // leading zeros
+        let mut msg = msgs::ClassGroupPuzzle::from(&puzzle);
+        msg.u.as_mut().unwrap().a.insert(0, 0);
+        assert!(Puzzle::try_from(msg).is_err());
+
+        // not reduced
+        let mut msg = msgs::ClassGroupPuzzle::from(&puzzle);
+        let unreduced = Form {
+            a: puzzle.u.a.clone(),
+            b: puzzle.u.b.add(&puzzle.u.a.mul_i64(2)),
+            c: puzzle.u.c.clone(),
+        };
+        msg.u = Some((&unreduced).into());
+        assert!(Puzzle::try_from(msg).is_err());
+    }
+}
@@ -0,0 +1,752 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+use monero_serai::{transaction::Transaction, wallet::address::MoneroAddress, Commitment};
+use prost::Message;
+use sha3::{Digest, Keccak256};
+
+use super::channel::{self, Channel, ChannelState, Closing, Funding, Keys, Params, Phase};
+use super::jspend::JointOutput;
+use super::keystore::Keystore;
+use super::utils::{atomic_write, decode_point, decode_scalar};
+use super::vtdlog::VtdlogParams;
+use super::Role;
+use crate::msgs::{self, channel_record::Update};
+
+/// How many journal entries are written between two snapshots, by default.
+pub const DEFAULT_SNAPSHOT_INTERVAL: usize = 64;
+
+const CHECKSUM_BYTES: usize = 32;
+
+// length (4 bytes, big-endian) || checksum
+const HEADER_BYTES: usize = 4 + CHECKSUM_BYTES;
+
+/// Channels of the data directory, kept in `data_dir/channels` with one directory per channel,
+/// named after its joint public key. A channel is only stored once its keys are exchanged,
+/// since the joint address may receive funds from then on.
+pub struct ChannelDb {
+    dir: PathBuf,
+}
+
+impl ChannelDb {
+    pub fn new(data_dir: &Path) -> Self {
+        Self {
+            dir: data_dir.join("channels"),
+        }
+    }
+
+    /// Every stored channel, closed or not.
+    pub fn load_all(&self) -> Result<Vec<ChannelStore>, Error> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut stores = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            if !path.is_dir() {
+                continue;
+            }
+
+            if let Some(store) = ChannelStore::load(path)? {
+                stores.push(store);
+            }
+        }
+
+        Ok(stores)
+    }
+
+    /// Starts storing `channel`, whose keys must be exchanged, opened with the node of the
+    /// other party whose key is `their_node_key`.
+    pub fn create(
+        &self,
+        channel: &Channel,
+        their_node_key: [u8; 32],
+    ) -> Result<ChannelStore, Error> {
+        let mut store = self.new_store(channel)?;
+        store.stored.their_node_key = their_node_key.to_vec();
+        store.save(channel)?;
+
+        Ok(store)
+    }
+
+    /// Stores a channel as another data directory saved it, with its latest payment and
+    /// Bob's VTD-Log; it must be restored with `keystore`. Since the client resumes a single
+    /// channel, one that is not closed is only imported if no other one is stored.
+    pub fn import(
+        &self,
+        stored: msgs::StoredChannel,
+        keystore: &Keystore,
+    ) -> Result<ChannelStore, Error> {
+        let channel = restore(&stored, keystore)?;
+
+        if is_resumable(&stored) && self.load_all()?.iter().any(ChannelStore::is_resumable) {
+            return Err(Error::OtherChannelResumed);
+        }
+
+        let mut store = self.new_store(&channel)?;
+        store.append(Update::Channel(stored))?;
+
+        Ok(store)
+    }
+
+    fn new_store(&self, channel: &Channel) -> Result<ChannelStore, Error> {
+        let joint_public_key = channel.keys()?.joint_public_key.compress().to_bytes();
+        let dir = self.dir.join(hex::encode(&joint_public_key[..8]));
+
+        if ChannelStore::load(dir.to_path_buf())?.is_some() {
+            return Err(Error::AlreadyStored(dir));
+        }
+
+        fs::create_dir_all(&dir)?;
+
+        Ok(ChannelStore::new(dir))
+    }
+}
+
+/// A stored channel. Every change is appended to a journal, which is synced before the change
+/// is applied; every few changes, the channel is written at once to a snapshot, and the
+/// journal starts over. A crash while appending only loses the change being appended, and
+/// one while writing the snapshot loses nothing.
+pub struct ChannelStore {
+    dir: PathBuf,
+    interval: usize,
+
+    // sequence number of the last change, and changes since the last snapshot
+    seq: u64,
+    journaled: usize,
+
+    stored: msgs::StoredChannel,
+}
+
+impl ChannelStore {
+    fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            interval: DEFAULT_SNAPSHOT_INTERVAL,
+            seq: 0,
+            journaled: 0,
+            stored: msgs::StoredChannel::default(),
+        }
+    }
+
+    pub fn with_interval(mut self, interval: usize) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// The channel as it was last saved, with our secrets from `keystore`.
+    pub fn channel(&self, keystore: &Keystore) -> Result<Channel, Error> {
+        restore(&self.stored, keystore)
+    }
+
+    /// Name of the directory of the channel.
+    pub fn id(&self) -> String {
+        self.dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Directory of the channel, which also has its latest signed payment.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The channel as it is stored, without our secrets.
+    pub fn stored(&self) -> &msgs::StoredChannel {
+        &self.stored
+    }
+
+    pub fn role(&self) -> msgs::ChannelRole {
+        self.stored.role()
+    }
+
+    pub fn phase(&self) -> msgs::ChannelPhase {
+        self.stored.phase()
+    }
+
+    /// Index our keys of the channel are derived with.
+    pub fn key_index(&self) -> u32 {
+        self.stored.key_index
+    }
+
+    /// Node key of the other party, the only node the channel is resumed with.
+    pub fn their_node_key(&self) -> Option<[u8; 32]> {
+        self.stored.their_node_key.as_slice().try_into().ok()
+    }
+
+    /// Whether the channel is resumed after a restart: it is set up, so that Alice may have
+    /// funded it, and not closed yet.
+    pub fn is_resumable(&self) -> bool {
+        is_resumable(&self.stored)
+    }
+
+    /// State of the latest payment; the initial one, before any.
+    pub fn payment(&self) -> ChannelState {
+        self.stored
+            .payment
+            .clone()
+            .map(ChannelState::from)
+            .unwrap_or_default()
+    }
+
+    /// Bob's VTD-Log and its opening, once Alice verified them.
+    pub fn bob_vtdlog(&self) -> Option<(msgs::VtdlogCommit, msgs::VtdlogProof)> {
+        let bob_vtdlog = self.stored.bob_vtdlog.clone()?;
+
+        Some((bob_vtdlog.commit?, bob_vtdlog.opening?))
+    }
+
+    /// Saves the keys and the phase of `channel`.
+    pub fn save(&mut self, channel: &Channel) -> Result<(), Error> {
+        let mut stored = stored_channel(channel)?;
+        stored.payment = self.stored.payment.clone();
+        stored.bob_vtdlog = self.stored.bob_vtdlog.clone();
+        stored.their_node_key = self.stored.their_node_key.clone();
+
+        self.append(Update::Channel(stored))
+    }
+
+    pub fn save_payment(&mut self, state: &ChannelState) -> Result<(), Error> {
+        self.append(Update::Payment(state.into()))
+    }
+
+    pub fn save_bob_vtdlog(
+        &mut self,
+        commit: msgs::VtdlogCommit,
+        opening: msgs::VtdlogProof,
+    ) -> Result<(), Error> {
+        let bob_vtdlog = msgs::BobVtdlog {
+            commit: Some(commit),
+            opening: Some(opening),
+        };
+
+        self.append(Update::BobVtdlog(bob_vtdlog))
+    }
+
+    // the snapshot, if any, and then the entries of the journal it does not have yet; `None`
+    // if nothing was ever stored in `dir`
+    fn load(dir: PathBuf) -> Result<Option<Self>, Error> {
+        let mut store = Self::new(dir);
+
+        let snapshot_path = store.snapshot_path();
+        match fs::read(&snapshot_path) {
+            Ok(bytes) => {
+                let record =
+                    decode_record(&bytes).ok_or_else(|| Error::Corrupted(snapshot_path.clone()))?;
+                store.apply(record);
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        }
+
+        let journal_path = store.journal_path();
+        let journal = match fs::read(&journal_path) {
+            Ok(journal) => journal,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut read = 0;
+        while read < journal.len() {
+            let entry = &journal[read..];
+
+            let len = match entry.get(..4) {
+                Some(len) => u32::from_be_bytes(len.try_into().unwrap()) as usize,
+                None => break,
+            };
+            let record = match entry.get(4..HEADER_BYTES + len) {
+                Some(record) => decode_record(record),
+                None => break,
+            };
+
+            match record {
+                Some(record) => store.apply(record),
+                None => break,
+            }
+
+            read += HEADER_BYTES + len;
+            store.journaled += 1;
+        }
+
+        // only the last entry can be partial, if a crash interrupted its write
+        if read < journal.len() {
+            warn!(
+                "Discarding the last {} bytes of the journal {}",
+                journal.len() - read,
+                journal_path.display()
+            );
+
+            let file = OpenOptions::new().write(true).open(&journal_path)?;
+            file.set_len(read as u64)?;
+            file.sync_all()?;
+        }
+
+        if store.seq == 0 {
+            return Ok(None);
+        }
+
+        debug!(
+            "Loaded channel {} at change {}",
+            store.dir.display(),
+            store.seq
+        );
+
+        Ok(Some(store))
+    }
+
+    // entries already in the snapshot, which the journal still has if a crash happened right
+    // after the snapshot was written, are skipped
+    fn apply(&mut self, record: msgs::ChannelRecord) {
+        if record.seq <= self.seq {
+            return;
+        }
+
+        match record.update {
+            Some(Update::Channel(stored)) => self.stored = stored,
+            Some(Update::Payment(state)) => self.stored.payment = Some(state),
+            Some(Update::BobVtdlog(bob_vtdlog)) => self.stored.bob_vtdlog = Some(bob_vtdlog),
+            None => (),
+        }
+
+        self.seq = record.seq;
+    }
+
+    fn append(&mut self, update: Update) -> Result<(), Error> {
+        let record = msgs::ChannelRecord {
+            seq: self.seq + 1,
+            update: Some(update),
+        };
+        let bytes = encode_record(&record);
+        let len = (bytes.len() - CHECKSUM_BYTES) as u32;
+
+        let mut journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.journal_path())?;
+        journal.write_all(&len.to_be_bytes())?;
+        journal.write_all(&bytes)?;
+        journal.sync_all()?;
+
+        self.apply(record);
+        self.journaled += 1;
+
+        if self.journaled >= self.interval {
+            self.snapshot()?;
+        }
+
+        Ok(())
+    }
+
+    // written to a temporary file that then replaces the old snapshot, and only then is the
+    // journal emptied
+    fn snapshot(&mut self) -> Result<(), Error> {
+        let record = msgs::ChannelRecord {
+            seq: self.seq,
+            update: Some(Update::Channel(self.stored.clone())),
+        };
+
+        atomic_write(&self.snapshot_path(), &encode_record(&record))?;
+
+        fs::File::create(self.journal_path())?.sync_all()?;
+        self.journaled = 0;
+
+        Ok(())
+    }
+
+    fn snapshot_path(&self) -> PathBuf {
+        self.dir.join("snapshot")
+    }
+
+    fn journal_path(&self) -> PathBuf {
+        self.dir.join("journal")
+    }
+}
+
+fn is_resumable(stored: &msgs::StoredChannel) -> bool {
+    use msgs::ChannelPhase::*;
+
+    match stored.phase() {
+        KeysExchanged => !stored.joint_tag.is_empty() && stored.vtdlog_opened,
+        Funded | Open | Closing => true,
+        Closed | Refunded | Unspecified => false,
+    }
+}
+
+// checksum || record
+fn encode_record(record: &msgs::ChannelRecord) -> Vec<u8> {
+    let record = record.encode_to_vec();
+
+    let mut bytes = Keccak256::digest(&record).to_vec();
+    bytes.extend(record);
+
+    bytes
+}
+
+fn decode_record(bytes: &[u8]) -> Option<msgs::ChannelRecord> {
+    if bytes.len() < CHECKSUM_BYTES {
+        return None;
+    }
+
+    let (checksum, record) = bytes.split_at(CHECKSUM_BYTES);
+    if Keccak256::digest(record)[..] != *checksum {
+        return None;
+    }
+
+    msgs::ChannelRecord::decode(record).ok()
+}
+
+fn stored_channel(channel: &Channel) -> Result<msgs::StoredChannel, Error> {
+    use msgs::ChannelPhase;
+
+    let (phase, closing) = match channel.phase() {
+        Phase::Negotiating(_) => {
+            return Err(channel::Error::UnexpectedPhase(channel.phase().name()).into())
+        }
+        Phase::KeysExchanged(_) => (ChannelPhase::KeysExchanged, None),
+        Phase::Funded(_) => (ChannelPhase::Funded, None),
+        Phase::Open(_) => (ChannelPhase::Open, None),
+        // the joint spend of a closing is not resumed, so the channel is open until it is signed
+        Phase::Closing(_, Closing { tx: None, .. }) => (ChannelPhase::Open, None),
+        Phase::Closing(_, closing) => (
+            ChannelPhase::Closing,
+            Some(msgs::StoredClosing {
+                state: Some((&closing.state).into()),
+                from_height: closing.from_height as u64,
+                tx: closing
+                    .tx
+                    .as_ref()
+                    .map(Transaction::serialize)
+                    .unwrap_or_default(),
+            }),
+        ),
+        Phase::Closed(_, state) => (
+            ChannelPhase::Closed,
+            Some(msgs::StoredClosing {
+                state: Some(state.into()),
+                ..Default::default()
+            }),
+        ),
+        Phase::Refunded(_) => (ChannelPhase::Refunded, None),
+    };
+
+    let keys = channel.keys()?;
+    let params = &keys.params;
+    let (their_public_key, their_view_secret) = keys.theirs();
+
+    let output = channel.funding().ok().map(|funding| msgs::StoredOutput {
+        key: funding.output.key.compress().to_bytes().to_vec(),
+        key_offset: funding.output.key_offset.to_bytes().to_vec(),
+        mask: funding.output.commitment.mask.to_bytes().to_vec(),
+        amount: funding.output.commitment.amount,
+        height: funding.height as u64,
+        image: funding
+            .image
+            .map(|image| image.compress().to_bytes().to_vec())
+            .unwrap_or_default(),
+    });
+
+    let mut stored = msgs::StoredChannel {
+        info: Some(msgs::ChannelInfo {
+            channel_amount: params.channel_amount.as_pico(),
+            time: params.time,
+            confirmations: params.confirmations,
+            vtdlog_params: Some((&params.vtdlog_params).into()),
+        }),
+        alice_address: keys.alice_address.to_string(),
+        bob_address: keys.bob_address.to_string(),
+
+        public_key: keys.own.public_key.compress().to_bytes().to_vec(),
+        key_index: keys.own.index,
+        their_public_key: their_public_key.compress().to_bytes().to_vec(),
+        their_view_secret: their_view_secret.to_bytes().to_vec(),
+        joint_tag: keys
+            .joint_tag
+            .map(|joint_tag| joint_tag.compress().to_bytes().to_vec())
+            .unwrap_or_default(),
+        vtdlog_opened: keys.vtdlog_opened,
+        from_height: keys.from_height as u64,
+
+        output,
+        closing,
+
+        ..Default::default()
+    };
+
+    stored.set_role(match keys.role {
+        Role::Alice => msgs::ChannelRole::Alice,
+        Role::Bob => msgs::ChannelRole::Bob,
+    });
+    stored.set_phase(phase);
+
+    Ok(stored)
+}
+
+fn restore(stored: &msgs::StoredChannel, keystore: &Keystore) -> Result<Channel, Error> {
+    use msgs::ChannelPhase;
+
+    let role = match stored.role() {
+        msgs::ChannelRole::Alice => Role::Alice,
+        msgs::ChannelRole::Bob => Role::Bob,
+        msgs::ChannelRole::Unspecified => return Err(Error::InvalidChannel("role")),
+    };
+
+    let info = stored
+        .info
+        .clone()
+        .ok_or(Error::InvalidChannel("channel info"))?;
+    let vtdlog_params = info
+        .vtdlog_params
+        .and_then(|params| VtdlogParams::try_from(params).ok())
+        .ok_or(Error::InvalidChannel("VTD-Log parameters"))?;
+    let params = Params {
+        channel_amount: monero::Amount::from_pico(info.channel_amount),
+        time: info.time,
+        confirmations: info.confirmations,
+        vtdlog_params,
+    };
+
+    let alice_address = MoneroAddress::from_str_raw(&stored.alice_address)
+        .map_err(|_| Error::InvalidChannel("Alice's address"))?;
+    let bob_address = MoneroAddress::from_str_raw(&stored.bob_address)
+        .map_err(|_| Error::InvalidChannel("Bob's address"))?;
+
+    let public_key = decode_point(&stored.public_key).ok_or(Error::InvalidChannel("public key"))?;
+    let own = keystore
+        .channel_keys(stored.key_index, &public_key)
+        .ok_or(Error::KeysNotFound)?;
+    let their_public_key = decode_point(&stored.their_public_key)
+        .ok_or(Error::InvalidChannel("public key of the other party"))?;
+    let their_view_secret = decode_scalar(&stored.their_view_secret)
+        .ok_or(Error::InvalidChannel("view secret of the other party"))?;
+
+    let mut keys = Keys::new(
+        role.clone(),
+        params,
+        alice_address,
+        bob_address,
+        own,
+        their_public_key,
+        their_view_secret,
+    );
+    if !stored.joint_tag.is_empty() {
+        let joint_tag = decode_point(&stored.joint_tag);
+        keys.joint_tag = Some(joint_tag.ok_or(Error::InvalidChannel("joint tag"))?);
+    }
+    keys.vtdlog_opened = stored.vtdlog_opened;
+    keys.from_height = stored.from_height as usize;
+
+    match stored.phase() {
+        ChannelPhase::KeysExchanged => {
+            return Ok(Channel::from_phase(role, Phase::KeysExchanged(keys)))
+        }
+        ChannelPhase::Unspecified => return Err(Error::InvalidChannel("phase")),
+        _ => (),
+    }
+
+    let output = stored
+        .output
+        .as_ref()
+        .ok_or(Error::InvalidChannel("joint output"))?;
+    let mut funding = Funding {
+        joint_tag: keys.joint_tag.ok_or(Error::InvalidChannel("joint tag"))?,
+        keys,
+        output: JointOutput {
+            key: decode_point(&output.key).ok_or(Error::InvalidChannel("joint output"))?,
+            key_offset: decode_scalar(&output.key_offset)
+                .ok_or(Error::InvalidChannel("joint output"))?,
+            commitment: Commitment::new(
+                decode_scalar(&output.mask).ok_or(Error::InvalidChannel("joint output"))?,
+                output.amount,
+            ),
+        },
+        height: output.height as usize,
+        image: None,
+    };
+    if !output.image.is_empty() {
+        let image = decode_point(&output.image);
+        funding.image = Some(image.ok_or(Error::InvalidChannel("key image of the joint output"))?);
+    }
+
+    let closing = || -> Result<Closing, Error> {
+        let closing = stored
+            .closing
+            .as_ref()
+            .ok_or(Error::InvalidChannel("closing"))?;
+        let state = closing
+            .state
+            .clone()
+            .ok_or(Error::InvalidChannel("closing state"))?;
+
+        let tx = if closing.tx.is_empty() {
+            None
+        } else {
+            let tx = Transaction::read(&mut closing.tx.as_slice());
+            Some(tx.map_err(|_| Error::InvalidChannel("closing transaction"))?)
+        };
+
+        Ok(Closing {
+            state: state.into(),
+            from_height: closing.from_height as usize,
+            tx,
+        })
+    };
+
+    let phase = match stored.phase() {
+        ChannelPhase::Funded => Phase::Funded(funding),
+        ChannelPhase::Open => Phase::Open(funding),
+        ChannelPhase::Closing => Phase::Closing(funding, closing()?),
+        ChannelPhase::Closed => Phase::Closed(funding, closing()?.state),
+        ChannelPhase::Refunded => Phase::Refunded(funding),
+        ChannelPhase::KeysExchanged | ChannelPhase::Unspecified => unreachable!(),
+    };
+
+    Ok(Channel::from_phase(role, phase))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Could not access the channel database: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("The channel database at {0} is corrupted")]
+    Corrupted(PathBuf),
+
+    #[error("The channel is already stored at {0}")]
+    AlreadyStored(PathBuf),
+
+    #[error("Invalid stored channel: bad {0}")]
+    InvalidChannel(&'static str),
+
+    #[error("The keys of the stored channel are neither derived from the seed nor imported")]
+    KeysNotFound,
+
+    #[error("The data directory already has a channel that is not closed")]
+    OtherChannelResumed,
+
+    #[error(transparent)]
+    Channel(#[from] channel::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::utils::temp_dir;
+
+    // a store of a channel that is already open, without going through its setup
+    fn open_store(dir: &Path) -> ChannelStore {
+        let mut stored = msgs::StoredChannel {
+            alice_address: "alice".into(),
+            their_node_key: vec![9; 32],
+            ..Default::default()
+        };
+        stored.set_role(msgs::ChannelRole::Alice);
+        stored.set_phase(msgs::ChannelPhase::Open);
+
+        let mut store = ChannelStore::new(dir.into());
+        store.append(Update::Channel(stored)).unwrap();
+
+        store
+    }
+
+    fn pay(store: &mut ChannelStore, numbers: std::ops::RangeInclusive<u64>) {
+        for number in numbers {
+            let state = ChannelState {
+                number,
+                bob_amount: number * 1_000,
+            };
+            store.save_payment(&state).unwrap();
+        }
+    }
+
+    #[test]
+    fn changes_are_reloaded() {
+        let dir = temp_dir();
+        let mut store = open_store(&dir);
+
+        pay(&mut store, 1..=3);
+        store
+            .save_bob_vtdlog(Default::default(), Default::default())
+            .unwrap();
+
+        let loaded = ChannelStore::load(dir.to_path_buf()).unwrap().unwrap();
+        assert_eq!(loaded.stored, store.stored);
+        assert_eq!(loaded.payment().number, 3);
+        assert_eq!(loaded.role(), msgs::ChannelRole::Alice);
+        assert_eq!(loaded.their_node_key(), Some([9; 32]));
+        assert!(loaded.is_resumable());
+        assert!(loaded.bob_vtdlog().is_some());
+
+        let empty = temp_dir();
+        assert!(ChannelStore::load(empty.to_path_buf()).unwrap().is_none());
+    }
+
+    #[test]
+    fn snapshots_empty_the_journal() {
+        let dir = temp_dir();
+        let mut store = open_store(&dir).with_interval(4);
+
+        pay(&mut store, 1..=9);
+        assert_eq!(store.journaled, 2);
+
+        let loaded = ChannelStore::load(dir.to_path_buf()).unwrap().unwrap();
+        assert_eq!(loaded.seq, 10);
+        assert_eq!(loaded.payment().number, 9);
+        assert_eq!(loaded.stored.alice_address, "alice");
+
+        // a crash right after a snapshot leaves the journal it already has
+        let journal = fs::read(store.journal_path()).unwrap();
+        pay(&mut store, 10..=11);
+        assert_eq!(store.journaled, 0);
+        fs::write(store.journal_path(), journal).unwrap();
+
+        let loaded = ChannelStore::load(dir.to_path_buf()).unwrap().unwrap();
+        assert_eq!(loaded.seq, 12);
+        assert_eq!(loaded.payment().number, 11);
+    }
+
+    #[test]
+    fn partial_entries_are_discarded() {
+        let dir = temp_dir();
+        let mut store = open_store(&dir);
+        pay(&mut store, 1..=2);
+
+        let journal_path = store.journal_path();
+        let complete = fs::read(&journal_path).unwrap();
+        pay(&mut store, 3..=3);
+
+        let mut journal = fs::read(&journal_path).unwrap();
+        journal.truncate(journal.len() - 5);
+        fs::write(&journal_path, &journal).unwrap();
+
+        let mut loaded = ChannelStore::load(dir.to_path_buf()).unwrap().unwrap();
+        assert_eq!(loaded.payment().number, 2);
+        assert_eq!(fs::read(&journal_path).unwrap(), complete);
+
+        // the journal goes on after the last complete entry
+        pay(&mut loaded, 3..=4);
+        let loaded = ChannelStore::load(dir.to_path_buf()).unwrap().unwrap();
+        assert_eq!(loaded.payment().number, 4);
+    }
+
+    #[test]
+    fn corrupted_snapshots_are_rejected() {
+        let dir = temp_dir();
+        let mut store = open_store(&dir).with_interval(2);
+        pay(&mut store, 1..=1);
+
+        let mut snapshot = fs::read(store.snapshot_path()).unwrap();
+        snapshot[CHECKSUM_BYTES] ^= 1;
+        fs::write(store.snapshot_path(), snapshot).unwrap();
+
+        assert!(matches!(
+            ChannelStore::load(dir.to_path_buf()),
+            Err(Error::Corrupted(_))
+        ));
+    }
+}
@@ -0,0 +1,376 @@
+#![allow(non_snake_case)]
+// two-party CLSAG spending the joint output, the counterpart of the pre-signatures in
+// `adaptor`: both use the same CLSAG context and transcript, and the final signature is
+// checked with monero-serai's `Clsag::verify` before it is published.
+//
+// monero-serai's ClsagMultisig is not used on purpose: it signs with FROST threshold keys,
+// whose shares are weighted by Lagrange coefficients (or by key aggregation coefficients,
+// for n-of-n keys), while the key of the joint output is the plain sum of a, b and the key
+// offset; the joint address is derived from it, and b is the share locked in the VTD-Log.
+// it also has no adaptor variant, which the payments need.
+
+use curve25519_dalek::{
+    constants::ED25519_BASEPOINT_TABLE, edwards::EdwardsPoint, scalar::Scalar, traits::IsIdentity,
+};
+use monero_serai::{
+    random_scalar,
+    ringct::{clsag::Clsag, hash_to_point},
+    Commitment,
+};
+use rand_core::{CryptoRng, RngCore};
+use zeroize::Zeroizing;
+
+use super::adaptor::{inv_eight, ClsagContext, Transcript};
+use super::utils::{decode_point, hash};
+use crate::msgs;
+
+const NONCES_DOMAIN: &[u8] = b"PayMo_jspend_nonces";
+
+/// The output at the joint address spent by the channel, which both parties find with the
+/// joint view key.
+#[derive(Clone, Debug)]
+pub struct JointOutput {
+    /// One-time key P = key_offset * G + joint public key.
+    pub key: EdwardsPoint,
+    pub key_offset: Scalar,
+    pub commitment: Commitment,
+}
+
+/// Nonces of one party, alpha_i * G and alpha_i * H_p(P).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Nonces {
+    pub G: EdwardsPoint,
+    pub H: EdwardsPoint,
+}
+
+impl Nonces {
+    /// Sent before the nonces themselves, so that no party chooses its nonces as a function
+    /// of the ones of the other.
+    pub fn commitment(&self) -> [u8; 32] {
+        let mut to_hash = NONCES_DOMAIN.to_vec();
+        to_hash.extend(self.G.compress().to_bytes());
+        to_hash.extend(self.H.compress().to_bytes());
+
+        hash(&to_hash)
+    }
+}
+
+impl From<&Nonces> for msgs::JointSpendNonces {
+    fn from(nonces: &Nonces) -> Self {
+        msgs::JointSpendNonces {
+            g: nonces.G.compress().to_bytes().to_vec(),
+            h: nonces.H.compress().to_bytes().to_vec(),
+        }
+    }
+}
+
+impl TryFrom<msgs::JointSpendNonces> for Nonces {
+    type Error = Error;
+
+    fn try_from(nonces: msgs::JointSpendNonces) -> Result<Self, Self::Error> {
+        Ok(Nonces {
+            G: decode_point(&nonces.g).ok_or(Error::InvalidNonces)?,
+            H: decode_point(&nonces.h).ok_or(Error::InvalidNonces)?,
+        })
+    }
+}
+
+/// One party of a joint spend, holding its share of the key x of the real input, such that
+/// the shares of both parties add up to x.
+pub struct Signer {
+    key: Zeroizing<Scalar>,
+    alpha: Zeroizing<Scalar>,
+    nonces: Nonces,
+    image: EdwardsPoint,
+}
+
+impl Signer {
+    pub fn new<R: RngCore + CryptoRng>(rng: &mut R, P: &EdwardsPoint, key: Scalar) -> Self {
+        let H = hash_to_point(*P);
+        let alpha = Zeroizing::new(random_scalar(rng));
+
+        Self {
+            key: Zeroizing::new(key),
+            nonces: Nonces {
+                G: &*alpha * &ED25519_BASEPOINT_TABLE,
+                H: *alpha * H,
+            },
+            alpha,
+            image: key * H,
+        }
+    }
+
+    /// Share of the key image, key * H_p(P); the key image is the sum of the shares.
+    pub fn image(&self) -> EdwardsPoint {
+        self.image
+    }
+
+    pub fn nonces(&self) -> Nonces {
+        self.nonces
+    }
+}
+
+/// A CLSAG signed by two parties, once both nonces are known. The party that adds `z` to its
+/// share, the difference between the mask of the real input and the one of the pseudo output,
+/// signs "with the mask".
+pub struct Session {
+    ctx: ClsagContext,
+    z: Scalar,
+    D: EdwardsPoint,
+    s: Vec<Scalar>,
+    c1: Scalar,
+    c: Scalar,
+    mu_P: Scalar,
+    mu_C: Scalar,
+}
+
+impl Session {
+    /// `responses` are the responses of the decoys, random and chosen by one of the parties;
+    /// the one of the real member is ignored.
+    pub fn new(
+        ctx: ClsagContext,
+        z: Scalar,
+        responses: Vec<Scalar>,
+        nonces: &[Nonces],
+    ) -> Result<Self, Error> {
+        let n = ctx.ring.len();
+
+        if n == 0 || responses.len() != n || ctx.index >= n || ctx.image.is_identity() {
+            return Err(Error::InvalidContext);
+        }
+
+        let D = z * hash_to_point(ctx.ring[ctx.index][0]);
+        let transcript = Transcript::new(&ctx, &(D * inv_eight()));
+
+        let L: EdwardsPoint = nonces.iter().map(|nonces| nonces.G).sum();
+        let R: EdwardsPoint = nonces.iter().map(|nonces| nonces.H).sum();
+        let (c1, c) = transcript.around_ring(&ctx, &D, &responses, &L, &R);
+
+        Ok(Self {
+            ctx,
+            z,
+            D,
+            s: responses,
+            c1,
+            c,
+            mu_P: transcript.mu_P,
+            mu_C: transcript.mu_C,
+        })
+    }
+
+    pub fn ctx(&self) -> &ClsagContext {
+        &self.ctx
+    }
+
+    /// Share of the response of the real member; consumes the signer, whose nonces must never
+    /// be used twice.
+    pub fn sign(&self, signer: Signer, with_mask: bool) -> Scalar {
+        let mut key = Zeroizing::new(self.mu_P * *signer.key);
+        if with_mask {
+            *key += self.mu_C * self.z;
+        }
+
+        *signer.alpha - self.c * *key
+    }
+
+    /// Checks the share of the party with public key share `public` = key * G, image share
+    /// `image` and nonces `nonces`.
+    pub fn verify_share(
+        &self,
+        public: &EdwardsPoint,
+        image: &EdwardsPoint,
+        nonces: &Nonces,
+        share: &Scalar,
+        with_mask: bool,
+    ) -> bool {
+        let H = hash_to_point(self.ctx.ring[self.ctx.index][0]);
+
+        let c_P = self.c * self.mu_P;
+        let mut L = share * &ED25519_BASEPOINT_TABLE + c_P * public;
+        let mut R = share * H + c_P * image;
+
+        if with_mask {
+            let c_C = self.c * self.mu_C;
+            L += &(c_C * self.z) * &ED25519_BASEPOINT_TABLE;
+            R += c_C * self.D;
+        }
+
+        L == nonces.G && R == nonces.H
+    }
+
+    /// CLSAG with the shares of both parties; it only verifies if both shares do.
+    pub fn complete(&self, shares: &[Scalar]) -> Clsag {
+        let mut s = self.s.clone();
+        s[self.ctx.index] = shares.iter().sum();
+
+        Clsag {
+            D: self.D * inv_eight(),
+            s,
+            c1: self.c1,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("The ring, responses or key image of the joint spend are invalid")]
+    InvalidContext,
+
+    #[error("Invalid nonces")]
+    InvalidNonces,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_core::OsRng;
+
+    const RING_LEN: usize = 11;
+
+    struct Fixture {
+        ctx: ClsagContext,
+        alice: Scalar,
+        bob: Scalar,
+        z: Scalar,
+    }
+
+    // Bob's share includes the key offset of the output
+    fn fixture(index: usize) -> Fixture {
+        let alice = random_scalar(&mut OsRng);
+        let bob = random_scalar(&mut OsRng) + random_scalar(&mut OsRng);
+
+        let amount = 1_000_000;
+        let mask = random_scalar(&mut OsRng);
+        let pseudo_mask = random_scalar(&mut OsRng);
+
+        let mut ring: Vec<[EdwardsPoint; 2]> = (0..RING_LEN)
+            .map(|_| {
+                [
+                    &random_scalar(&mut OsRng) * &ED25519_BASEPOINT_TABLE,
+                    &random_scalar(&mut OsRng) * &ED25519_BASEPOINT_TABLE,
+                ]
+            })
+            .collect();
+
+        let P = &(alice + bob) * &ED25519_BASEPOINT_TABLE;
+        ring[index] = [P, Commitment::new(mask, amount).calculate()];
+
+        let ctx = ClsagContext {
+            ring,
+            index,
+            image: (alice + bob) * hash_to_point(P),
+            pseudo_out: Commitment::new(pseudo_mask, amount).calculate(),
+            msg: random_scalar(&mut OsRng).to_bytes(),
+        };
+
+        Fixture {
+            ctx,
+            alice,
+            bob,
+            z: mask - pseudo_mask,
+        }
+    }
+
+    fn responses() -> Vec<Scalar> {
+        (0..RING_LEN).map(|_| random_scalar(&mut OsRng)).collect()
+    }
+
+    #[test]
+    fn joint_signature_verifies() {
+        for index in [0, 4, RING_LEN - 1] {
+            let f = fixture(index);
+            let P = f.ctx.ring[index][0];
+
+            let alice = Signer::new(&mut OsRng, &P, f.alice);
+            let bob = Signer::new(&mut OsRng, &P, f.bob);
+            assert_eq!(alice.image() + bob.image(), f.ctx.image);
+
+            let (alice_image, alice_nonces) = (alice.image(), alice.nonces());
+            let (bob_image, bob_nonces) = (bob.image(), bob.nonces());
+
+            let nonces = [bob_nonces, alice_nonces];
+            let session = Session::new(f.ctx.clone(), f.z, responses(), &nonces).unwrap();
+
+            let alice_share = session.sign(alice, false);
+            let bob_share = session.sign(bob, true);
+
+            let alice_public = &f.alice * &ED25519_BASEPOINT_TABLE;
+            let bob_public = &f.bob * &ED25519_BASEPOINT_TABLE;
+
+            assert!(session.verify_share(
+                &alice_public,
+                &alice_image,
+                &alice_nonces,
+                &alice_share,
+                false
+            ));
+            assert!(session.verify_share(&bob_public, &bob_image, &bob_nonces, &bob_share, true));
+
+            let clsag = session.complete(&[alice_share, bob_share]);
+            assert!(clsag
+                .verify(&f.ctx.ring, &f.ctx.image, &f.ctx.pseudo_out, &f.ctx.msg)
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn invalid_shares_are_rejected() {
+        let f = fixture(2);
+        let P = f.ctx.ring[2][0];
+
+        let alice = Signer::new(&mut OsRng, &P, f.alice);
+        let bob = Signer::new(&mut OsRng, &P, f.bob);
+
+        let (alice_image, alice_nonces) = (alice.image(), alice.nonces());
+        let nonces = [alice_nonces, bob.nonces()];
+        let session = Session::new(f.ctx.clone(), f.z, responses(), &nonces).unwrap();
+
+        let alice_public = &f.alice * &ED25519_BASEPOINT_TABLE;
+        let alice_share = session.sign(alice, false);
+
+        let tampered = alice_share + Scalar::one();
+        assert!(!session.verify_share(
+            &alice_public,
+            &alice_image,
+            &alice_nonces,
+            &tampered,
+            false
+        ));
+
+        // the mask is only added by one of the parties
+        assert!(!session.verify_share(
+            &alice_public,
+            &alice_image,
+            &alice_nonces,
+            &alice_share,
+            true
+        ));
+
+        let clsag = session.complete(&[tampered, session.sign(bob, true)]);
+        assert!(clsag
+            .verify(&f.ctx.ring, &f.ctx.image, &f.ctx.pseudo_out, &f.ctx.msg)
+            .is_err());
+    }
+
+    #[test]
+    fn nonces_roundtrip() {
+        let P = &random_scalar(&mut OsRng) * &ED25519_BASEPOINT_TABLE;
+        let nonces = Signer::new(&mut OsRng, &P, random_scalar(&mut OsRng)).nonces();
+
+        let swapped = Nonces {
+            G: nonces.H,
+            H: nonces.G,
+        };
+
+        assert_ne!(nonces.commitment(), swapped.commitment());
+
+        let msg = msgs::JointSpendNonces::from(&nonces);
+        assert_eq!(Nonces::try_from(msg.clone()).unwrap(), nonces);
+
+        let truncated = msgs::JointSpendNonces {
+            g: msg.g[1..].to_vec(),
+            ..msg
+        };
+        assert!(Nonces::try_from(truncated).is_err());
+    }
+}
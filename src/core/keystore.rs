@@ -0,0 +1,339 @@
+use std::path::{Path, PathBuf};
+
+use curve25519_dalek::{constants::ED25519_BASEPOINT_TABLE, edwards::EdwardsPoint};
+use prost::Message;
+use zeroize::{Zeroize, Zeroizing};
+
+use super::channel::OwnKeys;
+use super::sealed::{self, Format, KdfCost, SealingKey, DEFAULT_KDF_COST};
+use super::seed::MasterSeed;
+use super::utils::decode_scalar;
+use crate::msgs;
+
+pub const VERSION: u32 = 2;
+
+// without a domain, as the keystores written before the backups
+const FORMAT: Format = Format {
+    domain: b"",
+    version: VERSION,
+};
+
+/// Seed of the channel keys, kept encrypted in `data_dir/keystore` under a key derived from a
+/// passphrase, with the index of the next channel and the keys of the channels imported from
+/// the backups of another seed. Every change rewrites the whole keystore, with a new nonce.
+pub struct Keystore {
+    path: PathBuf,
+    key: SealingKey,
+
+    seed: MasterSeed,
+    next_index: u32,
+    imported: Vec<OwnKeys>,
+}
+
+impl Keystore {
+    pub fn exists(data_dir: &Path) -> bool {
+        keystore_path(data_dir).exists()
+    }
+
+    /// A keystore of `seed`, protected by `passphrase`; the channels below `next_index` were
+    /// already opened with it.
+    pub fn create(
+        data_dir: &Path,
+        passphrase: &str,
+        seed: MasterSeed,
+        next_index: u32,
+    ) -> Result<Self, Error> {
+        Self::create_with_cost(data_dir, passphrase, seed, next_index, DEFAULT_KDF_COST)
+    }
+
+    pub fn create_with_cost(
+        data_dir: &Path,
+        passphrase: &str,
+        seed: MasterSeed,
+        next_index: u32,
+        cost: KdfCost,
+    ) -> Result<Self, Error> {
+        let path = keystore_path(data_dir);
+        if path.exists() {
+            return Err(Error::AlreadyExists(path));
+        }
+
+        let keystore = Self {
+            key: SealingKey::new(passphrase, cost)?,
+            path,
+            seed,
+            next_index,
+            imported: vec![],
+        };
+        keystore.write()?;
+
+        Ok(keystore)
+    }
+
+    /// Decrypts the keystore of the data directory; a wrong passphrase and a modified keystore
+    /// are told apart by nothing but the authentication tag, so both fail the same way.
+    pub fn unlock(data_dir: &Path, passphrase: &str) -> Result<Self, Error> {
+        let path = keystore_path(data_dir);
+
+        let (plaintext, key) = sealed::open(FORMAT, &path, passphrase)?;
+
+        let mut secrets = msgs::KeystoreSecrets::decode(plaintext.as_slice())
+            .map_err(|_| Error::Corrupted(path.clone()))?;
+        let seed = MasterSeed::from_bytes(&secrets.seed);
+        let imported = secrets
+            .imported
+            .iter()
+            .map(imported_keys)
+            .collect::<Option<Vec<_>>>();
+        zeroize_secrets(&mut secrets);
+
+        Ok(Self {
+            seed: seed.ok_or_else(|| Error::Corrupted(path.clone()))?,
+            next_index: secrets.next_index,
+            imported: imported.ok_or_else(|| Error::Corrupted(path.clone()))?,
+            path,
+            key,
+        })
+    }
+
+    pub fn seed(&self) -> &MasterSeed {
+        &self.seed
+    }
+
+    /// Keys of a new channel; its index is saved before they are used anywhere, so that no
+    /// two channels get the same keys.
+    pub fn new_channel_keys(&mut self) -> Result<OwnKeys, Error> {
+        let index = self.next_index;
+        self.next_index = index.checked_add(1).ok_or(Error::NoMoreChannels)?;
+        self.write()?;
+
+        Ok(self.seed.channel_keys(index))
+    }
+
+    /// Our keys whose public key is `public_key`: the ones derived from the seed with `index`,
+    /// or imported ones.
+    pub fn channel_keys(&self, index: u32, public_key: &EdwardsPoint) -> Option<OwnKeys> {
+        let derived = self.seed.channel_keys(index);
+        if derived.public_key == *public_key {
+            return Some(derived);
+        }
+
+        self.imported
+            .iter()
+            .find(|keys| keys.public_key == *public_key)
+            .cloned()
+    }
+
+    /// Keeps the keys of a channel from the backup of another data directory, unless they
+    /// are already here.
+    pub fn import_keys(&mut self, keys: &OwnKeys) -> Result<(), Error> {
+        if self.channel_keys(keys.index, &keys.public_key).is_none() {
+            self.imported.push(keys.clone());
+            self.write()?;
+        }
+
+        Ok(())
+    }
+
+    // written to a temporary file that then replaces the old keystore
+    fn write(&self) -> Result<(), Error> {
+        let mut secrets = msgs::KeystoreSecrets {
+            seed: self.seed.as_bytes().to_vec(),
+            next_index: self.next_index,
+            imported: self
+                .imported
+                .iter()
+                .map(|keys| msgs::ImportedKeys {
+                    index: keys.index,
+                    secret: keys.secret.to_bytes().to_vec(),
+                    view_secret: keys.view_secret.to_bytes().to_vec(),
+                })
+                .collect(),
+        };
+        let plaintext = Zeroizing::new(secrets.encode_to_vec());
+        zeroize_secrets(&mut secrets);
+
+        self.key.seal(FORMAT, &self.path, &plaintext)?;
+
+        Ok(())
+    }
+}
+
+fn keystore_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("keystore")
+}
+
+fn imported_keys(imported: &msgs::ImportedKeys) -> Option<OwnKeys> {
+    let secret = Zeroizing::new(decode_scalar(&imported.secret)?);
+    let view_secret = Zeroizing::new(decode_scalar(&imported.view_secret)?);
+
+    Some(OwnKeys {
+        index: imported.index,
+        public_key: &*secret * &ED25519_BASEPOINT_TABLE,
+        secret,
+        view_secret,
+    })
+}
+
+fn zeroize_secrets(secrets: &mut msgs::KeystoreSecrets) {
+    secrets.seed.zeroize();
+    for imported in &mut secrets.imported {
+        imported.secret.zeroize();
+        imported.view_secret.zeroize();
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Sealed(#[from] sealed::Error),
+
+    #[error("A keystore already exists at {0}")]
+    AlreadyExists(PathBuf),
+
+    #[error("Corrupted keystore: {0}")]
+    Corrupted(PathBuf),
+
+    #[error("Every channel index of the seed was already used")]
+    NoMoreChannels,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::core::utils::temp_dir;
+
+    // cheap enough for tests
+    const TEST_COST: KdfCost = KdfCost {
+        m_cost: 64,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    fn seed() -> MasterSeed {
+        MasterSeed::generate().0
+    }
+
+    #[test]
+    fn seed_is_unlocked() {
+        let dir = temp_dir();
+        let seed = seed();
+
+        let mut keystore =
+            Keystore::create_with_cost(&dir, "passphrase", seed.clone(), 5, TEST_COST).unwrap();
+        let keys = keystore.new_channel_keys().unwrap();
+        assert_eq!(keys.index, 5);
+        assert_eq!(*keys.secret, *seed.channel_keys(5).secret);
+
+        let mut keystore = Keystore::unlock(&dir, "passphrase").unwrap();
+        assert_eq!(keystore.seed().as_bytes(), seed.as_bytes());
+
+        // the index of the last channel is not given again
+        let keys = keystore.new_channel_keys().unwrap();
+        assert_eq!(keys.index, 6);
+    }
+
+    #[test]
+    fn imported_keys_are_unlocked() {
+        let dir = temp_dir();
+        let seed = seed();
+        let other = MasterSeed::generate().0.channel_keys(2);
+
+        let mut keystore =
+            Keystore::create_with_cost(&dir, "passphrase", seed.clone(), 0, TEST_COST).unwrap();
+        assert!(keystore.channel_keys(2, &other.public_key).is_none());
+        keystore.import_keys(&other).unwrap();
+
+        // the ones of the seed are not imported again
+        keystore.import_keys(&seed.channel_keys(4)).unwrap();
+
+        let keystore = Keystore::unlock(&dir, "passphrase").unwrap();
+        let keys = keystore.channel_keys(2, &other.public_key).unwrap();
+        assert_eq!(*keys.secret, *other.secret);
+        assert_eq!(*keys.view_secret, *other.view_secret);
+        assert_eq!(keystore.imported.len(), 1);
+
+        let derived = seed.channel_keys(2);
+        let keys = keystore.channel_keys(2, &derived.public_key).unwrap();
+        assert_eq!(*keys.secret, *derived.secret);
+    }
+
+    #[test]
+    fn seed_is_not_stored_in_the_clear() {
+        let dir = temp_dir();
+        let seed = seed();
+
+        Keystore::create_with_cost(&dir, "passphrase", seed.clone(), 0, TEST_COST).unwrap();
+
+        let bytes = fs::read(keystore_path(&dir)).unwrap();
+        let secret = seed.as_bytes();
+        assert!(!bytes.windows(secret.len()).any(|window| window == secret));
+    }
+
+    #[test]
+    fn wrong_passphrases_are_rejected() {
+        let dir = temp_dir();
+        Keystore::create_with_cost(&dir, "passphrase", seed(), 0, TEST_COST).unwrap();
+
+        assert!(matches!(
+            Keystore::unlock(&dir, "passphrasf"),
+            Err(Error::Sealed(sealed::Error::WrongPassphrase))
+        ));
+    }
+
+    #[test]
+    fn modified_keystores_are_rejected() {
+        let dir = temp_dir();
+        let mut keystore =
+            Keystore::create_with_cost(&dir, "passphrase", seed(), 0, TEST_COST).unwrap();
+        keystore.new_channel_keys().unwrap();
+
+        let path = keystore_path(&dir);
+        let stored = msgs::SealedFile::decode(fs::read(&path).unwrap().as_slice()).unwrap();
+
+        // a cheaper key derivation, with the same ciphertext
+        let mut cheaper = stored.clone();
+        cheaper.kdf.as_mut().unwrap().t_cost = 2;
+        fs::write(&path, cheaper.encode_to_vec()).unwrap();
+        assert!(matches!(
+            Keystore::unlock(&dir, "passphrase"),
+            Err(Error::Sealed(sealed::Error::WrongPassphrase))
+        ));
+
+        // a key derivation too expensive to even try
+        let mut expensive = stored.clone();
+        expensive.kdf.as_mut().unwrap().m_cost = u32::MAX;
+        fs::write(&path, expensive.encode_to_vec()).unwrap();
+        assert!(matches!(
+            Keystore::unlock(&dir, "passphrase"),
+            Err(Error::Sealed(sealed::Error::KdfTooExpensive))
+        ));
+
+        let mut flipped = stored;
+        flipped.ciphertext[0] ^= 1;
+        fs::write(&path, flipped.encode_to_vec()).unwrap();
+        assert!(matches!(
+            Keystore::unlock(&dir, "passphrase"),
+            Err(Error::Sealed(sealed::Error::WrongPassphrase))
+        ));
+    }
+
+    #[test]
+    fn keystores_are_not_overwritten() {
+        let dir = temp_dir();
+        Keystore::create_with_cost(&dir, "passphrase", seed(), 0, TEST_COST).unwrap();
+
+        assert!(matches!(
+            Keystore::create_with_cost(&dir, "other", seed(), 0, TEST_COST),
+            Err(Error::AlreadyExists(_))
+        ));
+
+        let dir = temp_dir();
+        assert!(matches!(
+            Keystore::create_with_cost(&dir, "", seed(), 0, TEST_COST),
+            Err(Error::Sealed(sealed::Error::EmptyPassphrase))
+        ));
+    }
+}
@@ -1,15 +1,50 @@
+use rand_core::{CryptoRng, RngCore};
+use sha3::{Digest, Keccak256};
 use std::mem::MaybeUninit;
 
-mod bindings {
-    #![allow(non_upper_case_globals)]
-    #![allow(non_camel_case_types)]
-    #![allow(non_snake_case)]
-    #![allow(dead_code)]
-    include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+use super::bindings;
+use super::mpz::{export, Mpz};
+use crate::msgs;
+
+// smallest modulus accepted; fast to work with, but only meant for tests
+pub const MIN_SECURITY_PARAM: u64 = 512;
+
+// modulus used by default, for real channels
+pub const DEFAULT_SECURITY_PARAM: u64 = 2048;
+
+// decoded moduli bigger than this are rejected, so a peer cannot make us work with huge numbers
+pub const MAX_MODULUS_BITS: usize = 16384;
+
+/// Parameters used when setting up new puzzles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LhtlpParams {
+    /// Bit length of the RSA modulus N.
+    pub security_param: u64,
+}
+
+impl LhtlpParams {
+    /// Smallest parameters accepted, only meant for tests.
+    pub const MIN: Self = Self {
+        security_param: MIN_SECURITY_PARAM,
+    };
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.security_param < MIN_SECURITY_PARAM || self.security_param > MAX_MODULUS_BITS as u64
+        {
+            return Err(Error::InvalidSecurityParam(self.security_param));
+        }
+
+        Ok(())
+    }
 }
 
-// in practice, this number would be much higher (1024, 2048, etc)
-pub const SECURITY_PARAM: u64 = 512;
+impl Default for LhtlpParams {
+    fn default() -> Self {
+        Self {
+            security_param: DEFAULT_SECURITY_PARAM,
+        }
+    }
+}
 
 pub struct Puzzle {
     pp: MaybeUninit<bindings::LHP_param_t>,
@@ -21,17 +56,193 @@ pub struct Solution {
     s: MaybeUninit<bindings::LHP_puzzle_sol_t>,
 }
 
+/// Randomness used when generating a puzzle; revealing it together with the secret
+/// allows anyone to check which secret the puzzle hides, without solving it.
+#[derive(Clone)]
+pub struct Opening {
+    pub r: Vec<u8>,
+
+    /// Prime factor p of N, for puzzles with their own public parameters; it allows
+    /// anyone to check that h = g^(2^T), i.e. that the puzzle takes T squarings to solve.
+    pub factor: Option<Vec<u8>>,
+}
+
 type _Param = MaybeUninit<bindings::LHP_param_t>;
 type _Puzzle = MaybeUninit<bindings::LHP_puzzle_t>;
 
+// canonical integers are big-endian and have no leading zeros; zero is never valid here
+fn check_canonical(bytes: &[u8]) -> Result<(), Error> {
+    if bytes.first().map_or(true, |b| *b == 0) {
+        return Err(Error::InvalidEncoding);
+    }
+
+    Ok(())
+}
+
+// 0 < x < m and gcd(x, m) = 1
+unsafe fn is_unit(x: *const bindings::__mpz_struct, m: *const bindings::__mpz_struct) -> bool {
+    let mut gcd = Mpz::new();
+    bindings::__gmpz_gcd(gcd.as_mut_ptr(), x, m);
+
+    bindings::__gmpz_cmp_ui(x, 0) > 0
+        && bindings::__gmpz_cmp(x, m) < 0
+        && bindings::__gmpz_cmp_ui(gcd.as_ptr(), 1) == 0
+}
+
+// r is sampled from [0, N^2); the extra bytes make the modular bias negligible
+unsafe fn sample_randomness<R: RngCore + CryptoRng>(rng: &mut R, params: &_Param) -> Mpz {
+    let pp = params.assume_init_ref();
+
+    let n_len = export(pp.N.as_ptr()).len();
+    let mut r = vec![0u8; 2 * n_len + 16];
+    rng.fill_bytes(&mut r);
+
+    let mut n_squared = Mpz::new();
+    bindings::__gmpz_mul(n_squared.as_mut_ptr(), pp.N.as_ptr(), pp.N.as_ptr());
+
+    let mut r = Mpz::from_bytes(&r);
+    bindings::__gmpz_mod(r.as_mut_ptr(), r.as_ptr(), n_squared.as_ptr());
+
+    r
+}
+
+unsafe fn clone_params(params: &_Param) -> _Param {
+    let pp = params.assume_init_ref();
+    let mut copy: _Param = MaybeUninit::uninit();
+
+    bindings::LHP_init_param(copy.as_mut_ptr());
+    let c = copy.assume_init_mut();
+
+    bindings::__gmpz_set(c.T.as_mut_ptr(), pp.T.as_ptr());
+    bindings::__gmpz_set(c.N.as_mut_ptr(), pp.N.as_ptr());
+    bindings::__gmpz_set(c.g.as_mut_ptr(), pp.g.as_ptr());
+    bindings::__gmpz_set(c.h.as_mut_ptr(), pp.h.as_ptr());
+
+    copy
+}
+
+// rounds of Miller-Rabin when generating the factors of N, and when checking revealed ones
+const MILLER_RABIN_ROUNDS: i32 = 32;
+
+// random prime of exactly `bits` bits; the two top bits are set, so that the product of two
+// of them has exactly as many bits as they add up to
+fn random_prime<R: RngCore + CryptoRng>(rng: &mut R, bits: u64) -> Mpz {
+    loop {
+        let mut bytes = vec![0u8; ((bits + 7) / 8) as usize];
+        rng.fill_bytes(&mut bytes);
+        bytes[0] &= 0xff >> (bytes.len() as u64 * 8 - bits);
+
+        let mut p = Mpz::from_bytes(&bytes);
+        p.set_bit(bits - 1);
+        p.set_bit(bits - 2);
+        p.set_bit(0);
+
+        if p.is_probable_prime(MILLER_RABIN_ROUNDS) {
+            return p;
+        }
+    }
+}
+
+// 2^T mod phi(N), so that h = g^(2^T) can be computed without the T squarings
+fn setup_exponent(time: &Mpz, p: &Mpz, q: &Mpz) -> Mpz {
+    let one = Mpz::from_i64(1);
+    let phi = p.sub(&one).mul(&q.sub(&one));
+
+    Mpz::from_i64(2).powm(time, &phi)
+}
+
+// same parameters as `LHP_PSetup`, but drawn from `rng`, and returned along with p, which
+// proves that the setup is honest once revealed (see `Puzzle::check_setup`):
+//     N = p * q, g = -x^2 mod N, h = g^(2^T) mod N
+unsafe fn setup_with_factor<R: RngCore + CryptoRng>(
+    rng: &mut R,
+    security_param: u64,
+    time: u64,
+) -> (_Param, Mpz) {
+    let p_bits = security_param / 2;
+
+    let (p, q) = loop {
+        let p = random_prime(rng, p_bits);
+        let q = random_prime(rng, security_param - p_bits);
+
+        if p != q {
+            break (p, q);
+        }
+    };
+    let n = p.mul(&q);
+
+    let g = loop {
+        let mut x = vec![0u8; n.to_bytes().len() + 16];
+        rng.fill_bytes(&mut x);
+
+        let x = Mpz::from_bytes(&x).modulo(&n);
+        let g = x.mul(&x).neg().modulo(&n);
+
+        if is_unit(g.as_ptr(), n.as_ptr()) {
+            break g;
+        }
+    };
+
+    let time = Mpz::from_bytes(&time.to_be_bytes());
+    let h = g.powm(&setup_exponent(&time, &p, &q), &n);
+
+    let mut params: _Param = MaybeUninit::uninit();
+    bindings::LHP_init_param(params.as_mut_ptr());
+
+    let pp = params.assume_init_mut();
+    bindings::__gmpz_set(pp.T.as_mut_ptr(), time.as_ptr());
+    bindings::__gmpz_set(pp.N.as_mut_ptr(), n.as_ptr());
+    bindings::__gmpz_set(pp.g.as_mut_ptr(), g.as_ptr());
+    bindings::__gmpz_set(pp.h.as_mut_ptr(), h.as_ptr());
+
+    (params, p)
+}
+
+// same computation as `LHP_PGen`, but with the randomness `r` chosen by us, so that
+// the puzzle can later be opened:
+//     u = g^r mod N
+//     v = h^(r * N) * (1 + N)^s mod N^2
+unsafe fn gen_with_randomness(params: &_Param, secret: &[u8], r: &Mpz) -> _Puzzle {
+    let pp = params.assume_init_ref();
+    let mut puzzle: _Puzzle = MaybeUninit::uninit();
+
+    bindings::LHP_init_puzzle(puzzle.as_mut_ptr());
+    let z = puzzle.assume_init_mut();
+
+    let mut n_squared = Mpz::new();
+    bindings::__gmpz_mul(n_squared.as_mut_ptr(), pp.N.as_ptr(), pp.N.as_ptr());
+
+    bindings::__gmpz_powm(z.u.as_mut_ptr(), pp.g.as_ptr(), r.as_ptr(), pp.N.as_ptr());
+
+    let mut exponent = Mpz::new();
+    bindings::__gmpz_mul(exponent.as_mut_ptr(), r.as_ptr(), pp.N.as_ptr());
+    bindings::__gmpz_powm(
+        z.v.as_mut_ptr(),
+        pp.h.as_ptr(),
+        exponent.as_ptr(),
+        n_squared.as_ptr(),
+    );
+
+    // (1 + N)^s = 1 + s * N mod N^2
+    let s = Mpz::from_bytes(secret);
+    let mut one_plus_sn = Mpz::new();
+    bindings::__gmpz_mul(one_plus_sn.as_mut_ptr(), s.as_ptr(), pp.N.as_ptr());
+    bindings::__gmpz_add_ui(one_plus_sn.as_mut_ptr(), one_plus_sn.as_ptr(), 1);
+
+    bindings::__gmpz_mul(z.v.as_mut_ptr(), z.v.as_ptr(), one_plus_sn.as_ptr());
+    bindings::__gmpz_mod(z.v.as_mut_ptr(), z.v.as_ptr(), n_squared.as_ptr());
+
+    puzzle
+}
+
 impl Puzzle {
-    pub fn single(time: u64, secret: &[u8]) -> Self {
+    pub fn single(setup: LhtlpParams, time: u64, secret: &[u8]) -> Self {
         let mut params: _Param = MaybeUninit::uninit();
         let mut puzzle: _Puzzle = MaybeUninit::uninit();
 
         unsafe {
             bindings::LHP_init_param(params.as_mut_ptr());
-            bindings::LHP_PSetup(params.as_mut_ptr(), SECURITY_PARAM, time);
+            bindings::LHP_PSetup(params.as_mut_ptr(), setup.security_param, time);
 
             bindings::LHP_init_puzzle(puzzle.as_mut_ptr());
 
@@ -49,7 +260,36 @@ impl Puzzle {
         }
     }
 
-    pub fn batch(time: u64, secrets: &[&[u8]]) -> Self {
+    /// Same as [`Puzzle::single`], but also returns the randomness used and the factor of N,
+    /// so that the creator can later prove which secret the puzzle hides, and that it was
+    /// set up honestly; everything is drawn from `rng`.
+    pub fn single_with_opening<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        setup: LhtlpParams,
+        time: u64,
+        secret: &[u8],
+    ) -> (Self, Opening) {
+        unsafe {
+            let (params, p) = setup_with_factor(rng, setup.security_param, time);
+
+            let r = sample_randomness(rng, &params);
+            let puzzle = gen_with_randomness(&params, secret, &r);
+            let opening = Opening {
+                r: r.to_bytes(),
+                factor: Some(p.to_bytes()),
+            };
+
+            (
+                Self {
+                    pp: params,
+                    z: puzzle,
+                },
+                opening,
+            )
+        }
+    }
+
+    pub fn batch(setup: LhtlpParams, time: u64, secrets: &[&[u8]]) -> Self {
         let mut params: _Param = MaybeUninit::uninit();
         let mut final_puzzle: _Puzzle = MaybeUninit::uninit();
 
@@ -58,7 +298,7 @@ impl Puzzle {
 
         unsafe {
             bindings::LHP_init_param(params.as_mut_ptr());
-            bindings::LHP_PSetup(params.as_mut_ptr(), SECURITY_PARAM, time);
+            bindings::LHP_PSetup(params.as_mut_ptr(), setup.security_param, time);
 
             bindings::LHP_init_puzzle(final_puzzle.as_mut_ptr());
 
@@ -91,6 +331,116 @@ impl Puzzle {
         }
     }
 
+    /// Homomorphically adds puzzles that share the same public parameters.
+    pub fn eval(puzzles: &[&Puzzle]) -> Self {
+        assert!(!puzzles.is_empty());
+        assert!(puzzles.iter().all(|puzzle| puzzle.same_params(puzzles[0])));
+
+        let mut final_puzzle: _Puzzle = MaybeUninit::uninit();
+
+        unsafe {
+            let mut params = clone_params(&puzzles[0].pp);
+
+            bindings::LHP_init_puzzle(final_puzzle.as_mut_ptr());
+
+            // shallow copies, only read by `LHP_PEval`; they must not be cleared
+            let mut zs: Vec<bindings::LHP_puzzle_t> = puzzles
+                .iter()
+                .map(|puzzle| std::ptr::read(puzzle.z.as_ptr()))
+                .collect();
+
+            bindings::LHP_PEval(
+                params.as_mut_ptr(),
+                zs.as_mut_ptr(),
+                zs.len(),
+                final_puzzle.as_mut_ptr(),
+            );
+
+            Self {
+                pp: params,
+                z: final_puzzle,
+            }
+        }
+    }
+
+    /// Whether both puzzles were generated with the same public parameters.
+    pub fn same_params(&self, other: &Puzzle) -> bool {
+        unsafe {
+            let a = self.pp.assume_init_ref();
+            let b = other.pp.assume_init_ref();
+
+            bindings::__gmpz_cmp(a.T.as_ptr(), b.T.as_ptr()) == 0
+                && bindings::__gmpz_cmp(a.N.as_ptr(), b.N.as_ptr()) == 0
+                && bindings::__gmpz_cmp(a.g.as_ptr(), b.g.as_ptr()) == 0
+                && bindings::__gmpz_cmp(a.h.as_ptr(), b.h.as_ptr()) == 0
+        }
+    }
+
+    /// Bit length of the modulus N.
+    pub fn modulus_bits(&self) -> usize {
+        unsafe {
+            let pp = self.pp.assume_init_ref();
+            bindings::__gmpz_sizeinbase(pp.N.as_ptr(), 2)
+        }
+    }
+
+    /// Number of sequential squarings T needed to solve this puzzle.
+    pub fn time(&self) -> u64 {
+        let T = unsafe { export(self.pp.assume_init_ref().T.as_ptr()) };
+
+        if T.len() > 8 {
+            return u64::MAX;
+        }
+
+        let mut bytes = [0u8; 8];
+        bytes[8 - T.len()..].copy_from_slice(&T);
+
+        u64::from_be_bytes(bytes)
+    }
+
+    /// Starting point of the squarings done by [`Puzzle::square`], u.
+    pub fn squarings_start(&self) -> Vec<u8> {
+        unsafe { export(self.z.assume_init_ref().u.as_ptr()) }
+    }
+
+    /// Performs `steps` more sequential squarings of `w` mod N, as a big-endian integer;
+    /// after T of them, starting from [`Puzzle::squarings_start`], [`Puzzle::finish`]
+    /// recovers the secret. This is what `LHP_PSolve` does, but split in as many calls as
+    /// needed, so that the progress can be saved in between.
+    pub fn square(&self, w: &[u8], steps: u64) -> Vec<u8> {
+        let mut exponent = Mpz::new();
+        exponent.set_bit(steps);
+
+        unsafe {
+            let n = Mpz::from_ptr(self.pp.assume_init_ref().N.as_ptr());
+
+            Mpz::from_bytes(w).powm(&exponent, &n).to_bytes()
+        }
+    }
+
+    /// Secret hidden by the puzzle, given w = u^(2^T) mod N:
+    ///     s = (v / w^N mod N^2 - 1) / N
+    /// or None if `w` does not open the puzzle.
+    pub fn finish(&self, w: &[u8]) -> Option<Vec<u8>> {
+        let (n, v) = unsafe {
+            (
+                Mpz::from_ptr(self.pp.assume_init_ref().N.as_ptr()),
+                Mpz::from_ptr(self.z.assume_init_ref().v.as_ptr()),
+            )
+        };
+        let n_squared = n.mul(&n);
+
+        let w_n = Mpz::from_bytes(w).powm(&n, &n_squared);
+        let one_plus_sn = v.mul(&w_n.invert(&n_squared)?).modulo(&n_squared);
+        let sn = one_plus_sn.sub(&Mpz::from_i64(1));
+
+        if !n.divides(&sn) {
+            return None;
+        }
+
+        Some(sn.div_exact(&n).to_bytes())
+    }
+
     pub fn solve(&mut self) -> Solution {
         let mut s: MaybeUninit<bindings::LHP_puzzle_sol_t> = MaybeUninit::uninit();
 
@@ -98,32 +448,262 @@ impl Puzzle {
             bindings::LHP_init_solution(s.as_mut_ptr());
             bindings::LHP_PSolve(self.pp.as_mut_ptr(), self.z.as_mut_ptr(), s.as_mut_ptr());
 
-            let mpz_struct = s.assume_init().s[0];
+            let val = export(s.assume_init_ref().s.as_ptr());
 
-            let size = 1;
-            let nail = 0;
+            Solution { val, s }
+        }
+    }
 
-            let numb = 8 * size - nail;
+    /// Checks that this puzzle hides `secret`, by regenerating it with the revealed randomness,
+    /// and, if the opening reveals the factor of N, that the puzzle was set up honestly.
+    pub fn verify_opening(&self, secret: &[u8], opening: &Opening) -> bool {
+        if let Some(factor) = &opening.factor {
+            if !self.check_setup(&Mpz::from_bytes(factor)) {
+                return false;
+            }
+        }
 
-            let len_in_bytes = (bindings::__gmpz_sizeinbase(&mpz_struct, 2) + numb - 1) / numb;
+        unsafe {
+            let r = Mpz::from_bytes(&opening.r);
+            let mut expected = gen_with_randomness(&self.pp, secret, &r);
 
-            let mut val: Vec<u8> = Vec::with_capacity(len_in_bytes);
-            let mut countp: usize = 0;
+            let z = self.z.assume_init_ref();
+            let e = expected.assume_init_ref();
 
-            bindings::__gmpz_export(
-                val.as_mut_ptr() as *mut _,
-                &mut countp as *mut _,
-                1,
-                size,
-                0,
-                nail,
-                &mpz_struct,
-            );
+            let matches = bindings::__gmpz_cmp(z.u.as_ptr(), e.u.as_ptr()) == 0
+                && bindings::__gmpz_cmp(z.v.as_ptr(), e.v.as_ptr()) == 0;
 
-            val.set_len(countp);
+            bindings::LHP_clear_puzzle(expected.as_mut_ptr());
+            expected.assume_init_drop();
 
-            Solution { val, s }
+            matches
+        }
+    }
+
+    // N = p * q for two distinct primes, and h = g^(2^T); g is already known to be a unit,
+    // so solving then takes T squarings, and yields the secret
+    fn check_setup(&self, p: &Mpz) -> bool {
+        unsafe {
+            let pp = self.pp.assume_init_ref();
+            let n = Mpz::from_ptr(pp.N.as_ptr());
+
+            if p.bits() < 2 || !p.divides(&n) {
+                return false;
+            }
+
+            let q = n.div_exact(p);
+            if *p == q
+                || !p.is_probable_prime(MILLER_RABIN_ROUNDS)
+                || !q.is_probable_prime(MILLER_RABIN_ROUNDS)
+            {
+                return false;
+            }
+
+            let time = Mpz::from_ptr(pp.T.as_ptr());
+            let h = Mpz::from_ptr(pp.g.as_ptr()).powm(&setup_exponent(&time, p, &q), &n);
+
+            bindings::__gmpz_cmp(h.as_ptr(), pp.h.as_ptr()) == 0
+        }
+    }
+
+    /// Hash of the public parameters and of the puzzle itself.
+    pub fn digest(&self) -> [u8; 32] {
+        Keccak256::digest(self.to_bytes()).into()
+    }
+
+    // T, N, g, h, u and v, as canonical big-endian integers
+    fn integers(&self) -> [Vec<u8>; 6] {
+        unsafe {
+            let pp = self.pp.assume_init_ref();
+            let z = self.z.assume_init_ref();
+
+            [&pp.T, &pp.N, &pp.g, &pp.h, &z.u, &z.v].map(|value| export(value.as_ptr()))
+        }
+    }
+
+    // builds a puzzle from T, N, g, h, u and v, rejecting anything that is not canonical,
+    // or that is not a valid puzzle
+    fn from_integers(integers: [&[u8]; 6]) -> Result<Self, Error> {
+        for integer in integers {
+            check_canonical(integer)?;
+        }
+
+        if integers[1].len() * 8 > MAX_MODULUS_BITS {
+            return Err(Error::InvalidParams);
+        }
+
+        let mut params: _Param = MaybeUninit::uninit();
+        let mut puzzle: _Puzzle = MaybeUninit::uninit();
+
+        unsafe {
+            bindings::LHP_init_param(params.as_mut_ptr());
+            bindings::LHP_init_puzzle(puzzle.as_mut_ptr());
+
+            let pp = params.assume_init_mut();
+            let z = puzzle.assume_init_mut();
+
+            let targets = [
+                pp.T.as_mut_ptr(),
+                pp.N.as_mut_ptr(),
+                pp.g.as_mut_ptr(),
+                pp.h.as_mut_ptr(),
+                z.u.as_mut_ptr(),
+                z.v.as_mut_ptr(),
+            ];
+
+            for (target, integer) in std::iter::zip(targets, integers) {
+                bindings::__gmpz_import(
+                    target,
+                    integer.len(),
+                    1,
+                    1,
+                    0,
+                    0,
+                    integer.as_ptr() as *const _,
+                );
+            }
+
+            // from now on, dropping the puzzle clears the GMP integers
+            let puzzle = Self {
+                pp: params,
+                z: puzzle,
+            };
+
+            let pp = puzzle.pp.assume_init_ref();
+            let z = puzzle.z.assume_init_ref();
+
+            let mut n_squared = Mpz::new();
+            bindings::__gmpz_mul(n_squared.as_mut_ptr(), pp.N.as_ptr(), pp.N.as_ptr());
+
+            let valid_params = bindings::__gmpz_cmp_ui(pp.N.as_ptr(), 1) > 0
+                && bindings::__gmpz_tstbit(pp.N.as_ptr(), 0) == 1
+                && is_unit(pp.g.as_ptr(), pp.N.as_ptr())
+                && is_unit(pp.h.as_ptr(), pp.N.as_ptr());
+
+            if !valid_params {
+                return Err(Error::InvalidParams);
+            }
+
+            if !is_unit(z.u.as_ptr(), pp.N.as_ptr()) || !is_unit(z.v.as_ptr(), n_squared.as_ptr()) {
+                return Err(Error::InvalidPuzzle);
+            }
+
+            Ok(puzzle)
+        }
+    }
+
+    /// Canonical encoding: T, N, g, h, u and v, each one as a big-endian integer without
+    /// leading zeros, prefixed by its length as a big-endian u32.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+
+        for integer in self.integers() {
+            bytes.extend((integer.len() as u32).to_be_bytes());
+            bytes.extend(integer);
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut integers: [&[u8]; 6] = [&[]; 6];
+        let mut rest = bytes;
+
+        for integer in &mut integers {
+            if rest.len() < 4 {
+                return Err(Error::InvalidEncoding);
+            }
+
+            let (len, tail) = rest.split_at(4);
+            let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+
+            if tail.len() < len {
+                return Err(Error::InvalidEncoding);
+            }
+
+            (*integer, rest) = tail.split_at(len);
+        }
+
+        if !rest.is_empty() {
+            return Err(Error::InvalidEncoding);
         }
+
+        Self::from_integers(integers)
+    }
+}
+
+impl Opening {
+    /// Canonical encoding: r, then the factor of N if revealed, each one as a big-endian
+    /// integer without leading zeros, prefixed by its length as a big-endian u32.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+
+        for integer in std::iter::once(&self.r).chain(&self.factor) {
+            bytes.extend((integer.len() as u32).to_be_bytes());
+            bytes.extend(integer);
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut integers = vec![];
+        let mut rest = bytes;
+
+        while !rest.is_empty() && integers.len() < 2 {
+            if rest.len() < 4 {
+                return Err(Error::InvalidEncoding);
+            }
+
+            let (len, tail) = rest.split_at(4);
+            let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+
+            if tail.len() < len {
+                return Err(Error::InvalidEncoding);
+            }
+
+            let (integer, tail) = tail.split_at(len);
+            check_canonical(integer)?;
+
+            integers.push(integer.to_vec());
+            rest = tail;
+        }
+
+        if !rest.is_empty() {
+            return Err(Error::InvalidEncoding);
+        }
+
+        let mut integers = integers.into_iter();
+        let r = integers.next().ok_or(Error::InvalidEncoding)?;
+
+        Ok(Self {
+            r,
+            factor: integers.next(),
+        })
+    }
+}
+
+impl From<&Puzzle> for msgs::LhtlpPuzzle {
+    fn from(puzzle: &Puzzle) -> Self {
+        let [t, n, g, h, u, v] = puzzle.integers();
+
+        msgs::LhtlpPuzzle {
+            params: Some(msgs::LhtlpParams { t, n, g, h }),
+            u,
+            v,
+        }
+    }
+}
+
+impl TryFrom<msgs::LhtlpPuzzle> for Puzzle {
+    type Error = Error;
+
+    fn try_from(puzzle: msgs::LhtlpPuzzle) -> Result<Self, Self::Error> {
+        let params = puzzle.params.ok_or(Error::InvalidParams)?;
+
+        Puzzle::from_integers([
+            &params.t, &params.n, &params.g, &params.h, &puzzle.u, &puzzle.v,
+        ])
     }
 }
 
@@ -148,15 +728,32 @@ impl Drop for Solution {
     }
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid encoding of a time-lock puzzle")]
+    InvalidEncoding,
+
+    #[error("Invalid time-lock puzzle public parameters")]
+    InvalidParams,
+
+    #[error("Invalid time-lock puzzle")]
+    InvalidPuzzle,
+
+    #[error("Invalid time-lock puzzle modulus size: {0} bits")]
+    InvalidSecurityParam(u64),
+}
+
 #[cfg(test)]
 mod tests {
+    use rand_core::OsRng;
+
     use super::*;
     use num_bigint::*;
 
     #[test]
     fn generate_and_solve_puzzle() {
         let secret = b"paymo!";
-        let mut puzzle = Puzzle::single(1000000, secret);
+        let mut puzzle = Puzzle::single(LhtlpParams::MIN, 1000000, secret);
         let solution = puzzle.solve();
 
         assert_eq!(secret, &solution.val[..]);
@@ -165,7 +762,7 @@ mod tests {
     #[test]
     fn generate_and_solve_batch_puzzle() {
         let secrets: Vec<&[u8]> = vec![b"paymo!", b"is", b"cool", b"!!!!", b"SENIOR", b"PROJECT!"];
-        let mut puzzle = Puzzle::batch(1000000, &secrets);
+        let mut puzzle = Puzzle::batch(LhtlpParams::MIN, 1000000, &secrets);
 
         let solution = puzzle.solve();
         let expected: [u8; 8] = [80, 83, 18, 241, 145, 139, 12, 148];
@@ -180,4 +777,147 @@ mod tests {
 
         assert_eq!(expected_sum, secrets_sum);
     }
+
+    #[test]
+    fn open_puzzle() {
+        let secret = b"paymo!";
+        let (mut puzzle, opening) =
+            Puzzle::single_with_opening(&mut OsRng, LhtlpParams::MIN, 1000, secret);
+
+        assert!(puzzle.verify_opening(secret, &opening));
+        assert!(!puzzle.verify_opening(b"paymo?", &opening));
+
+        let solution = puzzle.solve();
+        assert_eq!(secret, &solution.val[..]);
+    }
+
+    #[test]
+    fn open_puzzle_setup() {
+        let secret = b"paymo!";
+        let (puzzle, opening) =
+            Puzzle::single_with_opening(&mut OsRng, LhtlpParams::MIN, 1000, secret);
+        assert_eq!(puzzle.modulus_bits() as u64, MIN_SECURITY_PARAM);
+
+        // h = g^(2^T) with a smaller T than the one claimed; u and v are still the same
+        let mut msg = msgs::LhtlpPuzzle::from(&puzzle);
+        msg.params.as_mut().unwrap().t = 2000u64.to_be_bytes()[6..].to_vec();
+        let longer = Puzzle::try_from(msg).unwrap();
+        assert!(!longer.verify_opening(secret, &opening));
+
+        let without_factor = Opening {
+            factor: None,
+            ..opening.clone()
+        };
+        assert!(longer.verify_opening(secret, &without_factor));
+
+        // the factor must be a prime factor of N
+        let n = Mpz::from_bytes(&puzzle.integers()[1]);
+        for factor in [Mpz::from_i64(1), n.clone(), n.add(&Mpz::from_i64(2))] {
+            let opening = Opening {
+                factor: Some(factor.to_bytes()),
+                ..opening.clone()
+            };
+            assert!(!puzzle.verify_opening(secret, &opening));
+        }
+
+        let decoded = Opening::from_bytes(&opening.to_bytes()).unwrap();
+        assert_eq!(decoded.factor, opening.factor);
+        assert!(Opening::from_bytes(&without_factor.to_bytes())
+            .unwrap()
+            .factor
+            .is_none());
+    }
+
+    #[test]
+    fn solve_in_steps() {
+        let secret = b"paymo!";
+        let puzzle = Puzzle::single(LhtlpParams::MIN, 1000, secret);
+        assert_eq!(puzzle.time(), 1000);
+
+        let mut w = puzzle.squarings_start();
+        for steps in [0, 1, 499, 500] {
+            w = puzzle.square(&w, steps);
+        }
+
+        assert_eq!(puzzle.finish(&w).unwrap(), secret);
+        assert_eq!(puzzle.finish(&puzzle.square(&w, 1)), None);
+    }
+
+    #[test]
+    fn puzzle_bytes_roundtrip() {
+        let secret = b"paymo!";
+        let puzzle = Puzzle::single(LhtlpParams::MIN, 1000, secret);
+
+        let bytes = puzzle.to_bytes();
+        let mut decoded = Puzzle::from_bytes(&bytes).unwrap();
+
+        assert_eq!(bytes, decoded.to_bytes());
+        assert_eq!(puzzle.digest(), decoded.digest());
+
+        let solution = decoded.solve();
+        assert_eq!(secret, &solution.val[..]);
+    }
+
+    #[test]
+    fn puzzle_msg_roundtrip() {
+        let secret = b"paymo!";
+        let (puzzle, opening) =
+            Puzzle::single_with_opening(&mut OsRng, LhtlpParams::MIN, 1000, secret);
+
+        let msg = msgs::LhtlpPuzzle::from(&puzzle);
+        let decoded = Puzzle::try_from(msg).unwrap();
+        let opening = Opening::from_bytes(&opening.to_bytes()).unwrap();
+
+        assert_eq!(puzzle.to_bytes(), decoded.to_bytes());
+        assert!(decoded.verify_opening(secret, &opening));
+    }
+
+    #[test]
+    fn params_are_validated() {
+        assert!(LhtlpParams::MIN.validate().is_ok());
+        assert!(LhtlpParams::default().validate().is_ok());
+
+        let params = LhtlpParams {
+            security_param: MIN_SECURITY_PARAM - 1,
+        };
+        assert!(matches!(
+            params.validate(),
+            Err(Error::InvalidSecurityParam(_))
+        ));
+
+        let params = LhtlpParams {
+            security_param: MAX_MODULUS_BITS as u64 + 1,
+        };
+        assert!(params.validate().is_err());
+    }
+
+    #[test]
+    fn puzzle_decoding_is_strict() {
+        let puzzle = Puzzle::single(LhtlpParams::MIN, 1000, b"paymo!");
+        let bytes = puzzle.to_bytes();
+
+        // truncated and trailing data
+        assert!(Puzzle::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Puzzle::from_bytes(&[&bytes[..], &[0]].concat()).is_err());
+
+        // leading zeros
+        let mut msg = msgs::LhtlpPuzzle::from(&puzzle);
+        msg.u.insert(0, 0);
+        assert!(matches!(Puzzle::try_from(msg), Err(Error::InvalidEncoding)));
+
+        // u must be lower than N
+        let mut msg = msgs::LhtlpPuzzle::from(&puzzle);
+        msg.u = msg.params.as_ref().unwrap().n.clone();
+        assert!(matches!(Puzzle::try_from(msg), Err(Error::InvalidPuzzle)));
+
+        // N must be odd
+        let mut msg = msgs::LhtlpPuzzle::from(&puzzle);
+        *msg.params.as_mut().unwrap().n.last_mut().unwrap() &= 0xfe;
+        assert!(Puzzle::try_from(msg).is_err());
+
+        // missing params
+        let mut msg = msgs::LhtlpPuzzle::from(&puzzle);
+        msg.params = None;
+        assert!(matches!(Puzzle::try_from(msg), Err(Error::InvalidParams)));
+    }
 }
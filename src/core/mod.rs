@@ -1,13 +1,29 @@
 use crate::cli;
-use curve25519_dalek::{edwards::EdwardsPoint, scalar::Scalar};
 use log::debug;
-use monero_serai::wallet::address;
 use std::{env, ffi::OsStr, fmt::Display, process};
 
+pub mod adaptor;
+pub mod backup;
+mod bindings;
+pub mod calibration;
 pub mod channel;
+pub mod checkpoint;
+pub mod classgroup;
+pub mod db;
+pub mod jspend;
+pub mod keystore;
 pub mod lhtlp;
+mod mpz;
+pub mod proofs;
+pub mod sealed;
+pub mod seed;
+pub mod timelock;
 pub mod utils;
 pub mod vtdlog;
+pub mod wallet;
+
+pub use channel::Channel;
+pub use wallet::JointWallet;
 
 #[derive(Debug)]
 pub enum PaymoProcess {
@@ -62,75 +78,38 @@ pub enum Role {
     Bob,
 }
 
-#[derive(Debug)]
-pub struct Channel {
-    pub alice_address: Option<address::MoneroAddress>,
-    pub bob_address: Option<address::MoneroAddress>,
-
-    pub channel_amount: Option<monero::Amount>,
-
-    pub time: Option<u64>,
-    pub confirmations: Option<u32>,
-
-    pub alice_secret: Option<Scalar>,
-    pub bob_secret: Option<Scalar>,
-
-    pub alice_public_key: Option<EdwardsPoint>,
-    pub bob_public_key: Option<EdwardsPoint>,
-
-    pub alice_hash: Option<Vec<u8>>,
-    pub bob_hash: Option<Vec<u8>>,
-
-    pub alice_tag: Option<EdwardsPoint>,
-    pub bob_tag: Option<EdwardsPoint>,
-
-    pub joint_public_key: Option<EdwardsPoint>,
-    pub joint_tag: Option<EdwardsPoint>,
-}
-
 impl Channel {
     pub fn from_opts(opts: &cli::Opts) -> Self {
-        let mut channel = Self {
-            alice_address: None,
-            bob_address: None,
-            channel_amount: None,
-            time: None,
-            confirmations: None,
-
-            alice_secret: None,
-            bob_secret: None,
-
-            alice_hash: None,
-            bob_hash: None,
-
-            alice_public_key: None,
-            bob_public_key: None,
-
-            alice_tag: None,
-            bob_tag: None,
-
-            joint_public_key: None,
-            joint_tag: None,
-        };
-
-        match opts.role {
-            Role::Alice => {
-                let alice_opts = opts.alice_opts.as_ref().unwrap();
-
-                channel.alice_address = Some(opts.address);
-                channel.channel_amount = alice_opts.channel_amount;
-                channel.time = alice_opts.time;
-                channel.confirmations = alice_opts.confirmations;
-            }
-            Role::Bob => {
-                // For Bob, the other fields will be set later
-                channel.bob_address = Some(opts.address);
-            }
-        };
-
-        channel
+        let role = opts.role.clone().unwrap();
+
+        // the other values are set later, in the order of the protocol
+        let mut negotiation = channel::Negotiation::default();
+        negotiation
+            .set_address(&role, opts.address.unwrap())
+            .unwrap();
+
+        if role == Role::Alice {
+            let alice_opts = opts.alice_opts.as_ref().unwrap();
+            let calibration = opts.calibration.as_ref().unwrap();
+
+            let params = channel::Params {
+                channel_amount: alice_opts.channel_amount.unwrap(),
+                time: alice_opts.squarings(calibration).unwrap(),
+                confirmations: alice_opts.confirmations.unwrap(),
+                vtdlog_params: alice_opts.vtdlog_params(),
+            };
+            negotiation.set_params(params).unwrap();
+        }
+
+        Channel::new(role, negotiation)
     }
 }
 
 #[derive(Debug, thiserror::Error)]
-pub enum Error {}
+pub enum Error {
+    #[error("VTD-Log error: {0}")]
+    Vtdlog(#[from] vtdlog::Error),
+
+    #[error("Joint wallet error: {0}")]
+    Wallet(#[from] wallet::Error),
+}
@@ -0,0 +1,280 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::mem::MaybeUninit;
+
+use super::bindings;
+
+// owned GMP integer, cleared on drop
+pub struct Mpz(bindings::mpz_t);
+
+impl Mpz {
+    pub fn new() -> Self {
+        let mut z: MaybeUninit<bindings::mpz_t> = MaybeUninit::uninit();
+
+        unsafe {
+            bindings::__gmpz_init(z.as_mut_ptr() as *mut _);
+            Self(z.assume_init())
+        }
+    }
+
+    pub fn from_i64(value: i64) -> Self {
+        let mut z = Self::new();
+        unsafe { bindings::__gmpz_set_si(z.as_mut_ptr(), value as _) }
+
+        z
+    }
+
+    // copy of a GMP integer owned by someone else, e.g. liblhtlp
+    pub unsafe fn from_ptr(z: *const bindings::__mpz_struct) -> Self {
+        let mut copy = Self::new();
+        bindings::__gmpz_set(copy.as_mut_ptr(), z);
+
+        copy
+    }
+
+    // big-endian bytes, interpreted as a non-negative integer
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut z = Self::new();
+
+        unsafe {
+            bindings::__gmpz_import(
+                z.as_mut_ptr(),
+                bytes.len(),
+                1,
+                1,
+                0,
+                0,
+                bytes.as_ptr() as *const _,
+            );
+        }
+
+        z
+    }
+
+    // big-endian bytes of the absolute value, without leading zeros
+    pub fn to_bytes(&self) -> Vec<u8> {
+        unsafe { export(self.as_ptr()) }
+    }
+
+    pub fn as_ptr(&self) -> *const bindings::__mpz_struct {
+        self.0.as_ptr()
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut bindings::__mpz_struct {
+        self.0.as_mut_ptr()
+    }
+
+    pub fn sign(&self) -> Ordering {
+        unsafe { bindings::__gmpz_cmp_si(self.as_ptr(), 0).cmp(&0) }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.sign() == Ordering::Equal
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.sign() == Ordering::Less
+    }
+
+    // number of bits of the absolute value; 0 for zero
+    pub fn bits(&self) -> u64 {
+        if self.is_zero() {
+            return 0;
+        }
+
+        unsafe { bindings::__gmpz_sizeinbase(self.as_ptr(), 2) as u64 }
+    }
+
+    pub fn bit(&self, index: u64) -> bool {
+        unsafe { bindings::__gmpz_tstbit(self.as_ptr(), index as _) == 1 }
+    }
+
+    pub fn set_bit(&mut self, index: u64) {
+        unsafe { bindings::__gmpz_setbit(self.as_mut_ptr(), index as _) }
+    }
+
+    pub fn add(&self, other: &Mpz) -> Mpz {
+        let mut z = Self::new();
+        unsafe { bindings::__gmpz_add(z.as_mut_ptr(), self.as_ptr(), other.as_ptr()) }
+
+        z
+    }
+
+    pub fn sub(&self, other: &Mpz) -> Mpz {
+        let mut z = Self::new();
+        unsafe { bindings::__gmpz_sub(z.as_mut_ptr(), self.as_ptr(), other.as_ptr()) }
+
+        z
+    }
+
+    pub fn mul(&self, other: &Mpz) -> Mpz {
+        let mut z = Self::new();
+        unsafe { bindings::__gmpz_mul(z.as_mut_ptr(), self.as_ptr(), other.as_ptr()) }
+
+        z
+    }
+
+    pub fn mul_i64(&self, other: i64) -> Mpz {
+        let mut z = Self::new();
+        unsafe { bindings::__gmpz_mul_si(z.as_mut_ptr(), self.as_ptr(), other as _) }
+
+        z
+    }
+
+    pub fn neg(&self) -> Mpz {
+        Self::new().sub(self)
+    }
+
+    // floor(self / d)
+    pub fn div_floor(&self, d: &Mpz) -> Mpz {
+        let mut z = Self::new();
+        unsafe { bindings::__gmpz_fdiv_q(z.as_mut_ptr(), self.as_ptr(), d.as_ptr()) }
+
+        z
+    }
+
+    // self / d, when d is known to divide self
+    pub fn div_exact(&self, d: &Mpz) -> Mpz {
+        let mut z = Self::new();
+        unsafe { bindings::__gmpz_divexact(z.as_mut_ptr(), self.as_ptr(), d.as_ptr()) }
+
+        z
+    }
+
+    // self mod d, in [0, d) for d > 0
+    pub fn modulo(&self, d: &Mpz) -> Mpz {
+        let mut z = Self::new();
+        unsafe { bindings::__gmpz_fdiv_r(z.as_mut_ptr(), self.as_ptr(), d.as_ptr()) }
+
+        z
+    }
+
+    pub fn divides(&self, n: &Mpz) -> bool {
+        n.modulo(self).is_zero()
+    }
+
+    // (d, x, y) such that self * x + other * y = d = gcd(self, other)
+    pub fn gcdext(&self, other: &Mpz) -> (Mpz, Mpz, Mpz) {
+        let (mut d, mut x, mut y) = (Self::new(), Self::new(), Self::new());
+
+        unsafe {
+            bindings::__gmpz_gcdext(
+                d.as_mut_ptr(),
+                x.as_mut_ptr(),
+                y.as_mut_ptr(),
+                self.as_ptr(),
+                other.as_ptr(),
+            );
+        }
+
+        (d, x, y)
+    }
+
+    pub fn powm(&self, exponent: &Mpz, modulus: &Mpz) -> Mpz {
+        let mut z = Self::new();
+
+        unsafe {
+            bindings::__gmpz_powm(
+                z.as_mut_ptr(),
+                self.as_ptr(),
+                exponent.as_ptr(),
+                modulus.as_ptr(),
+            );
+        }
+
+        z
+    }
+
+    pub fn invert(&self, modulus: &Mpz) -> Option<Mpz> {
+        let mut z = Self::new();
+
+        let exists =
+            unsafe { bindings::__gmpz_invert(z.as_mut_ptr(), self.as_ptr(), modulus.as_ptr()) };
+
+        (exists != 0).then_some(z)
+    }
+
+    // Jacobi symbol (self / n), for an odd n
+    pub fn jacobi(&self, n: &Mpz) -> i32 {
+        unsafe { bindings::__gmpz_jacobi(self.as_ptr(), n.as_ptr()) as i32 }
+    }
+
+    pub fn is_probable_prime(&self, rounds: i32) -> bool {
+        unsafe { bindings::__gmpz_probab_prime_p(self.as_ptr(), rounds as _) > 0 }
+    }
+}
+
+// big-endian bytes of the absolute value of `z`, without leading zeros
+pub unsafe fn export(z: *const bindings::__mpz_struct) -> Vec<u8> {
+    let size = 1;
+    let nail = 0;
+
+    let numb = 8 * size - nail;
+
+    let len_in_bytes = (bindings::__gmpz_sizeinbase(z, 2) + numb - 1) / numb;
+
+    let mut val: Vec<u8> = Vec::with_capacity(len_in_bytes);
+    let mut countp: usize = 0;
+
+    bindings::__gmpz_export(
+        val.as_mut_ptr() as *mut _,
+        &mut countp as *mut _,
+        1,
+        size,
+        0,
+        nail,
+        z,
+    );
+
+    val.set_len(countp);
+
+    val
+}
+
+impl Default for Mpz {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for Mpz {
+    fn clone(&self) -> Self {
+        let mut z = Self::new();
+        unsafe { bindings::__gmpz_set(z.as_mut_ptr(), self.as_ptr()) }
+
+        z
+    }
+}
+
+impl PartialEq for Mpz {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Mpz {}
+
+impl PartialOrd for Mpz {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Mpz {
+    fn cmp(&self, other: &Self) -> Ordering {
+        unsafe { bindings::__gmpz_cmp(self.as_ptr(), other.as_ptr()).cmp(&0) }
+    }
+}
+
+impl fmt::Debug for Mpz {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.is_negative() { "-" } else { "" };
+        write!(f, "{sign}0x{}", hex::encode(self.to_bytes()))
+    }
+}
+
+impl Drop for Mpz {
+    fn drop(&mut self) {
+        unsafe { bindings::__gmpz_clear(self.as_mut_ptr()) }
+    }
+}
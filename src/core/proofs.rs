@@ -0,0 +1,206 @@
+#![allow(non_snake_case)]
+use curve25519_dalek::{constants::ED25519_BASEPOINT_TABLE, edwards::EdwardsPoint, scalar::Scalar};
+use monero_serai::{hash_to_scalar, random_scalar};
+use rand_core::{CryptoRng, RngCore};
+
+use super::utils::decode_scalar;
+use crate::msgs;
+
+const DLEQ_DOMAIN: &[u8] = b"PayMo_dleq";
+const SCHNORR_DOMAIN: &[u8] = b"PayMo_schnorr";
+
+/// Non-interactive proof of knowledge of x, the secret of X = x * G. The proof is bound to a
+/// `context`, so that it cannot be replayed in another one, e.g. by the other party.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SchnorrProof {
+    pub c: Scalar,
+    pub s: Scalar,
+}
+
+impl SchnorrProof {
+    pub fn prove<R: RngCore + CryptoRng>(rng: &mut R, x: &Scalar, context: &[u8]) -> Self {
+        let k = random_scalar(rng);
+
+        let X = x * &ED25519_BASEPOINT_TABLE;
+        let c = schnorr_challenge(context, &X, &(&k * &ED25519_BASEPOINT_TABLE));
+
+        Self { c, s: k - c * x }
+    }
+
+    pub fn verify(&self, X: &EdwardsPoint, context: &[u8]) -> bool {
+        let A = &self.s * &ED25519_BASEPOINT_TABLE + self.c * X;
+
+        schnorr_challenge(context, X, &A) == self.c
+    }
+}
+
+fn schnorr_challenge(context: &[u8], X: &EdwardsPoint, A: &EdwardsPoint) -> Scalar {
+    let mut to_hash = SCHNORR_DOMAIN.to_vec();
+    to_hash.extend((context.len() as u64).to_le_bytes());
+    to_hash.extend(context);
+    to_hash.extend(X.compress().to_bytes());
+    to_hash.extend(A.compress().to_bytes());
+
+    hash_to_scalar(&to_hash)
+}
+
+/// Non-interactive proof that X = x * G and Y = x * H have the same discrete logarithm x,
+/// e.g. that the tag of a party uses the secret of its public key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DleqProof {
+    pub c: Scalar,
+    pub s: Scalar,
+}
+
+impl DleqProof {
+    pub fn prove<R: RngCore + CryptoRng>(rng: &mut R, x: &Scalar, H: &EdwardsPoint) -> Self {
+        let k = random_scalar(rng);
+
+        let X = x * &ED25519_BASEPOINT_TABLE;
+        let Y = x * H;
+
+        let c = challenge(H, &X, &Y, &(&k * &ED25519_BASEPOINT_TABLE), &(k * H));
+
+        Self { c, s: k - c * x }
+    }
+
+    pub fn verify(&self, H: &EdwardsPoint, X: &EdwardsPoint, Y: &EdwardsPoint) -> bool {
+        let A = &self.s * &ED25519_BASEPOINT_TABLE + self.c * X;
+        let B = self.s * H + self.c * Y;
+
+        challenge(H, X, Y, &A, &B) == self.c
+    }
+}
+
+fn challenge(
+    H: &EdwardsPoint,
+    X: &EdwardsPoint,
+    Y: &EdwardsPoint,
+    A: &EdwardsPoint,
+    B: &EdwardsPoint,
+) -> Scalar {
+    let mut to_hash = DLEQ_DOMAIN.to_vec();
+    for point in [H, X, Y, A, B] {
+        to_hash.extend(point.compress().to_bytes());
+    }
+
+    hash_to_scalar(&to_hash)
+}
+
+impl From<&SchnorrProof> for msgs::SchnorrProof {
+    fn from(proof: &SchnorrProof) -> Self {
+        msgs::SchnorrProof {
+            c: proof.c.to_bytes().to_vec(),
+            s: proof.s.to_bytes().to_vec(),
+        }
+    }
+}
+
+impl TryFrom<msgs::SchnorrProof> for SchnorrProof {
+    type Error = Error;
+
+    fn try_from(proof: msgs::SchnorrProof) -> Result<Self, Self::Error> {
+        Ok(SchnorrProof {
+            c: decode_scalar(&proof.c).ok_or(Error::InvalidProof)?,
+            s: decode_scalar(&proof.s).ok_or(Error::InvalidProof)?,
+        })
+    }
+}
+
+impl From<&DleqProof> for msgs::DleqProof {
+    fn from(proof: &DleqProof) -> Self {
+        msgs::DleqProof {
+            c: proof.c.to_bytes().to_vec(),
+            s: proof.s.to_bytes().to_vec(),
+        }
+    }
+}
+
+impl TryFrom<msgs::DleqProof> for DleqProof {
+    type Error = Error;
+
+    fn try_from(proof: msgs::DleqProof) -> Result<Self, Self::Error> {
+        Ok(DleqProof {
+            c: decode_scalar(&proof.c).ok_or(Error::InvalidProof)?,
+            s: decode_scalar(&proof.s).ok_or(Error::InvalidProof)?,
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Malformed proof")]
+    InvalidProof,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use monero_serai::ringct::hash_to_point;
+    use rand_core::OsRng;
+
+    #[test]
+    fn schnorr_proof() {
+        let x = random_scalar(&mut OsRng);
+        let X = &x * &ED25519_BASEPOINT_TABLE;
+
+        let proof = SchnorrProof::prove(&mut OsRng, &x, b"Alice");
+        assert!(proof.verify(&X, b"Alice"));
+
+        // replayed in another context
+        assert!(!proof.verify(&X, b"Bob"));
+
+        let other = &random_scalar(&mut OsRng) * &ED25519_BASEPOINT_TABLE;
+        assert!(!proof.verify(&other, b"Alice"));
+
+        let tampered = SchnorrProof {
+            c: proof.c + Scalar::one(),
+            ..proof.clone()
+        };
+        assert!(!tampered.verify(&X, b"Alice"));
+
+        let msg = msgs::SchnorrProof::from(&proof);
+        assert_eq!(SchnorrProof::try_from(msg).unwrap(), proof);
+    }
+
+    #[test]
+    fn dleq_proof() {
+        let x = random_scalar(&mut OsRng);
+        let H = hash_to_point(&random_scalar(&mut OsRng) * &ED25519_BASEPOINT_TABLE);
+
+        let X = &x * &ED25519_BASEPOINT_TABLE;
+        let Y = x * H;
+
+        let proof = DleqProof::prove(&mut OsRng, &x, &H);
+        assert!(proof.verify(&H, &X, &Y));
+
+        let other = random_scalar(&mut OsRng) * H;
+        assert!(!proof.verify(&H, &X, &other));
+        assert!(!proof.verify(&other, &X, &Y));
+
+        let tampered = DleqProof {
+            s: proof.s + Scalar::one(),
+            ..proof.clone()
+        };
+        assert!(!tampered.verify(&H, &X, &Y));
+
+        // a proof for another secret
+        let proof = DleqProof::prove(&mut OsRng, &random_scalar(&mut OsRng), &H);
+        assert!(!proof.verify(&H, &X, &Y));
+    }
+
+    #[test]
+    fn dleq_proof_msgs_roundtrip() {
+        let H = hash_to_point(&random_scalar(&mut OsRng) * &ED25519_BASEPOINT_TABLE);
+        let proof = DleqProof::prove(&mut OsRng, &random_scalar(&mut OsRng), &H);
+
+        let msg = msgs::DleqProof::from(&proof);
+        assert_eq!(DleqProof::try_from(msg.clone()).unwrap(), proof);
+
+        let invalid = msgs::DleqProof {
+            s: vec![0xff; 32],
+            ..msg
+        };
+        assert!(DleqProof::try_from(invalid).is_err());
+    }
+}
@@ -0,0 +1,204 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use argon2::{Algorithm, Argon2, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use prost::Message;
+use rand_core::{OsRng, RngCore};
+use zeroize::Zeroizing;
+
+use super::utils::atomic_write;
+use crate::msgs;
+
+/// Cost of the key derivation: 64 MiB and 3 passes.
+pub const DEFAULT_KDF_COST: KdfCost = KdfCost {
+    m_cost: 64 * 1024,
+    t_cost: 3,
+    p_cost: 1,
+};
+
+/// Most expensive key derivation accepted from a file: 1 GiB, 16 passes and 16 lanes, so
+/// that a modified file cannot make the derivation run out of memory or never end.
+pub const MAX_KDF_COST: KdfCost = KdfCost {
+    m_cost: 1024 * 1024,
+    t_cost: 16,
+    p_cost: 16,
+};
+
+const SALT_BYTES: usize = 16;
+const NONCE_BYTES: usize = 12;
+
+/// Argon2id parameters; the memory is in KiB.
+#[derive(Clone, Copy, Debug)]
+pub struct KdfCost {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+/// What a sealed file holds: its domain and version are authenticated with its contents, so
+/// that a file of one kind, or of another version, is never read as this one.
+#[derive(Clone, Copy, Debug)]
+pub struct Format {
+    pub domain: &'static [u8],
+    pub version: u32,
+}
+
+/// Key derived from a passphrase with Argon2id, that seals files with ChaCha20-Poly1305 and a
+/// new nonce on every write.
+pub struct SealingKey {
+    kdf: msgs::KdfParams,
+    key: Zeroizing<[u8; 32]>,
+}
+
+impl SealingKey {
+    /// The key of `passphrase`, with a new salt.
+    pub fn new(passphrase: &str, cost: KdfCost) -> Result<Self, Error> {
+        if passphrase.is_empty() {
+            return Err(Error::EmptyPassphrase);
+        }
+
+        let mut salt = vec![0; SALT_BYTES];
+        OsRng.fill_bytes(&mut salt);
+
+        let kdf = msgs::KdfParams {
+            salt,
+            m_cost: cost.m_cost,
+            t_cost: cost.t_cost,
+            p_cost: cost.p_cost,
+        };
+
+        Ok(Self {
+            key: derive_key(passphrase, &kdf)?,
+            kdf,
+        })
+    }
+
+    /// Replaces the file at `path` with `plaintext`, sealed.
+    pub fn seal(&self, format: Format, path: &Path, plaintext: &[u8]) -> Result<(), Error> {
+        let mut nonce = vec![0; NONCE_BYTES];
+        OsRng.fill_bytes(&mut nonce);
+
+        let aad = associated_data(format, &self.kdf);
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&self.key[..]))
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| Error::Encryption)?;
+
+        let file = msgs::SealedFile {
+            version: format.version,
+            kdf: Some(self.kdf.clone()),
+            nonce,
+            ciphertext,
+        };
+
+        atomic_write(path, &file.encode_to_vec())?;
+
+        Ok(())
+    }
+}
+
+/// Opens the file at `path`, with the key that sealed it; a modified file fails as a wrong
+/// passphrase does.
+pub fn open(
+    format: Format,
+    path: &Path,
+    passphrase: &str,
+) -> Result<(Zeroizing<Vec<u8>>, SealingKey), Error> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(Error::NotFound(path.into())),
+        Err(e) => return Err(e.into()),
+    };
+
+    let file =
+        msgs::SealedFile::decode(bytes.as_slice()).map_err(|_| Error::Corrupted(path.into()))?;
+    if file.version != format.version {
+        return Err(Error::UnsupportedVersion(file.version));
+    }
+
+    let kdf = file.kdf.ok_or_else(|| Error::Corrupted(path.into()))?;
+    if file.nonce.len() != NONCE_BYTES {
+        return Err(Error::Corrupted(path.into()));
+    }
+
+    let key = derive_key(passphrase, &kdf)?;
+    let aad = associated_data(format, &kdf);
+
+    let plaintext = ChaCha20Poly1305::new(Key::from_slice(&key[..]))
+        .decrypt(
+            Nonce::from_slice(&file.nonce),
+            Payload {
+                msg: &file.ciphertext,
+                aad: &aad,
+            },
+        )
+        .map_err(|_| Error::WrongPassphrase)?;
+
+    Ok((Zeroizing::new(plaintext), SealingKey { kdf, key }))
+}
+
+fn derive_key(passphrase: &str, kdf: &msgs::KdfParams) -> Result<Zeroizing<[u8; 32]>, Error> {
+    if kdf.m_cost > MAX_KDF_COST.m_cost
+        || kdf.t_cost > MAX_KDF_COST.t_cost
+        || kdf.p_cost > MAX_KDF_COST.p_cost
+    {
+        return Err(Error::KdfTooExpensive);
+    }
+
+    let params =
+        argon2::Params::new(kdf.m_cost, kdf.t_cost, kdf.p_cost, Some(32)).map_err(Error::Kdf)?;
+
+    let mut key = Zeroizing::new([0; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), &kdf.salt, &mut key[..])
+        .map_err(Error::Kdf)?;
+
+    Ok(key)
+}
+
+// domain || version || KDF parameters, so that they cannot be changed without the passphrase
+fn associated_data(format: Format, kdf: &msgs::KdfParams) -> Vec<u8> {
+    let mut aad = format.domain.to_vec();
+    aad.extend(format.version.to_be_bytes());
+    aad.extend(kdf.encode_to_vec());
+
+    aad
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Could not access the file: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("No file at {0}")]
+    NotFound(PathBuf),
+
+    #[error("Corrupted file: {0}")]
+    Corrupted(PathBuf),
+
+    #[error("Unsupported file version: {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("Wrong passphrase, or the file was modified")]
+    WrongPassphrase,
+
+    #[error("The passphrase cannot be empty")]
+    EmptyPassphrase,
+
+    #[error("Invalid key derivation parameters: {0}")]
+    Kdf(argon2::Error),
+
+    #[error("The key derivation is more expensive than allowed")]
+    KdfTooExpensive,
+
+    #[error("Could not encrypt the file")]
+    Encryption,
+}
@@ -0,0 +1,107 @@
+use curve25519_dalek::{constants::ED25519_BASEPOINT_TABLE, scalar::Scalar};
+use monero_serai::wallet::seed::{Language, Seed};
+use rand_core::OsRng;
+use sha3::{Digest, Keccak512};
+use zeroize::Zeroizing;
+
+use super::channel::OwnKeys;
+
+// domains of the derived values, so that none of them says anything about another
+const SPEND_DOMAIN: &[u8] = b"PayMo channel spend key";
+const VIEW_DOMAIN: &[u8] = b"PayMo channel view key";
+
+/// Seed every channel key is derived from, with the index of the channel: the keys of a
+/// channel are restored from the seed, its index, and what the other party sent. It is the
+/// entropy of a Monero seed, so that it can be written down as its 25-word mnemonic.
+#[derive(Clone)]
+pub struct MasterSeed(Zeroizing<[u8; 32]>);
+
+impl MasterSeed {
+    /// A new seed, and its mnemonic.
+    pub fn generate() -> (Self, Zeroizing<String>) {
+        let seed = Seed::new(&mut OsRng, Language::English);
+
+        (Self(seed.entropy()), seed.to_string())
+    }
+
+    pub fn from_mnemonic(mnemonic: &str) -> Result<Self, Error> {
+        let seed = Seed::from_string(Zeroizing::new(mnemonic.trim().to_string()))
+            .map_err(|_| Error::InvalidMnemonic)?;
+
+        Ok(Self(seed.entropy()))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(Self(Zeroizing::new(bytes.try_into().ok()?)))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Our key pair and share of the joint view key in the channel with `index`.
+    pub fn channel_keys(&self, index: u32) -> OwnKeys {
+        let secret = self.derive_scalar(SPEND_DOMAIN, index);
+        let view_secret = self.derive_scalar(VIEW_DOMAIN, index);
+
+        OwnKeys {
+            index,
+            public_key: &*secret * &ED25519_BASEPOINT_TABLE,
+            secret,
+            view_secret,
+        }
+    }
+
+    // H(domain || index || seed), wide enough to be reduced to a uniform scalar
+    fn derive_scalar(&self, domain: &[u8], index: u32) -> Zeroizing<Scalar> {
+        let mut hasher = Keccak512::new();
+        hasher.update(domain);
+        hasher.update(index.to_be_bytes());
+        hasher.update(&self.0[..]);
+
+        let mut wide = Zeroizing::new([0; 64]);
+        wide.copy_from_slice(&hasher.finalize());
+
+        Zeroizing::new(Scalar::from_bytes_mod_order_wide(&wide))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid mnemonic: it must be the 25 words of a Monero seed")]
+    InvalidMnemonic,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_restored_from_the_mnemonic() {
+        let (seed, mnemonic) = MasterSeed::generate();
+        let restored = MasterSeed::from_mnemonic(&mnemonic).unwrap();
+
+        let keys = seed.channel_keys(3);
+        let restored = restored.channel_keys(3);
+        assert_eq!(*keys.secret, *restored.secret);
+        assert_eq!(*keys.view_secret, *restored.view_secret);
+        assert_eq!(keys.public_key, restored.public_key);
+        assert_eq!(keys.public_key, &*keys.secret * &ED25519_BASEPOINT_TABLE);
+
+        assert!(MasterSeed::from_mnemonic("not a seed").is_err());
+    }
+
+    #[test]
+    fn channels_have_their_own_keys() {
+        let (seed, _) = MasterSeed::generate();
+
+        let first = seed.channel_keys(0);
+        let second = seed.channel_keys(1);
+        assert_ne!(*first.secret, *second.secret);
+        assert_ne!(*first.view_secret, *second.view_secret);
+        assert_ne!(*first.secret, *first.view_secret);
+
+        let (other, _) = MasterSeed::generate();
+        assert_ne!(*first.secret, *other.channel_keys(0).secret);
+    }
+}
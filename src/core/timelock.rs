@@ -0,0 +1,409 @@
+use rand_core::{CryptoRng, RngCore};
+
+use super::classgroup;
+use super::lhtlp::{self, LhtlpParams};
+use crate::msgs;
+
+/// Time-lock puzzle scheme used to lock the shares of a VTD-Log.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// puzzles in an RSA group (liblhtlp); every puzzle has its own modulus, generated by
+    /// the committer, who reveals its factorization when the puzzle is opened
+    Lhtlp,
+
+    /// puzzles in the class group of an imaginary quadratic order; no trusted setup, but
+    /// slower to generate and verify
+    ClassGroup,
+}
+
+/// Parameters used when setting up new puzzles, and that received puzzles must meet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeLockParams {
+    pub backend: Backend,
+
+    /// Bit length of the RSA modulus N, or of the discriminant of the maximal order.
+    pub security_param: u64,
+}
+
+impl TimeLockParams {
+    /// Smallest parameters accepted, only meant for tests.
+    pub const MIN: Self = Self {
+        backend: Backend::Lhtlp,
+        security_param: lhtlp::MIN_SECURITY_PARAM,
+    };
+
+    /// Smallest class group parameters accepted, only meant for tests.
+    pub const MIN_CLASS_GROUP: Self = Self {
+        backend: Backend::ClassGroup,
+        security_param: classgroup::MIN_SECURITY_PARAM,
+    };
+
+    /// Default parameters of `backend`, for real channels.
+    pub fn with_backend(backend: Backend) -> Self {
+        let security_param = match backend {
+            Backend::Lhtlp => lhtlp::DEFAULT_SECURITY_PARAM,
+            Backend::ClassGroup => classgroup::DEFAULT_SECURITY_PARAM,
+        };
+
+        Self {
+            backend,
+            security_param,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        match self.backend {
+            Backend::Lhtlp => self.lhtlp().validate()?,
+            Backend::ClassGroup => {
+                let range = classgroup::MIN_SECURITY_PARAM..=classgroup::MAX_DISCRIMINANT_BITS;
+
+                if !range.contains(&self.security_param) {
+                    let e = classgroup::Error::InvalidSecurityParam(self.security_param);
+                    return Err(e.into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether `puzzle` is of this backend, and was set up with a modulus or discriminant
+    /// at least as big as these parameters require.
+    pub fn accepts<P: TimeLockPuzzle>(&self, puzzle: &P) -> bool {
+        // the product of two primes may have one bit less than their lengths added up
+        P::BACKEND == self.backend && puzzle.security_bits() + 1 >= self.security_param
+    }
+
+    pub fn lhtlp(&self) -> LhtlpParams {
+        LhtlpParams {
+            security_param: self.security_param,
+        }
+    }
+}
+
+impl Default for TimeLockParams {
+    fn default() -> Self {
+        Self::with_backend(Backend::Lhtlp)
+    }
+}
+
+/// Linearly homomorphic time-lock puzzle, hiding a big-endian integer lower than the order
+/// of the ed25519 group.
+pub trait TimeLockPuzzle: Sized {
+    /// Randomness used to generate a puzzle, see [`TimeLockPuzzle::verify_opening`].
+    type Opening: Clone;
+
+    const BACKEND: Backend;
+
+    /// One puzzle for each secret, along with its opening, drawn from `rng`.
+    fn generate<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        params: &TimeLockParams,
+        time: u64,
+        secrets: &[&[u8]],
+    ) -> Vec<(Self, Self::Opening)>;
+
+    /// Homomorphically adds puzzles that share the same public parameters.
+    fn eval(puzzles: &[&Self]) -> Self;
+
+    fn same_params(&self, other: &Self) -> bool;
+
+    /// Bit length of the modulus or discriminant this puzzle was set up with.
+    fn security_bits(&self) -> u64;
+
+    /// Returns the secret as a big-endian integer, or None if the puzzle was not properly
+    /// generated and no secret can be recovered from it.
+    fn solve(&mut self) -> Option<Vec<u8>>;
+
+    /// Number of sequential squarings needed to solve the puzzle.
+    fn time(&self) -> u64;
+
+    /// State of the squarings before the first one, see [`TimeLockPuzzle::square`].
+    fn squarings_start(&self) -> Vec<u8>;
+
+    /// Performs `steps` more sequential squarings from `state`. Solving the puzzle takes
+    /// [`TimeLockPuzzle::time`] of them, split in as many calls as needed; the state in
+    /// between can be saved and resumed later.
+    fn square(&self, state: &[u8], steps: u64) -> Result<Vec<u8>, Error>;
+
+    /// Same as [`TimeLockPuzzle::solve`], from the state after all the squarings.
+    fn finish(&self, state: &[u8]) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Checks that this puzzle hides `secret`, by regenerating it with the revealed randomness,
+    /// and that it was set up to be solved with [`TimeLockPuzzle::time`] squarings.
+    fn verify_opening(&self, secret: &[u8], opening: &Self::Opening) -> bool;
+
+    /// Hash of the public parameters and of the puzzle itself.
+    fn digest(&self) -> [u8; 32];
+
+    fn to_msg(&self) -> msgs::TimeLockPuzzle;
+    fn from_msg(puzzle: msgs::TimeLockPuzzle) -> Result<Self, Error>;
+
+    fn opening_to_bytes(opening: &Self::Opening) -> Vec<u8>;
+    fn opening_from_bytes(bytes: &[u8]) -> Result<Self::Opening, Error>;
+}
+
+impl TimeLockPuzzle for lhtlp::Puzzle {
+    type Opening = lhtlp::Opening;
+
+    const BACKEND: Backend = Backend::Lhtlp;
+
+    fn generate<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        params: &TimeLockParams,
+        time: u64,
+        secrets: &[&[u8]],
+    ) -> Vec<(Self, Self::Opening)> {
+        secrets
+            .iter()
+            .map(|secret| Self::single_with_opening(rng, params.lhtlp(), time, secret))
+            .collect()
+    }
+
+    fn eval(puzzles: &[&Self]) -> Self {
+        lhtlp::Puzzle::eval(puzzles)
+    }
+
+    fn same_params(&self, other: &Self) -> bool {
+        lhtlp::Puzzle::same_params(self, other)
+    }
+
+    fn security_bits(&self) -> u64 {
+        self.modulus_bits() as u64
+    }
+
+    fn solve(&mut self) -> Option<Vec<u8>> {
+        Some(lhtlp::Puzzle::solve(self).val.clone())
+    }
+
+    fn time(&self) -> u64 {
+        lhtlp::Puzzle::time(self)
+    }
+
+    fn squarings_start(&self) -> Vec<u8> {
+        lhtlp::Puzzle::squarings_start(self)
+    }
+
+    fn square(&self, state: &[u8], steps: u64) -> Result<Vec<u8>, Error> {
+        Ok(lhtlp::Puzzle::square(self, state, steps))
+    }
+
+    fn finish(&self, state: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        Ok(lhtlp::Puzzle::finish(self, state))
+    }
+
+    // every puzzle has its own modulus, whose factor is revealed when opened
+    fn verify_opening(&self, secret: &[u8], opening: &Self::Opening) -> bool {
+        opening.factor.is_some() && lhtlp::Puzzle::verify_opening(self, secret, opening)
+    }
+
+    fn digest(&self) -> [u8; 32] {
+        lhtlp::Puzzle::digest(self)
+    }
+
+    fn to_msg(&self) -> msgs::TimeLockPuzzle {
+        msgs::TimeLockPuzzle {
+            puzzle: Some(msgs::time_lock_puzzle::Puzzle::Lhtlp(self.into())),
+        }
+    }
+
+    fn from_msg(puzzle: msgs::TimeLockPuzzle) -> Result<Self, Error> {
+        match puzzle.puzzle.ok_or(Error::InvalidEncoding)? {
+            msgs::time_lock_puzzle::Puzzle::Lhtlp(puzzle) => Ok(puzzle.try_into()?),
+            _ => Err(Error::UnexpectedBackend),
+        }
+    }
+
+    fn opening_to_bytes(opening: &Self::Opening) -> Vec<u8> {
+        opening.to_bytes()
+    }
+
+    fn opening_from_bytes(bytes: &[u8]) -> Result<Self::Opening, Error> {
+        Ok(lhtlp::Opening::from_bytes(bytes)?)
+    }
+}
+
+impl TimeLockPuzzle for classgroup::Puzzle {
+    type Opening = classgroup::Opening;
+
+    const BACKEND: Backend = Backend::ClassGroup;
+
+    // all the puzzles share the same public parameters, since the setup and its proof take
+    // about twice as long as solving a puzzle; they are still solved one by one
+    fn generate<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        params: &TimeLockParams,
+        time: u64,
+        secrets: &[&[u8]],
+    ) -> Vec<(Self, Self::Opening)> {
+        let pp = classgroup::Params::setup(params.security_param, time);
+
+        secrets
+            .iter()
+            .map(|secret| Self::with_params(rng, &pp, secret))
+            .collect()
+    }
+
+    fn eval(puzzles: &[&Self]) -> Self {
+        classgroup::Puzzle::eval(puzzles)
+    }
+
+    fn same_params(&self, other: &Self) -> bool {
+        classgroup::Puzzle::same_params(self, other)
+    }
+
+    fn security_bits(&self) -> u64 {
+        self.params().security_bits()
+    }
+
+    fn solve(&mut self) -> Option<Vec<u8>> {
+        classgroup::Puzzle::solve(self)
+    }
+
+    fn time(&self) -> u64 {
+        classgroup::Puzzle::time(self)
+    }
+
+    fn squarings_start(&self) -> Vec<u8> {
+        classgroup::Puzzle::squarings_start(self)
+    }
+
+    fn square(&self, state: &[u8], steps: u64) -> Result<Vec<u8>, Error> {
+        Ok(classgroup::Puzzle::square(self, state, steps)?)
+    }
+
+    fn finish(&self, state: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        Ok(classgroup::Puzzle::finish(self, state)?)
+    }
+
+    fn verify_opening(&self, secret: &[u8], opening: &Self::Opening) -> bool {
+        classgroup::Puzzle::verify_opening(self, secret, opening)
+    }
+
+    fn digest(&self) -> [u8; 32] {
+        classgroup::Puzzle::digest(self)
+    }
+
+    fn to_msg(&self) -> msgs::TimeLockPuzzle {
+        msgs::TimeLockPuzzle {
+            puzzle: Some(msgs::time_lock_puzzle::Puzzle::ClassGroup(self.into())),
+        }
+    }
+
+    fn from_msg(puzzle: msgs::TimeLockPuzzle) -> Result<Self, Error> {
+        match puzzle.puzzle.ok_or(Error::InvalidEncoding)? {
+            msgs::time_lock_puzzle::Puzzle::ClassGroup(puzzle) => Ok(puzzle.try_into()?),
+            _ => Err(Error::UnexpectedBackend),
+        }
+    }
+
+    fn opening_to_bytes(opening: &Self::Opening) -> Vec<u8> {
+        opening.to_bytes()
+    }
+
+    fn opening_from_bytes(bytes: &[u8]) -> Result<Self::Opening, Error> {
+        Ok(classgroup::Opening::from_bytes(bytes)?)
+    }
+}
+
+impl From<Backend> for msgs::TimeLockBackend {
+    fn from(backend: Backend) -> Self {
+        match backend {
+            Backend::Lhtlp => msgs::TimeLockBackend::Lhtlp,
+            Backend::ClassGroup => msgs::TimeLockBackend::ClassGroup,
+        }
+    }
+}
+
+impl TryFrom<i32> for Backend {
+    type Error = Error;
+
+    fn try_from(backend: i32) -> Result<Self, Self::Error> {
+        match msgs::TimeLockBackend::from_i32(backend) {
+            Some(msgs::TimeLockBackend::Lhtlp) => Ok(Backend::Lhtlp),
+            Some(msgs::TimeLockBackend::ClassGroup) => Ok(Backend::ClassGroup),
+            _ => Err(Error::InvalidEncoding),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid encoding of a time-lock puzzle")]
+    InvalidEncoding,
+
+    #[error("Time-lock puzzle of a different backend than the agreed one")]
+    UnexpectedBackend,
+
+    #[error(transparent)]
+    Lhtlp(#[from] lhtlp::Error),
+
+    #[error(transparent)]
+    ClassGroup(#[from] classgroup::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_core::OsRng;
+
+    fn generate_and_solve<P: TimeLockPuzzle>(params: &TimeLockParams) {
+        let secrets: Vec<&[u8]> = vec![b"paymo", b"monero"];
+        let mut puzzles = P::generate(&mut OsRng, params, 100, &secrets);
+
+        for ((puzzle, opening), secret) in puzzles.iter_mut().zip(&secrets) {
+            assert!(params.accepts(puzzle));
+            assert!(puzzle.verify_opening(secret, opening));
+            assert_eq!(puzzle.solve().unwrap(), *secret);
+
+            let bytes = P::opening_to_bytes(opening);
+            assert!(P::opening_from_bytes(&bytes).is_ok());
+
+            let decoded = P::from_msg(puzzle.to_msg()).unwrap();
+            assert_eq!(decoded.digest(), puzzle.digest());
+        }
+    }
+
+    #[test]
+    fn lhtlp_backend() {
+        generate_and_solve::<lhtlp::Puzzle>(&TimeLockParams::MIN);
+    }
+
+    #[test]
+    fn class_group_backend() {
+        generate_and_solve::<classgroup::Puzzle>(&TimeLockParams::MIN_CLASS_GROUP);
+    }
+
+    #[test]
+    fn puzzles_are_checked_against_params() {
+        let (puzzle, _) =
+            lhtlp::Puzzle::generate(&mut OsRng, &TimeLockParams::MIN, 100, &[&b"paymo"[..]])
+                .pop()
+                .unwrap();
+
+        assert!(TimeLockParams::MIN.accepts(&puzzle));
+        assert!(!TimeLockParams::default().accepts(&puzzle));
+        assert!(!TimeLockParams::MIN_CLASS_GROUP.accepts(&puzzle));
+        assert!(matches!(
+            classgroup::Puzzle::from_msg(puzzle.to_msg()),
+            Err(Error::UnexpectedBackend)
+        ));
+    }
+
+    #[test]
+    fn params_are_validated() {
+        assert!(TimeLockParams::MIN.validate().is_ok());
+        assert!(TimeLockParams::MIN_CLASS_GROUP.validate().is_ok());
+
+        for backend in [Backend::Lhtlp, Backend::ClassGroup] {
+            let params = TimeLockParams::with_backend(backend);
+            assert!(params.validate().is_ok());
+
+            let small = TimeLockParams {
+                security_param: 256,
+                ..params
+            };
+            assert!(small.validate().is_err());
+        }
+    }
+}
@@ -1,4 +1,13 @@
-use curve25519_dalek::{constants::ED25519_BASEPOINT_TABLE, edwards::EdwardsPoint, scalar::Scalar};
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use curve25519_dalek::{
+    constants::ED25519_BASEPOINT_TABLE,
+    edwards::{CompressedEdwardsY, EdwardsPoint},
+    scalar::Scalar,
+};
 use monero_serai::{random_scalar, ringct::hash_to_point};
 use rand_core::OsRng;
 use sha3::{Digest, Keccak256};
@@ -17,3 +26,98 @@ pub fn generate_user_key_pair() -> (Scalar, EdwardsPoint) {
 pub fn generate_user_tag(public: &EdwardsPoint, secret: &Scalar) -> EdwardsPoint {
     secret * hash_to_point(*public)
 }
+
+/// Decodes a canonically encoded point of the prime order subgroup.
+pub fn decode_point(bytes: &[u8]) -> Option<EdwardsPoint> {
+    if bytes.len() != 32 {
+        return None;
+    }
+
+    let compressed = CompressedEdwardsY::from_slice(bytes);
+    let point = compressed.decompress()?;
+
+    if point.compress() != compressed || !point.is_torsion_free() {
+        return None;
+    }
+
+    Some(point)
+}
+
+/// Decodes a canonically encoded scalar.
+pub fn decode_scalar(bytes: &[u8]) -> Option<Scalar> {
+    Scalar::from_canonical_bytes(bytes.try_into().ok()?)
+}
+
+/// Replaces the file at `path` with `contents` at once, so that a crash never leaves a partial
+/// file behind: they are written to a temporary file, readable only by its owner, which is
+/// renamed over it. The directory is synced too, so that the rename is not lost either.
+pub fn atomic_write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+
+    fs::rename(&tmp, path)?;
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::File::open(dir)?.sync_all()
+}
+
+/// Directory of a test, removed once it is dropped, even when the test panics.
+#[cfg(test)]
+pub struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+pub fn temp_dir() -> TempDir {
+    let dir =
+        std::env::temp_dir().join(format!("paymo-{}", hex::encode(rand::random::<[u8; 8]>())));
+    fs::create_dir_all(&dir).unwrap();
+
+    TempDir(dir)
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn atomic_writes_replace_the_file() {
+        let dir = temp_dir();
+        let path = dir.join("file");
+
+        atomic_write(&path, b"first").unwrap();
+        atomic_write(&path, b"second").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        assert!(!path.with_extension("tmp").exists());
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+    }
+}
@@ -1,16 +1,24 @@
 #![allow(non_snake_case)]
 use curve25519_dalek::{
-    constants::ED25519_BASEPOINT_TABLE, edwards::EdwardsPoint, scalar::Scalar, traits::Identity,
+    constants::ED25519_BASEPOINT_TABLE, edwards::EdwardsPoint, scalar::Scalar,
+    traits::VartimeMultiscalarMul,
 };
 use monero_serai::random_scalar;
-use rand::Rng;
-use rand_core::OsRng;
+use rand_core::{CryptoRng, OsRng, RngCore};
+use zeroize::Zeroizing;
 
-use super::lhtlp::Puzzle;
+use super::checkpoint::{self, CheckpointedSolver, Progress};
+use super::timelock::{self, Backend, TimeLockParams, TimeLockPuzzle};
+use super::utils::{decode_point, decode_scalar};
+use super::{classgroup, lhtlp};
+use crate::msgs;
 
-// in practice, those numbers would be much higher
-pub const N: usize = 20;
-pub const THRESHOLD: usize = 11;
+// smallest number of shares and threshold accepted; fast to work with, but only meant for tests
+pub const MIN_SHARES: usize = 20;
+pub const MIN_THRESHOLD: usize = 11;
+
+// decoded commitments with more shares than this are rejected
+pub const MAX_SHARES: usize = 256;
 
 // the Lagrange polynomial we use only has values calculated at 0
 pub struct LagrangePolynomial {
@@ -19,12 +27,16 @@ pub struct LagrangePolynomial {
 }
 
 impl LagrangePolynomial {
-    pub fn from_random_coefficients() -> Self {
-        let mut coefficients = Vec::with_capacity(N);
-        for _ in 0..N {
+    pub fn from_random_coefficients(n: usize) -> Self {
+        let mut coefficients = Vec::with_capacity(n);
+        for _ in 0..n {
             coefficients.push(random_scalar(&mut OsRng));
         }
 
+        Self::from_coefficients(coefficients)
+    }
+
+    pub fn from_coefficients(coefficients: Vec<Scalar>) -> Self {
         let basis = coefficients
             .iter()
             .enumerate()
@@ -77,146 +89,837 @@ impl LagrangeBasis {
     }
 }
 
-// the puzzles should be batched, otherwise the solver can just paralellize the
-// solving process; however, I am not sure how to extract each x_i from the batched puzzle;
-// therefore, for simplicity and since this is a PoC, we will solve the puzzles one by one.
+/// Lagrange basis polynomial of `points[index]` over `points`, evaluated at `at`.
+pub fn basis_at(points: &[Scalar], index: usize, at: &Scalar) -> Scalar {
+    let mut value = Scalar::one();
+
+    for (i, point) in points.iter().enumerate() {
+        if i == index {
+            continue;
+        }
+
+        value *= (at - point) * (points[index] - point).invert();
+    }
 
-pub struct Vtdlog {
-    c: Commit,
+    value
 }
 
-// in the real protocol described in the PayMo paper, the solver would give
-// I to the prover, where |I| = t-1, which would then reveal the x_i for i \in I,
-// and then we solver would run verification/proof algorithm, and then
-// would solve n − t + 1 puzzles.
-//
-// however, we found the explanation in the paper really confusing, and giving the
-// time constraints and the fact we are skipping the proofs, we decided to deviate
-// from the paper.
-//
-// we deviate as follows: the first t-1 shares will always be the randomized ones and correct ones;
-// for the n-t-1 left, one will be correct, the others will all be randomized.
-// therefore, the solver will need to keep solving the puzzles until it finds the one that
-// allows to reconstruct the secret.
-//
-// for this to work, the solver will need to solve, in the worst case, *all* the puzzles.
-//
-// also, note that the puzzles need to be batched, since otherwise the solver can just solve
-// the t puzzles in parallel. for simplicity, and given that it is a bit unclear how to
-// extract individual puzzles results from the batched puzzle, we decided to make
-// the solver solve the puzzles one by one.
-//
-//
-//
-//
-// of course, in a real implementation, everything described in the paper should be followed!
-// there is no point in not following the paper but to make the implementation simpler.
-//
-// in fact, we could just wrap x in a puzzle and that's it, if we really want to make things
-// simpler (by simpler = Alice and Bob are honest).
-//
-// we did not do that because we actually started implementing the protocol described in the paper,
-// but found its description confusing, and so we decided to play around with some ideas in the
-// original protocol, even though in the context of our implementation, they don't improve
-// security.
-impl Vtdlog {
-    pub fn commit(time: u64, x: Scalar) -> Commit {
-        let lagrange = LagrangePolynomial::from_random_coefficients();
-        let basis = lagrange.basis();
+/// Parameters of a VTD-Log, agreed on by both parties when the channel is opened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VtdlogParams {
+    /// Number of shares, n.
+    pub n: usize,
 
-        let H = &x * &ED25519_BASEPOINT_TABLE;
+    /// Number of shares needed to recover x, t; the committer opens t-1 of them.
+    pub threshold: usize,
 
-        let mut x_s = Vec::with_capacity(N);
-        let mut H_s = Vec::with_capacity(N);
+    pub timelock: TimeLockParams,
+}
 
-        let mut sum_t_xs = Scalar::zero();
-        let mut sum_t_Hs = EdwardsPoint::identity();
+impl VtdlogParams {
+    /// Smallest parameters accepted, only meant for tests.
+    pub const MIN: Self = Self {
+        n: MIN_SHARES,
+        threshold: MIN_THRESHOLD,
+        timelock: TimeLockParams::MIN,
+    };
 
-        let mut puzzles = Vec::with_capacity(N);
+    pub fn validate(&self) -> Result<(), Error> {
+        self.timelock.validate()?;
 
-        for i in 1..=THRESHOLD - 1 {
-            let x_i = random_scalar(&mut OsRng);
-            let H_i = &x_i * &ED25519_BASEPOINT_TABLE;
+        // at least as many opened and unopened puzzles as the minimums, so that cheating
+        // is never more likely than with them: C(n, t-1) >= C(MIN_SHARES, MIN_THRESHOLD-1)
+        let valid_shares = self.n <= MAX_SHARES
+            && self.threshold >= MIN_THRESHOLD
+            && self.threshold <= self.n
+            && self.n - self.threshold >= MIN_SHARES - MIN_THRESHOLD;
 
-            x_s.push(x_i);
-            H_s.push(H_i);
+        if !valid_shares {
+            return Err(Error::InvalidParams);
+        }
 
-            sum_t_xs += x_i * basis[i - 1].value_at_zero();
-            sum_t_Hs += H_i * basis[i - 1].value_at_zero();
+        Ok(())
+    }
+}
 
-            puzzles.push(Puzzle::single(time, x_i.as_bytes()));
+impl Default for VtdlogParams {
+    fn default() -> Self {
+        Self {
+            n: 40,
+            threshold: 21,
+            timelock: TimeLockParams::default(),
         }
+    }
+}
 
-        let mut rng = rand::thread_rng();
-        let correct = rng.gen_range(THRESHOLD..=N);
+pub struct Vtdlog<P: TimeLockPuzzle> {
+    c: Commit<P>,
+    // shares already known by the solver, as (index, x_i)
+    shares: Vec<(usize, Scalar)>,
+}
 
-        for i in THRESHOLD..=N {
-            let x_i;
-            let H_i;
+// x is shared with a (t, n) Shamir secret sharing, where the evaluation points are the
+// "coefficients" of the Lagrange polynomial: the polynomial is fixed by its value at 0,
+// which is x, and by t-1 random shares; the remaining n-t+1 shares are interpolated from them.
+// every share x_i is then locked in a puzzle, and H_i = x_i * G is published.
+//
+// since the H_i are public, anyone can check that they all lie in the same polynomial, whose
+// value at 0 (in the exponent) is H; to check that the puzzles actually hide the x_i,
+// the committer runs a cut-and-choose: the solver picks t-1 random indices, and the
+// committer opens the puzzles with those indices, revealing x_i, the randomness of the
+// puzzle, and the setup of its public parameters (see `Committer` and `Solver`). t-1 shares
+// reveal nothing about x, and a committer that locked a wrong share in each of the n-t+1
+// remaining puzzles passes the challenge with probability 1/C(n, t-1), i.e. 2^-17.5 with the
+// minimum parameters and 2^-37 with the default ones, and is caught otherwise.
+//
+// the challenge is never derived from a hash of the commitment (Fiat-Shamir): the committer
+// could then regenerate the puzzles offline until the hash picks only the honest ones, which
+// takes about C(n, t-1) tries, far from enough for a non-interactive proof.
+//
+// every puzzle has its own public parameters, since opening one reveals its setup; the
+// solver only needs one unopened puzzle besides the t-1 opened ones, so solving the others
+// in parallel gains nothing, unless the commitment was malformed.
+impl<P: TimeLockPuzzle> Vtdlog<P> {
+    /// The other shares of x, and the randomness of the puzzles, are drawn from `rng`.
+    pub fn commit<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        params: &VtdlogParams,
+        time: u64,
+        x: Scalar,
+    ) -> (Commit<P>, Witness<P>) {
+        assert_eq!(params.timelock.backend, P::BACKEND);
 
-            if i == correct {
-                x_i = (x - sum_t_xs) * basis[i - 1].inverse_at_zero();
-                H_i = (H - sum_t_Hs) * basis[i - 1].inverse_at_zero();
-            } else {
-                x_i = random_scalar(&mut OsRng);
-                H_i = &x_i * &ED25519_BASEPOINT_TABLE;
-            }
+        let threshold = params.threshold;
 
-            x_s.push(x_i);
-            H_s.push(H_i);
+        let lagrange = LagrangePolynomial::from_random_coefficients(params.n);
+        let points = lagrange.coefficients();
 
-            puzzles.push(Puzzle::single(time, x_i.as_bytes()));
+        let H = &x * &ED25519_BASEPOINT_TABLE;
+
+        let mut base_points = Vec::with_capacity(threshold);
+        let mut base_x_s = Zeroizing::new(Vec::with_capacity(threshold));
+
+        base_points.push(Scalar::zero());
+        base_x_s.push(x);
+
+        for point in &points[..threshold - 1] {
+            base_points.push(*point);
+            base_x_s.push(random_scalar(rng));
         }
 
-        Commit {
+        let x_s: Vec<Scalar> = points
+            .iter()
+            .enumerate()
+            .map(|(i, point)| {
+                if i < threshold - 1 {
+                    base_x_s[i + 1]
+                } else {
+                    base_x_s
+                        .iter()
+                        .enumerate()
+                        .map(|(k, x_k)| basis_at(&base_points, k, point) * x_k)
+                        .sum()
+                }
+            })
+            .collect();
+        let x_s = Zeroizing::new(x_s);
+
+        let H_s = x_s
+            .iter()
+            .map(|x_i| x_i * &ED25519_BASEPOINT_TABLE)
+            .collect();
+
+        let shares: Zeroizing<Vec<[u8; 32]>> =
+            Zeroizing::new(x_s.iter().map(share_to_bytes).collect());
+        let secrets: Vec<&[u8]> = shares.iter().map(|x_i| &x_i[..]).collect();
+
+        let (puzzles, openings) = P::generate(rng, &params.timelock, time, &secrets)
+            .into_iter()
+            .unzip();
+
+        let commit = Commit {
             H,
             H_s,
             lagrange,
             puzzles,
+            params: *params,
+        };
+
+        (commit, Witness { x_s, openings })
+    }
+
+    pub fn from_commit(commit: Commit<P>) -> Self {
+        Self {
+            c: commit,
+            shares: vec![],
         }
     }
 
-    pub fn from_commit(commit: Commit) -> Self {
-        Self { c: commit }
+    /// Same as [`Vtdlog::from_commit`], but the shares opened in `proof` do not need to be
+    /// solved; `proof` must have been checked by a [`Solver`].
+    pub fn from_verified(commit: Commit<P>, proof: Proof<P>) -> Self {
+        let shares = proof.openings.iter().map(|o| (o.index, o.x_i)).collect();
+
+        Self { c: commit, shares }
+    }
+
+    /// Recovers x from the puzzles not opened yet. Fails with [`Error::NotEnoughShares`],
+    /// blaming the puzzles that do not hide their H_i, if the commitment was malformed.
+    pub fn solve(&mut self) -> Result<Scalar, Error> {
+        self.solve_with(|puzzle| Ok(puzzle.solve()))
     }
 
-    pub fn solve(&mut self) -> Scalar {
-        let basis = self.c.lagrange.basis();
-        let mut x = Scalar::zero();
+    /// Same as [`Vtdlog::solve`], but the puzzles are solved by `solver`, which saves their
+    /// progress and can be cancelled; solving again after an error resumes from the last
+    /// checkpoints. The checkpoints are removed once x is recovered.
+    pub fn solve_checkpointed(
+        &mut self,
+        solver: &CheckpointedSolver,
+        mut on_progress: impl FnMut(Progress),
+    ) -> Result<Scalar, Error> {
+        let x = self.solve_with(|puzzle| Ok(solver.solve(&*puzzle, &mut on_progress)?))?;
 
-        for i in 1..=N {
-            let puzzle = &mut self.c.puzzles[i - 1];
+        // including the puzzles solved before an error, which are skipped when resuming
+        for puzzle in &self.c.puzzles {
+            solver.remove(&puzzle.digest())?;
+        }
 
-            let x_i = puzzle.solve();
-            let x_i = Scalar::from_bytes_mod_order(x_i.val.clone().try_into().unwrap());
+        Ok(x)
+    }
 
-            let H_i = &x_i * &ED25519_BASEPOINT_TABLE;
+    fn solve_with(
+        &mut self,
+        mut solve: impl FnMut(&mut P) -> Result<Option<Vec<u8>>, Error>,
+    ) -> Result<Scalar, Error> {
+        let VtdlogParams { n, threshold, .. } = self.c.params;
 
-            if H_i != &x_i * &ED25519_BASEPOINT_TABLE {
-                panic!("H_i != x_i * G");
+        let mut blame = vec![];
+
+        for i in 0..n {
+            if self.shares.len() >= threshold {
+                break;
             }
 
-            if i < THRESHOLD {
-                x += x_i * basis[i - 1].value_at_zero();
-            } else {
-                let candidate = x + x_i * basis[i - 1].value_at_zero();
+            if self.shares.iter().any(|(index, _)| *index == i) {
+                continue;
+            }
 
-                if self.c.H == &candidate * &ED25519_BASEPOINT_TABLE {
-                    return candidate;
+            let share = solve(&mut self.c.puzzles[i])?;
+
+            match share.as_deref().and_then(share_from_bytes) {
+                Some(x_i) if &x_i * &ED25519_BASEPOINT_TABLE == self.c.H_s[i] => {
+                    self.shares.push((i, x_i))
                 }
+                _ => blame.push(Blame {
+                    index: i,
+                    puzzle: self.c.puzzles[i].digest(),
+                    H_i: self.c.H_s[i],
+                    share,
+                }),
             }
         }
 
-        unreachable!()
+        if self.shares.len() < threshold {
+            return Err(Error::NotEnoughShares {
+                recovered: self.shares.len(),
+                threshold,
+                blame,
+            });
+        }
+
+        self.shares.truncate(threshold);
+
+        let points = self.c.lagrange.coefficients();
+        let share_points: Vec<Scalar> = self.shares.iter().map(|(i, _)| points[*i]).collect();
+        let x: Scalar = self
+            .shares
+            .iter()
+            .enumerate()
+            .map(|(k, (_, x_i))| basis_at(&share_points, k, &Scalar::zero()) * x_i)
+            .sum();
+
+        // only possible if the commitment was not verified, since the H_i of the recovered
+        // shares would then not lie in the same polynomial as H
+        if self.c.H != &x * &ED25519_BASEPOINT_TABLE {
+            return Err(Error::InvalidReconstruction);
+        }
+
+        Ok(x)
+    }
+}
+
+// shares are locked as big-endian integers, so that they are lower than the order of the
+// group, which is the message space of the class group puzzles
+fn share_to_bytes(x_i: &Scalar) -> [u8; 32] {
+    let mut bytes = x_i.to_bytes();
+    bytes.reverse();
+
+    bytes
+}
+
+// None if `bytes` is not the big-endian encoding of a canonical scalar
+fn share_from_bytes(bytes: &[u8]) -> Option<Scalar> {
+    if bytes.len() > 32 {
+        return None;
     }
+
+    let mut le_bytes = [0u8; 32];
+    for (byte, b) in le_bytes.iter_mut().zip(bytes.iter().rev()) {
+        *byte = *b;
+    }
+
+    Scalar::from_canonical_bytes(le_bytes)
 }
 
-pub struct Commit {
+pub struct Commit<P: TimeLockPuzzle> {
     pub H: EdwardsPoint,
     pub H_s: Vec<EdwardsPoint>,
 
     pub lagrange: LagrangePolynomial,
-    pub puzzles: Vec<Puzzle>,
+    pub puzzles: Vec<P>,
+
+    pub params: VtdlogParams,
+}
+
+impl<P: TimeLockPuzzle> Commit<P> {
+    // the H_i must all lie in the polynomial defined by H and the first t-1 shares
+    fn verify_shares(&self) -> bool {
+        let VtdlogParams { n, threshold, .. } = self.params;
+        let points = self.lagrange.coefficients();
+
+        if self.params.validate().is_err() {
+            return false;
+        }
+
+        if points.len() != n || self.H_s.len() != n || self.puzzles.len() != n {
+            return false;
+        }
+
+        // a puzzle with a small modulus or discriminant could be solved faster than expected
+        if !self.puzzles.iter().all(|p| self.params.timelock.accepts(p)) {
+            return false;
+        }
+
+        // points must be distinct and non-zero, otherwise the interpolation is meaningless
+        for (i, point) in points.iter().enumerate() {
+            if *point == Scalar::zero() || points[..i].contains(point) {
+                return false;
+            }
+        }
+
+        let mut base_points = vec![Scalar::zero()];
+        base_points.extend_from_slice(&points[..threshold - 1]);
+
+        let mut base_H_s = vec![self.H];
+        base_H_s.extend_from_slice(&self.H_s[..threshold - 1]);
+
+        points[threshold - 1..]
+            .iter()
+            .zip(&self.H_s[threshold - 1..])
+            .all(|(point, H_i)| {
+                let basis = (0..threshold).map(|k| basis_at(&base_points, k, point));
+                EdwardsPoint::vartime_multiscalar_mul(basis, &base_H_s) == *H_i
+            })
+    }
+
+    // the opened shares must be exactly the ones in `indices`, and match their H_i and puzzles
+    fn verify_openings(&self, indices: &[usize], proof: &Proof<P>) -> bool {
+        let opened: Vec<usize> = proof.openings.iter().map(|o| o.index).collect();
+        if opened != indices {
+            return false;
+        }
+
+        proof.openings.iter().all(|o| {
+            let puzzle = &self.puzzles[o.index];
+
+            &o.x_i * &ED25519_BASEPOINT_TABLE == self.H_s[o.index]
+                && puzzle.verify_opening(&share_to_bytes(&o.x_i), &o.opening)
+        })
+    }
+}
+
+/// Messages exchanged by [`Committer`] and [`Solver`] in the interactive opening.
+pub enum Message<P: TimeLockPuzzle> {
+    Commit(Box<Commit<P>>),
+    Challenge(Vec<usize>),
+    Opening(Proof<P>),
+}
+
+enum CommitterState<P: TimeLockPuzzle> {
+    Init(Box<Commit<P>>, Witness<P>),
+    Committed(Witness<P>),
+    Opened,
+}
+
+/// Committer side of the interactive cut-and-choose described in the PayMo paper:
+/// sends the commitment, then opens the t-1 shares chosen by the solver.
+pub struct Committer<P: TimeLockPuzzle> {
+    params: VtdlogParams,
+    state: Option<CommitterState<P>>,
+}
+
+impl<P: TimeLockPuzzle> Committer<P> {
+    pub fn new<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        params: &VtdlogParams,
+        time: u64,
+        x: Scalar,
+    ) -> Self {
+        let (commit, witness) = Vtdlog::commit(rng, params, time, x);
+
+        Self {
+            params: *params,
+            state: Some(CommitterState::Init(Box::new(commit), witness)),
+        }
+    }
+
+    /// First message of the protocol, to be sent to the solver.
+    pub fn commit(&mut self) -> Result<Message<P>, Error> {
+        match self.state.take() {
+            Some(CommitterState::Init(commit, witness)) => {
+                self.state = Some(CommitterState::Committed(witness));
+                Ok(Message::Commit(commit))
+            }
+            state => {
+                self.state = state;
+                Err(Error::UnexpectedMessage)
+            }
+        }
+    }
+
+    pub fn recv(&mut self, msg: Message<P>) -> Result<Message<P>, Error> {
+        match (self.state.take(), msg) {
+            (Some(CommitterState::Committed(witness)), Message::Challenge(indices)) => {
+                // opening t or more shares would reveal x
+                let mut sorted = indices.clone();
+                sorted.sort_unstable();
+                sorted.dedup();
+
+                if sorted.len() != self.params.threshold - 1 || indices.len() != sorted.len() {
+                    self.state = Some(CommitterState::Committed(witness));
+                    return Err(Error::InvalidChallenge);
+                }
+
+                if sorted.iter().any(|i| *i >= self.params.n) {
+                    self.state = Some(CommitterState::Committed(witness));
+                    return Err(Error::InvalidChallenge);
+                }
+
+                let openings = sorted
+                    .into_iter()
+                    .map(|index| ShareOpening {
+                        index,
+                        x_i: witness.x_s[index],
+                        opening: witness.openings[index].clone(),
+                    })
+                    .collect();
+
+                self.state = Some(CommitterState::Opened);
+
+                Ok(Message::Opening(Proof { openings }))
+            }
+            (state, _) => {
+                self.state = state;
+                Err(Error::UnexpectedMessage)
+            }
+        }
+    }
+
+    pub fn is_opened(&self) -> bool {
+        matches!(self.state, Some(CommitterState::Opened))
+    }
+}
+
+enum SolverState<P: TimeLockPuzzle> {
+    AwaitingCommit,
+    AwaitingOpening(Box<Commit<P>>, Vec<usize>),
+    Verified(Box<Commit<P>>, Proof<P>),
+}
+
+/// Solver side of the interactive cut-and-choose: checks the commitment, challenges the
+/// committer with a random I, |I| = t-1, and checks the opened shares; afterwards, only
+/// n-t+1 puzzles are left to solve.
+pub struct Solver<P: TimeLockPuzzle> {
+    params: VtdlogParams,
+    time: Option<u64>,
+    state: Option<SolverState<P>>,
+}
+
+impl<P: TimeLockPuzzle> Solver<P> {
+    /// `params` are the ones agreed on with the committer; any other commitment is rejected.
+    pub fn new(params: &VtdlogParams) -> Self {
+        Self {
+            params: *params,
+            time: None,
+            state: Some(SolverState::AwaitingCommit),
+        }
+    }
+
+    /// Also rejects commitments whose puzzles take more than `time` squarings to solve.
+    pub fn with_time(mut self, time: u64) -> Self {
+        self.time = Some(time);
+        self
+    }
+
+    pub fn recv(&mut self, msg: Message<P>) -> Result<Option<Message<P>>, Error> {
+        match (self.state.take(), msg) {
+            (Some(SolverState::AwaitingCommit), Message::Commit(commit)) => {
+                if commit.params != self.params {
+                    self.state = Some(SolverState::AwaitingCommit);
+                    return Err(Error::InvalidParams);
+                }
+
+                if let Some(time) = self.time {
+                    if commit.puzzles.iter().any(|puzzle| puzzle.time() > time) {
+                        self.state = Some(SolverState::AwaitingCommit);
+                        return Err(Error::InvalidTime);
+                    }
+                }
+
+                if !commit.verify_shares() {
+                    self.state = Some(SolverState::AwaitingCommit);
+                    return Err(Error::InvalidShares);
+                }
+
+                let VtdlogParams { n, threshold, .. } = self.params;
+                let mut indices = rand::seq::index::sample(&mut OsRng, n, threshold - 1).into_vec();
+                indices.sort_unstable();
+
+                self.state = Some(SolverState::AwaitingOpening(commit, indices.clone()));
+
+                Ok(Some(Message::Challenge(indices)))
+            }
+            (Some(SolverState::AwaitingOpening(commit, indices)), Message::Opening(proof)) => {
+                if !commit.verify_openings(&indices, &proof) {
+                    self.state = Some(SolverState::AwaitingOpening(commit, indices));
+                    return Err(Error::InvalidOpening);
+                }
+
+                self.state = Some(SolverState::Verified(commit, proof));
+
+                Ok(None)
+            }
+            (state, _) => {
+                self.state = state;
+                Err(Error::UnexpectedMessage)
+            }
+        }
+    }
+
+    pub fn is_verified(&self) -> bool {
+        matches!(self.state, Some(SolverState::Verified(..)))
+    }
+
+    /// Once the opening was verified, returns the [`Vtdlog`] with the n-t+1 puzzles left to solve.
+    pub fn into_vtdlog(self) -> Result<Vtdlog<P>, Error> {
+        match self.state {
+            Some(SolverState::Verified(commit, proof)) => Ok(Vtdlog::from_verified(*commit, proof)),
+            _ => Err(Error::NotVerified),
+        }
+    }
+}
+
+/// Either side of the interactive opening, exchanging [`msgs::VtdlogMsg`]s, for the time-lock
+/// backend of the agreed parameters, which is only known at runtime.
+pub trait Exchange {
+    /// First message of the committer.
+    fn commit_msg(&mut self) -> Result<msgs::VtdlogMsg, Error>;
+
+    /// Next message, after `msg` from the other party; none once the solver verified the
+    /// opening.
+    fn recv_msg(&mut self, msg: msgs::VtdlogMsg) -> Result<Option<msgs::VtdlogMsg>, Error>;
+}
+
+impl<P: TimeLockPuzzle> Exchange for Committer<P> {
+    fn commit_msg(&mut self) -> Result<msgs::VtdlogMsg, Error> {
+        Ok((&self.commit()?).into())
+    }
+
+    fn recv_msg(&mut self, msg: msgs::VtdlogMsg) -> Result<Option<msgs::VtdlogMsg>, Error> {
+        Ok(Some((&self.recv(msg.try_into()?)?).into()))
+    }
+}
+
+impl<P: TimeLockPuzzle> Exchange for Solver<P> {
+    fn commit_msg(&mut self) -> Result<msgs::VtdlogMsg, Error> {
+        Err(Error::UnexpectedMessage)
+    }
+
+    fn recv_msg(&mut self, msg: msgs::VtdlogMsg) -> Result<Option<msgs::VtdlogMsg>, Error> {
+        let reply = self.recv(msg.try_into()?)?;
+
+        Ok(reply.map(|reply| (&reply).into()))
+    }
+}
+
+/// [`Committer`] of `x`, with the time-lock backend of `params`.
+pub fn committer<R: RngCore + CryptoRng>(
+    rng: &mut R,
+    params: &VtdlogParams,
+    time: u64,
+    x: Scalar,
+) -> Box<dyn Exchange> {
+    match params.timelock.backend {
+        Backend::Lhtlp => Box::new(Committer::<lhtlp::Puzzle>::new(rng, params, time, x)),
+        Backend::ClassGroup => Box::new(Committer::<classgroup::Puzzle>::new(rng, params, time, x)),
+    }
+}
+
+/// [`Solver`] of puzzles of at most `time` squarings, with the time-lock backend of `params`.
+pub fn solver(params: &VtdlogParams, time: u64) -> Box<dyn Exchange> {
+    match params.timelock.backend {
+        Backend::Lhtlp => Box::new(Solver::<lhtlp::Puzzle>::new(params).with_time(time)),
+        Backend::ClassGroup => Box::new(Solver::<classgroup::Puzzle>::new(params).with_time(time)),
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Unexpected VTD-Log message for the current state")]
+    UnexpectedMessage,
+
+    #[error("Invalid VTD-Log parameters, or different from the agreed ones")]
+    InvalidParams,
+
+    #[error("The puzzles take longer to solve than agreed")]
+    InvalidTime,
+
+    #[error("The H_i are not shares of H")]
+    InvalidShares,
+
+    #[error("The challenge must contain exactly t-1 distinct indices lower than n")]
+    InvalidChallenge,
+
+    #[error("The opened shares do not match the challenge, their H_i or their puzzles")]
+    InvalidOpening,
+
+    #[error("The commitment opening has not been verified yet")]
+    NotVerified,
+
+    #[error("Invalid encoding of a VTD-Log message")]
+    InvalidEncoding,
+
+    #[error(
+        "Only {recovered} of the {threshold} shares needed could be recovered; the puzzles {:?} \
+        do not hide their H_i",
+        .blame.iter().map(|b| b.index).collect::<Vec<_>>()
+    )]
+    NotEnoughShares {
+        recovered: usize,
+        threshold: usize,
+        blame: Vec<Blame>,
+    },
+
+    #[error("The recovered shares do not reconstruct H")]
+    InvalidReconstruction,
+
+    #[error(transparent)]
+    TimeLock(#[from] timelock::Error),
+
+    #[error(transparent)]
+    Checkpoint(#[from] checkpoint::Error),
+}
+
+/// Evidence that the committer locked a wrong share in a puzzle: anyone can solve the puzzle
+/// with the given digest and check that it does not hide the discrete logarithm of H_i.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Blame {
+    pub index: usize,
+    pub puzzle: [u8; 32],
+    pub H_i: EdwardsPoint,
+    /// What the puzzle hides instead, as a big-endian integer; None if it cannot be solved.
+    pub share: Option<Vec<u8>>,
+}
+
+impl From<&VtdlogParams> for msgs::VtdlogParams {
+    fn from(params: &VtdlogParams) -> Self {
+        msgs::VtdlogParams {
+            n: params.n as u32,
+            threshold: params.threshold as u32,
+            timelock_security_param: params.timelock.security_param,
+            timelock_backend: msgs::TimeLockBackend::from(params.timelock.backend).into(),
+        }
+    }
+}
+
+impl TryFrom<msgs::VtdlogParams> for VtdlogParams {
+    type Error = Error;
+
+    fn try_from(params: msgs::VtdlogParams) -> Result<Self, Self::Error> {
+        let params = VtdlogParams {
+            n: params.n as usize,
+            threshold: params.threshold as usize,
+            timelock: TimeLockParams {
+                backend: params.timelock_backend.try_into()?,
+                security_param: params.timelock_security_param,
+            },
+        };
+
+        params.validate()?;
+
+        Ok(params)
+    }
+}
+
+impl<P: TimeLockPuzzle> From<&Commit<P>> for msgs::VtdlogCommit {
+    fn from(commit: &Commit<P>) -> Self {
+        msgs::VtdlogCommit {
+            h: commit.H.compress().to_bytes().to_vec(),
+            h_s: commit
+                .H_s
+                .iter()
+                .map(|H_i| H_i.compress().to_bytes().to_vec())
+                .collect(),
+            points: commit
+                .lagrange
+                .coefficients()
+                .iter()
+                .map(|point| point.to_bytes().to_vec())
+                .collect(),
+            puzzles: commit.puzzles.iter().map(P::to_msg).collect(),
+            params: Some((&commit.params).into()),
+        }
+    }
+}
+
+impl<P: TimeLockPuzzle> TryFrom<msgs::VtdlogCommit> for Commit<P> {
+    type Error = Error;
+
+    fn try_from(commit: msgs::VtdlogCommit) -> Result<Self, Self::Error> {
+        let params: VtdlogParams = commit.params.ok_or(Error::InvalidEncoding)?.try_into()?;
+        let n = params.n;
+
+        if params.timelock.backend != P::BACKEND {
+            return Err(Error::InvalidParams);
+        }
+
+        if commit.h_s.len() != n || commit.points.len() != n || commit.puzzles.len() != n {
+            return Err(Error::InvalidEncoding);
+        }
+
+        let H = decode_point(&commit.h).ok_or(Error::InvalidEncoding)?;
+
+        let H_s = commit
+            .h_s
+            .iter()
+            .map(|H_i| decode_point(H_i).ok_or(Error::InvalidEncoding))
+            .collect::<Result<_, _>>()?;
+
+        let points = commit
+            .points
+            .iter()
+            .map(|point| decode_scalar(point).ok_or(Error::InvalidEncoding))
+            .collect::<Result<_, _>>()?;
+
+        let puzzles = commit
+            .puzzles
+            .into_iter()
+            .map(P::from_msg)
+            .collect::<Result<_, _>>()?;
+
+        Ok(Commit {
+            H,
+            H_s,
+            lagrange: LagrangePolynomial::from_coefficients(points),
+            puzzles,
+            params,
+        })
+    }
+}
+
+impl<P: TimeLockPuzzle> From<&Proof<P>> for msgs::VtdlogProof {
+    fn from(proof: &Proof<P>) -> Self {
+        let openings = proof
+            .openings
+            .iter()
+            .map(|o| msgs::VtdlogShareOpening {
+                index: o.index as u32,
+                x_i: o.x_i.to_bytes().to_vec(),
+                r: P::opening_to_bytes(&o.opening),
+            })
+            .collect();
+
+        msgs::VtdlogProof { openings }
+    }
+}
+
+impl<P: TimeLockPuzzle> TryFrom<msgs::VtdlogProof> for Proof<P> {
+    type Error = Error;
+
+    fn try_from(proof: msgs::VtdlogProof) -> Result<Self, Self::Error> {
+        // the actual number of openings and their indices depend on the commitment, and
+        // are checked along with it
+        if proof.openings.len() >= MAX_SHARES {
+            return Err(Error::InvalidEncoding);
+        }
+
+        let openings = proof
+            .openings
+            .iter()
+            .map(|o| {
+                let index = o.index as usize;
+                if index >= MAX_SHARES {
+                    return Err(Error::InvalidEncoding);
+                }
+
+                Ok(ShareOpening {
+                    index,
+                    x_i: decode_scalar(&o.x_i).ok_or(Error::InvalidEncoding)?,
+                    opening: P::opening_from_bytes(&o.r)?,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Proof { openings })
+    }
+}
+
+impl<P: TimeLockPuzzle> From<&Message<P>> for msgs::VtdlogMsg {
+    fn from(msg: &Message<P>) -> Self {
+        use msgs::vtdlog_msg::Data;
+
+        let data = match msg {
+            Message::Commit(commit) => Data::Commit(commit.as_ref().into()),
+            Message::Challenge(indices) => Data::Challenge(msgs::VtdlogChallenge {
+                indices: indices.iter().map(|i| *i as u32).collect(),
+            }),
+            Message::Opening(proof) => Data::Opening(proof.into()),
+        };
+
+        msgs::VtdlogMsg { data: Some(data) }
+    }
+}
+
+impl<P: TimeLockPuzzle> TryFrom<msgs::VtdlogMsg> for Message<P> {
+    type Error = Error;
+
+    fn try_from(msg: msgs::VtdlogMsg) -> Result<Self, Self::Error> {
+        use msgs::vtdlog_msg::Data;
+
+        match msg.data.ok_or(Error::InvalidEncoding)? {
+            Data::Commit(commit) => Ok(Message::Commit(Box::new(commit.try_into()?))),
+            Data::Challenge(challenge) => Ok(Message::Challenge(
+                challenge.indices.iter().map(|i| *i as usize).collect(),
+            )),
+            Data::Opening(proof) => Ok(Message::Opening(proof.try_into()?)),
+        }
+    }
+}
+
+/// Shares and puzzle randomness, known only by the committer.
+pub struct Witness<P: TimeLockPuzzle> {
+    x_s: Zeroizing<Vec<Scalar>>,
+    openings: Vec<P::Opening>,
+}
+
+pub struct ShareOpening<P: TimeLockPuzzle> {
+    pub index: usize,
+    pub x_i: Scalar,
+    pub opening: P::Opening,
+}
+
+pub struct Proof<P: TimeLockPuzzle> {
+    pub openings: Vec<ShareOpening<P>>,
 }
 
 pub struct Solution {
@@ -225,11 +928,34 @@ pub struct Solution {
 
 #[cfg(test)]
 mod tests {
+    use super::checkpoint::CancelToken;
+    use super::lhtlp::{LhtlpParams, Puzzle};
     use super::*;
+    use crate::core::utils::temp_dir;
+    use crate::msgs::vtdlog_msg::Data;
+
+    const CLASS_GROUP: VtdlogParams = VtdlogParams {
+        timelock: TimeLockParams::MIN_CLASS_GROUP,
+        ..VtdlogParams::MIN
+    };
+
+    // runs the interactive opening of a commitment that may have been tampered with
+    fn open(commitment: Commit<Puzzle>, witness: Witness<Puzzle>) -> Result<Vtdlog<Puzzle>, Error> {
+        let mut committer = Committer {
+            params: commitment.params,
+            state: Some(CommitterState::Init(Box::new(commitment), witness)),
+        };
+        let mut solver = Solver::<Puzzle>::new(&VtdlogParams::MIN);
+
+        let challenge = solver.recv(committer.commit()?)?.unwrap();
+        solver.recv(committer.recv(challenge)?)?;
+
+        solver.into_vtdlog()
+    }
 
     #[test]
     fn test_lagrange_basis_inverse() {
-        let lagrange_polynomial = LagrangePolynomial::from_random_coefficients();
+        let lagrange_polynomial = LagrangePolynomial::from_random_coefficients(MIN_SHARES);
         let basis = lagrange_polynomial.basis();
 
         assert_eq!(
@@ -242,11 +968,435 @@ mod tests {
     fn test_vtdlog() {
         let secret = random_scalar(&mut OsRng);
 
-        let commitment = Vtdlog::commit(100, secret);
+        let (commitment, _) = Vtdlog::<Puzzle>::commit(&mut OsRng, &VtdlogParams::MIN, 100, secret);
 
         let mut vtdlog = Vtdlog::from_commit(commitment);
 
-        let solution = vtdlog.solve();
+        let solution = vtdlog.solve().unwrap();
         assert_eq!(solution, secret);
     }
+
+    #[test]
+    fn test_vtdlog_interactive() {
+        let secret = random_scalar(&mut OsRng);
+
+        let mut committer = Committer::<Puzzle>::new(&mut OsRng, &VtdlogParams::MIN, 100, secret);
+        let mut solver = Solver::<Puzzle>::new(&VtdlogParams::MIN);
+
+        let commit = committer.commit().unwrap();
+        let challenge = solver.recv(commit).unwrap().unwrap();
+        let opening = committer.recv(challenge).unwrap();
+
+        assert!(solver.recv(opening).unwrap().is_none());
+        assert!(committer.is_opened());
+        assert!(solver.is_verified());
+
+        let mut vtdlog = solver.into_vtdlog().unwrap();
+        assert_eq!(vtdlog.solve().unwrap(), secret);
+    }
+
+    #[test]
+    fn test_vtdlog_exchange() {
+        let secret = random_scalar(&mut OsRng);
+
+        for params in [VtdlogParams::MIN, CLASS_GROUP] {
+            let mut committer = committer(&mut OsRng, &params, 100, secret);
+            let mut solver = solver(&params, 100);
+
+            assert!(solver.commit_msg().is_err());
+
+            let commit = committer.commit_msg().unwrap();
+            let challenge = solver.recv_msg(commit.clone()).unwrap().unwrap();
+            let opening = committer.recv_msg(challenge).unwrap().unwrap();
+            assert!(solver.recv_msg(opening.clone()).unwrap().is_none());
+
+            // what the solver keeps to solve the VTD-Log later
+            let (commit, opening) = match (commit.data, opening.data) {
+                (Some(Data::Commit(commit)), Some(Data::Opening(opening))) => (commit, opening),
+                _ => panic!("unexpected VTD-Log messages"),
+            };
+
+            let x = match params.timelock.backend {
+                Backend::Lhtlp => Vtdlog::<Puzzle>::from_verified(
+                    commit.try_into().unwrap(),
+                    opening.try_into().unwrap(),
+                )
+                .solve(),
+                Backend::ClassGroup => Vtdlog::<classgroup::Puzzle>::from_verified(
+                    commit.try_into().unwrap(),
+                    opening.try_into().unwrap(),
+                )
+                .solve(),
+            };
+            assert_eq!(x.unwrap(), secret);
+        }
+    }
+
+    #[test]
+    fn test_vtdlog_interactive_rejects_longer_time() {
+        let secret = random_scalar(&mut OsRng);
+
+        let mut committer = Committer::<Puzzle>::new(&mut OsRng, &VtdlogParams::MIN, 200, secret);
+        let mut solver = Solver::<Puzzle>::new(&VtdlogParams::MIN).with_time(100);
+
+        let commit = committer.commit().unwrap();
+        assert!(matches!(solver.recv(commit), Err(Error::InvalidTime)));
+        assert!(!solver.is_verified());
+    }
+
+    #[test]
+    fn test_vtdlog_checkpointed() {
+        let secret = random_scalar(&mut OsRng);
+        let (commitment, _) = Vtdlog::<Puzzle>::commit(&mut OsRng, &VtdlogParams::MIN, 100, secret);
+        let mut vtdlog = Vtdlog::from_commit(commitment);
+
+        let data_dir = temp_dir();
+
+        // cancelled as soon as some progress is made, then resumed
+        let cancel = CancelToken::new();
+        let solver = CheckpointedSolver::new(&data_dir, cancel.clone());
+
+        let x = match vtdlog.solve_checkpointed(&solver, |_| cancel.cancel()) {
+            Err(Error::Checkpoint(checkpoint::Error::Cancelled)) => {
+                let solver = CheckpointedSolver::new(&data_dir, CancelToken::new());
+                vtdlog.solve_checkpointed(&solver, |_| ()).unwrap()
+            }
+            result => result.unwrap(),
+        };
+        assert_eq!(x, secret);
+
+        let checkpoints = std::fs::read_dir(data_dir.join("checkpoints")).unwrap();
+        assert_eq!(checkpoints.count(), 0);
+    }
+
+    #[test]
+    fn test_vtdlog_params_are_validated() {
+        assert!(VtdlogParams::MIN.validate().is_ok());
+        assert!(VtdlogParams::default().validate().is_ok());
+        assert!(CLASS_GROUP.validate().is_ok());
+
+        let invalid = [
+            VtdlogParams {
+                n: MIN_SHARES - 1,
+                ..VtdlogParams::MIN
+            },
+            VtdlogParams {
+                threshold: MIN_THRESHOLD - 1,
+                ..VtdlogParams::MIN
+            },
+            // too few unopened puzzles
+            VtdlogParams {
+                threshold: MIN_SHARES,
+                ..VtdlogParams::MIN
+            },
+            VtdlogParams {
+                n: MAX_SHARES + 1,
+                ..VtdlogParams::MIN
+            },
+            VtdlogParams {
+                timelock: TimeLockParams {
+                    security_param: 64,
+                    ..TimeLockParams::MIN
+                },
+                ..VtdlogParams::MIN
+            },
+        ];
+
+        for params in invalid {
+            assert!(params.validate().is_err(), "{params:?}");
+        }
+    }
+
+    #[test]
+    fn test_vtdlog_interactive_rejects_other_params() {
+        let params = VtdlogParams {
+            n: MIN_SHARES + 1,
+            ..VtdlogParams::MIN
+        };
+
+        let mut committer =
+            Committer::<Puzzle>::new(&mut OsRng, &params, 100, random_scalar(&mut OsRng));
+        let mut solver = Solver::<Puzzle>::new(&VtdlogParams::MIN);
+
+        assert!(matches!(
+            solver.recv(committer.commit().unwrap()),
+            Err(Error::InvalidParams)
+        ));
+    }
+
+    #[test]
+    fn test_vtdlog_interactive_rejects_large_challenge() {
+        let mut committer = Committer::<Puzzle>::new(
+            &mut OsRng,
+            &VtdlogParams::MIN,
+            100,
+            random_scalar(&mut OsRng),
+        );
+        committer.commit().unwrap();
+
+        let challenge = Message::Challenge((0..MIN_THRESHOLD).collect());
+        assert!(matches!(
+            committer.recv(challenge),
+            Err(Error::InvalidChallenge)
+        ));
+
+        let challenge = Message::Challenge(vec![0; MIN_THRESHOLD - 1]);
+        assert!(matches!(
+            committer.recv(challenge),
+            Err(Error::InvalidChallenge)
+        ));
+    }
+
+    #[test]
+    fn test_vtdlog_interactive_tampered_opening() {
+        let mut committer = Committer::<Puzzle>::new(
+            &mut OsRng,
+            &VtdlogParams::MIN,
+            100,
+            random_scalar(&mut OsRng),
+        );
+        let mut solver = Solver::<Puzzle>::new(&VtdlogParams::MIN);
+
+        let commit = committer.commit().unwrap();
+        let challenge = solver.recv(commit).unwrap().unwrap();
+
+        let mut opening = match committer.recv(challenge).unwrap() {
+            Message::Opening(proof) => proof,
+            _ => unreachable!(),
+        };
+        opening.openings[0].x_i += Scalar::one();
+
+        assert!(matches!(
+            solver.recv(Message::Opening(opening)),
+            Err(Error::InvalidOpening)
+        ));
+        assert!(matches!(solver.into_vtdlog(), Err(Error::NotVerified)));
+    }
+
+    #[test]
+    fn test_vtdlog_msgs_roundtrip() {
+        use prost::Message as _;
+
+        let secret = random_scalar(&mut OsRng);
+
+        let mut committer = Committer::<Puzzle>::new(&mut OsRng, &VtdlogParams::MIN, 100, secret);
+        let mut solver = Solver::<Puzzle>::new(&VtdlogParams::MIN);
+
+        let roundtrip = |msg: Message<Puzzle>| {
+            let bytes = msgs::VtdlogMsg::from(&msg).encode_to_vec();
+            Message::try_from(msgs::VtdlogMsg::decode(bytes.as_slice()).unwrap()).unwrap()
+        };
+
+        let commit = roundtrip(committer.commit().unwrap());
+        let challenge = roundtrip(solver.recv(commit).unwrap().unwrap());
+        let opening = roundtrip(committer.recv(challenge).unwrap());
+
+        solver.recv(opening).unwrap();
+
+        let mut vtdlog = solver.into_vtdlog().unwrap();
+        assert_eq!(vtdlog.solve().unwrap(), secret);
+    }
+
+    #[test]
+    fn test_vtdlog_commit_decoding_is_strict() {
+        let (commitment, _) = Vtdlog::<Puzzle>::commit(
+            &mut OsRng,
+            &VtdlogParams::MIN,
+            100,
+            random_scalar(&mut OsRng),
+        );
+
+        let mut msg = msgs::VtdlogCommit::from(&commitment);
+        msg.h_s.pop();
+        assert!(matches!(
+            Commit::<Puzzle>::try_from(msg),
+            Err(Error::InvalidEncoding)
+        ));
+
+        // non-canonical scalar
+        let mut msg = msgs::VtdlogCommit::from(&commitment);
+        msg.points[0] = [0xff; 32].to_vec();
+        assert!(matches!(
+            Commit::<Puzzle>::try_from(msg),
+            Err(Error::InvalidEncoding)
+        ));
+
+        // point with a torsion component
+        let mut msg = msgs::VtdlogCommit::from(&commitment);
+        let torsion = curve25519_dalek::constants::EIGHT_TORSION[1];
+        msg.h = (commitment.H + torsion).compress().to_bytes().to_vec();
+        assert!(matches!(
+            Commit::<Puzzle>::try_from(msg),
+            Err(Error::InvalidEncoding)
+        ));
+
+        let mut msg = msgs::VtdlogCommit::from(&commitment);
+        msg.params.as_mut().unwrap().threshold = 1;
+        assert!(matches!(
+            Commit::<Puzzle>::try_from(msg),
+            Err(Error::InvalidParams)
+        ));
+
+        let mut msg = msgs::VtdlogCommit::from(&commitment);
+        match msg.puzzles[0].puzzle.as_mut() {
+            Some(msgs::time_lock_puzzle::Puzzle::Lhtlp(puzzle)) => puzzle.u.clear(),
+            _ => unreachable!(),
+        }
+        assert!(matches!(
+            Commit::<Puzzle>::try_from(msg),
+            Err(Error::TimeLock(_))
+        ));
+
+        // puzzles of another backend
+        let msg = msgs::VtdlogCommit::from(&commitment);
+        assert!(matches!(
+            Commit::<classgroup::Puzzle>::try_from(msg),
+            Err(Error::InvalidParams)
+        ));
+    }
+
+    #[test]
+    fn test_vtdlog_interactive_tampered_puzzles() {
+        let secret = random_scalar(&mut OsRng);
+
+        let (mut commitment, mut witness) =
+            Vtdlog::<Puzzle>::commit(&mut OsRng, &VtdlogParams::MIN, 100, secret);
+
+        // every puzzle hides a share that does not match its H_i
+        for i in 0..MIN_SHARES {
+            let x_i = random_scalar(&mut OsRng);
+            let (puzzle, opening) =
+                Puzzle::single_with_opening(&mut OsRng, LhtlpParams::MIN, 100, x_i.as_bytes());
+
+            commitment.puzzles[i] = puzzle;
+            witness.x_s[i] = x_i;
+            witness.openings[i] = opening;
+        }
+
+        assert!(matches!(
+            open(commitment, witness),
+            Err(Error::InvalidOpening)
+        ));
+    }
+
+    #[test]
+    fn test_vtdlog_solve_blames_wrong_shares() {
+        let secret = random_scalar(&mut OsRng);
+        let params = VtdlogParams::MIN;
+
+        let (mut commitment, _) = Vtdlog::<Puzzle>::commit(&mut OsRng, &params, 100, secret);
+
+        // one puzzle too many hides a share that does not match its H_i
+        let wrong = params.n - params.threshold + 1;
+        let mut wrong_shares = vec![];
+        for i in 0..wrong {
+            let x_i = share_to_bytes(&random_scalar(&mut OsRng));
+            commitment.puzzles[i] = Puzzle::single(LhtlpParams::MIN, 100, &x_i);
+            wrong_shares.push(x_i);
+        }
+
+        let digests: Vec<[u8; 32]> = commitment.puzzles.iter().map(|p| p.digest()).collect();
+        let H_s = commitment.H_s.clone();
+
+        let mut vtdlog = Vtdlog::from_commit(commitment);
+        let blame = match vtdlog.solve() {
+            Err(Error::NotEnoughShares {
+                recovered,
+                threshold,
+                blame,
+            }) => {
+                assert_eq!(
+                    (recovered, threshold),
+                    (params.threshold - 1, params.threshold)
+                );
+                blame
+            }
+            _ => panic!("the wrong shares were not detected"),
+        };
+
+        assert_eq!(blame.len(), wrong);
+        for (i, blame) in blame.iter().enumerate() {
+            assert_eq!(blame.index, i);
+            assert_eq!(blame.puzzle, digests[i]);
+            assert_eq!(blame.H_i, H_s[i]);
+            assert_eq!(
+                share_from_bytes(blame.share.as_ref().unwrap()).unwrap(),
+                share_from_bytes(&wrong_shares[i]).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_vtdlog_solve_skips_wrong_shares() {
+        let secret = random_scalar(&mut OsRng);
+        let params = VtdlogParams::MIN;
+
+        let (mut commitment, _) = Vtdlog::<Puzzle>::commit(&mut OsRng, &params, 100, secret);
+
+        for i in 0..params.n - params.threshold {
+            commitment.puzzles[i] = Puzzle::single(LhtlpParams::MIN, 100, b"paymo");
+        }
+
+        let mut vtdlog = Vtdlog::from_commit(commitment);
+        assert_eq!(vtdlog.solve().unwrap(), secret);
+    }
+
+    #[test]
+    fn test_vtdlog_solve_wrong_H() {
+        let secret = random_scalar(&mut OsRng);
+
+        let (mut commitment, _) =
+            Vtdlog::<Puzzle>::commit(&mut OsRng, &VtdlogParams::MIN, 100, secret);
+        commitment.H += ED25519_BASEPOINT_TABLE.basepoint();
+
+        let mut vtdlog = Vtdlog::from_commit(commitment);
+        assert!(matches!(vtdlog.solve(), Err(Error::InvalidReconstruction)));
+    }
+
+    #[test]
+    fn test_vtdlog_interactive_tampered_H_s() {
+        let secret = random_scalar(&mut OsRng);
+        let one = &Scalar::one() * &ED25519_BASEPOINT_TABLE;
+
+        let (mut commitment, witness) =
+            Vtdlog::<Puzzle>::commit(&mut OsRng, &VtdlogParams::MIN, 100, secret);
+        commitment.H_s[MIN_SHARES - 1] += one;
+        assert!(matches!(
+            open(commitment, witness),
+            Err(Error::InvalidShares)
+        ));
+
+        let (mut commitment, witness) =
+            Vtdlog::<Puzzle>::commit(&mut OsRng, &VtdlogParams::MIN, 100, secret);
+        commitment.H += one;
+        assert!(matches!(
+            open(commitment, witness),
+            Err(Error::InvalidShares)
+        ));
+    }
+
+    #[test]
+    fn test_vtdlog_class_group() {
+        use prost::Message as _;
+
+        let secret = random_scalar(&mut OsRng);
+
+        let mut committer =
+            Committer::<classgroup::Puzzle>::new(&mut OsRng, &CLASS_GROUP, 100, secret);
+        let mut solver = Solver::<classgroup::Puzzle>::new(&CLASS_GROUP);
+
+        let roundtrip = |msg: Message<classgroup::Puzzle>| {
+            let bytes = msgs::VtdlogMsg::from(&msg).encode_to_vec();
+            Message::try_from(msgs::VtdlogMsg::decode(bytes.as_slice()).unwrap()).unwrap()
+        };
+
+        let commit = roundtrip(committer.commit().unwrap());
+        let challenge = roundtrip(solver.recv(commit).unwrap().unwrap());
+        let opening = roundtrip(committer.recv(challenge).unwrap());
+
+        solver.recv(opening).unwrap();
+
+        let mut vtdlog = solver.into_vtdlog().unwrap();
+        assert_eq!(vtdlog.solve().unwrap(), secret);
+    }
 }
@@ -0,0 +1,144 @@
+use std::collections::HashSet;
+
+use curve25519_dalek::{edwards::EdwardsPoint, scalar::Scalar};
+use monero_serai::rpc::{Rpc, RpcError};
+use monero_serai::wallet::address::{AddressSpec, MoneroAddress, Network};
+use monero_serai::wallet::{Scanner, SpendableOutput, ViewPair};
+use zeroize::Zeroizing;
+
+use super::jspend::JointOutput;
+
+/// Wallet of the joint address of a channel. Its spend key is the sum of the public keys of
+/// both parties, whose secrets they never share, and its view key the sum of their view
+/// secrets, which they do share, so that both can find the funding output.
+#[derive(Clone, Debug)]
+pub struct JointWallet {
+    spend: EdwardsPoint,
+    view: Zeroizing<Scalar>,
+    network: Network,
+}
+
+impl JointWallet {
+    pub fn new(
+        alice_spend: &EdwardsPoint,
+        bob_spend: &EdwardsPoint,
+        alice_view: &Scalar,
+        bob_view: &Scalar,
+        network: Network,
+    ) -> Self {
+        Self {
+            spend: alice_spend + bob_spend,
+            view: Zeroizing::new(alice_view + bob_view),
+            network,
+        }
+    }
+
+    pub fn spend_key(&self) -> EdwardsPoint {
+        self.spend
+    }
+
+    pub fn view_key(&self) -> &Scalar {
+        &self.view
+    }
+
+    pub fn view_pair(&self) -> ViewPair {
+        ViewPair::new(self.spend, self.view.clone())
+    }
+
+    /// Address that funds the channel.
+    pub fn address(&self) -> MoneroAddress {
+        self.view_pair()
+            .address(self.network, AddressSpec::Standard)
+    }
+
+    pub fn scanner(&self) -> Scanner {
+        Scanner::from_view(self.view_pair(), Some(HashSet::new()))
+    }
+
+    /// Scans the blocks from `from_height` for an output of `amount` at the joint address,
+    /// without an unlock time; returns it with the height of its block.
+    pub async fn find_output(
+        &self,
+        rpc: &Rpc,
+        from_height: usize,
+        amount: u64,
+    ) -> Result<Option<(JointOutput, usize)>, Error> {
+        let mut scanner = self.scanner();
+        let height = rpc.get_height().await.map_err(Error::Rpc)?;
+
+        for number in from_height..height {
+            let block = rpc.get_block_by_number(number).await.map_err(Error::Rpc)?;
+            let outputs = scanner.scan(rpc, &block).await.map_err(Error::Rpc)?;
+
+            // a time-locked output could not be spent when the channel closes or refunds Alice
+            let output = outputs
+                .into_iter()
+                .flat_map(|output| output.not_locked())
+                .find(|output| output.commitment().amount == amount);
+
+            if let Some(output) = output {
+                return Ok(Some((joint_output(&output), number)));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// The output spent by the channel, from the one found by the scanner of the joint wallet.
+pub fn joint_output(output: &SpendableOutput) -> JointOutput {
+    JointOutput {
+        key: output.key(),
+        key_offset: output.key_offset(),
+        commitment: output.commitment(),
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Monero RPC error: {0:?}")]
+    Rpc(RpcError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::utils::generate_user_key_pair;
+
+    #[test]
+    fn joint_address() {
+        let (alice_spend_secret, alice_spend) = generate_user_key_pair();
+        let (bob_spend_secret, bob_spend) = generate_user_key_pair();
+        let (alice_view, _) = generate_user_key_pair();
+        let (bob_view, _) = generate_user_key_pair();
+
+        let alice_wallet = JointWallet::new(
+            &alice_spend,
+            &bob_spend,
+            &alice_view,
+            &bob_view,
+            Network::Mainnet,
+        );
+        let bob_wallet = JointWallet::new(
+            &alice_spend,
+            &bob_spend,
+            &alice_view,
+            &bob_view,
+            Network::Mainnet,
+        );
+
+        let address = alice_wallet.address();
+        assert_eq!(address, bob_wallet.address());
+        assert_eq!(address.meta.network, Network::Mainnet);
+
+        let joint_spend_secret = alice_spend_secret + bob_spend_secret;
+        let view_pair = ViewPair::new(
+            &joint_spend_secret * &curve25519_dalek::constants::ED25519_BASEPOINT_TABLE,
+            Zeroizing::new(alice_view + bob_view),
+        );
+        assert_eq!(
+            address,
+            view_pair.address(Network::Mainnet, AddressSpec::Standard)
+        );
+    }
+}
@@ -22,9 +22,30 @@ pub enum Error {
     #[error("Core error: {0}")]
     Core(#[from] core::Error),
 
+    #[error("Channel error: {0}")]
+    Channel(#[from] core::channel::Error),
+
+    #[error("Channel backup error: {0}")]
+    Backup(#[from] core::backup::Error),
+
+    #[error("Checkpoint error: {0}")]
+    Checkpoint(#[from] core::checkpoint::Error),
+
+    #[error("Channel database error: {0}")]
+    Db(#[from] core::db::Error),
+
+    #[error("Keystore error: {0}")]
+    Keystore(#[from] core::keystore::Error),
+
+    #[error("Seed error: {0}")]
+    Seed(#[from] core::seed::Error),
+
     #[error("Peerd error: {0}")]
     Peerd(#[from] peerd::Error),
 
+    #[error("Peer transport error: {0}")]
+    PeerTransport(#[from] peerd::transport::Error),
+
     #[error("Walletd error: {0}")]
     Walletd(#[from] walletd::Error),
 
@@ -40,3 +61,17 @@ pub enum Error {
     #[error(transparent)]
     ProtobufDecode(#[from] DecodeError),
 }
+
+impl Error {
+    /// Failures of the process itself, of its sockets or its disk, rather than of the message
+    /// it was handling; a process stops on them, and only drops the message otherwise.
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            Error::Io(_)
+                | Error::Zmq(_)
+                | Error::PeerTransport(_)
+                | Error::Db(core::db::Error::Io(_))
+        )
+    }
+}
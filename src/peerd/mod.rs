@@ -1,10 +1,13 @@
 use crate::msgs;
 use clap::{ArgGroup, Parser};
 use colored::Colorize;
-use log::debug;
+use log::{debug, warn};
 use msgs::{peer_msg, peerd_msg};
 use prost::Message;
 use std::{fmt::Display, net, str::FromStr, thread, time::Duration};
+use transport::{NodeKey, Transport};
+
+pub mod transport;
 
 #[derive(Debug, Clone)]
 pub enum Protocol {
@@ -37,11 +40,29 @@ impl FromStr for Protocol {
 pub struct Url {
     pub protocol: Protocol,
     pub socket_addr: net::SocketAddrV4,
+
+    /// Public key of the peerd node at this address, which Bob authenticates it with
+    pub node_key: Option<[u8; 32]>,
+}
+
+impl Url {
+    /// The URL without the node key, which ZMQ binds or connects to.
+    pub fn endpoint(&self) -> String {
+        format!("{}://{}", self.protocol, self.socket_addr)
+    }
 }
 
 impl Display for Url {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&format!("{}://{}", self.protocol, self.socket_addr))
+        match self.node_key {
+            Some(node_key) => f.write_str(&format!(
+                "{}://{}@{}",
+                self.protocol,
+                hex::encode(node_key),
+                self.socket_addr
+            )),
+            None => f.write_str(&self.endpoint()),
+        }
     }
 }
 
@@ -67,13 +88,26 @@ impl FromStr for Url {
         let socket_addr = format!("{host}:{port}");
         let socket_addr = net::SocketAddrV4::from_str(&socket_addr).unwrap();
 
+        let node_key = match url.username() {
+            "" => None,
+            node_key => Some(parse_node_key(node_key)?),
+        };
+
         Ok(Url {
             protocol: scheme,
             socket_addr,
+            node_key,
         })
     }
 }
 
+pub fn parse_node_key(s: &str) -> Result<[u8; 32], Error> {
+    match hex::decode(s).map(<[u8; 32]>::try_from) {
+        Ok(Ok(node_key)) => Ok(node_key),
+        _ => Err(Error::InvalidNodeKey(s.to_string())),
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(name="peerd", bin_name="peerd", author, version, about, long_about = None)]
 #[command(group(
@@ -90,6 +124,14 @@ pub struct Opts {
 
     #[clap(long)]
     pub connect: Option<Url>,
+
+    /// The client resumed a channel from its data directory
+    #[clap(long, action = clap::ArgAction::Set, default_value_t = false)]
+    pub resume: bool,
+
+    /// Node key of Bob, the only node Alice accepts for the channel she resumed
+    #[clap(long, value_parser = parse_node_key)]
+    pub bob_key: Option<[u8; 32]>,
 }
 
 impl Opts {
@@ -109,7 +151,11 @@ pub struct Peerd {
     to_client_socket: Option<zmq::Socket>,
     from_client_socket: Option<zmq::Socket>,
 
-    peerd_socket: Option<zmq::Socket>,
+    transport: Option<Transport>,
+
+    resume: bool,
+    // Alice's, once Bob asked for the first acknowledgement
+    acked: bool,
 }
 
 impl Peerd {
@@ -120,29 +166,45 @@ impl Peerd {
             to_client_socket: None,
             from_client_socket: None,
 
-            peerd_socket: None,
+            transport: None,
+
+            resume: false,
+            acked: false,
         }
     }
 
-    fn bind_alice(&mut self, addr: &str) -> crate::Result<()> {
+    // Alice only talks to Bob's node once she knows it
+    fn bind_alice(
+        &mut self,
+        addr: &str,
+        node_key: NodeKey,
+        bob_key: Option<[u8; 32]>,
+    ) -> crate::Result<()> {
         let peerd_socket = self.zmq_context.socket(zmq::REP)?;
         peerd_socket.bind(addr)?;
 
-        self.peerd_socket = Some(peerd_socket);
+        self.transport = Some(Transport::responder(peerd_socket, node_key, bob_key)?);
 
         Ok(())
     }
 
-    fn connect_bob(&mut self, addr: &str) -> crate::Result<()> {
+    // Bob only talks to the node whose key is in Alice's URL
+    fn connect_bob(&mut self, url: &Url, node_key: NodeKey) -> crate::Result<()> {
+        let alice_key = url
+            .node_key
+            .ok_or_else(|| Error::MissingNodeKey(url.to_string()))?;
+
         let peerd_socket = self.zmq_context.socket(zmq::REQ)?;
-        peerd_socket.connect(addr)?;
+        peerd_socket.connect(&url.endpoint())?;
 
-        self.peerd_socket = Some(peerd_socket);
+        self.transport = Some(Transport::initiator(peerd_socket, node_key, alice_key));
 
         Ok(())
     }
 
     pub fn run(mut self, opts: Opts) -> crate::Result<()> {
+        let node_key = NodeKey::load_or_create(&opts.shared.data_dir)?;
+
         let (to_client_socket, from_client_socket) = crate::bus::connect_to_client_sockets(
             opts.shared.data_dir,
             self.zmq_context.clone(),
@@ -154,11 +216,12 @@ impl Peerd {
 
         self.to_client_socket = Some(to_client_socket);
         self.from_client_socket = Some(from_client_socket);
+        self.resume = opts.resume;
 
         if let Some(addr) = opts.bind {
-            self.bind_alice(&addr)?;
+            self.bind_alice(&addr, node_key, opts.bob_key)?;
         } else if let Some(url) = opts.connect {
-            self.connect_bob(&url.to_string())?;
+            self.connect_bob(&url, node_key)?;
 
             self.init_communication()?;
         }
@@ -172,17 +235,70 @@ impl Peerd {
         self.send_to_peer(peer_msg::PeerMsgType::AckMe, None)
     }
 
+    // waits for the other party and for the client, which starts some protocols itself, e.g.
+    // payments
     fn recv(&mut self) -> crate::Result<()> {
         loop {
-            let peerd_socket = self.peerd_socket.as_mut().unwrap();
+            let peerd_socket = self.transport.as_ref().unwrap().socket();
+            let from_client_socket = self.from_client_socket.as_ref().unwrap();
+
+            let mut items = [
+                peerd_socket.as_poll_item(zmq::POLLIN),
+                from_client_socket.as_poll_item(zmq::POLLIN),
+            ];
+            zmq::poll(&mut items, -1)?;
+
+            let from_peer = items[0].is_readable();
+            let from_client = items[1].is_readable();
+
+            if from_peer {
+                // only what the other party of the handshake sent, once and in order
+                match self.transport.as_mut().unwrap().recv() {
+                    Ok(Some(data)) => match self.recv_from_peer(data) {
+                        Err(e) if e.is_fatal() => return Err(e),
+                        Err(e) => warn!("Dropped a message from the other node: {e}"),
+                        Ok(()) => {}
+                    },
+                    Ok(None) => debug!("Answered a handshake message"),
+                    Err(
+                        e @ (transport::Error::Handshake
+                        | transport::Error::Unauthenticated
+                        | transport::Error::NotConnected
+                        | transport::Error::UnknownNode(_)),
+                    ) => warn!("Rejected a message from the other node: {e}"),
+                    Err(e) => return Err(e.into()),
+                }
+            } else if from_client {
+                self.recv_started_by_client()?;
+            }
+        }
+    }
+
+    // a message that the client sent without being asked
+    fn recv_started_by_client(&mut self) -> crate::Result<()> {
+        let from_client_socket = self.from_client_socket.as_ref().unwrap();
+
+        let _ = from_client_socket.recv_string(0)?;
+        let data = from_client_socket.recv_bytes(0)?;
+
+        let msg = msgs::PeerdMsg::decode(data.as_slice())?;
 
-            let data = peerd_socket.recv_bytes(0)?;
-            self.recv_from_peer(data)?;
+        match (msg.msg_type(), msg.data) {
+            (peerd_msg::PeerdMsgType::SendPay, Some(peerd_msg::Data::Pay(pay))) => {
+                println!("{}", "STARTING PAYMENT".yellow());
+                self.send_to_peer(peer_msg::PeerMsgType::Pay, Some(peer_msg::Data::Pay(pay)))
+            }
+            (peerd_msg::PeerdMsgType::SendClose, Some(peerd_msg::Data::Close(state))) => {
+                println!("{}", "CLOSING CHANNEL".yellow());
+                let data = peer_msg::Data::Close(state);
+                self.send_to_peer(peer_msg::PeerMsgType::Close, Some(data))
+            }
+            (msg_type, _) => Err(Error::UnexpectedPeerdMsgType(msg_type).into()),
         }
     }
 
     fn send_to_peer(
-        &self,
+        &mut self,
         msg_type: peer_msg::PeerMsgType,
         data: Option<peer_msg::Data>,
     ) -> crate::Result<()> {
@@ -191,8 +307,8 @@ impl Peerd {
             data,
         };
 
-        let peerd_socket = self.peerd_socket.as_ref().unwrap();
-        peerd_socket.send(msg.encode_to_vec(), 0)?;
+        let transport = self.transport.as_mut().unwrap();
+        transport.send(&msg.encode_to_vec())?;
 
         Ok(())
     }
@@ -216,27 +332,59 @@ impl Peerd {
         Ok(())
     }
 
-    fn recv_from_peer(&self, data: Vec<u8>) -> crate::Result<()> {
+    fn recv_from_peer(&mut self, data: Vec<u8>) -> crate::Result<()> {
         use peer_msg::PeerMsgType::*;
 
         let data = msgs::PeerMsg::decode(data.as_slice())?;
+        let msg_type = data.msg_type();
 
-        match data.msg_type() {
+        match msg_type {
             AckMe => {
-                println!("{}", "BOB CONNECTED".cyan());
+                let bob_key = self.transport.as_ref().unwrap().remote_key().unwrap();
+                println!(
+                    "{} {}",
+                    "BOB CONNECTED, WITH THE NODE KEY".cyan(),
+                    hex::encode(bob_key)
+                );
+
+                // Bob restarted, so he resumes the channel, without the payment in progress
+                if self.acked {
+                    self.resume = true;
+                    self.send_to_client(peerd_msg::PeerdMsgType::PeerReset, None)?;
+                }
+                self.acked = true;
+
                 self.send_to_peer(Acked, None)?
             }
+            Acked if self.resume => {
+                println!("{}", "ALICE ACKED".cyan());
+                println!("{}", "RESUMING THE CHANNEL".cyan());
+                self.send_to_peer(Resume, None)?
+            }
             Acked => {
                 println!("{}", "ALICE ACKED".cyan());
                 println!("{}", "NOW ASKING FOR CHANNEL INFO...".cyan());
                 self.send_to_peer(ReqChannelInfo, None)?
             }
 
+            // Alice replies with the next payment or closing she starts
+            Resume if self.resume => {
+                println!("{}", "BOB RESUMED THE CHANNEL".cyan());
+            }
+            Resume => return Err(Error::ResumeMismatch.into()),
+
+            ReqChannelInfo if self.resume => return Err(Error::ResumeMismatch.into()),
             ReqChannelInfo => {
                 println!("{}", "RECEIVED REQUEST FOR CHANNEL INFO".cyan());
 
+                // kept with the channel, so that only Bob's node resumes it
+                let bob_key = self.transport.as_ref().unwrap().remote_key().unwrap();
+
                 println!("{}", "Asking client for channel info...".cyan());
-                self.send_to_client(peerd_msg::PeerdMsgType::ReqChannelInfo, None)?;
+                self.send_to_client(
+                    peerd_msg::PeerdMsgType::ReqChannelInfo,
+                    Some(peerd_msg::Data::NodeKey(bob_key.to_vec())),
+                )?;
 
                 let client_data = self.recv_from_client(peerd_msg::PeerdMsgType::ResChannelInfo)?;
 
@@ -265,12 +413,10 @@ impl Peerd {
                     "RECEIVED CHANNEL INFO, NOW SENDING IT TO CLIENT".cyan()
                 );
 
-                let channel_info =
-                    if let Some(peer_msg::Data::ChannelInfo(channel_info)) = data.data {
-                        channel_info
-                    } else {
-                        unreachable!()
-                    };
+                let channel_info = match data.data {
+                    Some(peer_msg::Data::ChannelInfo(channel_info)) => channel_info,
+                    _ => return Err(Error::UnexpectedPeerMsg(msg_type).into()),
+                };
 
                 self.send_to_client(
                     peerd_msg::PeerdMsgType::SendChannelInfo,
@@ -302,10 +448,9 @@ impl Peerd {
             }
 
             ReqAddress => {
-                let bob_address = if let Some(peer_msg::Data::Address(bob_address)) = data.data {
-                    bob_address
-                } else {
-                    unreachable!()
+                let bob_address = match data.data {
+                    Some(peer_msg::Data::Address(bob_address)) => bob_address,
+                    _ => return Err(Error::UnexpectedPeerMsg(msg_type).into()),
                 };
 
                 println!("{}", "UPDATING BOB'S ADDRESS IN MY CHANNEL".cyan());
@@ -341,11 +486,9 @@ impl Peerd {
             }
 
             ResAddress => {
-                let alice_address = if let Some(peer_msg::Data::Address(alice_address)) = data.data
-                {
-                    alice_address
-                } else {
-                    unreachable!()
+                let alice_address = match data.data {
+                    Some(peer_msg::Data::Address(alice_address)) => alice_address,
+                    _ => return Err(Error::UnexpectedPeerMsg(msg_type).into()),
                 };
 
                 println!("{}", "UPDATING ALICE'S ADDRESS IN MY CHANNEL".cyan());
@@ -388,10 +531,9 @@ impl Peerd {
             }
 
             AliceResHash => {
-                let alice_hash = if let peer_msg::Data::Hash(alice_hash) = data.data.unwrap() {
-                    alice_hash
-                } else {
-                    unreachable!()
+                let alice_hash = match data.data {
+                    Some(peer_msg::Data::Hash(alice_hash)) => alice_hash,
+                    _ => return Err(Error::UnexpectedPeerMsg(msg_type).into()),
                 };
 
                 println!("HASH RECEIVED FROM ALICE {}", hex::encode(&alice_hash));
@@ -404,28 +546,31 @@ impl Peerd {
                 println!("{}", "ASK CLIENT TO GENERATE A SECRET".purple());
                 self.send_to_client(peerd_msg::PeerdMsgType::BobCreateSecret, None)?;
 
-                self.send_to_client(peerd_msg::PeerdMsgType::BobReqPubkey, None)?;
-                let client_data = self.recv_from_client(peerd_msg::PeerdMsgType::BobResPubkey)?;
-                let pubkey = if let peerd_msg::Data::Pubkey(pubkey) = client_data {
-                    pubkey
+                self.send_to_client(peerd_msg::PeerdMsgType::BobReqHash, None)?;
+                let client_data = self.recv_from_client(peerd_msg::PeerdMsgType::BobResHash)?;
+                let hash = if let peerd_msg::Data::Hash(hash) = client_data {
+                    hash
                 } else {
                     unreachable!()
                 };
 
-                println!("{}", "SENDING BOB'S PUBLIC KEY TO ALICE".purple());
-                let data = peer_msg::Data::Pubkey(pubkey);
-                self.send_to_peer(peer_msg::PeerMsgType::BobResPubkey, Some(data))?;
+                println!("{}", "SENDING HASH TO ALICE".purple());
+                let data = peer_msg::Data::Hash(hash);
+                self.send_to_peer(peer_msg::PeerMsgType::BobResHash, Some(data))?;
             }
 
-            BobResPubkey => {
-                let bob_pubkey = if let peer_msg::Data::Pubkey(bob_pubkey) = data.data.unwrap() {
-                    bob_pubkey
-                } else {
-                    unreachable!()
+            BobResHash => {
+                let bob_hash = match data.data {
+                    Some(peer_msg::Data::Hash(bob_hash)) => bob_hash,
+                    _ => return Err(Error::UnexpectedPeerMsg(msg_type).into()),
                 };
 
-                let data = peerd_msg::Data::Pubkey(bob_pubkey);
-                self.send_to_client(peerd_msg::PeerdMsgType::AliceUpdateBobKey, Some(data))?;
+                println!("HASH RECEIVED FROM BOB {}", hex::encode(&bob_hash));
+
+                self.send_to_client(
+                    peerd_msg::PeerdMsgType::AliceUpdateBobHash,
+                    Some(peerd_msg::Data::Hash(bob_hash)),
+                )?;
 
                 self.send_to_client(peerd_msg::PeerdMsgType::AliceReqPubkey, None)?;
                 let client_data = self.recv_from_client(peerd_msg::PeerdMsgType::AliceResPubkey)?;
@@ -441,39 +586,35 @@ impl Peerd {
             }
 
             AliceResPubkey => {
-                let alice_pubkey = if let peer_msg::Data::Pubkey(alice_pubkey) = data.data.unwrap()
-                {
-                    alice_pubkey
-                } else {
-                    unreachable!()
+                let alice_pubkey = match data.data {
+                    Some(peer_msg::Data::Pubkey(alice_pubkey)) => alice_pubkey,
+                    _ => return Err(Error::UnexpectedPeerMsg(msg_type).into()),
                 };
 
                 let data = peerd_msg::Data::Pubkey(alice_pubkey);
                 self.send_to_client(peerd_msg::PeerdMsgType::BobUpdateAliceKey, Some(data))?;
 
-                self.send_to_client(peerd_msg::PeerdMsgType::BobReqTag, None)?;
-                let client_data = self.recv_from_client(peerd_msg::PeerdMsgType::BobResTag)?;
-                let tag = if let peerd_msg::Data::Tag(tag) = client_data {
-                    tag
+                self.send_to_client(peerd_msg::PeerdMsgType::BobReqPubkey, None)?;
+                let client_data = self.recv_from_client(peerd_msg::PeerdMsgType::BobResPubkey)?;
+                let pubkey = if let peerd_msg::Data::Pubkey(pubkey) = client_data {
+                    pubkey
                 } else {
                     unreachable!()
                 };
 
-                self.send_to_peer(
-                    peer_msg::PeerMsgType::BobResTag,
-                    Some(peer_msg::Data::Tag(tag)),
-                )?;
+                println!("{}", "SENDING BOB'S PUBLIC KEY TO ALICE".purple());
+                let data = peer_msg::Data::Pubkey(pubkey);
+                self.send_to_peer(peer_msg::PeerMsgType::BobResPubkey, Some(data))?;
             }
 
-            BobResTag => {
-                let bob_tag = if let peer_msg::Data::Tag(bob_tag) = data.data.unwrap() {
-                    bob_tag
-                } else {
-                    unreachable!()
+            BobResPubkey => {
+                let bob_pubkey = match data.data {
+                    Some(peer_msg::Data::Pubkey(bob_pubkey)) => bob_pubkey,
+                    _ => return Err(Error::UnexpectedPeerMsg(msg_type).into()),
                 };
 
-                let data = peerd_msg::Data::Tag(bob_tag);
-                self.send_to_client(peerd_msg::PeerdMsgType::AliceUpdateBobTag, Some(data))?;
+                let data = peerd_msg::Data::Pubkey(bob_pubkey);
+                self.send_to_client(peerd_msg::PeerdMsgType::AliceUpdateBobKey, Some(data))?;
 
                 self.send_to_client(peerd_msg::PeerdMsgType::AliceReqTag, None)?;
                 let client_data = self.recv_from_client(peerd_msg::PeerdMsgType::AliceResTag)?;
@@ -490,22 +631,178 @@ impl Peerd {
             }
 
             AliceResTag => {
-                let alice_tag = if let peer_msg::Data::Tag(alice_tag) = data.data.unwrap() {
-                    alice_tag
-                } else {
-                    unreachable!()
+                let alice_tag = match data.data {
+                    Some(peer_msg::Data::Tag(alice_tag)) => alice_tag,
+                    _ => return Err(Error::UnexpectedPeerMsg(msg_type).into()),
                 };
 
                 let data = peerd_msg::Data::Tag(alice_tag);
                 self.send_to_client(peerd_msg::PeerdMsgType::BobUpdateAliceTag, Some(data))?;
+
+                self.send_to_client(peerd_msg::PeerdMsgType::BobReqTag, None)?;
+                let client_data = self.recv_from_client(peerd_msg::PeerdMsgType::BobResTag)?;
+                let tag = if let peerd_msg::Data::Tag(tag) = client_data {
+                    tag
+                } else {
+                    unreachable!()
+                };
+
+                self.send_to_peer(
+                    peer_msg::PeerMsgType::BobResTag,
+                    Some(peer_msg::Data::Tag(tag)),
+                )?;
+            }
+
+            BobResTag => {
+                let bob_tag = match data.data {
+                    Some(peer_msg::Data::Tag(bob_tag)) => bob_tag,
+                    _ => return Err(Error::UnexpectedPeerMsg(msg_type).into()),
+                };
+
+                let data = peerd_msg::Data::Tag(bob_tag);
+                self.send_to_client(peerd_msg::PeerdMsgType::AliceUpdateBobTag, Some(data))?;
+
+                println!("{}", "ASKING BOB FOR HIS VTD-LOG".purple());
+                self.send_to_peer(peer_msg::PeerMsgType::ReqVtdlog, None)?;
+            }
+
+            ReqVtdlog => {
+                self.send_to_client(peerd_msg::PeerdMsgType::BobReqVtdlog, None)?;
+
+                println!("{}", "SENDING BOB'S VTD-LOG TO ALICE".purple());
+                self.relay_vtdlog()?;
+            }
+
+            Vtdlog => {
+                let vtdlog = match data.data {
+                    Some(peer_msg::Data::Vtdlog(vtdlog)) => vtdlog,
+                    _ => return Err(Error::UnexpectedPeerMsg(msg_type).into()),
+                };
+
+                self.send_to_client(
+                    peerd_msg::PeerdMsgType::RecvVtdlog,
+                    Some(peerd_msg::Data::Vtdlog(vtdlog)),
+                )?;
+
+                self.relay_vtdlog()?;
             }
 
-            Unspecified => unreachable!(),
+            JointSpend => {
+                let joint_spend = match data.data {
+                    Some(peer_msg::Data::JointSpend(joint_spend)) => joint_spend,
+                    _ => return Err(Error::UnexpectedPeerMsg(msg_type).into()),
+                };
+
+                // the other party has nothing left to send
+                if joint_spend.data.is_none() {
+                    println!("{}", "JOINT SPEND DONE".yellow());
+                    return Ok(());
+                }
+
+                self.send_to_client(
+                    peerd_msg::PeerdMsgType::RecvJointSpend,
+                    Some(peerd_msg::Data::JointSpend(joint_spend)),
+                )?;
+
+                self.relay_joint_spend()?;
+            }
+
+            Pay => {
+                let pay = match data.data {
+                    Some(peer_msg::Data::Pay(pay)) => pay,
+                    _ => return Err(Error::UnexpectedPeerMsg(msg_type).into()),
+                };
+
+                self.send_to_client(
+                    peerd_msg::PeerdMsgType::RecvPay,
+                    Some(peerd_msg::Data::Pay(pay)),
+                )?;
+
+                self.relay_pay()?;
+            }
+
+            // Bob leads the joint spend that closes the channel
+            Close => {
+                let state = match data.data {
+                    Some(peer_msg::Data::Close(state)) => state,
+                    _ => return Err(Error::UnexpectedPeerMsg(msg_type).into()),
+                };
+
+                println!("{}", "ALICE IS CLOSING THE CHANNEL".yellow());
+                self.send_to_client(
+                    peerd_msg::PeerdMsgType::RecvClose,
+                    Some(peerd_msg::Data::Close(state)),
+                )?;
+
+                self.relay_joint_spend()?;
+            }
+
+            Unspecified => return Err(Error::UnexpectedPeerMsg(msg_type).into()),
         };
 
         Ok(())
     }
 
+    /// Asks the client to start spending the channel output together with the other party.
+    pub fn start_joint_spend(&mut self) -> crate::Result<()> {
+        println!("{}", "STARTING JOINT SPEND".yellow());
+        self.send_to_client(peerd_msg::PeerdMsgType::StartJointSpend, None)?;
+
+        self.relay_joint_spend()
+    }
+
+    // sends the next message of the client in the joint spend to the other party
+    fn relay_joint_spend(&mut self) -> crate::Result<()> {
+        let client_data = self.recv_from_client(peerd_msg::PeerdMsgType::SendJointSpend)?;
+        let joint_spend = if let peerd_msg::Data::JointSpend(joint_spend) = client_data {
+            joint_spend
+        } else {
+            unreachable!()
+        };
+
+        self.send_to_peer(
+            peer_msg::PeerMsgType::JointSpend,
+            Some(peer_msg::Data::JointSpend(joint_spend)),
+        )
+    }
+
+    // sends the next message of the client in the payment to the other party, if any
+    fn relay_pay(&mut self) -> crate::Result<()> {
+        let client_data = self.recv_from_client(peerd_msg::PeerdMsgType::SendPay)?;
+        let pay = if let peerd_msg::Data::Pay(pay) = client_data {
+            pay
+        } else {
+            unreachable!()
+        };
+
+        if pay.data.is_none() {
+            println!("{}", "PAYMENT DONE".yellow());
+            return Ok(());
+        }
+
+        self.send_to_peer(peer_msg::PeerMsgType::Pay, Some(peer_msg::Data::Pay(pay)))
+    }
+
+    // sends the next message of the client in the VTD-Log opening to the other party, if any
+    fn relay_vtdlog(&mut self) -> crate::Result<()> {
+        let client_data = self.recv_from_client(peerd_msg::PeerdMsgType::SendVtdlog)?;
+        let vtdlog = if let peerd_msg::Data::Vtdlog(vtdlog) = client_data {
+            vtdlog
+        } else {
+            unreachable!()
+        };
+
+        if vtdlog.data.is_none() {
+            println!("{}", "VTD-LOG VERIFIED".purple());
+            return Ok(());
+        }
+
+        self.send_to_peer(
+            peer_msg::PeerMsgType::Vtdlog,
+            Some(peer_msg::Data::Vtdlog(vtdlog)),
+        )
+    }
+
     fn recv_from_client(
         &self,
         msg_type: peerd_msg::PeerdMsgType,
@@ -546,6 +843,21 @@ must contain a port and a host, being IPv4 formatted."
     #[error(transparent)]
     UrlParseError(#[from] url::ParseError),
 
+    #[error("Invalid node key: {0}; it must be the 32 bytes of the key, in hex")]
+    InvalidNodeKey(String),
+
+    #[error("Missing node key in {0}; the URL of Alice is tcp://<node key>@<host>:<port>")]
+    MissingNodeKey(String),
+
     #[error("Unmatched peerd msg types. Expected: {0:?}, got: {1:?}")]
     UnmatchedPeerdMsgType(peerd_msg::PeerdMsgType, peerd_msg::PeerdMsgType),
+
+    #[error("Unexpected peerd msg type from the client: {0:?}")]
+    UnexpectedPeerdMsgType(peerd_msg::PeerdMsgType),
+
+    #[error("Unexpected or malformed message from the other party: {0:?}")]
+    UnexpectedPeerMsg(peer_msg::PeerMsgType),
+
+    #[error("The other party did not resume the channel, or resumed one that this party did not")]
+    ResumeMismatch,
 }
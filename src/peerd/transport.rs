@@ -0,0 +1,673 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use curve25519_dalek::{constants::ED25519_BASEPOINT_TABLE, scalar::Scalar};
+use log::{debug, warn};
+use rand_core::{OsRng, RngCore};
+use zeroize::Zeroizing;
+
+use crate::core::utils::atomic_write;
+
+/// Noise protocol of the peer transport, as in BOLT 8 but over Curve25519: Bob knows Alice's
+/// static key from her URL, and sends his own, encrypted, in the last handshake message.
+pub const NOISE_PARAMS: &str = "Noise_XK_25519_ChaChaPoly_SHA256";
+
+// binds the handshake to this protocol
+const PROLOGUE: &[u8] = b"PayMo peerd";
+
+// a Noise message is at most 65535 bytes, with a tag of 16
+const MAX_FRAME_BYTES: usize = 65535;
+const TAG_BYTES: usize = 16;
+const MAX_CHUNK_BYTES: usize = MAX_FRAME_BYTES - TAG_BYTES;
+
+// handshakes that Alice keeps in progress at once, the oldest being dropped first
+const MAX_PENDING_HANDSHAKES: usize = 8;
+
+/// Static key of the node, which authenticates it to its peers: Alice gives her public key
+/// to Bob along with her address. It is kept in `data_dir/node_key`, readable only by the
+/// user, and not in the keystore, since peerd runs without the passphrase; it guards no funds.
+pub struct NodeKey(Zeroizing<[u8; 32]>);
+
+impl NodeKey {
+    pub fn load_or_create(data_dir: &Path) -> Result<Self, Error> {
+        let path = data_dir.join("node_key");
+
+        match fs::read(&path) {
+            Ok(bytes) => {
+                let secret = <[u8; 32]>::try_from(bytes.as_slice())
+                    .map_err(|_| Error::CorruptedNodeKey(path.clone()))?;
+
+                return Ok(Self(Zeroizing::new(secret)));
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut secret = Zeroizing::new([0; 32]);
+        OsRng.fill_bytes(&mut secret[..]);
+
+        atomic_write(&path, &secret[..])?;
+        debug!("Created the node key {}", path.display());
+
+        Ok(Self(secret))
+    }
+
+    /// X25519 public key.
+    pub fn public_key(&self) -> [u8; 32] {
+        let mut clamped = Zeroizing::new(*self.0);
+        clamped[0] &= 248;
+        clamped[31] &= 127;
+        clamped[31] |= 64;
+
+        (&ED25519_BASEPOINT_TABLE * &Scalar::from_bits(*clamped))
+            .to_montgomery()
+            .to_bytes()
+    }
+}
+
+/// Encrypted and authenticated transport to the other peerd, over the REQ/REP socket of the
+/// channel. Bob starts the handshake with his first message, which is only sent, encrypted,
+/// with the last handshake message; after that, every message is split into frames of at
+/// most 64 KiB, each sent as a part of a multipart ZMQ message and encrypted with the next
+/// nonce of its direction. A frame that is modified, replayed, reordered or from anyone
+/// but the other party of the handshake fails to decrypt, and its message is rejected
+/// without changing the nonces. Alice keeps accepting handshakes, so that a restarted Bob
+/// connects again, but only from the node of the first one. Since the REP socket does not
+/// tell who sent a frame, she keeps a few handshakes in progress at once, and the first
+/// message of another node does not drop Bob's.
+pub struct Transport {
+    socket: zmq::Socket,
+    node_key: NodeKey,
+    state: State,
+    // Alice's: static key of Bob's node, once known
+    pinned_key: Option<[u8; 32]>,
+}
+
+enum State {
+    // Bob, who knows Alice's static key, before his first message
+    Connecting([u8; 32]),
+    Connected(Session),
+    // Alice, with the handshakes in progress, which wait for the last message of their Bob,
+    // and the session of the last one
+    Accepting(Vec<snow::HandshakeState>, Option<Session>),
+}
+
+impl Transport {
+    /// Alice's side, on the REP socket; only Bob's node, with the static key `bob_key`, is
+    /// accepted if it is known, e.g. for a resumed channel.
+    pub fn responder(
+        socket: zmq::Socket,
+        node_key: NodeKey,
+        bob_key: Option<[u8; 32]>,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            socket,
+            node_key,
+            state: State::Accepting(vec![], None),
+            pinned_key: bob_key,
+        })
+    }
+
+    /// Bob's side, on the REQ socket, with the static key of Alice's node.
+    pub fn initiator(socket: zmq::Socket, node_key: NodeKey, remote_key: [u8; 32]) -> Self {
+        Self {
+            socket,
+            node_key,
+            state: State::Connecting(remote_key),
+            pinned_key: None,
+        }
+    }
+
+    /// Static key of the other node, once the handshake is done.
+    pub fn remote_key(&self) -> Option<[u8; 32]> {
+        match &self.state {
+            State::Connected(session) | State::Accepting(_, Some(session)) => {
+                Some(session.remote_key)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn socket(&self) -> &zmq::Socket {
+        &self.socket
+    }
+
+    /// Sends `msg` to the other party; Bob's first message does the handshake.
+    pub fn send(&mut self, msg: &[u8]) -> Result<(), Error> {
+        if let State::Connecting(remote_key) = self.state {
+            let session = self.connect(&remote_key, msg)?;
+            self.state = State::Connected(session);
+
+            return Ok(());
+        }
+
+        let session = match &mut self.state {
+            State::Connected(session) | State::Accepting(_, Some(session)) => session,
+            _ => return Err(Error::NotConnected),
+        };
+
+        let frames = session.seal(msg)?;
+        self.socket.send_multipart(frames, 0)?;
+
+        Ok(())
+    }
+
+    /// The next message of the other party; `None` if it was a handshake message, which
+    /// Alice answers herself. Rejected messages are answered with an empty message, since
+    /// the REP socket cannot receive again before it answers.
+    pub fn recv(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let frames = self.socket.recv_multipart(0)?;
+
+        let res = match &mut self.state {
+            State::Connected(session) => session.open(&frames).map(Some),
+            State::Accepting(..) => self.accept(&frames),
+            State::Connecting(_) => Err(Error::NotConnected),
+        };
+
+        if res.is_err() && self.socket.get_socket_type()? == zmq::REP {
+            self.socket.send(Vec::<u8>::new(), 0)?;
+        }
+
+        res
+    }
+
+    // e, es -> <- e, ee; s, se -> with the first message
+    fn connect(&self, remote_key: &[u8; 32], msg: &[u8]) -> Result<Session, Error> {
+        let mut handshake = snow::Builder::new(noise_params())
+            .local_private_key(&self.node_key.0[..])
+            .remote_public_key(remote_key)
+            .prologue(PROLOGUE)
+            .build_initiator()?;
+
+        let mut buf = vec![0; MAX_FRAME_BYTES];
+
+        let len = handshake.write_message(&[], &mut buf)?;
+        self.socket.send(&buf[..len], 0)?;
+
+        let reply = self.socket.recv_bytes(0)?;
+        handshake
+            .read_message(&reply, &mut vec![0; MAX_FRAME_BYTES])
+            .map_err(|_| Error::Handshake)?;
+
+        // the message shares the last handshake message with Bob's encrypted static key
+        if msg.len() > MAX_FRAME_BYTES - 32 - 2 * TAG_BYTES {
+            return Err(Error::FirstMessageTooLong);
+        }
+
+        let len = handshake.write_message(msg, &mut buf)?;
+        self.socket.send(&buf[..len], 0)?;
+
+        Session::new(handshake)
+    }
+
+    // a message of the session, or else one of the handshake of the next Bob, e.g. of the
+    // same one after a restart
+    fn accept(&mut self, frames: &[Vec<u8>]) -> Result<Option<Vec<u8>>, Error> {
+        let session = match &mut self.state {
+            State::Accepting(_, session) => session,
+            _ => unreachable!(),
+        };
+
+        let frame = match (session, frames) {
+            (Some(session), _) => match (session.open(frames), frames) {
+                (Ok(msg), _) => return Ok(Some(msg)),
+                (Err(_), [frame]) => frame,
+                (Err(e), _) => return Err(e),
+            },
+            (None, [frame]) => frame,
+            (None, _) => return Err(Error::Handshake),
+        };
+
+        self.handshake(frame)
+    }
+
+    // e, es -> <- e, ee; s, se -> with Bob's first message; a completed handshake replaces the
+    // session of the last Bob, if it is from the same node
+    fn handshake(&mut self, frame: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let pending = match &mut self.state {
+            State::Accepting(pending, _) => pending,
+            _ => unreachable!(),
+        };
+
+        let mut payload = vec![0; MAX_FRAME_BYTES];
+
+        // the last message of a handshake in progress; a failed read leaves it as it was
+        let mut finished = None;
+        for i in 0..pending.len() {
+            if let Ok(len) = pending[i].read_message(frame, &mut payload) {
+                finished = Some((pending.remove(i), len));
+                break;
+            }
+        }
+
+        let (handshake, len) = match finished {
+            Some(finished) => finished,
+            // or else the first message of a new one, e.g. Bob's again after his side of the
+            // last one failed
+            None => {
+                let mut handshake = responder_handshake(&self.node_key)?;
+                handshake
+                    .read_message(frame, &mut payload)
+                    .map_err(|_| Error::Handshake)?;
+
+                let mut buf = vec![0; MAX_FRAME_BYTES];
+                let len = handshake.write_message(&[], &mut buf)?;
+                self.socket.send(&buf[..len], 0)?;
+
+                if pending.len() == MAX_PENDING_HANDSHAKES {
+                    pending.remove(0);
+                }
+                pending.push(handshake);
+
+                return Ok(None);
+            }
+        };
+
+        let session = Session::new(handshake)?;
+        if matches!(self.pinned_key, Some(key) if key != session.remote_key) {
+            return Err(Error::UnknownNode(hex::encode(session.remote_key)));
+        }
+
+        debug!(
+            "Handshake done with node {}",
+            hex::encode(session.remote_key)
+        );
+        self.pinned_key = Some(session.remote_key);
+        if let State::Accepting(_, last) = &mut self.state {
+            *last = Some(session);
+        }
+
+        payload.truncate(len);
+        Ok(Some(payload))
+    }
+}
+
+/// Keys of both directions, after the handshake, and the nonce of the next frame of each.
+pub struct Session {
+    noise: snow::StatelessTransportState,
+    remote_key: [u8; 32],
+    send_nonce: u64,
+    recv_nonce: u64,
+}
+
+impl Session {
+    fn new(handshake: snow::HandshakeState) -> Result<Self, Error> {
+        let remote_key = handshake
+            .get_remote_static()
+            .and_then(|key| <[u8; 32]>::try_from(key).ok())
+            .ok_or(Error::Handshake)?;
+
+        Ok(Self {
+            noise: handshake.into_stateless_transport_mode()?,
+            remote_key,
+            send_nonce: 0,
+            recv_nonce: 0,
+        })
+    }
+
+    // at least one frame, so that empty messages are authenticated too
+    fn seal(&mut self, msg: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+        let mut frames = vec![];
+        let mut buf = vec![0; MAX_FRAME_BYTES];
+
+        let mut chunks: Vec<&[u8]> = msg.chunks(MAX_CHUNK_BYTES).collect();
+        if chunks.is_empty() {
+            chunks.push(&[]);
+        }
+
+        for chunk in chunks {
+            let len = self.noise.write_message(self.send_nonce, chunk, &mut buf)?;
+            frames.push(buf[..len].to_vec());
+
+            self.send_nonce += 1;
+        }
+
+        Ok(frames)
+    }
+
+    // the nonce only moves once the whole message decrypts, so that a rejected message
+    // does not keep the next genuine one from decrypting
+    fn open(&mut self, frames: &[Vec<u8>]) -> Result<Vec<u8>, Error> {
+        if frames.is_empty() {
+            return Err(Error::Unauthenticated);
+        }
+
+        let mut msg = vec![];
+        let mut buf = vec![0; MAX_FRAME_BYTES];
+
+        for (nonce, frame) in (self.recv_nonce..).zip(frames) {
+            let len = match self.noise.read_message(nonce, frame, &mut buf) {
+                Ok(len) => len,
+                Err(err) => {
+                    warn!("Rejected a frame from the other node: {err}");
+                    return Err(Error::Unauthenticated);
+                }
+            };
+            msg.extend(&buf[..len]);
+        }
+
+        self.recv_nonce += frames.len() as u64;
+
+        Ok(msg)
+    }
+}
+
+fn noise_params() -> snow::params::NoiseParams {
+    NOISE_PARAMS.parse().unwrap()
+}
+
+fn responder_handshake(node_key: &NodeKey) -> Result<snow::HandshakeState, Error> {
+    Ok(snow::Builder::new(noise_params())
+        .local_private_key(&node_key.0[..])
+        .prologue(PROLOGUE)
+        .build_responder()?)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Could not access the node key: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Corrupted node key: {0}")]
+    CorruptedNodeKey(PathBuf),
+
+    #[error("Noise error: {0}")]
+    Noise(#[from] snow::Error),
+
+    #[error(transparent)]
+    Zmq(#[from] zmq::Error),
+
+    #[error("Handshake with the other node failed: it does not have the node key of the URL")]
+    Handshake,
+
+    #[error("Rejected a message that the other node did not send, or already sent")]
+    Unauthenticated,
+
+    #[error("The first message to the other node does not fit in the handshake")]
+    FirstMessageTooLong,
+
+    #[error("No session with the other node yet")]
+    NotConnected,
+
+    #[error("Rejected the handshake of node {0}, which is not the one of the channel")]
+    UnknownNode(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node_key() -> NodeKey {
+        let mut secret = Zeroizing::new([0; 32]);
+        OsRng.fill_bytes(&mut secret[..]);
+
+        NodeKey(secret)
+    }
+
+    // a session of both sides, without sockets
+    fn sessions() -> (Session, Session) {
+        let (alice_key, bob_key) = (node_key(), node_key());
+
+        let mut alice = responder_handshake(&alice_key).unwrap();
+        let mut bob = snow::Builder::new(noise_params())
+            .local_private_key(&bob_key.0[..])
+            .remote_public_key(&alice_key.public_key())
+            .prologue(PROLOGUE)
+            .build_initiator()
+            .unwrap();
+
+        let (mut buf, mut payload) = (vec![0; MAX_FRAME_BYTES], vec![0; MAX_FRAME_BYTES]);
+        let len = bob.write_message(&[], &mut buf).unwrap();
+        alice.read_message(&buf[..len], &mut payload).unwrap();
+        let len = alice.write_message(&[], &mut buf).unwrap();
+        bob.read_message(&buf[..len], &mut payload).unwrap();
+        let len = bob.write_message(&[], &mut buf).unwrap();
+        alice.read_message(&buf[..len], &mut payload).unwrap();
+
+        let (alice, bob) = (Session::new(alice).unwrap(), Session::new(bob).unwrap());
+        assert_eq!(alice.remote_key, bob_key.public_key());
+        assert_eq!(bob.remote_key, alice_key.public_key());
+
+        (alice, bob)
+    }
+
+    #[test]
+    fn node_keys_are_x25519_keys() {
+        let keypair = snow::Builder::new(noise_params())
+            .generate_keypair()
+            .unwrap();
+        let node_key = NodeKey(Zeroizing::new(keypair.private.try_into().unwrap()));
+
+        assert_eq!(node_key.public_key()[..], keypair.public[..]);
+    }
+
+    #[test]
+    fn messages_are_framed() {
+        let (mut alice, mut bob) = sessions();
+
+        for msg in [vec![], b"paymo".to_vec(), vec![7; 3 * MAX_CHUNK_BYTES + 5]] {
+            let frames = bob.seal(&msg).unwrap();
+            assert_eq!(frames.len(), msg.len() / MAX_CHUNK_BYTES + 1);
+            assert!(frames.iter().all(|frame| frame.len() <= MAX_FRAME_BYTES));
+            assert_eq!(alice.open(&frames).unwrap(), msg);
+
+            let frames = alice.seal(&msg).unwrap();
+            assert_eq!(bob.open(&frames).unwrap(), msg);
+        }
+    }
+
+    #[test]
+    fn replayed_and_modified_messages_are_rejected() {
+        let (mut alice, mut bob) = sessions();
+
+        let first = bob.seal(b"first").unwrap();
+        let second = bob.seal(b"second").unwrap();
+        alice.open(&first).unwrap();
+
+        // replayed
+        assert!(matches!(alice.open(&first), Err(Error::Unauthenticated)));
+
+        // reflected, from the other direction
+        let own = alice.seal(b"own").unwrap();
+        assert!(matches!(alice.open(&own), Err(Error::Unauthenticated)));
+
+        // modified
+        let mut modified = second.clone();
+        modified[0][0] ^= 1;
+        assert!(matches!(alice.open(&modified), Err(Error::Unauthenticated)));
+
+        // from another session
+        let (_, mut other) = sessions();
+        let forged = other.seal(b"second").unwrap();
+        assert!(matches!(alice.open(&forged), Err(Error::Unauthenticated)));
+
+        // none of them moved the nonce
+        assert_eq!(alice.open(&second).unwrap(), b"second");
+
+        // neither does a message with only its last frame modified
+        let large = vec![7; MAX_CHUNK_BYTES + 1];
+        let frames = bob.seal(&large).unwrap();
+        let mut modified = frames.clone();
+        modified[1][0] ^= 1;
+        assert!(matches!(alice.open(&modified), Err(Error::Unauthenticated)));
+        assert_eq!(alice.open(&frames).unwrap(), large);
+    }
+
+    #[test]
+    fn handshakes_need_the_node_key() {
+        let context = zmq::Context::new();
+        let addr = format!("inproc://paymo-{}", hex::encode(rand::random::<[u8; 8]>()));
+
+        let alice_key = node_key();
+        let alice_public_key = alice_key.public_key();
+
+        let rep = context.socket(zmq::REP).unwrap();
+        rep.bind(&addr).unwrap();
+        let mut alice = Transport::responder(rep, alice_key, None).unwrap();
+
+        let alice = std::thread::spawn(move || {
+            let mut msgs = vec![];
+            while msgs.len() < 2 {
+                if let Ok(Some(msg)) = alice.recv() {
+                    msgs.push(msg);
+                    alice.send(b"acked").unwrap();
+                }
+            }
+
+            msgs
+        });
+
+        // another key than Alice's
+        let req = context.socket(zmq::REQ).unwrap();
+        req.connect(&addr).unwrap();
+        let mut mallory = Transport::initiator(req, node_key(), node_key().public_key());
+        assert!(matches!(mallory.send(b"hi"), Err(Error::Handshake)));
+
+        let req = context.socket(zmq::REQ).unwrap();
+        req.connect(&addr).unwrap();
+        let mut bob = Transport::initiator(req, node_key(), alice_public_key);
+
+        bob.send(b"ack me").unwrap();
+        assert_eq!(bob.recv().unwrap().unwrap(), b"acked");
+        assert_eq!(bob.remote_key(), Some(alice_public_key));
+
+        bob.send(b"pay").unwrap();
+        assert_eq!(bob.recv().unwrap().unwrap(), b"acked");
+
+        assert_eq!(
+            alice.join().unwrap(),
+            vec![b"ack me".to_vec(), b"pay".to_vec()]
+        );
+    }
+
+    #[test]
+    fn restarted_bobs_handshake_again() {
+        let context = zmq::Context::new();
+        let addr = format!("inproc://paymo-{}", hex::encode(rand::random::<[u8; 8]>()));
+
+        let (alice_key, bob_key) = (node_key(), node_key());
+        let (alice_public_key, bob_public_key) = (alice_key.public_key(), bob_key.public_key());
+
+        let rep = context.socket(zmq::REP).unwrap();
+        rep.bind(&addr).unwrap();
+        let mut alice = Transport::responder(rep, alice_key, None).unwrap();
+
+        let alice = std::thread::spawn(move || {
+            let mut msgs = vec![];
+            while msgs.len() < 3 {
+                if let Ok(Some(msg)) = alice.recv() {
+                    msgs.push(msg);
+                    alice.send(b"acked").unwrap();
+                }
+            }
+
+            (msgs, alice.remote_key())
+        });
+
+        let bob = |node_key: &NodeKey| {
+            let req = context.socket(zmq::REQ).unwrap();
+            req.connect(&addr).unwrap();
+
+            Transport::initiator(req, NodeKey(node_key.0.clone()), alice_public_key)
+        };
+
+        let mut first = bob(&bob_key);
+        first.send(b"ack me").unwrap();
+        assert_eq!(first.recv().unwrap().unwrap(), b"acked");
+
+        // the same node, after a restart
+        let mut restarted = bob(&bob_key);
+        restarted.send(b"ack me again").unwrap();
+        assert_eq!(restarted.recv().unwrap().unwrap(), b"acked");
+
+        // another node completes the handshake, but Alice does not answer it
+        let mut mallory = bob(&node_key());
+        mallory.send(b"ack me").unwrap();
+        assert!(matches!(mallory.recv(), Err(Error::Unauthenticated)));
+
+        restarted.send(b"pay").unwrap();
+        assert_eq!(restarted.recv().unwrap().unwrap(), b"acked");
+
+        let (msgs, remote_key) = alice.join().unwrap();
+        assert_eq!(
+            msgs,
+            vec![
+                b"ack me".to_vec(),
+                b"ack me again".to_vec(),
+                b"pay".to_vec()
+            ]
+        );
+        assert_eq!(remote_key, Some(bob_public_key));
+    }
+
+    #[test]
+    fn strangers_do_not_reset_handshakes_in_progress() {
+        let context = zmq::Context::new();
+        let addr = format!("inproc://paymo-{}", hex::encode(rand::random::<[u8; 8]>()));
+
+        let (alice_key, bob_key) = (node_key(), node_key());
+        let (alice_public_key, bob_public_key) = (alice_key.public_key(), bob_key.public_key());
+
+        let rep = context.socket(zmq::REP).unwrap();
+        rep.bind(&addr).unwrap();
+        let mut alice = Transport::responder(rep, alice_key, None).unwrap();
+
+        let alice = std::thread::spawn(move || {
+            let mut msgs = vec![];
+            while msgs.len() < 2 {
+                if let Ok(Some(msg)) = alice.recv() {
+                    msgs.push(msg);
+                    alice.send(b"acked").unwrap();
+                }
+            }
+
+            (msgs, alice.remote_key())
+        });
+
+        let req = context.socket(zmq::REQ).unwrap();
+        req.connect(&addr).unwrap();
+        let mut first = Transport::initiator(req, NodeKey(bob_key.0.clone()), alice_public_key);
+        first.send(b"ack me").unwrap();
+        assert_eq!(first.recv().unwrap().unwrap(), b"acked");
+
+        // the handshakes of the restarted Bob and of another node, one message at a time
+        let initiator = |node_key: &NodeKey| {
+            let req = context.socket(zmq::REQ).unwrap();
+            req.connect(&addr).unwrap();
+
+            let handshake = snow::Builder::new(noise_params())
+                .local_private_key(&node_key.0[..])
+                .remote_public_key(&alice_public_key)
+                .prologue(PROLOGUE)
+                .build_initiator()
+                .unwrap();
+
+            (req, handshake)
+        };
+        let (mut buf, mut payload) = (vec![0; MAX_FRAME_BYTES], vec![0; MAX_FRAME_BYTES]);
+
+        let (bob_req, mut bob) = initiator(&bob_key);
+        let len = bob.write_message(&[], &mut buf).unwrap();
+        bob_req.send(&buf[..len], 0).unwrap();
+        let reply = bob_req.recv_bytes(0).unwrap();
+        bob.read_message(&reply, &mut payload).unwrap();
+
+        // the first message of the stranger, between the restarted Bob's first and last
+        let (stranger_req, mut stranger) = initiator(&node_key());
+        let len = stranger.write_message(&[], &mut buf).unwrap();
+        stranger_req.send(&buf[..len], 0).unwrap();
+        let reply = stranger_req.recv_bytes(0).unwrap();
+        stranger.read_message(&reply, &mut payload).unwrap();
+
+        let len = bob.write_message(b"ack me again", &mut buf).unwrap();
+        bob_req.send(&buf[..len], 0).unwrap();
+        let mut session = Session::new(bob).unwrap();
+        let reply = bob_req.recv_multipart(0).unwrap();
+        assert_eq!(session.open(&reply).unwrap(), b"acked");
+
+        let (msgs, remote_key) = alice.join().unwrap();
+        assert_eq!(msgs, vec![b"ack me".to_vec(), b"ack me again".to_vec()]);
+        assert_eq!(remote_key, Some(bob_public_key));
+    }
+}
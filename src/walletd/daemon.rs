@@ -0,0 +1,363 @@
+use curve25519_dalek::{edwards::EdwardsPoint, traits::Identity};
+use monero_serai::{
+    ringct::RctPrunable,
+    rpc::Rpc,
+    transaction::Input,
+    wallet::{address::MoneroAddress, Change, Decoys, SignableTransaction, SpendableOutput},
+    Commitment,
+};
+use rand_core::{CryptoRng, OsRng, RngCore};
+
+use super::{Error, SpendBuilder, UnsignedSpend};
+use crate::core::channel::Funding;
+
+/// Builds the spends of the joint output with the fee and the decoys of a Monero daemon.
+pub struct DaemonSpendBuilder {
+    rpc: Rpc,
+    runtime: tokio::runtime::Runtime,
+    // the joint output with its global index, once it is needed
+    output: Option<SpendableOutput>,
+}
+
+impl DaemonSpendBuilder {
+    pub fn new(rpc: Rpc) -> std::io::Result<Self> {
+        Ok(Self {
+            rpc,
+            runtime: tokio::runtime::Runtime::new()?,
+            output: None,
+        })
+    }
+}
+
+impl SpendBuilder for DaemonSpendBuilder {
+    fn build(
+        &mut self,
+        funding: &Funding,
+        to: &MoneroAddress,
+        amount: u64,
+        change: &MoneroAddress,
+    ) -> Result<UnsignedSpend, Error> {
+        let Self {
+            rpc,
+            runtime,
+            output,
+        } = self;
+
+        let input = runtime.block_on(spendable(rpc, output, funding))?;
+        runtime.block_on(build_spend(
+            &mut OsRng, rpc, input, funding, to, amount, change,
+        ))
+    }
+}
+
+// the joint output as found by the scanner, which the decoys are selected for, from the block
+// that has it
+async fn spendable(
+    rpc: &Rpc,
+    output: &mut Option<SpendableOutput>,
+    funding: &Funding,
+) -> Result<SpendableOutput, Error> {
+    if let Some(output) = output {
+        return Ok(output.clone());
+    }
+
+    let block = rpc
+        .get_block_by_number(funding.height)
+        .await
+        .map_err(Error::Rpc)?;
+    let mut scanner = funding.keys.joint_wallet.scanner();
+    let outputs = scanner.scan(rpc, &block).await.map_err(Error::Rpc)?;
+
+    let found = outputs
+        .into_iter()
+        .flat_map(|outputs| outputs.not_locked())
+        .find(|found| found.key() == funding.output.key)
+        .ok_or(Error::OutputNotFound)?;
+
+    Ok(output.insert(found).clone())
+}
+
+async fn build_spend<R: RngCore + CryptoRng>(
+    rng: &mut R,
+    rpc: &Rpc,
+    input: SpendableOutput,
+    funding: &Funding,
+    to: &MoneroAddress,
+    amount: u64,
+    change: &MoneroAddress,
+) -> Result<UnsignedSpend, Error> {
+    let protocol = rpc.get_protocol().await.map_err(Error::Rpc)?;
+    let fee = rpc.get_fee().await.map_err(Error::Rpc)?;
+    let height = rpc.get_height().await.map_err(Error::Rpc)?;
+
+    let payments = match amount {
+        0 => vec![],
+        amount => vec![(*to, amount)],
+    };
+    let mut signable = SignableTransaction::new(
+        protocol,
+        vec![input.clone()],
+        payments,
+        Some(Change::fingerprintable(*change)),
+        vec![],
+        fee,
+    )
+    .map_err(Error::Build)?;
+
+    let decoys = Decoys::select(rng, rpc, protocol.ring_len(), height, &[input])
+        .await
+        .map_err(Error::Rpc)?;
+    let decoys = decoys.into_iter().next().ok_or(Error::OutputNotFound)?;
+
+    // the key image, from which the keys of the transaction are usually derived, is only known
+    // once both parties sign, so they are derived from random bytes instead
+    let mut uniqueness = [0; 32];
+    rng.fill_bytes(&mut uniqueness);
+    let (mut tx, output_masks) = signable.prepare_transaction(rng, uniqueness);
+
+    // the pseudo output commits to the amount of the joint output with the masks of the
+    // outputs, so that the commitments of the transaction balance
+    let pseudo_out = Commitment::new(output_masks, funding.output.commitment.amount);
+    match &mut tx.rct_signatures.prunable {
+        RctPrunable::Clsag { pseudo_outs, .. } => *pseudo_outs = vec![pseudo_out.calculate()],
+        _ => return Err(Error::InvalidTransaction),
+    }
+
+    tx.prefix.inputs = vec![Input::ToKey {
+        amount: 0,
+        key_offsets: decoys.offsets.clone(),
+        // set during the joint spend, or with the pre-signature
+        key_image: EdwardsPoint::identity(),
+    }];
+
+    Ok(UnsignedSpend {
+        tx,
+        ring: decoys.ring,
+        z: funding.output.commitment.mask - output_masks,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    use curve25519_dalek::{constants::ED25519_BASEPOINT_TABLE, scalar::Scalar};
+    use monero_serai::random_scalar;
+    use monero_serai::wallet::address::{AddressSpec, Network};
+    use monero_serai::wallet::{Scanner, ViewPair};
+    use zeroize::Zeroizing;
+
+    use crate::core::channel::{ChannelState, Keys, Params};
+    use crate::core::seed::MasterSeed;
+    use crate::core::vtdlog::VtdlogParams;
+    use crate::core::Role;
+    use crate::msgs::{self, joint_spend_msg::Data};
+    use crate::walletd::{JointSpend, ViewKey};
+
+    const CHANNEL_AMOUNT: u64 = 1_000_000_000_000;
+
+    // a daemon in regtest mode, as in the README, unless PAYMO_TEST_DAEMON is set
+    fn daemon_url() -> String {
+        std::env::var("PAYMO_TEST_DAEMON").unwrap_or_else(|_| "http://127.0.0.1:18081".into())
+    }
+
+    // address, spend key and view key of a new wallet
+    fn wallet() -> (MoneroAddress, Zeroizing<Scalar>, Zeroizing<Scalar>) {
+        let spend = Zeroizing::new(random_scalar(&mut OsRng));
+        let view = Zeroizing::new(random_scalar(&mut OsRng));
+
+        let pair = ViewPair::new(&*spend * &ED25519_BASEPOINT_TABLE, view.clone());
+        let address = pair.address(Network::Mainnet, AddressSpec::Standard);
+
+        (address, spend, view)
+    }
+
+    async fn mine(address: &MoneroAddress, blocks: u64) {
+        let daemon = monero_rpc::RpcClientBuilder::new()
+            .build(daemon_url())
+            .unwrap()
+            .daemon()
+            .regtest();
+        let address = monero::Address::from_str(&address.to_string()).unwrap();
+
+        daemon.generate_blocks(blocks, address).await.unwrap();
+    }
+
+    // Alice's and Bob's side of a channel funded with a transaction from mined coins
+    async fn funded_channel(
+        rpc: &Rpc,
+        alice_address: MoneroAddress,
+        bob_address: MoneroAddress,
+    ) -> (Funding, Funding) {
+        let params = Params {
+            channel_amount: monero::Amount::from_pico(CHANNEL_AMOUNT),
+            time: 100,
+            confirmations: 1,
+            vtdlog_params: VtdlogParams::default(),
+        };
+        let alice = MasterSeed::generate().0.channel_keys(0);
+        let bob = MasterSeed::generate().0.channel_keys(0);
+
+        let keys = |role: Role| match role {
+            Role::Alice => Keys::new(
+                role,
+                params.clone(),
+                alice_address,
+                bob_address,
+                alice.clone(),
+                bob.public_key,
+                *bob.view_secret,
+            ),
+            Role::Bob => Keys::new(
+                role,
+                params.clone(),
+                alice_address,
+                bob_address,
+                bob.clone(),
+                alice.public_key,
+                *alice.view_secret,
+            ),
+        };
+        let joint_wallet = keys(Role::Alice).joint_wallet;
+
+        // coinbase outputs are spendable after 60 blocks
+        let (miner, spend, view) = wallet();
+        let from_height = rpc.get_height().await.unwrap();
+        mine(&miner, 70).await;
+
+        let block = rpc.get_block_by_number(from_height).await.unwrap();
+        let pair = ViewPair::new(&*spend * &ED25519_BASEPOINT_TABLE, view);
+        let coinbase = Scanner::from_view(pair, None)
+            .scan(rpc, &block)
+            .await
+            .unwrap()
+            .swap_remove(0)
+            .ignore_timelock()
+            .swap_remove(0);
+
+        let mut funding_tx = SignableTransaction::new(
+            rpc.get_protocol().await.unwrap(),
+            vec![coinbase],
+            vec![(joint_wallet.address(), CHANNEL_AMOUNT)],
+            Some(Change::fingerprintable(miner)),
+            vec![],
+            rpc.get_fee().await.unwrap(),
+        )
+        .unwrap();
+        let funding_tx = funding_tx.sign(&mut OsRng, rpc, &spend).await.unwrap();
+        rpc.publish_transaction(&funding_tx).await.unwrap();
+
+        // outputs are only selected as decoys, or spent, after 10 blocks
+        mine(&miner, 10).await;
+
+        let (output, height) = joint_wallet
+            .find_output(rpc, from_height, CHANNEL_AMOUNT)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let joint_tag = keys(Role::Alice).tag + keys(Role::Bob).tag;
+        let funding = |role| Funding {
+            joint_tag,
+            keys: keys(role),
+            output: output.clone(),
+            height,
+            image: None,
+        };
+
+        (funding(Role::Alice), funding(Role::Bob))
+    }
+
+    fn data(msg: msgs::JointSpendMsg) -> Data {
+        msg.data.unwrap()
+    }
+
+    // Bob's proposal of `spend`, once both parties started the joint spend
+    fn propose(
+        alice: &mut JointSpend,
+        bob: &mut JointSpend,
+        spend: UnsignedSpend,
+    ) -> msgs::JointSpendProposal {
+        let init = match data(bob.init()) {
+            Data::Init(init) => init,
+            data => panic!("unexpected {data:?}"),
+        };
+        let image = alice.receive_init(init).unwrap();
+
+        let init = match data(alice.init()) {
+            Data::Init(init) => init,
+            data => panic!("unexpected {data:?}"),
+        };
+        assert_eq!(bob.receive_init(init).unwrap(), image);
+
+        match data(bob.propose(&mut OsRng, spend).unwrap()) {
+            Data::Proposal(proposal) => proposal,
+            data => panic!("unexpected {data:?}"),
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a Monero daemon in regtest mode"]
+    fn build_and_publish_a_joint_spend() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let rpc = Rpc::new(daemon_url()).unwrap();
+
+        let (alice_address, _, alice_view) = wallet();
+        let (bob_address, _, bob_view) = wallet();
+        let (alice_view, bob_view) = (ViewKey::new(*alice_view), ViewKey::new(*bob_view));
+        let (alice_funding, bob_funding) =
+            runtime.block_on(funded_channel(&rpc, alice_address, bob_address));
+
+        let state = ChannelState {
+            number: 1,
+            bob_amount: CHANNEL_AMOUNT / 4,
+        };
+        let mut builder = DaemonSpendBuilder::new(Rpc::new(daemon_url()).unwrap()).unwrap();
+
+        // Alice does not sign a spend that pays Bob more than the state
+        let spend = builder
+            .build(
+                &bob_funding,
+                &bob_address,
+                state.bob_amount + 1,
+                &alice_address,
+            )
+            .unwrap();
+        let mut alice = JointSpend::new(&mut OsRng, &alice_funding);
+        let mut bob = JointSpend::new(&mut OsRng, &bob_funding);
+        let proposal = propose(&mut alice, &mut bob, spend);
+        assert!(matches!(
+            alice.sign(proposal, &state, &alice_view),
+            Err(Error::UnexpectedOutputs)
+        ));
+
+        let spend = builder
+            .build(&bob_funding, &bob_address, state.bob_amount, &alice_address)
+            .unwrap();
+        let mut alice = JointSpend::new(&mut OsRng, &alice_funding);
+        let mut bob = JointSpend::new(&mut OsRng, &bob_funding);
+        let proposal = propose(&mut alice, &mut bob, spend);
+        let partial = match data(alice.sign(proposal, &state, &alice_view).unwrap()) {
+            Data::Partial(partial) => partial,
+            data => panic!("unexpected {data:?}"),
+        };
+
+        let (tx, signed) = bob.complete(partial).unwrap();
+        let signed = match data(signed) {
+            Data::Signed(signed) => signed,
+            data => panic!("unexpected {data:?}"),
+        };
+        assert_eq!(alice.receive_signed(signed).unwrap().hash(), tx.hash());
+        assert_eq!(bob_view.received(&tx, &bob_address), state.bob_amount);
+
+        runtime.block_on(rpc.publish_transaction(&tx)).unwrap();
+        runtime.block_on(mine(&bob_address, 1));
+
+        let image = match tx.prefix.inputs.as_slice() {
+            [Input::ToKey { key_image, .. }] => key_image.compress().to_bytes(),
+            _ => panic!("the spend has one input"),
+        };
+        assert!(runtime.block_on(rpc.is_key_image_spent(&image)).unwrap());
+    }
+}
@@ -1,8 +1,571 @@
 // TODO 6.2, Figure 6
-// TODO JSpend
+use std::fmt;
+use std::io;
 
-// TODO Figure 14
-// TODO Joint Spending
+use curve25519_dalek::{
+    constants::ED25519_BASEPOINT_TABLE, edwards::EdwardsPoint, scalar::Scalar, traits::Identity,
+};
+use monero_serai::{
+    random_scalar,
+    ringct::{clsag::Clsag, hash_to_point, RctPrunable},
+    rpc::RpcError,
+    transaction::{Input, Transaction},
+    wallet::{address::MoneroAddress, Scanner, TransactionError, ViewPair},
+};
+use rand_core::{CryptoRng, RngCore};
+use zeroize::Zeroizing;
+
+use crate::core::adaptor::{self, ClsagContext};
+use crate::core::channel::{ChannelState, Funding};
+use crate::core::jspend::{self, JointOutput, Nonces, Session, Signer};
+use crate::core::utils::{decode_point, decode_scalar};
+use crate::core::Role;
+use crate::msgs::{self, joint_spend_msg};
+
+mod daemon;
+mod pay;
+pub use daemon::DaemonSpendBuilder;
+pub use pay::{decode_payment, payment_image, Payee, Payer, Payments};
+
+/// Highest fee of a closing spend that Alice signs, in piconero: 0.001 XMR, well above what
+/// the daemon asks for a transaction of one input and two outputs.
+pub const MAX_CLOSE_FEE: u64 = 1_000_000_000;
+
+/// Transaction spending the joint output as its only input, with its pseudo output but
+/// without its CLSAG; the key image of the input is set during the joint spend.
+#[derive(Clone, Debug)]
+pub struct UnsignedSpend {
+    pub tx: Transaction,
+    /// (P_i, C_i) of every member of the ring, in the order of the key offsets of the input.
+    pub ring: Vec<[EdwardsPoint; 2]>,
+    /// Difference between the mask of the joint output and the one of the pseudo output.
+    pub z: Scalar,
+}
+
+impl UnsignedSpend {
+    /// Key image of the input, once set.
+    pub fn image(&self) -> Option<EdwardsPoint> {
+        match self.tx.prefix.inputs.as_slice() {
+            [Input::ToKey { key_image, .. }] => Some(*key_image),
+            _ => None,
+        }
+    }
+
+    // sets the key image of the input, and returns what its CLSAG signs over
+    fn context(
+        &mut self,
+        output: &JointOutput,
+        image: EdwardsPoint,
+    ) -> Result<ClsagContext, Error> {
+        let ring_len = self.ring.len();
+
+        match self.tx.prefix.inputs.as_mut_slice() {
+            [Input::ToKey {
+                key_offsets,
+                key_image,
+                ..
+            }] if key_offsets.len() == ring_len => *key_image = image,
+            _ => return Err(Error::InvalidTransaction),
+        }
+
+        let pseudo_out = match &self.tx.rct_signatures.prunable {
+            RctPrunable::Clsag { pseudo_outs, .. } if pseudo_outs.len() == 1 => pseudo_outs[0],
+            _ => return Err(Error::InvalidTransaction),
+        };
+
+        let commitment = output.commitment.calculate();
+        let index = self
+            .ring
+            .iter()
+            .position(|member| *member == [output.key, commitment])
+            .ok_or(Error::InvalidTransaction)?;
+
+        // the pseudo output must commit to the amount of the joint output
+        if commitment - pseudo_out != &self.z * &ED25519_BASEPOINT_TABLE {
+            return Err(Error::InvalidTransaction);
+        }
+
+        Ok(ClsagContext {
+            ring: self.ring.clone(),
+            index,
+            image,
+            pseudo_out,
+            msg: self.tx.signature_hash(),
+        })
+    }
+}
+
+/// Builds the transactions spending the joint output, which are only signed afterwards, by
+/// both parties or with a pre-signature; PayMo itself never holds the key of the output.
+pub trait SpendBuilder {
+    /// Spend of the joint output of `funding` paying `amount` to `to`, and what is left, minus
+    /// the fee, to `change`; nothing is paid to `to` if `amount` is 0.
+    fn build(
+        &mut self,
+        funding: &Funding,
+        to: &MoneroAddress,
+        amount: u64,
+        change: &MoneroAddress,
+    ) -> Result<UnsignedSpend, Error>;
+}
+
+/// Private view key of our own address, with which we find what a transaction spending the
+/// joint output pays us before we sign it.
+#[derive(Clone)]
+pub struct ViewKey(Zeroizing<Scalar>);
+
+impl ViewKey {
+    pub fn new(key: Scalar) -> Self {
+        Self(Zeroizing::new(key))
+    }
+
+    /// Whether it is the view key of `address`.
+    pub fn is_of(&self, address: &MoneroAddress) -> bool {
+        &*self.0 * &ED25519_BASEPOINT_TABLE == address.view
+    }
+
+    /// Amount that `tx` pays to `address`, whose view key it is, in outputs without an unlock
+    /// time.
+    pub fn received(&self, tx: &Transaction, address: &MoneroAddress) -> u64 {
+        let mut scanner = Scanner::from_view(ViewPair::new(address.spend, self.0.clone()), None);
+
+        scanner
+            .scan_transaction(tx)
+            .not_locked()
+            .iter()
+            .fold(0, |sum, output| {
+                sum.saturating_add(output.commitment().amount)
+            })
+    }
+}
+
+impl fmt::Debug for ViewKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ViewKey(..)")
+    }
+}
+
+// the transaction being signed, with the random responses of the decoys of its CLSAG
+struct Proposal {
+    spend: UnsignedSpend,
+    ctx: ClsagContext,
+    responses: Vec<Scalar>,
+}
+
+/// Spends the joint output of a channel together with the other party, each signing with
+/// its own secret, so that neither ever learns the key of the output. Bob leads: he proposes
+/// the transaction and completes the signature once Alice signed her share.
+///
+/// Bob calls [`JointSpend::init`], [`JointSpend::receive_init`], [`JointSpend::propose`] and
+/// [`JointSpend::complete`]; Alice calls [`JointSpend::receive_init`], [`JointSpend::init`],
+/// [`JointSpend::sign`] and [`JointSpend::receive_signed`].
+pub struct JointSpend {
+    role: Role,
+    output: JointOutput,
+    alice_address: MoneroAddress,
+    channel_amount: u64,
+
+    signer: Option<Signer>,
+    image: EdwardsPoint,
+    nonces: Nonces,
+
+    // public key share of the other party, key * G
+    their_public: EdwardsPoint,
+    their_image: Option<EdwardsPoint>,
+    their_commitment: Option<[u8; 32]>,
+
+    proposal: Option<Proposal>,
+}
+
+impl JointSpend {
+    /// Bob's share of the key also includes the key offset of the output.
+    pub fn new<R: RngCore + CryptoRng>(rng: &mut R, funding: &Funding) -> Self {
+        let keys = &funding.keys;
+        let role = keys.role.clone();
+        let output = funding.output.clone();
+        let offset = &output.key_offset * &ED25519_BASEPOINT_TABLE;
+
+        let (key, their_public) = match role {
+            Role::Alice => (*keys.own.secret, keys.bob_public_key + offset),
+            Role::Bob => (*keys.own.secret + output.key_offset, keys.alice_public_key),
+        };
+
+        let signer = Signer::new(rng, &output.key, key);
+
+        Self {
+            role,
+            alice_address: keys.alice_address,
+            channel_amount: keys.params.channel_amount.as_pico(),
+            image: signer.image(),
+            nonces: signer.nonces(),
+            signer: Some(signer),
+            output,
+            their_public,
+            their_image: None,
+            their_commitment: None,
+            proposal: None,
+        }
+    }
+
+    /// Share of the key image, and commitment to the nonces.
+    pub fn init(&self) -> msgs::JointSpendMsg {
+        let init = msgs::JointSpendInit {
+            output_key: self.output.key.compress().to_bytes().to_vec(),
+            image: self.image.compress().to_bytes().to_vec(),
+            nonce_commitment: self.nonces.commitment().to_vec(),
+        };
+
+        msg(joint_spend_msg::Data::Init(init))
+    }
+
+    /// Returns the key image of the joint output.
+    pub fn receive_init(&mut self, init: msgs::JointSpendInit) -> Result<EdwardsPoint, Error> {
+        if self.their_image.is_some() {
+            return Err(Error::UnexpectedMessage);
+        }
+
+        let output_key = decode_point(&init.output_key).ok_or(Error::InvalidMessage)?;
+        if output_key != self.output.key {
+            return Err(Error::UnknownOutput);
+        }
+
+        let image = decode_point(&init.image).ok_or(Error::InvalidMessage)?;
+        let commitment = init.nonce_commitment.try_into();
+        let commitment = commitment.map_err(|_| Error::InvalidMessage)?;
+
+        self.their_image = Some(image);
+        self.their_commitment = Some(commitment);
+
+        Ok(self.image + image)
+    }
+
+    /// Bob proposes `spend`, and reveals his nonces, once he has Alice's commitment.
+    pub fn propose<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        mut spend: UnsignedSpend,
+    ) -> Result<msgs::JointSpendMsg, Error> {
+        let their_image = self.their_image.ok_or(Error::UnexpectedMessage)?;
+
+        if self.role != Role::Bob || self.proposal.is_some() {
+            return Err(Error::UnexpectedMessage);
+        }
+
+        let ctx = spend.context(&self.output, self.image + their_image)?;
+        let responses: Vec<Scalar> = ctx.ring.iter().map(|_| random_scalar(rng)).collect();
+
+        let proposal = msgs::JointSpendProposal {
+            tx: encode_unsigned(&spend.tx),
+            ring: ring_msg(&ctx.ring),
+            z: spend.z.to_bytes().to_vec(),
+            responses: responses.iter().map(|s| s.to_bytes().to_vec()).collect(),
+            nonces: Some((&self.nonces).into()),
+        };
+
+        self.proposal = Some(Proposal {
+            spend,
+            ctx,
+            responses,
+        });
+
+        Ok(msg(joint_spend_msg::Data::Proposal(proposal)))
+    }
+
+    /// Alice signs her share of the transaction proposed by Bob, if it pays `state`, and
+    /// reveals her nonces; `view_key` is the one of her address.
+    pub fn sign(
+        &mut self,
+        proposal: msgs::JointSpendProposal,
+        state: &ChannelState,
+        view_key: &ViewKey,
+    ) -> Result<msgs::JointSpendMsg, Error> {
+        let their_image = self.their_image.ok_or(Error::UnexpectedMessage)?;
+
+        if self.role != Role::Alice || self.proposal.is_some() {
+            return Err(Error::UnexpectedMessage);
+        }
+
+        let their_nonces = self.their_nonces(proposal.nonces)?;
+
+        let responses = proposal
+            .responses
+            .iter()
+            .map(|s| decode_scalar(s))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(Error::InvalidMessage)?;
+
+        let mut spend = UnsignedSpend {
+            tx: decode_unsigned(&proposal.tx)?,
+            ring: decode_ring(&proposal.ring)?,
+            z: decode_scalar(&proposal.z).ok_or(Error::InvalidMessage)?,
+        };
+
+        let ctx = spend.context(&self.output, self.image + their_image)?;
+
+        // Bob sets the fee, so that it cannot take Alice's balance
+        let fee = spend.tx.rct_signatures.base.fee;
+        if fee > MAX_CLOSE_FEE {
+            return Err(Error::FeeTooHigh(fee));
+        }
+
+        // Alice pays the fee, and Bob whatever of it is above her balance; since the outputs
+        // and the fee add up to the joint output, Bob then gets at most his amount
+        let alice_amount = state.alice_amount(self.channel_amount).saturating_sub(fee);
+        if view_key.received(&spend.tx, &self.alice_address) < alice_amount {
+            return Err(Error::UnexpectedOutputs);
+        }
+
+        let nonces = [their_nonces, self.nonces];
+        let session = Session::new(ctx.clone(), spend.z, responses.clone(), &nonces)?;
+        let share = session.sign(self.signer.take().unwrap(), false);
+
+        self.proposal = Some(Proposal {
+            spend,
+            ctx,
+            responses,
+        });
+
+        let partial = msgs::JointSpendPartial {
+            nonces: Some((&self.nonces).into()),
+            share: share.to_bytes().to_vec(),
+        };
+
+        Ok(msg(joint_spend_msg::Data::Partial(partial)))
+    }
+
+    /// Bob checks Alice's share and completes the signature; returns the signed transaction,
+    /// and the message that sends it to Alice.
+    pub fn complete(
+        &mut self,
+        partial: msgs::JointSpendPartial,
+    ) -> Result<(Transaction, msgs::JointSpendMsg), Error> {
+        if self.role != Role::Bob {
+            return Err(Error::UnexpectedMessage);
+        }
+
+        let proposal = self.proposal.take().ok_or(Error::UnexpectedMessage)?;
+        let their_image = self.their_image.unwrap();
+
+        let their_nonces = self.their_nonces(partial.nonces)?;
+        let share = decode_scalar(&partial.share).ok_or(Error::InvalidMessage)?;
+
+        let session = Session::new(
+            proposal.ctx,
+            proposal.spend.z,
+            proposal.responses,
+            &[self.nonces, their_nonces],
+        )?;
+
+        if !session.verify_share(
+            &self.their_public,
+            &their_image,
+            &their_nonces,
+            &share,
+            false,
+        ) {
+            return Err(Error::InvalidShare);
+        }
+
+        let own_share = session.sign(self.signer.take().unwrap(), true);
+        let clsag = session.complete(&[own_share, share]);
+
+        let tx = signed_tx(proposal.spend.tx, session.ctx(), clsag)?;
+
+        let signed = msgs::JointSpendSigned { tx: tx.serialize() };
+
+        Ok((tx, msg(joint_spend_msg::Data::Signed(signed))))
+    }
+
+    /// Alice checks that Bob sent her the transaction she signed, with a valid signature.
+    pub fn receive_signed(&mut self, signed: msgs::JointSpendSigned) -> Result<Transaction, Error> {
+        if self.role != Role::Alice {
+            return Err(Error::UnexpectedMessage);
+        }
+
+        let proposal = self.proposal.take().ok_or(Error::UnexpectedMessage)?;
+
+        let tx = Transaction::read(&mut signed.tx.as_slice())?;
+        let clsag = match &tx.rct_signatures.prunable {
+            RctPrunable::Clsag { clsags, .. } if clsags.len() == 1 => clsags[0].clone(),
+            _ => return Err(Error::InvalidTransaction),
+        };
+
+        let expected = signed_tx(proposal.spend.tx, &proposal.ctx, clsag)?;
+        if expected.serialize() != tx.serialize() {
+            return Err(Error::InvalidTransaction);
+        }
+
+        Ok(tx)
+    }
+
+    fn their_nonces(&self, nonces: Option<msgs::JointSpendNonces>) -> Result<Nonces, Error> {
+        let nonces = Nonces::try_from(nonces.ok_or(Error::InvalidMessage)?)?;
+
+        if Some(nonces.commitment()) != self.their_commitment {
+            return Err(Error::NonceCommitmentMismatch);
+        }
+
+        Ok(nonces)
+    }
+}
+
+/// Alice signs `spend` alone, once she recovered Bob's secret from his VTD-Log: she pre-signs
+/// it with her share of the key, and completes it with his, as Bob does with a payment.
+pub fn sign_refund<R: RngCore + CryptoRng>(
+    rng: &mut R,
+    mut spend: UnsignedSpend,
+    output: &JointOutput,
+    alice_secret: &Scalar,
+    bob_secret: &Scalar,
+) -> Result<Transaction, Error> {
+    let bob_key = bob_secret + output.key_offset;
+    let image = (alice_secret + bob_key) * hash_to_point(output.key);
+
+    let ctx = spend.context(output, image)?;
+    let pre = adaptor::pre_sign(rng, &ctx, alice_secret, &spend.z);
+    let clsag = adaptor::adapt(&ctx, &pre, &bob_key)?;
+
+    signed_tx(spend.tx, &ctx, clsag)
+}
+
+fn msg(data: joint_spend_msg::Data) -> msgs::JointSpendMsg {
+    msgs::JointSpendMsg { data: Some(data) }
+}
+
+fn ring_msg(ring: &[[EdwardsPoint; 2]]) -> Vec<msgs::RingMember> {
+    ring.iter()
+        .map(|[key, commitment]| msgs::RingMember {
+            key: key.compress().to_bytes().to_vec(),
+            commitment: commitment.compress().to_bytes().to_vec(),
+        })
+        .collect()
+}
+
+fn decode_ring(ring: &[msgs::RingMember]) -> Result<Vec<[EdwardsPoint; 2]>, Error> {
+    ring.iter()
+        .map(|member| {
+            Some([
+                decode_point(&member.key)?,
+                decode_point(&member.commitment)?,
+            ])
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or(Error::InvalidMessage)
+}
+
+// a transaction is only read back with a CLSAG for every input, so the one of an unsigned
+// spend is encoded with a placeholder, which its signature hash does not cover
+fn encode_unsigned(tx: &Transaction) -> Vec<u8> {
+    let mut tx = tx.clone();
+    let ring_len = match tx.prefix.inputs.as_slice() {
+        [Input::ToKey { key_offsets, .. }] => key_offsets.len(),
+        _ => 0,
+    };
+
+    if let RctPrunable::Clsag { clsags, .. } = &mut tx.rct_signatures.prunable {
+        *clsags = vec![Clsag {
+            D: EdwardsPoint::identity(),
+            s: vec![Scalar::zero(); ring_len],
+            c1: Scalar::zero(),
+        }];
+    }
+
+    tx.serialize()
+}
+
+// the transaction of an unsigned spend, without its placeholder CLSAG
+fn decode_unsigned(mut bytes: &[u8]) -> Result<Transaction, Error> {
+    let mut tx = Transaction::read(&mut bytes)?;
+
+    if let RctPrunable::Clsag { clsags, .. } = &mut tx.rct_signatures.prunable {
+        clsags.clear();
+    }
+
+    Ok(tx)
+}
+
+// `tx` with its CLSAG, if it verifies
+fn signed_tx(mut tx: Transaction, ctx: &ClsagContext, clsag: Clsag) -> Result<Transaction, Error> {
+    clsag
+        .verify(&ctx.ring, &ctx.image, &ctx.pseudo_out, &ctx.msg)
+        .map_err(|_| Error::InvalidSignature)?;
+
+    match &mut tx.rct_signatures.prunable {
+        RctPrunable::Clsag { clsags, .. } => *clsags = vec![clsag],
+        _ => unreachable!(),
+    }
+
+    Ok(tx)
+}
 
 #[derive(Debug, thiserror::Error)]
-pub enum Error {}
+pub enum Error {
+    #[error("Unexpected joint spend message")]
+    UnexpectedMessage,
+
+    #[error("Malformed joint spend message")]
+    InvalidMessage,
+
+    #[error("The other party is spending another output")]
+    UnknownOutput,
+
+    #[error("The nonces of the other party do not match their commitment")]
+    NonceCommitmentMismatch,
+
+    #[error("The transaction does not spend the joint output")]
+    InvalidTransaction,
+
+    #[error("The transaction does not pay the state of the channel")]
+    UnexpectedOutputs,
+
+    #[error("The fee of the transaction is too high: {0} piconero")]
+    FeeTooHigh(u64),
+
+    #[error("The share of the signature of the other party is invalid")]
+    InvalidShare,
+
+    #[error("The joint CLSAG signature is invalid")]
+    InvalidSignature,
+
+    #[error("Could not decode the transaction: {0}")]
+    TransactionDecode(#[from] io::Error),
+
+    #[error("A payment is already in progress")]
+    PaymentPending,
+
+    #[error("The payment is not for the expected channel state")]
+    UnexpectedState,
+
+    #[error("The share of the key image of the other party is not the one of their key")]
+    InvalidImage,
+
+    #[error("The pre-signature of the payment does not complete with our share of the key")]
+    InvalidPreSignature,
+
+    #[error("Could not find the joint output on chain")]
+    OutputNotFound,
+
+    #[error("Could not build the transaction: {0:?}")]
+    Build(TransactionError),
+
+    #[error("Monero RPC error: {0:?}")]
+    Rpc(RpcError),
+
+    #[error("Could not access the latest payment: {0}")]
+    Payments(io::Error),
+
+    #[error("The channel already has a payment")]
+    PaymentExists,
+
+    #[error("Corrupt latest payment: {0}")]
+    CorruptPayment(#[from] prost::DecodeError),
+
+    #[error(transparent)]
+    Channel(#[from] crate::core::channel::Error),
+
+    #[error(transparent)]
+    Adaptor(#[from] crate::core::adaptor::Error),
+
+    #[error(transparent)]
+    Jspend(#[from] jspend::Error),
+}
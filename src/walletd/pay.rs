@@ -0,0 +1,364 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use curve25519_dalek::{constants::ED25519_BASEPOINT_TABLE, edwards::EdwardsPoint, scalar::Scalar};
+use monero_serai::{
+    ringct::hash_to_point, transaction::Transaction, wallet::address::MoneroAddress,
+};
+use prost::Message;
+use rand_core::{CryptoRng, RngCore};
+use zeroize::Zeroizing;
+
+use super::{
+    decode_ring, decode_unsigned, encode_unsigned, ring_msg, signed_tx, Error, UnsignedSpend,
+    ViewKey,
+};
+use crate::core::adaptor::{self, PreSignature, Statement};
+use crate::core::channel::{ChannelState, Funding};
+use crate::core::jspend::JointOutput;
+use crate::core::proofs::DleqProof;
+use crate::core::utils::{atomic_write, decode_point, decode_scalar};
+use crate::msgs::{self, pay_msg};
+
+/// Alice's side of the payments of a channel. She pre-signs, with her share of the key of the
+/// joint output, the spend paying every new state, so that Bob can only complete it with his.
+pub struct Payer {
+    output: JointOutput,
+    key: Zeroizing<Scalar>,
+    image: EdwardsPoint,
+    // Bob's share of the key, which includes the key offset of the output, times G
+    bob_public: EdwardsPoint,
+    channel_amount: u64,
+
+    state: ChannelState,
+    pending: Option<ChannelState>,
+    payment: Option<msgs::Payment>,
+}
+
+impl Payer {
+    /// `state` is the last one Bob acknowledged.
+    pub fn new(funding: &Funding, state: ChannelState) -> Self {
+        let output = funding.output.clone();
+        let key = funding.keys.own.secret.clone();
+        let offset = &output.key_offset * &ED25519_BASEPOINT_TABLE;
+
+        Self {
+            image: *key * hash_to_point(output.key),
+            bob_public: funding.keys.bob_public_key + offset,
+            channel_amount: funding.keys.params.channel_amount.as_pico(),
+            key,
+            output,
+            state,
+            pending: None,
+            payment: None,
+        }
+    }
+
+    pub fn state(&self) -> ChannelState {
+        self.state
+    }
+
+    /// Requests paying Bob `amount` more; returns the requested state.
+    pub fn request(&mut self, amount: u64) -> Result<(ChannelState, msgs::PayMsg), Error> {
+        if self.pending.is_some() {
+            return Err(Error::PaymentPending);
+        }
+
+        let next = self.state.pay(amount, self.channel_amount)?;
+        self.pending = Some(next);
+
+        Ok((next, msg(pay_msg::Data::Request((&next).into()))))
+    }
+
+    /// The requested state, until Bob acknowledges it.
+    pub fn pending(&self) -> Option<ChannelState> {
+        self.pending
+    }
+
+    /// Gives up the requested state, e.g. because Bob restarted before acknowledging it, so
+    /// that another payment or the close can start; returns it.
+    pub fn abort(&mut self) -> Option<ChannelState> {
+        self.payment = None;
+        self.pending.take()
+    }
+
+    /// Pre-signs `spend`, which pays the requested state, once Bob accepted it with his share
+    /// of the key image.
+    pub fn pre_sign<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        image: msgs::PaymentImage,
+        mut spend: UnsignedSpend,
+    ) -> Result<msgs::PayMsg, Error> {
+        let state = self.pending.ok_or(Error::UnexpectedMessage)?;
+        if self.payment.is_some() {
+            return Err(Error::UnexpectedMessage);
+        }
+
+        let bob_image = decode_point(&image.image).ok_or(Error::InvalidMessage)?;
+        let proof = image.proof.map(DleqProof::try_from);
+
+        match proof {
+            Some(Ok(proof))
+                if proof.verify(
+                    &hash_to_point(self.output.key),
+                    &self.bob_public,
+                    &bob_image,
+                ) => {}
+            _ => return Err(Error::InvalidImage),
+        }
+
+        let ctx = spend.context(&self.output, self.image + bob_image)?;
+        let pre = adaptor::pre_sign(rng, &ctx, &self.key, &spend.z);
+
+        let payment = msgs::Payment {
+            state: Some((&state).into()),
+            tx: encode_unsigned(&spend.tx),
+            ring: ring_msg(&ctx.ring),
+            z: spend.z.to_bytes().to_vec(),
+            pre_signature: Some((&pre).into()),
+        };
+        self.payment = Some(payment.clone());
+
+        Ok(msg(pay_msg::Data::Payment(payment)))
+    }
+
+    /// The requested state becomes the one of the channel once Bob acknowledges it; returns
+    /// its payment.
+    pub fn receive_ack(&mut self, ack: msgs::ChannelState) -> Result<msgs::Payment, Error> {
+        let state = self.pending.ok_or(Error::UnexpectedMessage)?;
+        if ChannelState::from(ack) != state {
+            return Err(Error::UnexpectedState);
+        }
+
+        let payment = self.payment.take().ok_or(Error::UnexpectedMessage)?;
+
+        self.pending = None;
+        self.state = state;
+
+        Ok(payment)
+    }
+}
+
+/// Bob's side of the payments of a channel: he accepts every new state that pays him more,
+/// and only acknowledges it once its spend is pre-signed for his share of the key.
+pub struct Payee {
+    output: JointOutput,
+    // Bob's share of the key, which includes the key offset of the output
+    key: Zeroizing<Scalar>,
+    channel_amount: u64,
+
+    address: MoneroAddress,
+    view_key: ViewKey,
+
+    state: ChannelState,
+    pending: Option<ChannelState>,
+}
+
+impl Payee {
+    /// `state` is the last one Bob acknowledged; `view_key` is the one of his address.
+    pub fn new(funding: &Funding, state: ChannelState, view_key: ViewKey) -> Self {
+        let output = funding.output.clone();
+
+        Self {
+            key: Zeroizing::new(*funding.keys.own.secret + output.key_offset),
+            channel_amount: funding.keys.params.channel_amount.as_pico(),
+            address: funding.keys.bob_address,
+            view_key,
+            output,
+            state,
+            pending: None,
+        }
+    }
+
+    pub fn state(&self) -> ChannelState {
+        self.state
+    }
+
+    /// Accepts the state requested by Alice, if it follows the current one, with his share of
+    /// the key image and a proof that it uses his share of the key.
+    pub fn receive_request<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        request: msgs::ChannelState,
+    ) -> Result<msgs::PayMsg, Error> {
+        if self.pending.is_some() {
+            return Err(Error::PaymentPending);
+        }
+
+        let next = ChannelState::from(request);
+        self.state.check_next(&next, self.channel_amount)?;
+
+        let h = hash_to_point(self.output.key);
+        let proof = DleqProof::prove(rng, &self.key, &h);
+
+        self.pending = Some(next);
+
+        let image = msgs::PaymentImage {
+            image: (*self.key * h).compress().to_bytes().to_vec(),
+            proof: Some((&proof).into()),
+        };
+
+        Ok(msg(pay_msg::Data::Image(image)))
+    }
+
+    /// Checks that `payment` spends the joint output, paying Bob at least the accepted state,
+    /// and that its pre-signature becomes a valid signature with his share of the key; the
+    /// accepted state then becomes the one of the channel.
+    pub fn receive_payment(
+        &mut self,
+        payment: &msgs::Payment,
+    ) -> Result<(ChannelState, msgs::PayMsg), Error> {
+        let state = self.pending.ok_or(Error::UnexpectedMessage)?;
+        if payment.state.clone().map(ChannelState::from) != Some(state) {
+            return Err(Error::UnexpectedState);
+        }
+
+        let (mut spend, pre) = decode_payment(payment)?;
+
+        // the pre-signature only verifies if the key image is the one of the joint output
+        let image = spend.image().ok_or(Error::InvalidTransaction)?;
+        let ctx = spend.context(&self.output, image)?;
+
+        let statement = Statement::new(&self.key, &self.output.key);
+        if !adaptor::pre_verify(&ctx, &pre, &statement) {
+            return Err(Error::InvalidPreSignature);
+        }
+
+        if self.view_key.received(&spend.tx, &self.address) < state.bob_amount {
+            return Err(Error::UnexpectedOutputs);
+        }
+
+        self.pending = None;
+        self.state = state;
+
+        Ok((state, msg(pay_msg::Data::Ack((&state).into()))))
+    }
+
+    /// Completes the pre-signature of `payment`, one he acknowledged, with his share of the
+    /// key: the transaction with which he closes the channel without Alice.
+    pub fn complete(&self, payment: &msgs::Payment) -> Result<Transaction, Error> {
+        let (mut spend, pre) = decode_payment(payment)?;
+
+        let image = spend.image().ok_or(Error::InvalidTransaction)?;
+        let ctx = spend.context(&self.output, image)?;
+        let clsag = adaptor::adapt(&ctx, &pre, &self.key)?;
+
+        signed_tx(spend.tx, &ctx, clsag)
+    }
+}
+
+/// Spend and pre-signature of `payment`.
+pub fn decode_payment(payment: &msgs::Payment) -> Result<(UnsignedSpend, PreSignature), Error> {
+    let spend = UnsignedSpend {
+        tx: decode_unsigned(&payment.tx)?,
+        ring: decode_ring(&payment.ring)?,
+        z: decode_scalar(&payment.z).ok_or(Error::InvalidMessage)?,
+    };
+
+    let pre = payment.pre_signature.clone().ok_or(Error::InvalidMessage)?;
+
+    Ok((spend, PreSignature::try_from(pre)?))
+}
+
+/// Key image of the joint output, as set in the spend of `payment`; every payment of the
+/// channel spends it with the same one.
+pub fn payment_image(payment: &msgs::Payment) -> Result<EdwardsPoint, Error> {
+    let (spend, _) = decode_payment(payment)?;
+
+    spend.image().ok_or(Error::InvalidTransaction)
+}
+
+fn msg(data: pay_msg::Data) -> msgs::PayMsg {
+    msgs::PayMsg { data: Some(data) }
+}
+
+/// Latest payment of a channel, kept in the directory of the channel in the channel database:
+/// Bob completes it to close the channel, and Alice knows from it what Bob can close with.
+pub struct Payments {
+    path: PathBuf,
+}
+
+impl Payments {
+    pub fn new(channel_dir: &Path) -> Self {
+        Self {
+            path: channel_dir.join("payment"),
+        }
+    }
+
+    /// Replaces the latest payment.
+    pub fn save(&self, payment: &msgs::Payment) -> Result<(), Error> {
+        // replaces the old file at once, so that a crash never loses the previous payment
+        atomic_write(&self.path, &payment.encode_to_vec()).map_err(Error::Payments)
+    }
+
+    /// Saves the payment of an imported channel, which must not have one yet.
+    pub fn import(&self, payment: &msgs::Payment) -> Result<(), Error> {
+        if self.load()?.is_some() {
+            return Err(Error::PaymentExists);
+        }
+
+        self.save(payment)
+    }
+
+    pub fn load(&self) -> Result<Option<msgs::Payment>, Error> {
+        let bytes = match fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::Payments(e)),
+        };
+
+        Ok(Some(msgs::Payment::decode(bytes.as_slice())?))
+    }
+
+    /// State of the latest payment; the initial one, before any.
+    pub fn state(&self) -> Result<ChannelState, Error> {
+        let payment = self.load()?;
+
+        Ok(payment
+            .and_then(|payment| payment.state)
+            .map(ChannelState::from)
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::utils::temp_dir;
+
+    #[test]
+    fn save_and_load_payments() {
+        let data_dir = temp_dir();
+
+        let payments = Payments::new(&data_dir);
+        assert!(payments.load().unwrap().is_none());
+        assert_eq!(payments.state().unwrap(), ChannelState::default());
+
+        for number in 1..3 {
+            let state = ChannelState {
+                number,
+                bob_amount: number * 1_000,
+            };
+            let payment = msgs::Payment {
+                state: Some((&state).into()),
+                tx: vec![number as u8; 16],
+                ..Default::default()
+            };
+
+            payments.save(&payment).unwrap();
+            assert_eq!(payments.load().unwrap(), Some(payment.clone()));
+            assert_eq!(payments.state().unwrap(), state);
+
+            // an imported payment never replaces another one
+            assert!(matches!(
+                payments.import(&payment),
+                Err(Error::PaymentExists)
+            ));
+        }
+
+        fs::write(data_dir.join("payment"), [0xff; 4]).unwrap();
+        assert!(matches!(payments.load(), Err(Error::CorruptPayment(_))));
+    }
+}
@@ -1,2 +1,195 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use curve25519_dalek::edwards::EdwardsPoint;
+use monero_serai::{
+    rpc::{Rpc, RpcError},
+    transaction::Transaction,
+};
+
+use crate::core::utils::{atomic_write, decode_point};
+
+/// Key images of the channel outputs that watcherd watches, so that it notices when one is
+/// spent on chain; kept in the data directory, one hex encoded key image per line.
+pub struct KeyImages {
+    path: PathBuf,
+}
+
+impl KeyImages {
+    pub fn new(data_dir: &Path) -> Self {
+        Self {
+            path: data_dir.join("key_images"),
+        }
+    }
+
+    /// Adds `image`, if it is not watched yet.
+    pub fn add(&self, image: &EdwardsPoint) -> Result<(), Error> {
+        let mut images = self.load()?;
+        if images.contains(image) {
+            return Ok(());
+        }
+
+        images.push(*image);
+
+        let contents: String = images
+            .iter()
+            .map(|image| hex::encode(image.compress().to_bytes()) + "\n")
+            .collect();
+
+        // replaces the old file at once, so that a crash never loses the images already there
+        atomic_write(&self.path, contents.as_bytes())?;
+
+        Ok(())
+    }
+
+    pub fn load(&self) -> Result<Vec<EdwardsPoint>, Error> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        contents
+            .lines()
+            .map(|line| {
+                let bytes = hex::decode(line).map_err(|_| Error::InvalidKeyImage(line.into()))?;
+                decode_point(&bytes).ok_or_else(|| Error::InvalidKeyImage(line.into()))
+            })
+            .collect()
+    }
+}
+
+/// Those of `images` that are already spent on chain.
+pub async fn spent(rpc: &Rpc, images: &[EdwardsPoint]) -> Result<Vec<EdwardsPoint>, Error> {
+    let mut spent = vec![];
+
+    for image in images {
+        let image_bytes = image.compress().to_bytes();
+
+        if rpc
+            .is_key_image_spent(&image_bytes)
+            .await
+            .map_err(Error::Rpc)?
+        {
+            spent.push(*image);
+        }
+    }
+
+    Ok(spent)
+}
+
+/// Confirmations of the transaction `hash`, if it is in a block from `from_height`.
+pub async fn confirmations(
+    rpc: &Rpc,
+    hash: &[u8; 32],
+    from_height: usize,
+) -> Result<Option<usize>, Error> {
+    let height = rpc.get_height().await.map_err(Error::Rpc)?;
+
+    for number in from_height..height {
+        let block = rpc.get_block_by_number(number).await.map_err(Error::Rpc)?;
+
+        if block.txs.contains(hash) {
+            return Ok(Some(height - number));
+        }
+    }
+
+    Ok(None)
+}
+
+/// The chain as the client sees it: what it asks about the channel output, and the
+/// transactions it publishes.
+pub trait Chain {
+    fn height(&self) -> Result<usize, Error>;
+
+    /// Those of `images` that are already spent on chain.
+    fn spent(&self, images: &[EdwardsPoint]) -> Result<Vec<EdwardsPoint>, Error>;
+
+    /// Confirmations of the transaction `hash`, if it is in a block from `from_height`.
+    fn confirmations(&self, hash: &[u8; 32], from_height: usize) -> Result<Option<usize>, Error>;
+
+    fn publish(&self, tx: &Transaction) -> Result<(), Error>;
+}
+
+/// The chain of a Monero daemon, through its RPC.
+pub struct DaemonChain {
+    rpc: Rpc,
+    runtime: tokio::runtime::Runtime,
+}
+
+impl DaemonChain {
+    pub fn new(rpc: Rpc) -> io::Result<Self> {
+        Ok(Self {
+            rpc,
+            runtime: tokio::runtime::Runtime::new()?,
+        })
+    }
+}
+
+impl Chain for DaemonChain {
+    fn height(&self) -> Result<usize, Error> {
+        self.runtime
+            .block_on(self.rpc.get_height())
+            .map_err(Error::Rpc)
+    }
+
+    fn spent(&self, images: &[EdwardsPoint]) -> Result<Vec<EdwardsPoint>, Error> {
+        self.runtime.block_on(spent(&self.rpc, images))
+    }
+
+    fn confirmations(&self, hash: &[u8; 32], from_height: usize) -> Result<Option<usize>, Error> {
+        self.runtime
+            .block_on(confirmations(&self.rpc, hash, from_height))
+    }
+
+    fn publish(&self, tx: &Transaction) -> Result<(), Error> {
+        self.runtime
+            .block_on(self.rpc.publish_transaction(tx))
+            .map_err(Error::Rpc)
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
-pub enum Error {}
+pub enum Error {
+    #[error("Could not access the watched key images: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Invalid watched key image: {0}")]
+    InvalidKeyImage(String),
+
+    #[error("Monero RPC error: {0:?}")]
+    Rpc(RpcError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use curve25519_dalek::constants::ED25519_BASEPOINT_TABLE;
+    use monero_serai::random_scalar;
+    use rand_core::OsRng;
+
+    use crate::core::utils::temp_dir;
+
+    #[test]
+    fn add_and_load_key_images() {
+        let data_dir = temp_dir();
+
+        let key_images = KeyImages::new(&data_dir);
+        assert!(key_images.load().unwrap().is_empty());
+
+        let images: Vec<EdwardsPoint> = (0..3)
+            .map(|_| &random_scalar(&mut OsRng) * &ED25519_BASEPOINT_TABLE)
+            .collect();
+
+        for image in &images {
+            key_images.add(image).unwrap();
+        }
+        key_images.add(&images[1]).unwrap();
+
+        assert_eq!(key_images.load().unwrap(), images);
+
+        fs::write(data_dir.join("key_images"), "paymo\n").unwrap();
+        assert!(matches!(key_images.load(), Err(Error::InvalidKeyImage(_))));
+    }
+}